@@ -0,0 +1,14 @@
+// Closures reading and updating captured variables from outer scopes.
+fun counter() {
+  var count = 0;
+  return fun () {
+    count = count + 1;
+    return count;
+  };
+}
+var next = counter();
+var sum = 0;
+for i in 0..300000 {
+  sum = sum + next() + i;
+}
+print sum;
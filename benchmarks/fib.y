@@ -0,0 +1,6 @@
+// Recursive calls, dominated by argument binding and lookups.
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+print fib(25);
@@ -0,0 +1,4 @@
+// Builds and filters a large list, dominated by the size of each value.
+var doubled = map(0..1000000, (n) => n * 2);
+var quadrupled = filter(doubled, (n) => n % 4 == 0);
+print len(doubled) + len(quadrupled);
@@ -0,0 +1,14 @@
+// Sums in nested loops, reading and writing locals and globals.
+var total = 0;
+fun sum(n) {
+  var acc = 0;
+  for (var i = 0; i < n; i = i + 1) {
+    var j = i % 7;
+    acc = acc + j * 2;
+  }
+  return acc;
+}
+for (var k = 0; k < 20; k = k + 1) {
+  total = total + sum(50000);
+}
+print total;
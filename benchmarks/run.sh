@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+# Times each benchmark script with a release build, best of three runs.
+# Pass another interpreter binary to compare against, e.g. one built from
+# an earlier commit: benchmarks/run.sh /tmp/y-old
+set -e
+cd "$(dirname "$0")/.."
+cargo build --release --quiet
+binaries=(target/release/y "$@")
+TIMEFORMAT=%R
+for script in benchmarks/*.y; do
+    for binary in "${binaries[@]}"; do
+        best=
+        for _ in 1 2 3; do
+            seconds=$( { time "$binary" "$script" > /dev/null; } 2>&1 )
+            if [ -z "$best" ] || (( 10#${seconds/./} < 10#${best/./} )); then
+                best=$seconds
+            fi
+        done
+        printf '%-24s %-28s %ss\n' "$script" "$binary" "$best"
+    done
+done
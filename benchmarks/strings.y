@@ -0,0 +1,15 @@
+// String literals flowing through variables, fields and map keys.
+class Entry {
+  init(name, tag) {
+    this.name = name;
+    this.tag = tag;
+  }
+}
+var counts = {};
+var total = 0;
+for i in 0..200000 {
+  var entry = Entry("a fairly long name for an entry", "tag");
+  counts[entry.tag] = i;
+  total = total + len(entry.name);
+}
+print total;
@@ -0,0 +1,46 @@
+// An echo server and its clients as tasks on one event loop. The clients
+// send at different times, but `gather` keeps their replies in order.
+// Prints ["one", "two", "three"], then fast.
+
+var server = listen("127.0.0.1:0");
+
+async fun handle(connection) {
+  var text = await read(connection);
+  while text != nil {
+    await write(connection, text);
+    text = await read(connection);
+  }
+}
+
+// Each connection gets its own task, so one slow client holds up nobody.
+async fun serve(clients) {
+  for i in 0..clients {
+    var connection = await accept(server);
+    handle(connection);
+  }
+}
+
+async fun client(message, delay) {
+  var socket = await connect(address(server));
+  await sleep(delay);
+  await write(socket, message);
+  var reply = await read(socket);
+  close(socket);
+  return reply;
+}
+
+async fun main() {
+  serve(4);
+  var replies = await gather([
+    client("one", 30),
+    client("two", 10),
+    client("three", 20)
+  ]);
+  print replies;
+
+  var timeout = sleep(1000);
+  var winner = await race([client("fast", 0), timeout]);
+  print winner;
+}
+
+main();
@@ -0,0 +1,33 @@
+// A deterministic producer/consumer pipeline: each stage runs on its own
+// thread and passes copies of values downstream over channels, closing its
+// output when its input runs dry. Prints 0, 2, 8, 18 and 32, then 60.
+
+fun stage(input, output, f) {
+  for x in input {
+    send(output, f(x));
+  }
+  close(output);
+}
+
+var numbers = channel();
+var squares = channel();
+var results = channel();
+
+spawn(() => stage(numbers, squares, x => x * x));
+spawn(() => stage(squares, results, x => x + x));
+
+var producer = spawn(fun () {
+  for i in 0..5 {
+    send(numbers, i);
+  }
+  close(numbers);
+  return "done";
+});
+
+var total = 0;
+for x in results {
+  print x;
+  total += x;
+}
+print total;
+join(producer);
@@ -1,9 +1,20 @@
+use crate::bigint::BigInt;
+use crate::class::{Accessors, Class, Trait};
+use crate::debug;
+use crate::enums::Enum;
 use crate::environment::Environment;
 use crate::error::RuntimeError;
+use crate::function::Function;
+use crate::interpreter::{declare, show};
+use crate::iterator::{self, Iter};
+use crate::limits;
+use crate::rational::Rational;
 use crate::token::Token;
 use crate::value::Value;
 use core::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 
 pub enum Expr {
     Binary {
@@ -16,9 +27,160 @@ pub enum Expr {
         right: Box<Expr>,
     },
     Literal {
-        value: Value,
+        value: Literal,
     },
     Grouping(Box<Expr>),
+    Variable {
+        name: Token,
+        slot: Slot,
+    },
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+        slot: Slot,
+    },
+    /// Read-modify-write of an assignable `target`, covering `x += e` as
+    /// well as `++x` and `x--` (which use an implicit value of 1). `operator`
+    /// is the underlying binary operator; `postfix` expressions evaluate to
+    /// the value from before the update.
+    CompoundAssign {
+        target: Box<Expr>,
+        operator: Token,
+        value: Box<Expr>,
+        postfix: bool,
+    },
+    /// Short-circuiting `and`, `or` and `??`.
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    /// `condition ? then_branch : else_branch`
+    Conditional {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    /// `callee(arguments)`, or `callee?.(arguments)` when `optional`.
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Argument>,
+        optional: bool,
+    },
+    /// `object.name`, or `object?.name` when `optional`.
+    Get {
+        object: Box<Expr>,
+        name: Token,
+        optional: bool,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        keyword: Token,
+        slot: Slot,
+    },
+    /// `super.name`: a member of the superclass of the class whose method
+    /// this is, bound to `this`. `slot` is the superclass's.
+    Super {
+        name: Token,
+        slot: Slot,
+        this: Box<Expr>,
+    },
+    /// Anonymous function, from `fun (params) { ... }` or `(params) => ...`.
+    Lambda(Arc<FunctionDecl>),
+    List {
+        elements: Vec<Expr>,
+    },
+    /// `{key: value, ...}`, where a bare identifier key stands for its name
+    /// as a string.
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    /// `start..end` or `start..=end`, optionally followed by `step n`.
+    Range {
+        start: Box<Expr>,
+        operator: Token,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+    },
+    /// `object[index]`
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// `match subject { pattern if guard => value, ... }`, evaluating to the
+    /// value of the first arm whose pattern matches and whose guard holds.
+    Match {
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+}
+
+/// One `pattern if guard => body` arm of a `match`. The names the pattern
+/// binds are visible to the guard and the body.
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+    /// The names the pattern binds, each in its own slot.
+    pub names: Names,
+}
+
+/// The shape of a value, as tested by a `match` arm or taken apart by a
+/// destructuring `var` or assignment.
+pub enum Pattern {
+    /// `_`, matching anything.
+    Wildcard,
+    /// A name, matching anything and binding it.
+    Bind(Token, Slot),
+    /// A literal, possibly negated, or a capitalised or dotted name such as
+    /// `None` or `Shape.Empty`, matching equal values.
+    Value(Expr),
+    /// `low..high` or `low..=high`, matching numbers or strings in between.
+    Range {
+        low: Expr,
+        operator: Token,
+        high: Expr,
+    },
+    /// `[a, b, ...rest, z]`, matching lists of at least the given length
+    /// (exactly it without a rest pattern), which receives the middle part.
+    List {
+        before: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+        after: Vec<Pattern>,
+    },
+    /// `pattern = default` inside a list, map or instance pattern, which
+    /// matches `default` instead when the part is missing or `nil`.
+    Default {
+        pattern: Box<Pattern>,
+        default: Expr,
+    },
+    /// `{key: pattern, name}`, matching maps that have each key, or
+    /// instances that have each field. A bare `name` binds that entry.
+    Map { entries: Vec<(Expr, Pattern)> },
+    /// `Class(patterns)`, matching instances of the class or its subclasses.
+    /// Positional patterns stand for the fields named by the parameters of
+    /// its `init`, in order, which instances must have; `name: pattern`
+    /// matches a field by name. The class may
+    /// instead be an enum variant constructor such as `Shape.Circle` or
+    /// `Some`, matching that variant and its fields in declaration order.
+    Instance {
+        class: Expr,
+        fields: Vec<(Option<Token>, Pattern)>,
+    },
 }
 
 impl Expr {
@@ -31,18 +193,399 @@ impl Expr {
             } => parenthesize(&operator.lexeme, &[left, right]),
             Expr::Unary { operator, right } => parenthesize(&operator.lexeme, &[right]),
             Expr::Literal { value } => match value {
-                Value::Number(n) => n.to_string(),
-                Value::String(s) => s.clone(),
-                Value::Boolean(b) => b.to_string(),
-                Value::Nil => "nil".to_string(),
+                Literal::Number(n) => n.to_string(),
+                Literal::Integer(n) => n.to_string(),
+                Literal::BigInt(n) => n.to_string(),
+                Literal::Rational(r) => format!("{}r", r),
+                Literal::String(s) => s.to_string(),
+                Literal::Boolean(b) => b.to_string(),
+                Literal::Nil => "nil".to_string(),
             },
             Expr::Grouping(expression) => {
                 format!("(group {})", expression.print())
             }
+            Expr::Variable { name, .. } => name.lexeme.to_string(),
+            Expr::Assign { name, value, .. } => format!("(= {} {})", name.lexeme, value.print()),
+            Expr::CompoundAssign {
+                target,
+                operator,
+                value,
+                postfix,
+            } => {
+                let name = if *postfix {
+                    format!("post{}=", operator.lexeme)
+                } else {
+                    format!("{}=", operator.lexeme)
+                };
+                parenthesize(&name, &[target, value])
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => parenthesize(&operator.lexeme, &[left, right]),
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => parenthesize("?:", &[condition, then_branch, else_branch]),
+            Expr::Call {
+                callee,
+                arguments,
+                optional,
+                ..
+            } => {
+                let mut s = format!(
+                    "({} {}",
+                    if *optional { "?.call" } else { "call" },
+                    callee.print()
+                );
+                for argument in arguments {
+                    match &argument.name {
+                        Some(name) => s += &format!(" {}: {}", name.lexeme, argument.value.print()),
+                        None => s += &format!(" {}", argument.value.print()),
+                    }
+                }
+                s + ")"
+            }
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => {
+                let accessor = if *optional { "?." } else { "." };
+                parenthesize(&format!("{}{}", accessor, name.lexeme), &[object])
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => parenthesize(&format!("={}", name.lexeme), &[object, value]),
+            Expr::This { .. } => "this".to_string(),
+            Expr::Super { name, .. } => format!("super.{}", name.lexeme),
+            Expr::Lambda(declaration) => format!("(lambda {})", declaration.signature()),
+            Expr::List { elements } => {
+                let elements: Vec<&Expr> = elements.iter().collect();
+                parenthesize("list", &elements)
+            }
+            Expr::Map { entries, .. } => {
+                let mut s = "(map".to_string();
+                for (key, value) in entries {
+                    s += &format!(" {}: {}", key.print(), value.print());
+                }
+                s + ")"
+            }
+            Expr::Range {
+                start,
+                operator,
+                end,
+                step,
+            } => match step {
+                Some(step) => parenthesize(&operator.lexeme, &[start, end, step]),
+                None => parenthesize(&operator.lexeme, &[start, end]),
+            },
+            Expr::Index { object, index, .. } => parenthesize("[]", &[object, index]),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => parenthesize("[]=", &[object, index, value]),
+            Expr::Match { subject, arms, .. } => {
+                let mut s = format!("(match {}", subject.print());
+                for arm in arms {
+                    s += &format!(" ({}", arm.pattern.print());
+                    if let Some(guard) = &arm.guard {
+                        s += &format!(" if {}", guard.print());
+                    }
+                    s += &format!(" {})", arm.body.print());
+                }
+                s + ")"
+            }
+        }
+    }
+}
+
+impl Pattern {
+    pub fn print(&self) -> String {
+        match self {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Bind(name, _) => name.lexeme.to_string(),
+            Pattern::Value(value) => value.print(),
+            Pattern::Range {
+                low,
+                operator,
+                high,
+            } => format!("{}{}{}", low.print(), operator.lexeme, high.print()),
+            Pattern::Default { pattern, default } => {
+                format!("{} = {}", pattern.print(), default.print())
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                let mut elements: Vec<String> = before.iter().map(Pattern::print).collect();
+                if let Some(rest) = rest {
+                    elements.push(format!("...{}", rest.print()));
+                }
+                elements.extend(after.iter().map(Pattern::print));
+                format!("[{}]", elements.join(", "))
+            }
+            Pattern::Map { entries } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, pattern)| match (key, pattern) {
+                        (
+                            Expr::Literal {
+                                value: Literal::String(key),
+                            },
+                            Pattern::Bind(name, _),
+                        ) if *key == name.lexeme => key.to_string(),
+                        _ => format!("{}: {}", key.print(), pattern.print()),
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Pattern::Instance { class, fields } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, pattern)| match name {
+                        Some(name) => format!("{}: {}", name.lexeme, pattern.print()),
+                        None => pattern.print(),
+                    })
+                    .collect();
+                format!("{}({})", class.print(), fields.join(", "))
+            }
+        }
+    }
+
+    /// Adds the names the pattern looks up when matching: those of the
+    /// classes it tests for and those its defaults mention.
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        match self {
+            Pattern::Wildcard | Pattern::Bind(..) => {}
+            Pattern::Value(value) => value.collect_names(names),
+            Pattern::Default { pattern, default } => {
+                pattern.collect_names(names);
+                default.collect_names(names);
+            }
+            Pattern::Range { low, high, .. } => {
+                low.collect_names(names);
+                high.collect_names(names);
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                for pattern in before.iter().chain(rest.as_deref()).chain(after) {
+                    pattern.collect_names(names);
+                }
+            }
+            Pattern::Map { entries } => {
+                for (key, pattern) in entries {
+                    key.collect_names(names);
+                    pattern.collect_names(names);
+                }
+            }
+            Pattern::Instance { class, fields } => {
+                class.collect_names(names);
+                for (_, pattern) in fields {
+                    pattern.collect_names(names);
+                }
+            }
+        }
+    }
+
+    /// Adds the names the pattern binds, in the order a match binds them.
+    pub fn bound_names(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Wildcard | Pattern::Value(_) | Pattern::Range { .. } => {}
+            Pattern::Bind(name, _) => names.push(name.lexeme.to_string()),
+            Pattern::Default { pattern, .. } => pattern.bound_names(names),
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                for pattern in before.iter().chain(after).chain(rest.as_deref()) {
+                    pattern.bound_names(names);
+                }
+            }
+            Pattern::Map { entries } => {
+                for (_, pattern) in entries {
+                    pattern.bound_names(names);
+                }
+            }
+            Pattern::Instance { fields, .. } => {
+                for (_, pattern) in fields {
+                    pattern.bound_names(names);
+                }
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// The line the expression starts on, as far as its tokens tell; a
+    /// literal has none.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Binary { left, .. }
+            | Expr::Logical { left, .. }
+            | Expr::Conditional {
+                condition: left, ..
+            }
+            | Expr::CompoundAssign { target: left, .. } => left.line(),
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line)),
+            Expr::Get { object, name, .. } | Expr::Set { object, name, .. } => {
+                object.line().or(Some(name.line))
+            }
+            Expr::Index {
+                object, bracket, ..
+            }
+            | Expr::SetIndex {
+                object, bracket, ..
+            } => object.line().or(Some(bracket.line)),
+            Expr::Range {
+                start, operator, ..
+            } => start.line().or(Some(operator.line)),
+            Expr::Unary { operator, .. } => Some(operator.line),
+            Expr::Grouping(expression) => expression.line(),
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => Some(name.line),
+            Expr::This { keyword, .. } | Expr::Match { keyword, .. } => Some(keyword.line),
+            Expr::Super { name, .. } => Some(name.line),
+            Expr::Lambda(declaration) => Some(declaration.name.line),
+            Expr::List { elements } => elements.iter().find_map(Expr::line),
+            Expr::Map { brace, .. } => Some(brace.line),
+            Expr::Literal { .. } => None,
+        }
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        let children: Vec<&Expr> = match self {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                vec![left, right]
+            }
+            Expr::Unary { right, .. } => vec![right],
+            Expr::Literal { .. } => vec![],
+            Expr::Grouping(expression) => vec![expression],
+            Expr::Variable { name, .. } => {
+                names.insert(name.lexeme.to_string());
+                vec![]
+            }
+            Expr::Assign { name, value, .. } => {
+                names.insert(name.lexeme.to_string());
+                vec![value]
+            }
+            Expr::CompoundAssign { target, value, .. } => vec![target, value],
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => vec![condition, then_branch, else_branch],
+            Expr::Call {
+                callee, arguments, ..
+            } => std::iter::once(callee.as_ref())
+                .chain(arguments.iter().map(|argument| &argument.value))
+                .collect(),
+            Expr::Get { object, .. } => vec![object],
+            Expr::Set { object, value, .. } => vec![object, value],
+            Expr::This { .. } => {
+                names.insert("this".to_string());
+                vec![]
+            }
+            Expr::Super { this, .. } => {
+                names.insert("super".to_string());
+                vec![this]
+            }
+            Expr::Lambda(declaration) => {
+                declaration.collect_names(names);
+                vec![]
+            }
+            Expr::List { elements } => elements.iter().collect(),
+            Expr::Map { entries, .. } => entries
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            Expr::Range {
+                start, end, step, ..
+            } => [start, end]
+                .into_iter()
+                .chain(step)
+                .map(|e| e.as_ref())
+                .collect(),
+            Expr::Index { object, index, .. } => vec![object, index],
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => vec![object, index, value],
+            Expr::Match { subject, arms, .. } => {
+                for arm in arms {
+                    arm.pattern.collect_names(names);
+                }
+                std::iter::once(subject.as_ref())
+                    .chain(
+                        arms.iter()
+                            .flat_map(|arm| arm.guard.iter().chain([&arm.body])),
+                    )
+                    .collect()
+            }
+        };
+        for child in children {
+            child.collect_names(names);
+        }
+    }
+}
+
+/// A constant written in the source. Kept apart from `Value` so that the
+/// syntax tree holds no `Rc`s and functions can be shared between threads.
+#[derive(Clone, Debug)]
+pub enum Literal {
+    Number(f64),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(Rational),
+    String(Arc<str>),
+    Boolean(bool),
+    Nil,
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Number(n) => Value::Number(*n),
+            Literal::Integer(n) => Value::Integer(*n),
+            Literal::BigInt(n) => Value::BigInt(Rc::new(n.clone())),
+            Literal::Rational(r) => Value::Rational(Rc::new(r.clone())),
+            Literal::String(s) => Value::String(s.clone()),
+            Literal::Boolean(b) => Value::Boolean(*b),
+            Literal::Nil => Value::Nil,
         }
     }
 }
 
+/// The literal for a value computed ahead of time, if it has one.
+impl TryFrom<Value> for Literal {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Self, Value> {
+        Ok(match value {
+            Value::Number(n) => Literal::Number(n),
+            Value::Integer(n) => Literal::Integer(n),
+            Value::BigInt(n) => Literal::BigInt(Rc::unwrap_or_clone(n)),
+            Value::Rational(r) => Literal::Rational(Rc::unwrap_or_clone(r)),
+            Value::String(s) => Literal::String(s),
+            Value::Boolean(b) => Literal::Boolean(b),
+            Value::Nil => Literal::Nil,
+            other => return Err(other),
+        })
+    }
+}
+
 fn parenthesize(name: &str, exprs: &[&Expr]) -> String {
     let mut s = format!("({}", name);
     for expr in exprs {
@@ -51,38 +594,916 @@ fn parenthesize(name: &str, exprs: &[&Expr]) -> String {
     s + ")"
 }
 
+/// A call argument, passed by position or as `name: value`.
+pub struct Argument {
+    pub name: Option<Token>,
+    pub value: Expr,
+}
+
+/// A declared parameter, whose `default` is evaluated at call time when the
+/// caller doesn't supply it.
+pub struct Param {
+    pub name: Token,
+    pub annotation: Option<TypeExpr>,
+    pub default: Option<Expr>,
+}
+
+/// The name in each slot of a scope, as numbered by the resolver. How many
+/// there are is how many slots the scope needs; the names themselves are
+/// for the debugger and for telling which captured variables a closure
+/// uses.
+pub type Names = Arc<[Arc<str>]>;
+
+/// Where the resolver found a name: the local slots that may hold it,
+/// innermost first, as a number of scopes out and an index into that
+/// scope, and its index among the globals, used when none of them is bound.
+/// A local slot is unbound until its declaration runs, so code that reads a
+/// name before a scope declares it sees an outer binding instead.
+#[derive(Clone, Default)]
+pub struct Slot {
+    pub locals: Vec<(usize, usize)>,
+    pub global: usize,
+}
+
+/// A type annotation such as `Number`, `List[String]` or `Shape?`, read
+/// only by `y check`; the interpreter ignores annotations.
+pub struct TypeExpr {
+    pub name: Token,
+    /// The element types in brackets, as in `Map[String, Number]`.
+    pub arguments: Vec<TypeExpr>,
+    /// Set by a trailing `?`, which also admits `nil`.
+    pub optional: bool,
+}
+
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Param>,
+    /// Trailing `...name` parameter collecting surplus positional arguments.
+    pub rest: Option<Token>,
+    /// The annotation after `->`.
+    pub return_type: Option<TypeExpr>,
+    pub body: Vec<Stmt>,
+    /// The slots a call needs: the parameters, then the names the body
+    /// declares at its top level.
+    pub names: Names,
+    /// Set when the body contains `yield`, making calls return a generator.
+    pub is_generator: bool,
+    /// Set for `async` functions, whose calls start a task on the event
+    /// loop and return its future.
+    pub is_async: bool,
+    /// Set for methods, getters and setters, which run in a scope binding
+    /// `this` once bound to an instance.
+    pub is_method: bool,
+}
+
+impl FunctionDecl {
+    /// Renders the parameter list for error messages, e.g.
+    /// `greet(name, greeting = "hi", ...rest)`.
+    pub fn signature(&self) -> String {
+        let mut params: Vec<String> = self
+            .params
+            .iter()
+            .map(|param| match &param.default {
+                Some(Expr::Literal {
+                    value: Literal::String(text),
+                }) => format!("{} = {:?}", param.name.lexeme, text),
+                Some(default @ Expr::Literal { .. }) => {
+                    format!("{} = {}", param.name.lexeme, default.print())
+                }
+                Some(_) => format!("{} = …", param.name.lexeme),
+                None => param.name.lexeme.to_string(),
+            })
+            .collect();
+        if let Some(rest) = &self.rest {
+            params.push(format!("...{}", rest.lexeme));
+        }
+        format!("{}({})", self.name.lexeme, params.join(", "))
+    }
+
+    /// Every variable name the function's body or defaults mention, nested
+    /// functions included. Locals aren't excluded, so this over-approximates
+    /// the set of variables the function can read from its closure.
+    pub fn referenced_names(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        for param in &self.params {
+            if let Some(default) = &param.default {
+                default.collect_names(names);
+            }
+        }
+        for statement in &self.body {
+            statement.collect_names(names);
+        }
+    }
+}
+
 pub enum Stmt {
     Expression(Expr),
-    Print(Expr),
-    Var(String, Option<Expr>),
-    Block(Vec<Stmt>),
+    Print {
+        keyword: Token,
+        value: Expr,
+    },
+    /// `var name: Type = value;`, where the annotation and the initializer
+    /// are both optional.
+    Var {
+        name: Token,
+        annotation: Option<TypeExpr>,
+        initializer: Option<Expr>,
+        slot: Slot,
+    },
+    /// `const name: Type = value;`, a variable that can't be reassigned or
+    /// redeclared in its scope.
+    Const {
+        name: Token,
+        annotation: Option<TypeExpr>,
+        initializer: Expr,
+        slot: Slot,
+    },
+    /// `var pattern = value;`, `const pattern = value;` or an assignment
+    /// like `[a, b] = [b, a];` to existing variables.
+    Destructure {
+        pattern: Pattern,
+        equals: Token,
+        value: Expr,
+        binding: Binding,
+    },
+    /// A block, which gets a scope of its own if it declares anything.
+    Block {
+        statements: Vec<Stmt>,
+        names: Names,
+    },
+    Function {
+        declaration: Arc<FunctionDecl>,
+        slot: Slot,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Class {
+        name: Token,
+        slot: Slot,
+        /// The class named after `<`.
+        superclass: Option<(Token, Slot)>,
+        /// The traits named after `impl`.
+        traits: Vec<(Token, Slot)>,
+        methods: Vec<Arc<FunctionDecl>>,
+        /// `get name { ... }` accessors.
+        getters: Vec<Arc<FunctionDecl>>,
+        /// `set name(value) { ... }` accessors.
+        setters: Vec<Arc<FunctionDecl>>,
+        /// `static name(...) { ... }` methods.
+        static_methods: Vec<Arc<FunctionDecl>>,
+        /// `static name = value;` fields, initialized in order once the
+        /// class exists.
+        static_fields: Vec<(Token, Expr)>,
+    },
+    /// `trait Name { fun required(a); fun provided() { ... } }`.
+    Trait {
+        name: Token,
+        slot: Slot,
+        required: Vec<Token>,
+        methods: Vec<Arc<FunctionDecl>>,
+        getters: Vec<Arc<FunctionDecl>>,
+        setters: Vec<Arc<FunctionDecl>>,
+    },
+    /// `enum Name { Tag, Variant(field, ...) }`; variants declared without
+    /// parentheses have no field list.
+    Enum {
+        name: Token,
+        slot: Slot,
+        variants: Vec<(Token, Option<Vec<Token>>)>,
+    },
+    If {
+        keyword: Token,
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    /// A `while` loop, or the loop part of a C-style `for` whose
+    /// `increment` runs after each iteration, including on `continue`.
+    While {
+        keyword: Token,
+        label: Option<Token>,
+        condition: Expr,
+        body: Box<Stmt>,
+        increment: Option<Expr>,
+    },
+    /// `for x in iterable { ... }`, or `for a, b in iterable { ... }` which
+    /// unpacks map entries and other two-element items.
+    ForIn {
+        label: Option<Token>,
+        variables: Vec<Token>,
+        iterable: Expr,
+        body: Box<Stmt>,
+        /// The names in each iteration's scope: the loop variables.
+        names: Names,
+    },
+    Break {
+        keyword: Token,
+        label: Option<Token>,
+    },
+    Continue {
+        keyword: Token,
+        label: Option<Token>,
+    },
+    /// Suspends the enclosing generator, producing `value`, or with an
+    /// `await` keyword, suspends the enclosing async function until `value`
+    /// settles. On resumption the value passed to `send`, or the awaited
+    /// result, goes to `target`.
+    Yield {
+        keyword: Token,
+        value: Option<Expr>,
+        target: YieldTarget,
+    },
+}
+
+/// How a destructuring statement binds the names in its pattern.
+pub enum Binding {
+    Var,
+    Const,
+    Assign,
+}
+
+/// Where a `yield` or `await` statement stores the value it is resumed
+/// with.
+pub enum YieldTarget {
+    /// `yield value;`
+    Discard,
+    /// `var name = yield value;`
+    Declare(Token, Slot),
+    /// `name = yield value;`
+    Assign(Token, Slot),
+    /// `return yield value;`
+    Return,
+    /// `print yield value;`
+    Print,
+}
+
+/// How control leaves a statement: normally, by unwinding to the enclosing
+/// function with a `return` value, or by leaving or restarting a loop (the
+/// innermost one, or the one with the given label).
+pub enum Flow {
+    Normal,
+    Return(Value),
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+impl Flow {
+    /// Whether a `break`/`continue` with `target` applies to the loop
+    /// labeled `label`.
+    pub fn targets(target: &Option<String>, label: &Option<Token>) -> bool {
+        match (target, label) {
+            (None, _) => true,
+            (Some(target), Some(label)) => **target == *label.lexeme,
+            (Some(_), None) => false,
+        }
+    }
 }
 
 impl Stmt {
-    pub fn execute(&self, env: Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    /// Renders the statement like `Expr::print`, with the statements it
+    /// contains on their own lines, indented by `depth` levels.
+    pub fn print(&self, depth: usize) -> String {
+        let nested = |name: String, body: &mut dyn Iterator<Item = &Stmt>| {
+            let mut s = format!("({}", name);
+            for statement in body {
+                s += &format!("\n{}{}", "  ".repeat(depth + 1), statement.print(depth + 1));
+            }
+            s + ")"
+        };
+        let function = |kind: &str, declaration: &FunctionDecl| {
+            nested(
+                format!("{} {}", kind, declaration.signature()),
+                &mut declaration.body.iter(),
+            )
+        };
+        match self {
+            Stmt::Expression(expr) => expr.print(),
+            Stmt::Print { value, .. } => parenthesize("print", &[value]),
+            Stmt::Var {
+                name, initializer, ..
+            } => match initializer {
+                Some(initializer) => parenthesize(&format!("var {}", name.lexeme), &[initializer]),
+                None => format!("(var {})", name.lexeme),
+            },
+            Stmt::Const {
+                name, initializer, ..
+            } => parenthesize(&format!("const {}", name.lexeme), &[initializer]),
+            Stmt::Destructure {
+                pattern,
+                value,
+                binding,
+                ..
+            } => {
+                let keyword = match binding {
+                    Binding::Var => "var",
+                    Binding::Const => "const",
+                    Binding::Assign => "=",
+                };
+                parenthesize(&format!("{} {}", keyword, pattern.print()), &[value])
+            }
+            Stmt::Block { statements, .. } => nested("block".to_string(), &mut statements.iter()),
+            Stmt::Function { declaration, .. } => function("fun", declaration),
+            Stmt::Return { value, .. } => match value {
+                Some(value) => parenthesize("return", &[value]),
+                None => "(return)".to_string(),
+            },
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+                ..
+            } => {
+                let members: Vec<String> = (methods.iter().map(|method| function("fun", method)))
+                    .chain(getters.iter().map(|getter| function("get", getter)))
+                    .chain(setters.iter().map(|setter| function("set", setter)))
+                    .chain(
+                        static_methods
+                            .iter()
+                            .map(|method| function("static", method)),
+                    )
+                    .chain(static_fields.iter().map(|(field, value)| {
+                        parenthesize(&format!("static {}", field.lexeme), &[value])
+                    }))
+                    .collect();
+                let indent = "  ".repeat(depth + 1);
+                let superclass = superclass
+                    .as_ref()
+                    .map_or(String::new(), |(parent, _)| format!(" < {}", parent.lexeme));
+                format!("(class {}{}", name.lexeme, superclass)
+                    + &members
+                        .iter()
+                        .map(|member| format!("\n{}{}", indent, member))
+                        .collect::<String>()
+                    + ")"
+            }
+            Stmt::Trait {
+                name,
+                required,
+                methods,
+                ..
+            } => {
+                let required: Vec<&str> = required.iter().map(|name| name.lexeme()).collect();
+                let indent = "  ".repeat(depth + 1);
+                format!("(trait {} ({})", name.lexeme, required.join(" "))
+                    + &methods
+                        .iter()
+                        .map(|method| format!("\n{}{}", indent, function("fun", method)))
+                        .collect::<String>()
+                    + ")"
+            }
+            Stmt::Enum { name, variants, .. } => {
+                let variants: Vec<String> = variants
+                    .iter()
+                    .map(|(variant, fields)| match fields {
+                        Some(fields) => {
+                            let fields: Vec<&str> =
+                                fields.iter().map(|field| field.lexeme()).collect();
+                            format!("{}({})", variant.lexeme, fields.join(", "))
+                        }
+                        None => variant.lexeme.to_string(),
+                    })
+                    .collect();
+                format!("(enum {} {})", name.lexeme, variants.join(" "))
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => nested(
+                format!("if {}", condition.print()),
+                &mut std::iter::once(then_branch.as_ref()).chain(else_branch.as_deref()),
+            ),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                let mut header = format!("while {}", condition.print());
+                if let Some(increment) = increment {
+                    header += &format!(" {}", increment.print());
+                }
+                nested(header, &mut std::iter::once(body.as_ref()))
+            }
+            Stmt::ForIn {
+                variables,
+                iterable,
+                body,
+                ..
+            } => {
+                let variables: Vec<&str> = variables.iter().map(|name| name.lexeme()).collect();
+                nested(
+                    format!("for {} in {}", variables.join(", "), iterable.print()),
+                    &mut std::iter::once(body.as_ref()),
+                )
+            }
+            Stmt::Break { label, .. } => match label {
+                Some(label) => format!("(break {})", label.lexeme),
+                None => "(break)".to_string(),
+            },
+            Stmt::Continue { label, .. } => match label {
+                Some(label) => format!("(continue {})", label.lexeme),
+                None => "(continue)".to_string(),
+            },
+            Stmt::Yield { keyword, value, .. } => match value {
+                Some(value) => parenthesize(&keyword.lexeme, &[value]),
+                None => format!("({})", keyword.lexeme),
+            },
+        }
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        match self {
+            Stmt::Expression(expr)
+            | Stmt::Print { value: expr, .. }
+            | Stmt::Const {
+                initializer: expr, ..
+            } => expr.collect_names(names),
+            Stmt::Var { initializer, .. }
+            | Stmt::Return {
+                value: initializer, ..
+            } => {
+                if let Some(expr) = initializer {
+                    expr.collect_names(names);
+                }
+            }
+            Stmt::Destructure {
+                pattern,
+                value,
+                binding,
+                ..
+            } => {
+                pattern.collect_names(names);
+                if let Binding::Assign = binding {
+                    let mut bound = Vec::new();
+                    pattern.bound_names(&mut bound);
+                    names.extend(bound);
+                }
+                value.collect_names(names);
+            }
+            Stmt::Block { statements, .. } => {
+                for statement in statements {
+                    statement.collect_names(names);
+                }
+            }
+            Stmt::Function { declaration, .. } => declaration.collect_names(names),
+            Stmt::Class {
+                superclass,
+                traits,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+                ..
+            } => {
+                for (implemented, _) in superclass.iter().chain(traits) {
+                    names.insert(implemented.lexeme.to_string());
+                }
+                for method in methods
+                    .iter()
+                    .chain(getters)
+                    .chain(setters)
+                    .chain(static_methods)
+                {
+                    method.collect_names(names);
+                }
+                for (_, value) in static_fields {
+                    value.collect_names(names);
+                }
+            }
+            Stmt::Trait {
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                for method in methods.iter().chain(getters).chain(setters) {
+                    method.collect_names(names);
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                condition.collect_names(names);
+                then_branch.collect_names(names);
+                if let Some(else_branch) = else_branch {
+                    else_branch.collect_names(names);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                condition.collect_names(names);
+                body.collect_names(names);
+                if let Some(increment) = increment {
+                    increment.collect_names(names);
+                }
+            }
+            Stmt::ForIn { iterable, body, .. } => {
+                iterable.collect_names(names);
+                body.collect_names(names);
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Enum { .. } => {}
+            Stmt::Yield { value, target, .. } => {
+                if let Some(value) = value {
+                    value.collect_names(names);
+                }
+                if let YieldTarget::Assign(name, _) = target {
+                    names.insert(name.lexeme.to_string());
+                }
+            }
+        }
+    }
+
+    /// The line the statement starts on, where the debugger stops before
+    /// running it. A block has none, as it stops at each of its statements
+    /// instead.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) => expr.line(),
+            Stmt::Print { keyword, .. }
+            | Stmt::Return { keyword, .. }
+            | Stmt::If { keyword, .. }
+            | Stmt::While { keyword, .. }
+            | Stmt::Break { keyword, .. }
+            | Stmt::Continue { keyword, .. }
+            | Stmt::Yield { keyword, .. } => Some(keyword.line),
+            Stmt::Var { name, .. }
+            | Stmt::Const { name, .. }
+            | Stmt::Class { name, .. }
+            | Stmt::Trait { name, .. }
+            | Stmt::Enum { name, .. } => Some(name.line),
+            Stmt::Destructure { equals, .. } => Some(equals.line),
+            Stmt::Function { declaration, .. } => Some(declaration.name.line),
+            Stmt::ForIn {
+                label, variables, ..
+            } => label.as_ref().or(variables.first()).map(|token| token.line),
+            Stmt::Block { .. } => None,
+        }
+    }
+
+    pub fn execute(&self, env: Rc<RefCell<Environment>>) -> Result<Flow, RuntimeError> {
+        if debug::attached() {
+            if let Some(line) = self.line() {
+                debug::statement(line, &env)?;
+            }
+        }
         match self {
             Stmt::Expression(expr) => {
                 expr.evaluate(&env)?;
             }
-            Stmt::Print(expr) => {
-                let value = expr.evaluate(&env)?;
-                println!("{}", value);
+            Stmt::Print { value, keyword } => {
+                let value = value.evaluate(&env)?;
+                println!("{}", show(&value, keyword.line)?);
             }
-            Stmt::Var(name, maybe_expr) => {
-                let value = if let Some(expr) = maybe_expr {
+            Stmt::Var {
+                name,
+                initializer,
+                slot,
+                ..
+            } => {
+                let value = if let Some(expr) = initializer {
                     expr.evaluate(&env)?
                 } else {
                     Value::Nil
                 };
-                env.borrow_mut().define(name.to_string(), value);
+                declare(&env, name, slot, value, false)?;
             }
-            Stmt::Block(statements) => {
-                let new_env = Environment::with_enclosing(Rc::clone(&env));
-                for statement in statements {
-                    statement.execute(new_env.clone())?;
+            Stmt::Const {
+                name,
+                initializer,
+                slot,
+                ..
+            } => {
+                let value = initializer.evaluate(&env)?;
+                declare(&env, name, slot, value, true)?;
+            }
+            Stmt::Destructure {
+                pattern,
+                equals,
+                value,
+                binding,
+            } => {
+                let value = value.evaluate(&env)?;
+                pattern.destructure(&value, &env, binding, equals.line)?;
+            }
+            Stmt::Block { statements, names } => {
+                return execute_block(statements, block_scope(env, names));
+            }
+            Stmt::Function { declaration, slot } => {
+                let function = Function::new(Arc::clone(declaration), Rc::clone(&env), false);
+                declare(
+                    &env,
+                    &declaration.name,
+                    slot,
+                    Value::Function(Rc::new(function)),
+                    false,
+                )?;
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(expr) => expr.evaluate(&env)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Class {
+                name,
+                slot,
+                superclass,
+                traits,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+            } => {
+                let superclass = match superclass {
+                    Some((parent, parent_slot)) => match Environment::get(&env, parent_slot) {
+                        Some(Value::Class(superclass)) => Some(superclass),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                format!("'{}' is not a class", parent.lexeme()),
+                                parent.line,
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+                // A subclass's members see its superclass as `super`, in a
+                // scope of its own around them.
+                let members = match &superclass {
+                    Some(superclass) => {
+                        let members = Environment::with_enclosing(Rc::clone(&env), 1);
+                        members
+                            .borrow_mut()
+                            .define(0, Value::Class(Rc::clone(superclass)));
+                        members.borrow_mut().name_slots(super_names());
+                        members
+                    }
+                    None => Rc::clone(&env),
+                };
+                let methods = methods_in(methods, &members);
+                let accessors = Accessors {
+                    getters: methods_in(getters, &members),
+                    setters: methods_in(setters, &members),
+                };
+                let traits = traits
+                    .iter()
+                    .map(|(name, slot)| match Environment::get(&env, slot) {
+                        Some(Value::Trait(implemented)) => Ok(implemented),
+                        _ => Err(RuntimeError::new(
+                            format!("'{}' is not a trait", name.lexeme()),
+                            name.line,
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+                let class = Rc::new(Class::implementing(
+                    name, superclass, methods, accessors, traits,
+                )?);
+                for (method, function) in methods_in(static_methods, &members) {
+                    class.set_static(method, Value::Function(function));
                 }
+                declare(&env, name, slot, Value::Class(Rc::clone(&class)), false)?;
+                for (field, value) in static_fields {
+                    let value = value.evaluate(&members)?;
+                    class.set_static(field.lexeme().to_string(), value);
+                }
+            }
+            Stmt::Trait {
+                name,
+                slot,
+                required,
+                methods,
+                getters,
+                setters,
+            } => {
+                let required = required
+                    .iter()
+                    .map(|method| method.lexeme().to_string())
+                    .collect();
+                let accessors = Accessors {
+                    getters: methods_in(getters, &env),
+                    setters: methods_in(setters, &env),
+                };
+                let implemented = Trait::new(
+                    name.lexeme().to_string(),
+                    required,
+                    methods_in(methods, &env),
+                    accessors,
+                );
+                declare(&env, name, slot, Value::Trait(Rc::new(implemented)), false)?;
+            }
+            Stmt::Enum {
+                name,
+                slot,
+                variants,
+            } => {
+                let variants = variants
+                    .iter()
+                    .map(|(variant, fields)| {
+                        let fields = fields.as_ref().map(|fields| {
+                            fields
+                                .iter()
+                                .map(|field| field.lexeme().to_string())
+                                .collect()
+                        });
+                        (variant.lexeme().to_string(), fields)
+                    })
+                    .collect();
+                let enumeration = Enum::new(name.lexeme().to_string(), variants);
+                declare(&env, name, slot, Value::Enum(Arc::new(enumeration)), false)?;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if condition.evaluate(&env)?.is_truthy() {
+                    return then_branch.execute(env);
+                } else if let Some(else_branch) = else_branch {
+                    return else_branch.execute(env);
+                }
+            }
+            Stmt::While {
+                keyword,
+                label,
+                condition,
+                body,
+                increment,
+            } => {
+                while condition.evaluate(&env)?.is_truthy() {
+                    limits::step(keyword.line)?;
+                    match body.execute(Rc::clone(&env))? {
+                        Flow::Normal => {}
+                        Flow::Break(target) if Flow::targets(&target, label) => break,
+                        Flow::Continue(target) if Flow::targets(&target, label) => {}
+                        flow => return Ok(flow),
+                    }
+                    if let Some(increment) = increment {
+                        increment.evaluate(&env)?;
+                    }
+                }
+            }
+            Stmt::ForIn {
+                label,
+                variables,
+                iterable,
+                body,
+                names,
+            } => {
+                let line = variables[0].line;
+                let mut iter = for_in_iterator(variables, &iterable.evaluate(&env)?)?;
+                while let Some(item) = iter.next(line)? {
+                    limits::step(line)?;
+                    // Each iteration gets a fresh scope so closures capture
+                    // that iteration's values.
+                    let loop_env = Environment::named(Rc::clone(&env), names);
+                    bind_loop_variables(&mut loop_env.borrow_mut(), variables, item)?;
+                    match body.execute(loop_env)? {
+                        Flow::Normal => {}
+                        Flow::Break(target) if Flow::targets(&target, label) => break,
+                        Flow::Continue(target) if Flow::targets(&target, label) => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stmt::Break { label, .. } => {
+                return Ok(Flow::Break(label.as_ref().map(|l| l.lexeme.to_string())));
+            }
+            Stmt::Continue { label, .. } => {
+                return Ok(Flow::Continue(label.as_ref().map(|l| l.lexeme.to_string())));
+            }
+            Stmt::Yield { keyword, .. } => {
+                // Generator and async bodies run on the resumable executor
+                // instead.
+                return Err(RuntimeError::new(
+                    format!("Can't {} here", keyword.lexeme()),
+                    keyword.line,
+                ));
             }
         }
-        Ok(())
+        Ok(Flow::Normal)
+    }
+}
+
+/// Starts iterating for a `for in` loop over `variables`; with two of
+/// them, maps produce their entries rather than their keys.
+pub fn for_in_iterator(variables: &[Token], iterable: &Value) -> Result<Iter, RuntimeError> {
+    let line = variables[0].line;
+    if variables.len() == 2 {
+        iterator::iterate_entries(iterable, line)
+    } else {
+        iterator::iterate(iterable, line)
     }
 }
+
+/// Defines the variables of a `for in` loop for one `item`, unpacking it
+/// when there are two.
+pub fn bind_loop_variables(
+    env: &mut Environment,
+    variables: &[Token],
+    item: Value,
+) -> Result<(), RuntimeError> {
+    if variables.len() == 1 {
+        env.define(0, item);
+        return Ok(());
+    }
+    let pair = match &item {
+        Value::List(elements) if elements.borrow().len() == variables.len() => {
+            elements.borrow().clone()
+        }
+        _ => {
+            return Err(RuntimeError::new(
+                format!(
+                    "Expected a pair to unpack into '{}', got '{}'",
+                    variables
+                        .iter()
+                        .map(|variable| variable.lexeme())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    item
+                ),
+                variables[0].line,
+            ))
+        }
+    };
+    for (index, value) in pair.into_iter().enumerate() {
+        env.define(index, value);
+    }
+    Ok(())
+}
+
+/// The scope a block declaring `names` runs in: a new one, or `env` itself
+/// when the block declares nothing.
+pub fn block_scope(env: Rc<RefCell<Environment>>, names: &Names) -> Rc<RefCell<Environment>> {
+    match names.len() {
+        0 => env,
+        _ => Environment::named(env, names),
+    }
+}
+
+/// The names `tokens` declare, one to a slot.
+pub fn names_of<'a>(tokens: impl IntoIterator<Item = &'a Token>) -> Names {
+    tokens
+        .into_iter()
+        .map(|token| Arc::clone(&token.lexeme))
+        .collect()
+}
+
+/// Runs `statements` in `env`, stopping early if one of them returns.
+pub fn execute_block(
+    statements: &[Stmt],
+    env: Rc<RefCell<Environment>>,
+) -> Result<Flow, RuntimeError> {
+    for statement in statements {
+        match statement.execute(Rc::clone(&env))? {
+            Flow::Normal => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/// Creates the functions for the methods of a class or trait declared in
+/// `env`, keyed by name.
+/// The names in the scope binding a subclass's `super`, shared by every
+/// such scope.
+fn super_names() -> Names {
+    static NAMES: OnceLock<Names> = OnceLock::new();
+    Arc::clone(NAMES.get_or_init(|| Arc::from([Arc::from("super")])))
+}
+
+fn methods_in(
+    declarations: &[Arc<FunctionDecl>],
+    env: &Rc<RefCell<Environment>>,
+) -> HashMap<String, Rc<Function>> {
+    declarations
+        .iter()
+        .map(|declaration| {
+            let is_initializer = declaration.name.lexeme() == "init";
+            let method = Function::new(Arc::clone(declaration), Rc::clone(env), is_initializer);
+            (declaration.name.lexeme().to_string(), Rc::new(method))
+        })
+        .collect()
+}
@@ -0,0 +1,291 @@
+use crate::error::RuntimeError;
+use crate::event_loop::{self, Future};
+use crate::value::Value;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// Most bytes a single `read()` returns.
+const READ_SIZE: usize = 64 * 1024;
+
+/// A TCP connection or listening socket. Sockets are shared, not copied,
+/// when sent to another thread.
+pub enum Socket {
+    /// A connection, with the bytes of a character the last read cut off.
+    Stream(TcpStream, Mutex<Vec<u8>>),
+    Listener(TcpListener),
+}
+
+impl Socket {
+    fn stream(stream: TcpStream) -> Socket {
+        Socket::Stream(stream, Mutex::new(Vec::new()))
+    }
+
+    pub fn close(&self) {
+        if let Socket::Stream(stream, _) = self {
+            // Already being closed by the peer is fine too.
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn address(&self) -> Option<String> {
+        match self {
+            Socket::Stream(stream, _) => stream.peer_addr(),
+            Socket::Listener(listener) => listener.local_addr(),
+        }
+        .ok()
+        .map(|address| address.to_string())
+    }
+}
+
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Socket::Stream(..) => "socket",
+            Socket::Listener(_) => "listener",
+        };
+        match self.address() {
+            Some(address) => write!(f, "<{} {}>", kind, address),
+            None => write!(f, "<{}>", kind),
+        }
+    }
+}
+
+/// What a finished IO operation hands back to the event loop.
+pub enum Completed {
+    Nil,
+    Text(String),
+    Socket(Socket),
+}
+
+impl Completed {
+    pub fn into_value(self) -> Value {
+        match self {
+            Completed::Nil => Value::Nil,
+            Completed::Text(text) => Value::String(text.into()),
+            Completed::Socket(socket) => Value::Socket(Arc::new(socket)),
+        }
+    }
+}
+
+/// Reads the file at `resolved`, which the script named `name`.
+pub fn read_file(name: String, resolved: PathBuf, line: usize) -> Rc<Future> {
+    event_loop::start_io(
+        "read_file",
+        move || {
+            fs::read_to_string(&resolved)
+                .map(Completed::Text)
+                .map_err(|error| format!("Couldn't read '{}': {}", name, error))
+        },
+        line,
+    )
+}
+
+/// Writes the file at `resolved`, which the script named `name`.
+pub fn write_file(name: String, resolved: PathBuf, contents: String, line: usize) -> Rc<Future> {
+    event_loop::start_io(
+        "write_file",
+        move || {
+            fs::write(&resolved, contents)
+                .map(|_| Completed::Nil)
+                .map_err(|error| format!("Couldn't write '{}': {}", name, error))
+        },
+        line,
+    )
+}
+
+/// Runs `program` to completion, failing if it can't be started or exits
+/// unsuccessfully.
+pub fn exec(program: String, args: Vec<String>, line: usize) -> Rc<Future> {
+    event_loop::start_io(
+        "exec",
+        move || {
+            let output = Command::new(&program)
+                .args(&args)
+                .output()
+                .map_err(|error| format!("Couldn't run '{}': {}", program, error))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(match stderr.trim_end() {
+                    "" => format!("'{}' failed with {}", program, output.status),
+                    stderr => format!("'{}' failed with {}: {}", program, output.status, stderr),
+                });
+            }
+            Ok(Completed::Text(
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+            ))
+        },
+        line,
+    )
+}
+
+pub fn connect(address: String, line: usize) -> Rc<Future> {
+    event_loop::start_io(
+        "connect",
+        move || {
+            TcpStream::connect(&address)
+                .map(|stream| Completed::Socket(Socket::stream(stream)))
+                .map_err(|error| format!("Couldn't connect to '{}': {}", address, error))
+        },
+        line,
+    )
+}
+
+/// Binds a listening socket right away; only accepting connections waits.
+pub fn listen(address: &str, line: usize) -> Result<Value, RuntimeError> {
+    TcpListener::bind(address)
+        .map(|listener| Value::Socket(Arc::new(Socket::Listener(listener))))
+        .map_err(|error| {
+            RuntimeError::new(format!("Couldn't listen on '{}': {}", address, error), line)
+        })
+}
+
+pub fn accept(socket: Arc<Socket>, line: usize) -> Result<Rc<Future>, RuntimeError> {
+    if !matches!(*socket, Socket::Listener(_)) {
+        return Err(RuntimeError::new(
+            format!("accept() expects a listener, got '{:?}'", socket),
+            line,
+        ));
+    }
+    Ok(event_loop::start_io(
+        "accept",
+        move || match &*socket {
+            Socket::Listener(listener) => listener
+                .accept()
+                .map(|(stream, _)| Completed::Socket(Socket::stream(stream)))
+                .map_err(|error| format!("Couldn't accept a connection: {}", error)),
+            Socket::Stream(..) => unreachable!("checked to be a listener"),
+        },
+        line,
+    ))
+}
+
+/// Reads whatever text has arrived, up to `READ_SIZE` bytes, resolving to
+/// `nil` once the peer has closed the connection. A character split between
+/// reads is held back until the rest of it arrives.
+pub fn read(socket: Arc<Socket>, line: usize) -> Result<Rc<Future>, RuntimeError> {
+    if !matches!(*socket, Socket::Stream(..)) {
+        return Err(RuntimeError::new(
+            format!("read() expects a connected socket, got '{:?}'", socket),
+            line,
+        ));
+    }
+    Ok(event_loop::start_io(
+        "read",
+        move || {
+            let Socket::Stream(stream, cut_off) = &*socket else {
+                unreachable!("checked to be a stream");
+            };
+            let mut stream = stream;
+            let mut cut_off = cut_off.lock().unwrap_or_else(|error| error.into_inner());
+            loop {
+                let mut buffer = std::mem::take(&mut *cut_off);
+                let start = buffer.len();
+                buffer.resize(start + READ_SIZE, 0);
+                let count = stream
+                    .read(&mut buffer[start..])
+                    .map_err(|error| format!("Couldn't read from socket: {}", error))?;
+                if count == 0 && start == 0 {
+                    return Ok(Completed::Nil);
+                }
+                buffer.truncate(start + count);
+                // A closed connection leaves nothing to complete the
+                // character with.
+                if count > 0 {
+                    *cut_off = buffer.split_off(buffer.len() - incomplete_tail(&buffer));
+                }
+                if !buffer.is_empty() {
+                    return Ok(Completed::Text(
+                        String::from_utf8_lossy(&buffer).into_owned(),
+                    ));
+                }
+            }
+        },
+        line,
+    ))
+}
+
+/// How many bytes at the end of `bytes` start a UTF-8 character without
+/// finishing it.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let width = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if width > back { back } else { 0 };
+        }
+    }
+    0
+}
+
+pub fn write(socket: Arc<Socket>, text: String, line: usize) -> Result<Rc<Future>, RuntimeError> {
+    if !matches!(*socket, Socket::Stream(..)) {
+        return Err(RuntimeError::new(
+            format!("write() expects a connected socket, got '{:?}'", socket),
+            line,
+        ));
+    }
+    Ok(event_loop::start_io(
+        "write",
+        move || {
+            let Socket::Stream(stream, _) = &*socket else {
+                unreachable!("checked to be a stream");
+            };
+            let mut stream = stream;
+            stream
+                .write_all(text.as_bytes())
+                .map(|_| Completed::Nil)
+                .map_err(|error| format!("Couldn't write to socket: {}", error))
+        },
+        line,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn characters_split_between_reads_are_kept_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"h\xC3").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"\xA9llo \xF0\x9F").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"\x98\x80").unwrap();
+        });
+        let source = format!(
+            "async fun main() {{
+                var socket = await connect(\"{address}\");
+                var first = await read(socket);
+                var second = await read(socket);
+                var third = await read(socket);
+                var last = await read(socket);
+                return [first, second, third, last];
+            }}
+            main();"
+        );
+        assert_eq!(
+            eval(&source),
+            Ok("[\"h\", \"\u{e9}llo \", \"\u{1f600}\", nil]".to_string())
+        );
+        peer.join().unwrap();
+    }
+}
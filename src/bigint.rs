@@ -0,0 +1,489 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Signed integer of arbitrary size, stored as sign and magnitude.
+///
+/// The magnitude is a little-endian vector of base 2^32 limbs without
+/// trailing zero limbs, so zero is the empty vector and is never negative.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+/// Returned by a computation that its `Progress` stopped.
+#[derive(Debug)]
+pub struct Stopped;
+
+/// Told, as a long computation goes, how many limb operations it has done
+/// since it was last told, and able to stop it. Multiplication and division
+/// take time quadratic in the size of their operands, so a script waiting
+/// on one still has its limits and interrupts checked.
+pub type Progress<'a> = dyn FnMut(usize) -> Result<(), Stopped> + 'a;
+
+/// Runs `compute` with a `Progress` that never stops it.
+pub fn unpaced<T>(compute: impl FnOnce(&mut Progress) -> Result<T, Stopped>) -> T {
+    compute(&mut |_| Ok(())).expect("nothing stops an unpaced computation")
+}
+
+const LIMB_BITS: u32 = 32;
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+impl BigInt {
+    pub fn zero() -> Self {
+        Self {
+            negative: false,
+            limbs: Vec::new(),
+        }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        Self::from_magnitude(n.unsigned_abs() as u128, n < 0)
+    }
+
+    pub fn from_u128(n: u128) -> Self {
+        Self::from_magnitude(n, false)
+    }
+
+    fn from_magnitude(mut magnitude: u128, negative: bool) -> Self {
+        let mut limbs = Vec::new();
+        while magnitude > 0 {
+            limbs.push(magnitude as u32);
+            magnitude >>= LIMB_BITS;
+        }
+        Self::from_parts(negative, limbs)
+    }
+
+    fn from_parts(negative: bool, mut limbs: Vec<u32>) -> Self {
+        trim(&mut limbs);
+        let negative = negative && !limbs.is_empty();
+        Self { negative, limbs }
+    }
+
+    /// Parses an optionally signed string of decimal digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut limbs = Vec::new();
+        let first_chunk = digits.len() % DECIMAL_CHUNK_DIGITS;
+        let mut start = 0;
+        let mut end = if first_chunk == 0 {
+            DECIMAL_CHUNK_DIGITS
+        } else {
+            first_chunk
+        };
+        while start < digits.len() {
+            let chunk: u32 = digits[start..end].parse().ok()?;
+            let scale = 10u32.pow((end - start) as u32);
+            mul_small_add(&mut limbs, scale, chunk);
+            start = end;
+            end += DECIMAL_CHUNK_DIGITS;
+        }
+
+        Some(Self::from_parts(negative, limbs))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << LIMB_BITS) | limb as u64);
+        if self.negative {
+            if magnitude <= i64::MAX as u64 + 1 {
+                Some((magnitude as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4_294_967_296.0 + limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_one(&self) -> bool {
+        !self.negative && self.limbs == [1]
+    }
+
+    pub fn abs(&self) -> Self {
+        Self {
+            negative: false,
+            limbs: self.limbs.clone(),
+        }
+    }
+
+    /// Truncating division, returning quotient and remainder.
+    ///
+    /// The remainder takes the sign of the dividend. Panics when `divisor`
+    /// is zero; callers are expected to report that as a runtime error.
+    pub fn div_rem(&self, divisor: &BigInt) -> (BigInt, BigInt) {
+        unpaced(|progress| self.div_rem_with(divisor, progress))
+    }
+
+    /// `div_rem`, telling `progress` how it goes.
+    pub fn div_rem_with(
+        &self,
+        divisor: &BigInt,
+        progress: &mut Progress,
+    ) -> Result<(BigInt, BigInt), Stopped> {
+        assert!(!divisor.is_zero(), "BigInt division by zero");
+        let (quotient, remainder) = div_rem_magnitude(&self.limbs, &divisor.limbs, progress)?;
+        Ok((
+            Self::from_parts(self.negative != divisor.negative, quotient),
+            Self::from_parts(self.negative, remainder),
+        ))
+    }
+
+    /// `self * other`, telling `progress` how it goes.
+    pub fn mul_with(&self, other: &BigInt, progress: &mut Progress) -> Result<BigInt, Stopped> {
+        Ok(BigInt::from_parts(
+            self.negative != other.negative,
+            mul_magnitude(&self.limbs, &other.limbs, progress)?,
+        ))
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        unpaced(|progress| self.gcd_with(other, progress))
+    }
+
+    /// `gcd`, telling `progress` how it goes.
+    pub fn gcd_with(&self, other: &BigInt, progress: &mut Progress) -> Result<BigInt, Stopped> {
+        let mut a = self.abs();
+        let mut b = other.abs();
+        while !b.is_zero() {
+            let (_, remainder) = a.div_rem_with(&b, progress)?;
+            a = b;
+            b = remainder;
+        }
+        Ok(a)
+    }
+
+    pub fn pow(&self, exponent: u32) -> BigInt {
+        unpaced(|progress| self.pow_with(exponent, progress))
+    }
+
+    /// `pow`, telling `progress` how it goes.
+    pub fn pow_with(&self, mut exponent: u32, progress: &mut Progress) -> Result<BigInt, Stopped> {
+        let mut base = self.clone();
+        let mut result = BigInt::from_i64(1);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul_with(&base, progress)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul_with(&base, progress)?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.limbs, &other.limbs),
+            (true, true) => cmp_magnitude(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_magnitude(&self.limbs, &other.limbs));
+        }
+        match cmp_magnitude(&self.limbs, &other.limbs) {
+            Ordering::Equal => BigInt::zero(),
+            Ordering::Greater => {
+                BigInt::from_parts(self.negative, sub_magnitude(&self.limbs, &other.limbs))
+            }
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, sub_magnitude(&other.limbs, &self.limbs))
+            }
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &(-other)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        unpaced(|progress| self.mul_with(other, progress))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.limbs.clone())
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chunks = Vec::new();
+        let mut magnitude = self.limbs.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = div_rem_small(&magnitude, DECIMAL_CHUNK);
+            chunks.push(remainder);
+            magnitude = quotient;
+        }
+
+        let mut digits = String::new();
+        match chunks.split_last() {
+            None => digits.push('0'),
+            Some((most_significant, rest)) => {
+                digits.push_str(&most_significant.to_string());
+                for chunk in rest.iter().rev() {
+                    digits.push_str(&format!("{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS));
+                }
+            }
+        }
+        f.pad_integral(!self.negative, "", &digits)
+    }
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> LIMB_BITS;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// Subtracts magnitudes, requiring `a >= b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut difference = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = if difference < 0 {
+            difference += 1 << LIMB_BITS;
+            1
+        } else {
+            0
+        };
+        result.push(difference as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32], progress: &mut Progress) -> Result<Vec<u32>, Stopped> {
+    if a.is_empty() || b.is_empty() {
+        return Ok(Vec::new());
+    }
+    // One row per limb of the shorter operand, so that `progress` hears of
+    // long rows.
+    let (a, b) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        progress(b.len())?;
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> LIMB_BITS;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    Ok(result)
+}
+
+fn mul_small_add(limbs: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> LIMB_BITS;
+    }
+    if carry > 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+fn div_rem_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for i in (0..a.len()).rev() {
+        let current = (remainder << LIMB_BITS) | a[i] as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+fn shift_left(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u32;
+    for &limb in a {
+        result.push((limb << shift) | carry);
+        carry = if shift == 0 {
+            0
+        } else {
+            limb >> (LIMB_BITS - shift)
+        };
+    }
+    result.push(carry);
+    result
+}
+
+fn shift_right(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    for i in 0..a.len() {
+        let high = if shift == 0 {
+            0
+        } else {
+            a.get(i + 1).map_or(0, |&next| next << (LIMB_BITS - shift))
+        };
+        result.push((a[i] >> shift) | high);
+    }
+    trim(&mut result);
+    result
+}
+
+/// Long division of magnitudes (Knuth, TAOCP vol. 2, algorithm D).
+fn div_rem_magnitude(
+    a: &[u32],
+    b: &[u32],
+    progress: &mut Progress,
+) -> Result<(Vec<u32>, Vec<u32>), Stopped> {
+    if cmp_magnitude(a, b) == Ordering::Less {
+        return Ok((Vec::new(), a.to_vec()));
+    }
+    if b.len() == 1 {
+        progress(a.len())?;
+        let (quotient, remainder) = div_rem_small(a, b[0]);
+        let mut remainder = vec![remainder];
+        trim(&mut remainder);
+        return Ok((quotient, remainder));
+    }
+
+    // Normalize so the divisor's top limb has its high bit set, which keeps
+    // each quotient estimate within two of the true digit.
+    let shift = b[b.len() - 1].leading_zeros();
+    let mut divisor = shift_left(b, shift);
+    divisor.pop();
+    let mut dividend = shift_left(a, shift);
+
+    let n = divisor.len();
+    let m = a.len() - n;
+    let base = 1u64 << LIMB_BITS;
+    let mut quotient = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        progress(n)?;
+        let numerator = ((dividend[j + n] as u64) << LIMB_BITS) | dividend[j + n - 1] as u64;
+        let mut estimate = numerator / divisor[n - 1] as u64;
+        let mut rest = numerator % divisor[n - 1] as u64;
+        while estimate >= base
+            || estimate * divisor[n - 2] as u64 > ((rest << LIMB_BITS) | dividend[j + n - 2] as u64)
+        {
+            estimate -= 1;
+            rest += divisor[n - 1] as u64;
+            if rest >= base {
+                break;
+            }
+        }
+
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = estimate * divisor[i] as u64 + carry;
+            carry = product >> LIMB_BITS;
+            let difference = dividend[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            dividend[i + j] = difference as u32;
+            borrow = if difference < 0 { 1 } else { 0 };
+        }
+        let difference = dividend[j + n] as i64 - borrow - carry as i64;
+        dividend[j + n] = difference as u32;
+
+        if difference < 0 {
+            // The estimate was one too large; add the divisor back once.
+            estimate -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = dividend[i + j] as u64 + divisor[i] as u64 + carry;
+                dividend[i + j] = sum as u32;
+                carry = sum >> LIMB_BITS;
+            }
+            dividend[j + n] = dividend[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = estimate as u32;
+    }
+
+    trim(&mut quotient);
+    Ok((quotient, shift_right(&dividend[..n], shift)))
+}
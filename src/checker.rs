@@ -0,0 +1,1432 @@
+use crate::ast::{Argument, Expr, FunctionDecl, Literal, Pattern, Stmt, TypeExpr, YieldTarget};
+use crate::function;
+use crate::native::NATIVES;
+use crate::token::Token;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// A type mismatch found by `y check`.
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+/// What the checker knows about a value. `Any` is the type of everything
+/// unannotated and is compatible with every other type in both directions,
+/// so unannotated code is never reported.
+#[derive(Clone, PartialEq)]
+enum Type {
+    Any,
+    Nil,
+    Bool,
+    Number,
+    String,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Range,
+    /// A function or method, with its signature when known.
+    Function(Option<Rc<Signature>>),
+    /// The class object itself, which makes instances when called.
+    Class(String),
+    Instance(String),
+    /// An instance of any class implementing the trait.
+    Trait(String),
+    /// The enum object itself, whose properties are its variants.
+    Enumeration(String),
+    /// A variant of the enum.
+    Enum(String),
+    /// `T?`: either a `T` or `nil`.
+    Optional(Box<Type>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nil => write!(f, "Nil"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::List(element) => write!(f, "List[{}]", element),
+            Type::Map(key, value) => write!(f, "Map[{}, {}]", key, value),
+            Type::Range => write!(f, "Range"),
+            Type::Function(_) => write!(f, "Function"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Enumeration(name) => write!(f, "enum {}", name),
+            Type::Instance(name) | Type::Trait(name) | Type::Enum(name) => write!(f, "{}", name),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Signature {
+    name: String,
+    /// `None` when the parameters aren't known, leaving calls unchecked.
+    params: Option<Vec<Parameter>>,
+    rest: bool,
+    returns: Type,
+}
+
+#[derive(PartialEq)]
+struct Parameter {
+    name: String,
+    kind: Type,
+    has_default: bool,
+}
+
+/// The members of a class, or of a trait to be mixed into one.
+#[derive(Default)]
+struct Members {
+    superclass: Option<String>,
+    traits: Vec<String>,
+    methods: HashMap<String, Rc<Signature>>,
+    getters: HashMap<String, Type>,
+    /// The annotated type of each setter's parameter.
+    setters: HashMap<String, Type>,
+    statics: HashMap<String, Type>,
+}
+
+impl Members {
+    /// Adds what a subclass inherits from `superclass` without defining it
+    /// itself.
+    fn inherit(&mut self, superclass: &Members) {
+        self.traits.extend(superclass.traits.iter().cloned());
+        for (method, signature) in &superclass.methods {
+            self.methods
+                .entry(method.clone())
+                .or_insert_with(|| Rc::clone(signature));
+        }
+        for (own, inherited) in [
+            (&mut self.getters, &superclass.getters),
+            (&mut self.setters, &superclass.setters),
+            (&mut self.statics, &superclass.statics),
+        ] {
+            for (name, kind) in inherited {
+                own.entry(name.clone()).or_insert_with(|| kind.clone());
+            }
+        }
+    }
+}
+
+/// Each variant of an enum, with its field names unless it's a plain tag.
+type Variants = Vec<(String, Option<Vec<String>>)>;
+
+/// What the checker knows about a name in scope.
+struct Variable {
+    kind: Type,
+    /// Whether `kind` was inferred from the initializer of an unannotated
+    /// variable, which widens when something else is assigned to it.
+    inferred: bool,
+}
+
+/// The function whose body is being checked.
+struct Context {
+    name: String,
+    returns: Type,
+}
+
+/// Checks a program with gradual typing before it runs. Annotated
+/// variables, parameters and return types are checked against the types
+/// inferred for the expressions that flow into them. Unannotated variables
+/// and constants take the type of their initializer; assigning a variable
+/// something else widens it, to `Any` unless the two types join. Anything
+/// else unannotated is `Any`.
+pub struct Checker {
+    scopes: Vec<HashMap<String, Variable>>,
+    classes: HashMap<String, Members>,
+    traits: HashMap<String, Members>,
+    enums: HashMap<String, Variants>,
+    function: Option<Context>,
+    this: Type,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        let mut checker = Self {
+            scopes: vec![HashMap::new()],
+            classes: HashMap::new(),
+            traits: HashMap::new(),
+            enums: HashMap::new(),
+            function: None,
+            this: Type::Any,
+            diagnostics: Vec::new(),
+        };
+        for native in NATIVES {
+            let params = (0..native.arity)
+                .map(|_| Parameter {
+                    name: String::new(),
+                    kind: Type::Any,
+                    has_default: false,
+                })
+                .collect();
+            checker.define(
+                native.name,
+                function(native.name, Some(params), native_returns(native.name)),
+            );
+        }
+        for (name, variants) in [
+            ("Option", [("Some", Some("value")), ("None", None)]),
+            ("Result", [("Ok", Some("value")), ("Err", Some("error"))]),
+        ] {
+            checker.define(name, Type::Enumeration(name.to_string()));
+            let variants = variants
+                .map(|(variant, field)| {
+                    let fields = field.map(|field| vec![field.to_string()]);
+                    checker.define(variant, checker.variant_type(name, variant, &fields));
+                    (variant.to_string(), fields)
+                })
+                .to_vec();
+            checker.enums.insert(name.to_string(), variants);
+        }
+        checker
+    }
+
+    /// Checks `statements` as a whole program, returning the mismatches
+    /// found in order of line.
+    pub fn check(mut self, statements: &[Stmt]) -> Vec<Diagnostic> {
+        self.declare_all(statements);
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+        self.diagnostics
+    }
+
+    fn report(&mut self, message: String, line: usize) {
+        self.diagnostics.push(Diagnostic { message, line });
+    }
+
+    fn define(&mut self, name: &str, kind: Type) {
+        self.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            Variable {
+                kind,
+                inferred: false,
+            },
+        );
+    }
+
+    /// Defines an unannotated variable with the type of its initializer.
+    /// A variable starting out `nil` is left `Any`, since it is waiting for
+    /// its real value.
+    fn define_inferred(&mut self, name: &str, kind: Type) {
+        let kind = if kind == Type::Nil { Type::Any } else { kind };
+        self.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            Variable {
+                kind,
+                inferred: true,
+            },
+        );
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).map(|variable| variable.kind.clone()))
+            .unwrap_or(Type::Any)
+    }
+
+    /// The variable `name` if its type was inferred rather than declared.
+    fn inferred(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .filter(|variable| variable.inferred)
+    }
+
+    /// Checks assigning a value of type `value` to the variable `name`, or
+    /// widens the variable's type if it was inferred.
+    fn assign(&mut self, name: &Token, value: &Type) {
+        if let Some(variable) = self.inferred(name.lexeme()) {
+            variable.kind = join(variable.kind.clone(), value.clone());
+            return;
+        }
+        let declared = self.lookup(name.lexeme());
+        self.expect(value, &declared, name.line, || {
+            format!("Variable '{}'", name.lexeme)
+        });
+    }
+
+    /// Runs `check` in a new scope.
+    fn scoped(&mut self, check: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        check(self);
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.scoped(|checker| {
+            checker.declare_all(statements);
+            for statement in statements {
+                checker.statement(statement);
+            }
+        });
+    }
+
+    /// Declares the functions, classes, traits and enums of a block up
+    /// front, so annotations and calls can refer to those declared later.
+    fn declare_all(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            match statement {
+                Stmt::Class { name, .. } => {
+                    self.classes
+                        .insert(name.lexeme.to_string(), Members::default());
+                }
+                Stmt::Trait { name, .. } => {
+                    self.traits
+                        .insert(name.lexeme.to_string(), Members::default());
+                }
+                Stmt::Enum { name, variants, .. } => {
+                    let variants = variants
+                        .iter()
+                        .map(|(variant, fields)| {
+                            let fields = fields.as_ref().map(|fields| {
+                                fields
+                                    .iter()
+                                    .map(|field| field.lexeme.to_string())
+                                    .collect()
+                            });
+                            (variant.lexeme.to_string(), fields)
+                        })
+                        .collect();
+                    self.enums.insert(name.lexeme.to_string(), variants);
+                    self.define(&name.lexeme, Type::Enumeration(name.lexeme.to_string()));
+                }
+                _ => {}
+            }
+        }
+        for statement in statements {
+            match statement {
+                Stmt::Function { declaration, .. } => {
+                    let signature = self.signature(declaration);
+                    self.define(&declaration.name.lexeme, signature);
+                }
+                Stmt::Trait {
+                    name,
+                    methods,
+                    getters,
+                    setters,
+                    ..
+                } => {
+                    let members = self.members(methods, getters, setters);
+                    self.traits.insert(name.lexeme.to_string(), members);
+                }
+                _ => {}
+            }
+        }
+        for statement in statements {
+            if let Stmt::Class {
+                name,
+                superclass,
+                traits,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                ..
+            } = statement
+            {
+                let mut members = self.members(methods, getters, setters);
+                for method in static_methods {
+                    let signature = self.signature(method);
+                    members
+                        .statics
+                        .insert(method.name.lexeme.to_string(), signature);
+                }
+                for (implemented, _) in traits {
+                    members.traits.push(implemented.lexeme.to_string());
+                    let Some(provided) = self.traits.get(implemented.lexeme()) else {
+                        continue;
+                    };
+                    for (method, signature) in &provided.methods {
+                        members
+                            .methods
+                            .entry(method.clone())
+                            .or_insert_with(|| Rc::clone(signature));
+                    }
+                    for (getter, kind) in &provided.getters {
+                        members
+                            .getters
+                            .entry(getter.clone())
+                            .or_insert_with(|| kind.clone());
+                    }
+                    for (setter, kind) in &provided.setters {
+                        members
+                            .setters
+                            .entry(setter.clone())
+                            .or_insert_with(|| kind.clone());
+                    }
+                }
+                if let Some((parent, _)) = superclass {
+                    members.superclass = Some(parent.lexeme.to_string());
+                    if let Some(inherited) = self.classes.get(parent.lexeme()) {
+                        members.inherit(inherited);
+                    }
+                }
+                self.classes.insert(name.lexeme.to_string(), members);
+                self.define(&name.lexeme, Type::Class(name.lexeme.to_string()));
+            }
+        }
+    }
+
+    fn members(
+        &mut self,
+        methods: &[std::sync::Arc<FunctionDecl>],
+        getters: &[std::sync::Arc<FunctionDecl>],
+        setters: &[std::sync::Arc<FunctionDecl>],
+    ) -> Members {
+        let mut members = Members::default();
+        for method in methods {
+            if let Type::Function(Some(signature)) = self.signature(method) {
+                members
+                    .methods
+                    .insert(method.name.lexeme.to_string(), signature);
+            }
+        }
+        for getter in getters {
+            let kind = self.annotated(&getter.return_type);
+            members.getters.insert(getter.name.lexeme.to_string(), kind);
+        }
+        for setter in setters {
+            let kind = self.annotated(&setter.params[0].annotation);
+            members.setters.insert(setter.name.lexeme.to_string(), kind);
+        }
+        members
+    }
+
+    /// The type of a declared function. Calls to generators and async
+    /// functions return iterators and futures, which have no annotation.
+    fn signature(&mut self, declaration: &FunctionDecl) -> Type {
+        let params = declaration
+            .params
+            .iter()
+            .map(|param| Parameter {
+                name: param.name.lexeme.to_string(),
+                kind: self.annotated(&param.annotation),
+                has_default: param.default.is_some(),
+            })
+            .collect();
+        let returns = if declaration.is_generator || declaration.is_async {
+            Type::Any
+        } else {
+            self.annotated(&declaration.return_type)
+        };
+        Type::Function(Some(Rc::new(Signature {
+            name: declaration.name.lexeme.to_string(),
+            params: Some(params),
+            rest: declaration.rest.is_some(),
+            returns,
+        })))
+    }
+
+    fn annotated(&mut self, annotation: &Option<TypeExpr>) -> Type {
+        match annotation {
+            Some(annotation) => self.resolve(annotation),
+            None => Type::Any,
+        }
+    }
+
+    /// Turns an annotation into the type it names.
+    fn resolve(&mut self, annotation: &TypeExpr) -> Type {
+        let name = annotation.name.lexeme();
+        let arguments: Vec<Type> = annotation
+            .arguments
+            .iter()
+            .map(|argument| self.resolve(argument))
+            .collect();
+        let expected = match name {
+            "List" => [0, 1].as_slice(),
+            "Map" => &[0, 2],
+            _ => &[0],
+        };
+        if !expected.contains(&arguments.len()) {
+            self.report(
+                format!(
+                    "Type '{}' takes {} type arguments, found {}",
+                    name,
+                    expected[expected.len() - 1],
+                    arguments.len()
+                ),
+                annotation.name.line,
+            );
+            return Type::Any;
+        }
+        let mut arguments = arguments.into_iter();
+        let mut argument = || Box::new(arguments.next().unwrap_or(Type::Any));
+        let kind = match name {
+            "Any" => Type::Any,
+            "Nil" => Type::Nil,
+            "Bool" => Type::Bool,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "List" => Type::List(argument()),
+            "Map" => Type::Map(argument(), argument()),
+            "Range" => Type::Range,
+            "Function" => Type::Function(None),
+            _ if self.classes.contains_key(name) => Type::Instance(name.to_string()),
+            _ if self.traits.contains_key(name) => Type::Trait(name.to_string()),
+            _ if self.enums.contains_key(name) => Type::Enum(name.to_string()),
+            _ => {
+                self.report(format!("Unknown type '{}'", name), annotation.name.line);
+                Type::Any
+            }
+        };
+        if annotation.optional && kind != Type::Any && kind != Type::Nil {
+            Type::Optional(Box::new(kind))
+        } else {
+            kind
+        }
+    }
+
+    /// Whether a value of type `from` may be used where `to` is expected.
+    fn assignable(&self, from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Nil, Type::Optional(_)) => true,
+            (Type::Optional(from), Type::Optional(to)) => self.assignable(from, to),
+            (_, Type::Optional(to)) => self.assignable(from, to),
+            (Type::List(from), Type::List(to)) => self.assignable(from, to),
+            (Type::Map(from_key, from_value), Type::Map(to_key, to_value)) => {
+                self.assignable(from_key, to_key) && self.assignable(from_value, to_value)
+            }
+            (Type::Function(_) | Type::Class(_), Type::Function(_)) => true,
+            (Type::Instance(class), Type::Trait(name)) => self
+                .classes
+                .get(class)
+                .is_some_and(|members| members.traits.contains(name)),
+            (Type::Instance(class), Type::Instance(expected)) => {
+                self.lineage(class).any(|class| class == expected)
+            }
+            _ => from == to,
+        }
+    }
+
+    /// The classes from `class` up its superclass chain, as far as the
+    /// checker knows it.
+    fn lineage<'a>(&'a self, class: &'a str) -> impl Iterator<Item = &'a str> {
+        let mut seen = HashSet::new();
+        std::iter::successors(Some(class), move |class| {
+            let superclass = self.classes.get(*class)?.superclass.as_deref()?;
+            // A class that isn't defined yet may share a name with one of
+            // its subclasses, so the names could loop.
+            seen.insert(*class).then_some(superclass)
+        })
+    }
+
+    /// Reports `message` unless `from` fits `to`.
+    fn expect(&mut self, from: &Type, to: &Type, line: usize, message: impl FnOnce() -> String) {
+        if !self.assignable(from, to) {
+            let message = format!("{} must be {}, found {}", message(), to, from);
+            self.report(message, line);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(expr) | Stmt::Print { value: expr, .. } => {
+                self.infer(expr);
+            }
+            Stmt::Var {
+                name,
+                annotation,
+                initializer,
+                ..
+            } => {
+                let initial = match initializer {
+                    Some(initializer) => self.infer(initializer),
+                    None => Type::Nil,
+                };
+                match annotation {
+                    Some(annotation) => {
+                        let declared = self.resolve(annotation);
+                        self.expect(&initial, &declared, name.line, || {
+                            format!("Variable '{}'", name.lexeme)
+                        });
+                        self.define(&name.lexeme, declared);
+                    }
+                    None => self.define_inferred(&name.lexeme, initial),
+                }
+            }
+            Stmt::Const {
+                name,
+                annotation,
+                initializer,
+                ..
+            } => {
+                let initial = self.infer(initializer);
+                let declared = match annotation {
+                    Some(annotation) => {
+                        let declared = self.resolve(annotation);
+                        self.expect(&initial, &declared, name.line, || {
+                            format!("Constant '{}'", name.lexeme)
+                        });
+                        declared
+                    }
+                    None => initial,
+                };
+                self.define(&name.lexeme, declared);
+            }
+            Stmt::Destructure { pattern, value, .. } => {
+                self.infer(value);
+                self.pattern(pattern);
+            }
+            Stmt::Block { statements, .. } => self.block(statements),
+            Stmt::Function { declaration, .. } => {
+                self.function(declaration, Type::Any);
+            }
+            Stmt::Return { keyword, value } => {
+                let returned = match value {
+                    Some(value) => self.infer(value),
+                    None => Type::Nil,
+                };
+                if let Some(context) = &self.function {
+                    let (name, returns) = (context.name.clone(), context.returns.clone());
+                    self.expect(&returned, &returns, keyword.line, || {
+                        format!("Return value of '{}'", name)
+                    });
+                }
+            }
+            Stmt::Class {
+                name,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+                ..
+            } => {
+                let instance = Type::Instance(name.lexeme.to_string());
+                for method in methods.iter().chain(getters).chain(setters) {
+                    self.function(method, instance.clone());
+                }
+                for method in static_methods {
+                    self.function(method, Type::Class(name.lexeme.to_string()));
+                }
+                for (field, value) in static_fields {
+                    let kind = self.infer(value);
+                    if let Some(members) = self.classes.get_mut(name.lexeme()) {
+                        members.statics.insert(field.lexeme.to_string(), kind);
+                    }
+                }
+            }
+            Stmt::Trait {
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                for method in methods.iter().chain(getters).chain(setters) {
+                    self.function(method, Type::Any);
+                }
+            }
+            Stmt::Enum { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.infer(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.infer(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.infer(increment);
+                }
+            }
+            Stmt::ForIn {
+                variables,
+                iterable,
+                body,
+                ..
+            } => {
+                let iterable = self.infer(iterable);
+                let items = match (&iterable, variables.len()) {
+                    (Type::List(element), 1) => vec![*element.clone()],
+                    (Type::Range, 1) => vec![Type::Number],
+                    (Type::String, 1) => vec![Type::String],
+                    (Type::Map(key, value), 2) => vec![*key.clone(), *value.clone()],
+                    _ => vec![],
+                };
+                self.scoped(|checker| {
+                    for (i, variable) in variables.iter().enumerate() {
+                        let kind = items.get(i).cloned().unwrap_or(Type::Any);
+                        checker.define(&variable.lexeme, kind);
+                    }
+                    checker.statement(body);
+                });
+            }
+            Stmt::Yield { value, target, .. } => {
+                if let Some(value) = value {
+                    self.infer(value);
+                }
+                if let YieldTarget::Declare(name, _) = target {
+                    self.define(&name.lexeme, Type::Any);
+                }
+            }
+        }
+    }
+
+    /// Checks a function's defaults and body, with `this` of type `this`,
+    /// and returns its type.
+    fn function(&mut self, declaration: &FunctionDecl, this: Type) -> Type {
+        let signature = self.signature(declaration);
+        let returns = if declaration.is_generator {
+            Type::Any
+        } else {
+            self.annotated(&declaration.return_type)
+        };
+        if !always_returns(&declaration.body) {
+            self.expect(&Type::Nil, &returns, declaration.name.line, || {
+                format!("Return value of '{}'", declaration.name.lexeme)
+            });
+        }
+        let context = Context {
+            name: declaration.name.lexeme.to_string(),
+            returns,
+        };
+        let enclosing = self.function.replace(context);
+        let enclosing_this = std::mem::replace(&mut self.this, this);
+        self.scoped(|checker| {
+            for param in &declaration.params {
+                let declared = checker.annotated(&param.annotation);
+                if let Some(default) = &param.default {
+                    let default = checker.infer(default);
+                    checker.expect(&default, &declared, param.name.line, || {
+                        format!("Default of parameter '{}'", param.name.lexeme)
+                    });
+                }
+                checker.define(&param.name.lexeme, declared);
+            }
+            if let Some(rest) = &declaration.rest {
+                checker.define(&rest.lexeme, Type::List(Box::new(Type::Any)));
+            }
+            checker.block(&declaration.body);
+        });
+        self.this = enclosing_this;
+        self.function = enclosing;
+        signature
+    }
+
+    /// Declares the names a pattern binds and checks its expressions.
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Bind(name, _) => self.define(&name.lexeme, Type::Any),
+            Pattern::Value(value) => {
+                self.infer(value);
+            }
+            Pattern::Range { low, high, .. } => {
+                self.infer(low);
+                self.infer(high);
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                for pattern in before.iter().chain(rest.as_deref()).chain(after) {
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Default { pattern, default } => {
+                self.infer(default);
+                self.pattern(pattern);
+            }
+            Pattern::Map { entries } => {
+                for (_, pattern) in entries {
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Instance { fields, .. } => {
+                for (_, pattern) in fields {
+                    self.pattern(pattern);
+                }
+            }
+        }
+    }
+
+    /// Infers the type of `expr`, reporting mismatches inside it.
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal { value } => match value {
+                Literal::String(_) => Type::String,
+                Literal::Boolean(_) => Type::Bool,
+                Literal::Nil => Type::Nil,
+                _ => Type::Number,
+            },
+            Expr::Grouping(expr) => self.infer(expr),
+            Expr::Variable { name, .. } => self.lookup(&name.lexeme),
+            Expr::Assign { name, value, .. } => {
+                let value = self.infer(value);
+                self.assign(name, &value);
+                value
+            }
+            Expr::CompoundAssign {
+                target,
+                operator,
+                value,
+                ..
+            } => {
+                let current = self.infer(target);
+                let value = self.infer(value);
+                let result = self.binary(&current, operator, &value);
+                if let Expr::Variable { name, .. } = target.as_ref() {
+                    self.assign(name, &result);
+                }
+                result
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                self.binary(&left, operator, &right)
+            }
+            Expr::Unary { operator, right } => {
+                let right = self.infer(right);
+                match operator.lexeme() {
+                    "!" => Type::Bool,
+                    _ if matches!(right, Type::Instance(_)) => Type::Any,
+                    _ => {
+                        if !numeric(&right) {
+                            self.report(
+                                format!(
+                                    "Operand of '{}' must be a number, found {}",
+                                    operator.lexeme, right
+                                ),
+                                operator.line,
+                            );
+                        }
+                        Type::Number
+                    }
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                match (operator.lexeme(), left) {
+                    ("??", Type::Nil) => right,
+                    ("??", Type::Optional(left)) => join(*left, right),
+                    ("??", left) => left,
+                    (_, left) => join(left, right),
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.infer(condition);
+                let then_branch = self.infer(then_branch);
+                let else_branch = self.infer(else_branch);
+                join(then_branch, else_branch)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                optional,
+            } => {
+                let callee = self.infer(callee);
+                let result = self.call(&callee, arguments, paren.line);
+                if *optional {
+                    join(result, Type::Nil)
+                } else {
+                    result
+                }
+            }
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => {
+                let object = self.infer(object);
+                if *optional {
+                    return Type::Any;
+                }
+                self.property(&object, name)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object = self.infer(object);
+                let value = self.infer(value);
+                if let Type::Instance(class) = &object {
+                    let setter = self
+                        .classes
+                        .get(class)
+                        .and_then(|members| members.setters.get(name.lexeme()))
+                        .cloned();
+                    if let Some(setter) = setter {
+                        self.expect(&value, &setter, name.line, || {
+                            format!("Property '{}' of '{}'", name.lexeme, class)
+                        });
+                    }
+                }
+                value
+            }
+            Expr::This { .. } => self.this.clone(),
+            Expr::Super { name, .. } => {
+                let superclass = |class: &String| {
+                    self.classes
+                        .get(class)
+                        .and_then(|members| members.superclass.clone())
+                };
+                match &self.this {
+                    Type::Instance(class) => match superclass(class) {
+                        Some(superclass) => self.property(&Type::Instance(superclass), name),
+                        None => Type::Any,
+                    },
+                    Type::Class(class) => match superclass(class) {
+                        Some(superclass) => self.property(&Type::Class(superclass), name),
+                        None => Type::Any,
+                    },
+                    _ => Type::Any,
+                }
+            }
+            Expr::Lambda(declaration) => self.function(declaration, self.this.clone()),
+            Expr::List { elements } => {
+                let element = elements
+                    .iter()
+                    .map(|element| self.infer(element))
+                    .reduce(join)
+                    .unwrap_or(Type::Any);
+                Type::List(Box::new(element))
+            }
+            Expr::Map { entries, .. } => {
+                let (mut key, mut value) = (None, None::<Type>);
+                for (k, v) in entries {
+                    let (k, v) = (self.infer(k), self.infer(v));
+                    key = Some(key.map_or(k.clone(), |key| join(key, k)));
+                    value = Some(value.map_or(v.clone(), |value| join(value, v)));
+                }
+                Type::Map(
+                    Box::new(key.unwrap_or(Type::Any)),
+                    Box::new(value.unwrap_or(Type::Any)),
+                )
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.infer(start);
+                self.infer(end);
+                if let Some(step) = step {
+                    self.infer(step);
+                }
+                Type::Range
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.infer(object);
+                let index = self.infer(index);
+                match object {
+                    Type::List(element) => {
+                        if index == Type::Range {
+                            return Type::List(element);
+                        }
+                        self.expect(&index, &Type::Number, bracket.line, || {
+                            "List index".to_string()
+                        });
+                        *element
+                    }
+                    Type::Map(_, value) => *value,
+                    Type::String => Type::String,
+                    _ => Type::Any,
+                }
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let container = self.infer(object);
+                let index = self.infer(index);
+                let value = self.infer(value);
+                if let Expr::Variable { name, .. } = object.as_ref() {
+                    if let Some(variable) = self.inferred(name.lexeme()) {
+                        variable.kind = match variable.kind.clone() {
+                            Type::List(element) => {
+                                Type::List(Box::new(join(*element, value.clone())))
+                            }
+                            Type::Map(key, element) => Type::Map(
+                                Box::new(join(*key, index)),
+                                Box::new(join(*element, value.clone())),
+                            ),
+                            other => other,
+                        };
+                        return value;
+                    }
+                }
+                match &container {
+                    Type::List(element) | Type::Map(_, element) => {
+                        self.expect(&value, element, bracket.line, || {
+                            format!("Element of {}", container)
+                        });
+                    }
+                    _ => {}
+                }
+                value
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.infer(subject);
+                let mut result = None;
+                for arm in arms {
+                    self.scoped(|checker| {
+                        checker.pattern(&arm.pattern);
+                        if let Some(guard) = &arm.guard {
+                            checker.infer(guard);
+                        }
+                        let body = checker.infer(&arm.body);
+                        result = Some(match result.take() {
+                            Some(result) => join(result, body),
+                            None => body,
+                        });
+                    });
+                }
+                result.unwrap_or(Type::Any)
+            }
+        }
+    }
+
+    /// The type of `left operator right`. Instances may overload any
+    /// operator, so they are let through.
+    fn binary(&mut self, left: &Type, operator: &Token, right: &Type) -> Type {
+        if let Type::Instance(_) | Type::Trait(_) = left {
+            return Type::Any;
+        }
+        match operator.lexeme() {
+            "==" | "!=" | "is" => Type::Bool,
+            "<" | "<=" | ">" | ">=" => {
+                let comparable = matches!((left, right), (Type::Any, _) | (_, Type::Any))
+                    || (numeric(left) && numeric(right))
+                    || (left == &Type::String && right == &Type::String);
+                if !comparable {
+                    self.report(
+                        format!(
+                            "Can't compare {} and {} with '{}'",
+                            left, right, operator.lexeme
+                        ),
+                        operator.line,
+                    );
+                }
+                Type::Bool
+            }
+            _ => {
+                if !numeric(left) || !numeric(right) {
+                    self.report(
+                        format!(
+                            "Operands of '{}' must be numbers, found {} and {}",
+                            operator.lexeme, left, right
+                        ),
+                        operator.line,
+                    );
+                }
+                Type::Number
+            }
+        }
+    }
+
+    /// Checks the arguments of a call to a value of type `callee` and
+    /// returns the type of its result.
+    fn call(&mut self, callee: &Type, arguments: &[Argument], line: usize) -> Type {
+        let arguments: Vec<(Option<&Token>, Type)> = arguments
+            .iter()
+            .map(|argument| (argument.name.as_ref(), self.infer(&argument.value)))
+            .collect();
+        match callee {
+            Type::Function(Some(signature)) => {
+                self.arguments(signature, &arguments, line);
+                signature.returns.clone()
+            }
+            Type::Class(class) => {
+                let init = self
+                    .classes
+                    .get(class)
+                    .and_then(|members| members.methods.get("init"))
+                    .cloned();
+                if let Some(init) = init {
+                    self.arguments(&init, &arguments, line);
+                }
+                Type::Instance(class.clone())
+            }
+            Type::Instance(class) => self
+                .classes
+                .get(class)
+                .and_then(|members| members.methods.get("__call__"))
+                .map_or(Type::Any, |call| call.returns.clone()),
+            Type::Nil
+            | Type::Bool
+            | Type::Number
+            | Type::String
+            | Type::List(_)
+            | Type::Map(..)
+            | Type::Range
+            | Type::Enum(_) => {
+                self.report(format!("Can't call a value of type {}", callee), line);
+                Type::Any
+            }
+            _ => Type::Any,
+        }
+    }
+
+    fn arguments(
+        &mut self,
+        signature: &Signature,
+        arguments: &[(Option<&Token>, Type)],
+        line: usize,
+    ) {
+        let Some(params) = &signature.params else {
+            return;
+        };
+        let mut given = vec![false; params.len()];
+        let mut positional = 0;
+        for (name, argument) in arguments {
+            let index = match name {
+                Some(name) => {
+                    let index = params.iter().position(|param| *param.name == *name.lexeme);
+                    if index.is_none() {
+                        self.report(
+                            format!("'{}' has no parameter '{}'", signature.name, name.lexeme),
+                            name.line,
+                        );
+                    }
+                    index
+                }
+                None => {
+                    positional += 1;
+                    Some(positional - 1).filter(|&index| index < params.len())
+                }
+            };
+            if let Some(index) = index {
+                given[index] = true;
+                let param = &params[index];
+                self.expect(argument, &param.kind, line, || {
+                    format!("Argument '{}' of '{}'", param.name, signature.name)
+                });
+            }
+        }
+        if positional > params.len() && !signature.rest {
+            self.report(
+                format!(
+                    "'{}' takes at most {}, found {}",
+                    signature.name,
+                    function::arguments(params.len()),
+                    arguments.len()
+                ),
+                line,
+            );
+        }
+        for (param, given) in params.iter().zip(given) {
+            if !given && !param.has_default {
+                let message = if param.name.is_empty() {
+                    format!(
+                        "'{}' takes {}",
+                        signature.name,
+                        function::arguments(params.len())
+                    )
+                } else {
+                    format!(
+                        "Missing argument '{}' in call to '{}'",
+                        param.name, signature.name
+                    )
+                };
+                self.report(message, line);
+                break;
+            }
+        }
+    }
+
+    /// The type of `object.name`.
+    fn property(&mut self, object: &Type, name: &Token) -> Type {
+        match object {
+            Type::Instance(class) => {
+                let Some(members) = self.classes.get(class) else {
+                    return Type::Any;
+                };
+                if let Some(getter) = members.getters.get(name.lexeme()) {
+                    getter.clone()
+                } else if let Some(method) = members.methods.get(name.lexeme()) {
+                    Type::Function(Some(Rc::clone(method)))
+                } else {
+                    Type::Any
+                }
+            }
+            Type::Class(class) => self
+                .classes
+                .get(class)
+                .and_then(|members| members.statics.get(name.lexeme()))
+                .cloned()
+                .unwrap_or(Type::Any),
+            Type::Enumeration(enumeration) => {
+                let fields = self.enums.get(enumeration).and_then(|variants| {
+                    variants
+                        .iter()
+                        .find(|(variant, _)| **variant == *name.lexeme)
+                        .map(|(_, fields)| fields.clone())
+                });
+                match fields {
+                    Some(fields) => self.variant_type(enumeration, &name.lexeme, &fields),
+                    None => {
+                        self.report(
+                            format!("Enum '{}' has no variant '{}'", enumeration, name.lexeme),
+                            name.line,
+                        );
+                        Type::Any
+                    }
+                }
+            }
+            _ => Type::Any,
+        }
+    }
+
+    /// The type of `Enum.Variant`: the variant itself, or a constructor
+    /// taking its fields.
+    fn variant_type(&self, enumeration: &str, variant: &str, fields: &Option<Vec<String>>) -> Type {
+        let Some(fields) = fields else {
+            return Type::Enum(enumeration.to_string());
+        };
+        let params = fields
+            .iter()
+            .map(|field| Parameter {
+                name: field.clone(),
+                kind: Type::Any,
+                has_default: false,
+            })
+            .collect();
+        function(variant, Some(params), Type::Enum(enumeration.to_string()))
+    }
+}
+
+fn function(name: &str, params: Option<Vec<Parameter>>, returns: Type) -> Type {
+    Type::Function(Some(Rc::new(Signature {
+        name: name.to_string(),
+        params,
+        rest: false,
+        returns,
+    })))
+}
+
+/// What the builtins return, where it's always the same.
+fn native_returns(name: &str) -> Type {
+    match name {
+        "clock" | "len" | "decimal" => Type::Number,
+        "is_frozen" | "implements" => Type::Bool,
+        "map" | "filter" | "sort_by" => Type::List(Box::new(Type::Any)),
+        "parse_number" => Type::Enum("Option".to_string()),
+        "try" => Type::Enum("Result".to_string()),
+        _ => Type::Any,
+    }
+}
+
+/// Whether running `statements` always ends in a `return`, so a function
+/// with them as its body never falls off the end and returns `nil`.
+fn always_returns(statements: &[Stmt]) -> bool {
+    statements.iter().any(returns)
+}
+
+fn returns(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Return { .. } => true,
+        Stmt::Block { statements, .. } => always_returns(statements),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => returns(then_branch) && returns(else_branch),
+        // `while (true)` only ends by breaking out of it.
+        Stmt::While {
+            condition, body, ..
+        } if always_true(condition) => !breaks_out(body, &[]),
+        _ => false,
+    }
+}
+
+fn always_true(condition: &Expr) -> bool {
+    match condition {
+        Expr::Grouping(inner) => always_true(inner),
+        Expr::Literal {
+            value: Literal::Boolean(value),
+        } => *value,
+        _ => false,
+    }
+}
+
+/// Whether `statement`, in the body of a loop, can break out of that loop.
+/// `inner` holds the labels of the loops in between, which catch unlabelled
+/// breaks and those naming them.
+fn breaks_out(statement: &Stmt, inner: &[Option<&str>]) -> bool {
+    match statement {
+        Stmt::Break { label: None, .. } => inner.is_empty(),
+        Stmt::Break {
+            label: Some(target),
+            ..
+        } => !inner.contains(&Some(target.lexeme())),
+        Stmt::Block { statements, .. } => statements
+            .iter()
+            .any(|statement| breaks_out(statement, inner)),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            breaks_out(then_branch, inner)
+                || else_branch
+                    .as_ref()
+                    .is_some_and(|else_branch| breaks_out(else_branch, inner))
+        }
+        Stmt::While {
+            label: nested,
+            body,
+            ..
+        }
+        | Stmt::ForIn {
+            label: nested,
+            body,
+            ..
+        } => {
+            let mut inner = inner.to_vec();
+            inner.push(nested.as_ref().map(Token::lexeme));
+            breaks_out(body, &inner)
+        }
+        _ => false,
+    }
+}
+
+fn numeric(kind: &Type) -> bool {
+    matches!(kind, Type::Any | Type::Number)
+}
+
+/// The narrowest type covering both `a` and `b`.
+fn join(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (Type::Any, _) | (_, Type::Any) => Type::Any,
+        (Type::Nil, Type::Optional(other)) | (Type::Optional(other), Type::Nil) => {
+            Type::Optional(other)
+        }
+        (Type::Nil, other) | (other, Type::Nil) => Type::Optional(Box::new(other)),
+        (Type::Optional(a), b) | (b, Type::Optional(a)) if *a == b => Type::Optional(a),
+        _ => Type::Any,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check;
+
+    #[test]
+    fn unannotated_variables_take_their_initializers_type() {
+        assert_eq!(
+            check("var x = 1; var y: String = x;"),
+            Ok(vec![
+                "Variable 'y' must be String, found Number at line 1".to_string()
+            ])
+        );
+        for source in [
+            "var x = 1; x = \"a\"; var y: String = x;",
+            "var x = nil; x = 5; var y: String = x;",
+            "var x = 1; if (true) x = \"a\"; var y: Number = x;",
+            "var l = [1]; l[0] = l; var n: Number = l[0];",
+            "var m = {\"a\": 1}; m[\"b\"] = \"c\"; var n: Number = m[\"a\"];",
+        ] {
+            assert_eq!(check(source), Ok(vec![]), "{}", source);
+        }
+    }
+
+    #[test]
+    fn functions_that_can_end_without_returning_return_nil() {
+        for source in [
+            "fun f() -> Number {}",
+            "fun f(n) -> Number { if (n) return 1; }",
+            "fun f() -> Number { while (true) { break; } }",
+            "fun f() -> Number { a: while (true) { for x in [1] { break a; } } }",
+        ] {
+            assert_eq!(
+                check(source),
+                Ok(vec![
+                    "Return value of 'f' must be Number, found Nil at line 1".to_string()
+                ]),
+                "{}",
+                source
+            );
+        }
+        for source in [
+            "fun f(n) -> Number { if (n) return 1; else { return 2; } }",
+            "fun f() -> Number { while (true) { for x in [1] { break; } } }",
+            "fun f() -> Number? {}",
+            "fun f() {}",
+        ] {
+            assert_eq!(check(source), Ok(vec![]), "{}", source);
+        }
+    }
+
+    #[test]
+    fn subclasses_fit_where_their_superclasses_are_expected() {
+        let classes = "class A { f() -> Number { return 1; } } class B < A {}";
+        assert_eq!(
+            check(&format!(
+                "{classes} var a: A = B(); var n: Number = B().f();"
+            )),
+            Ok(vec![])
+        );
+        assert_eq!(
+            check(&format!(
+                "{classes} var b: B = A(); var s: String = B().f();"
+            )),
+            Ok(vec![
+                "Variable 'b' must be B, found A at line 1".to_string(),
+                "Variable 's' must be String, found Number at line 1".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn arrow_functions_take_annotations() {
+        assert_eq!(
+            check("var f = (x: Number) -> String => x; f(\"a\");"),
+            Ok(vec![
+                "Return value of 'lambda' must be String, found Number at line 1".to_string(),
+                "Argument 'x' of 'lambda' must be Number, found String at line 1".to_string(),
+            ])
+        );
+        assert_eq!(
+            crate::eval("var f = (x: Number) -> Number => x + 1; f(1);"),
+            Ok("2".to_string())
+        );
+    }
+}
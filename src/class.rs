@@ -0,0 +1,455 @@
+use crate::error::RuntimeError;
+use crate::function::Function;
+use crate::interpreter::frozen;
+use crate::token::Token;
+use crate::value::{self, Value};
+use core::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+pub struct Class {
+    pub name: String,
+    /// The class named after `<`, whose members this one inherits unless it
+    /// defines its own.
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<String, Rc<Function>>,
+    accessors: Accessors,
+    /// Static fields and methods, read and assigned as `Class.name`.
+    statics: RefCell<HashMap<String, Value>>,
+    /// Set by `freeze`, after which statics can't be assigned.
+    frozen: Cell<bool>,
+    /// The traits named in the class's `impl` clause, whose default methods
+    /// are already among `methods`.
+    traits: Vec<Rc<Trait>>,
+}
+
+/// A class's `get` and `set` accessors, keyed by property name.
+#[derive(Default)]
+pub struct Accessors {
+    pub getters: HashMap<String, Rc<Function>>,
+    pub setters: HashMap<String, Rc<Function>>,
+}
+
+/// A `trait`: methods a class must define, and default methods and
+/// accessors it gets unless it defines its own.
+pub struct Trait {
+    pub name: String,
+    required: Vec<String>,
+    methods: HashMap<String, Rc<Function>>,
+    accessors: Accessors,
+}
+
+impl Class {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<Class>>,
+        methods: HashMap<String, Rc<Function>>,
+        accessors: Accessors,
+        traits: Vec<Rc<Trait>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+            accessors,
+            statics: RefCell::new(HashMap::new()),
+            frozen: Cell::new(false),
+            traits,
+        }
+    }
+
+    /// Creates a class inheriting from `superclass` and implementing
+    /// `traits`: their default methods and accessors fill in whatever the
+    /// class neither defines nor inherits, and every required method must
+    /// end up defined.
+    pub fn implementing(
+        name: &Token,
+        superclass: Option<Rc<Class>>,
+        mut methods: HashMap<String, Rc<Function>>,
+        mut accessors: Accessors,
+        traits: Vec<Rc<Trait>>,
+    ) -> Result<Self, RuntimeError> {
+        let inherits = |find: fn(&Class, &str) -> Option<Rc<Function>>| {
+            let superclass = superclass.clone();
+            move |name: &str| superclass.as_ref().is_some_and(|c| find(c, name).is_some())
+        };
+        mix_in(
+            name,
+            &mut methods,
+            &traits,
+            |t| &t.methods,
+            inherits(Class::find_method),
+        )?;
+        mix_in(
+            name,
+            &mut accessors.getters,
+            &traits,
+            |t| &t.accessors.getters,
+            inherits(Class::find_getter),
+        )?;
+        mix_in(
+            name,
+            &mut accessors.setters,
+            &traits,
+            |t| &t.accessors.setters,
+            inherits(Class::find_setter),
+        )?;
+        let defined = inherits(Class::find_method);
+        for implemented in &traits {
+            if let Some(missing) = implemented
+                .required
+                .iter()
+                .find(|method| !methods.contains_key(*method) && !defined(method))
+            {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Class '{}' is missing method '{}' required by trait '{}'",
+                        name.lexeme(),
+                        missing,
+                        implemented.name
+                    ),
+                    name.line,
+                ));
+            }
+        }
+        Ok(Self::new(
+            name.lexeme().to_string(),
+            superclass,
+            methods,
+            accessors,
+            traits,
+        ))
+    }
+
+    pub fn superclass(&self) -> Option<&Rc<Class>> {
+        self.superclass.as_ref()
+    }
+
+    /// The classes from this one up its superclass chain.
+    fn lineage(&self) -> impl Iterator<Item = &Class> {
+        std::iter::successors(Some(self), |class| class.superclass.as_deref())
+    }
+
+    /// The method `name`, the class's own or else the nearest inherited one.
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        self.lineage()
+            .find_map(|class| class.methods.get(name))
+            .cloned()
+    }
+
+    /// The getter for `name`, looked up like a method.
+    pub fn find_getter(&self, name: &str) -> Option<Rc<Function>> {
+        self.lineage()
+            .find_map(|class| class.accessors.getters.get(name))
+            .cloned()
+    }
+
+    /// The setter for `name`, looked up like a method.
+    pub fn find_setter(&self, name: &str) -> Option<Rc<Function>> {
+        self.lineage()
+            .find_map(|class| class.accessors.setters.get(name))
+            .cloned()
+    }
+
+    /// Whether this class is `other` or inherits from it.
+    pub fn is_subclass_of(&self, other: &Rc<Class>) -> bool {
+        self.lineage()
+            .any(|class| std::ptr::eq(class, Rc::as_ptr(other)))
+    }
+
+    pub fn methods(&self) -> &HashMap<String, Rc<Function>> {
+        &self.methods
+    }
+
+    pub fn accessors(&self) -> &Accessors {
+        &self.accessors
+    }
+
+    /// The class's own static members, in no particular order.
+    pub fn statics(&self) -> Vec<(String, Value)> {
+        self.statics
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn set_static(&self, name: String, value: Value) {
+        self.statics.borrow_mut().insert(name, value);
+    }
+
+    /// Assigns `Class.name`, which a frozen class refuses.
+    pub fn assign_static(&self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if self.is_frozen() {
+            return Err(frozen(&format!("class {}", self.name), name.line));
+        }
+        self.set_static(name.lexeme().to_string(), value);
+        Ok(())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.get()
+    }
+
+    /// Stops statics being assigned, returning whether they already were.
+    pub fn freeze(&self) -> bool {
+        self.frozen.replace(true)
+    }
+
+    /// Reads `Class.name`, which may be inherited. Static methods see the
+    /// class as `this`.
+    pub fn get_static(class: &Rc<Class>, name: &Token) -> Result<Value, RuntimeError> {
+        Class::get_static_from(class, class, name)
+    }
+
+    /// Reads `name` from the statics of `from` or its superclasses, binding
+    /// a static method to `class`. `super.name` in a static method starts
+    /// from the superclass.
+    pub fn get_static_from(
+        from: &Class,
+        class: &Rc<Class>,
+        name: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let found = from
+            .lineage()
+            .find_map(|owner| owner.statics.borrow().get(name.lexeme()).cloned());
+        match found {
+            Some(Value::Function(method)) => Ok(Value::Function(Rc::new(
+                method.bind(Value::Class(Rc::clone(class))),
+            ))),
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::new(
+                format!(
+                    "Undefined static member '{}' of class '{}'",
+                    name.lexeme(),
+                    class.name
+                ),
+                name.line,
+            )),
+        }
+    }
+
+    /// Reads `super.name` in a method of a subclass of `superclass` bound to
+    /// `this`: a getter, which is called, or a method bound to `this`. In a
+    /// static method, `this` is the subclass and `name` a static member.
+    pub fn get_super(superclass: &Class, this: Value, name: &Token) -> Result<Value, RuntimeError> {
+        if let Value::Class(class) = &this {
+            return Class::get_static_from(superclass, class, name);
+        }
+        if let Some(getter) = superclass.find_getter(name.lexeme()) {
+            return getter.bind(this).call(Vec::new(), Vec::new(), name.line);
+        }
+        match superclass.find_method(name.lexeme()) {
+            Some(method) => Ok(Value::Function(Rc::new(method.bind(this)))),
+            None => Err(RuntimeError::new(
+                format!(
+                    "Undefined property '{}' of superclass '{}'",
+                    name.lexeme(),
+                    superclass.name
+                ),
+                name.line,
+            )),
+        }
+    }
+
+    pub fn traits(&self) -> &[Rc<Trait>] {
+        &self.traits
+    }
+
+    /// Whether the class, or a class it inherits from, names `implemented`
+    /// in its `impl` clause.
+    pub fn implements(&self, implemented: &Rc<Trait>) -> bool {
+        self.lineage()
+            .flat_map(|class| &class.traits)
+            .any(|own| Rc::ptr_eq(own, implemented))
+    }
+}
+
+impl Trait {
+    pub fn new(
+        name: String,
+        required: Vec<String>,
+        methods: HashMap<String, Rc<Function>>,
+        accessors: Accessors,
+    ) -> Self {
+        Self {
+            name,
+            required,
+            methods,
+            accessors,
+        }
+    }
+
+    pub fn accessors(&self) -> &Accessors {
+        &self.accessors
+    }
+
+    pub fn required(&self) -> &[String] {
+        &self.required
+    }
+
+    pub fn methods(&self) -> &HashMap<String, Rc<Function>> {
+        &self.methods
+    }
+}
+
+/// Adds the defaults that `traits` offer through `defaults` to a class's
+/// `own` functions. The class's own functions, and those it inherits from
+/// its superclass, win over defaults, but two traits offering the same
+/// default is ambiguous.
+fn mix_in(
+    class: &Token,
+    own: &mut HashMap<String, Rc<Function>>,
+    traits: &[Rc<Trait>],
+    defaults: impl Fn(&Trait) -> &HashMap<String, Rc<Function>>,
+    from_superclass: impl Fn(&str) -> bool,
+) -> Result<(), RuntimeError> {
+    let mut inherited: HashMap<&str, &Trait> = HashMap::new();
+    for implemented in traits {
+        for (name, function) in defaults(implemented) {
+            let defined = own.contains_key(name) || from_superclass(name);
+            if defined && !inherited.contains_key(name.as_str()) {
+                continue;
+            }
+            if let Some(other) = inherited.insert(name, implemented) {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Class '{}' gets '{}' from both '{}' and '{}'; define it to choose",
+                        class.lexeme(),
+                        name,
+                        other.name,
+                        implemented.name
+                    ),
+                    class.line,
+                ));
+            }
+            own.insert(name.clone(), Rc::clone(function));
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Trait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<trait {}>", self.name)
+    }
+}
+
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<Arc<str>, Value>,
+    /// Set by `freeze`, after which fields can't be assigned.
+    pub frozen: bool,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+            frozen: false,
+        }
+    }
+
+    /// Looks up a field, then a getter, which is called, and finally a
+    /// method bound to `instance`.
+    pub fn get(instance: &Rc<RefCell<Instance>>, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = instance.borrow().fields.get(name.lexeme()) {
+            return Ok(value.clone());
+        }
+
+        let getter = instance.borrow().class.find_getter(name.lexeme());
+        if let Some(getter) = getter {
+            return getter.bind(Value::Instance(Rc::clone(instance))).call(
+                Vec::new(),
+                Vec::new(),
+                name.line,
+            );
+        }
+
+        let method = instance.borrow().class.find_method(name.lexeme());
+        match method {
+            Some(method) => Ok(Value::Function(Rc::new(
+                method.bind(Value::Instance(Rc::clone(instance))),
+            ))),
+            None => Err(RuntimeError::new(
+                format!("Undefined property '{}'", name.lexeme()),
+                name.line,
+            )),
+        }
+    }
+
+    /// Assigns a field, or calls the setter for `name` if the class has one.
+    /// A property with only a getter can't be assigned.
+    pub fn set(
+        instance: &Rc<RefCell<Instance>>,
+        name: &Token,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let class = Rc::clone(&instance.borrow().class);
+        if instance.borrow().frozen {
+            return Err(frozen(&format!("{} instance", class.name), name.line));
+        }
+        if let Some(setter) = class.find_setter(name.lexeme()) {
+            setter.bind(Value::Instance(Rc::clone(instance))).call(
+                vec![value],
+                Vec::new(),
+                name.line,
+            )?;
+            return Ok(());
+        }
+        if class.find_getter(name.lexeme()).is_some() {
+            return Err(RuntimeError::new(
+                format!(
+                    "Can't assign to '{}', which has a getter but no setter",
+                    name.lexeme()
+                ),
+                name.line,
+            ));
+        }
+        instance
+            .borrow_mut()
+            .fields
+            .insert(Arc::clone(&name.lexeme), value);
+        Ok(())
+    }
+
+    /// Calls the method `name` on `instance`, or returns `None` if its class
+    /// doesn't define one. This is how the interpreter reaches the methods
+    /// behind iteration and operators.
+    pub fn call_method(
+        instance: &Rc<RefCell<Instance>>,
+        name: &str,
+        arguments: Vec<Value>,
+        line: usize,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let method = instance.borrow().class.find_method(name);
+        match method {
+            Some(method) => method
+                .bind(Value::Instance(Rc::clone(instance)))
+                .call(arguments, Vec::new(), line)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        value::drop_nested(self.fields.drain().map(|(_, value)| value).collect());
+    }
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
@@ -0,0 +1,96 @@
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use core::cell::RefCell;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Watches a script run one statement at a time, as the debugger does. A
+/// hook is attached to the thread that runs the script; threads it spawns
+/// run without one.
+pub trait Hook {
+    /// Called before the statement starting on `line` runs in `env`, with
+    /// the calls in progress, outermost first. Failing stops the script as
+    /// if the statement had.
+    fn statement(
+        &mut self,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+        calls: &[Call],
+    ) -> Result<(), RuntimeError>;
+}
+
+/// A call in progress: the function called, and the line it was called
+/// from.
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub function: String,
+    pub line: usize,
+}
+
+thread_local! {
+    static ATTACHED: Cell<bool> = const { Cell::new(false) };
+    static HOOK: RefCell<Option<Box<dyn Hook>>> = const { RefCell::new(None) };
+    static CALLS: RefCell<Vec<Call>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Calls `hook` before each statement the current thread runs from now on.
+pub fn attach(hook: Box<dyn Hook>) {
+    CALLS.with(|calls| calls.borrow_mut().clear());
+    HOOK.with(|current| *current.borrow_mut() = Some(hook));
+    ATTACHED.set(true);
+}
+
+/// Removes the current thread's hook, returning it.
+pub fn detach() -> Option<Box<dyn Hook>> {
+    ATTACHED.set(false);
+    HOOK.with(|current| current.borrow_mut().take())
+}
+
+/// Whether the current thread has a hook, so that what only a hook needs
+/// can be skipped otherwise.
+#[inline]
+pub fn attached() -> bool {
+    ATTACHED.get()
+}
+
+/// Runs the hook for the statement starting on `line`. While it runs,
+/// any statements it causes to run don't run it again.
+pub fn statement(line: usize, env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let Some(mut hook) = HOOK.with(|current| current.borrow_mut().take()) else {
+        return Ok(());
+    };
+    let calls = CALLS.with(|calls| calls.borrow().clone());
+    let result = hook.statement(line, env, &calls);
+    HOOK.with(|current| {
+        let mut current = current.borrow_mut();
+        if current.is_none() && attached() {
+            *current = Some(hook);
+        }
+    });
+    result
+}
+
+/// A call the hook is told about, which ends when this is dropped.
+pub struct Entered(bool);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if self.0 {
+            CALLS.with(|calls| calls.borrow_mut().pop());
+        }
+    }
+}
+
+/// Enters a call to `function` made at `line`, if a hook is attached.
+pub fn enter(function: &str, line: usize) -> Entered {
+    if !attached() {
+        return Entered(false);
+    }
+    CALLS.with(|calls| {
+        calls.borrow_mut().push(Call {
+            function: function.to_string(),
+            line,
+        })
+    });
+    Entered(true)
+}
@@ -0,0 +1,538 @@
+use crate::debug::{Call, Hook};
+use crate::environment::{Environment, Globals};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::interrupt;
+use crate::value::Value;
+use core::cell::RefCell;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const HELP: &str = "\
+  step, s           run to the next statement, going into calls
+  next, n           run to the next statement in this call
+  out, o            run until this call returns
+  continue, c       run to the next breakpoint
+  break, b [LINE]   stop at LINE, or list the breakpoints
+  delete, d LINE    remove the breakpoint at LINE
+  backtrace, bt     show the calls in progress
+  env, e            show the variables in each scope, innermost first
+  globals, g        show the globals the script has declared
+  print, p NAME     show the variable NAME
+  list, l           show the source around this line
+  quit, q           stop the script
+An empty line repeats the last command.";
+
+/// How far to run before stopping again.
+enum Mode {
+    /// Only stop at breakpoints.
+    Continue,
+    /// Stop at the next statement.
+    Step,
+    /// Stop at the next statement no deeper in calls than the given depth.
+    Next(usize),
+    /// Stop at the next statement less deep in calls than the given depth.
+    Out(usize),
+}
+
+/// The `y debug` command line, a hook that stops before statements and
+/// asks what to do on standard input. It stops before the first one.
+pub struct Debugger {
+    lines: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// The line and call depth last stopped at, which isn't stopped at
+    /// again until a statement elsewhere has run, so that stepping moves
+    /// to the next line rather than the next statement on the same line.
+    stopped: Option<(usize, usize)>,
+    last_command: String,
+    /// Where commands come from and what they show goes.
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    /// Set when the user quits, so that stopping the script that way isn't
+    /// taken for it failing.
+    quit: Rc<Cell<bool>>,
+}
+
+impl Debugger {
+    /// A debugger for `source` on standard input and output.
+    pub fn new(source: &str, quit: Rc<Cell<bool>>) -> Self {
+        Self::with_io(
+            source,
+            quit,
+            Box::new(io::stdin().lock()),
+            Box::new(io::stdout()),
+        )
+    }
+
+    /// A debugger for `source` reading commands from `input` and showing
+    /// what they ask for on `output`.
+    pub fn with_io(
+        source: &str,
+        quit: Rc<Cell<bool>>,
+        input: Box<dyn BufRead>,
+        output: Box<dyn Write>,
+    ) -> Self {
+        Self {
+            lines: source.lines().map(str::to_string).collect(),
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+            stopped: None,
+            last_command: String::new(),
+            input,
+            output,
+            quit,
+        }
+    }
+
+    /// Shows `text` on a line of its own. The script goes on if the output
+    /// is gone.
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    fn should_stop(&self, line: usize, depth: usize) -> bool {
+        self.breakpoints.contains(&line)
+            || match self.mode {
+                Mode::Continue => false,
+                Mode::Step => true,
+                Mode::Next(from) => depth <= from,
+                Mode::Out(from) => depth < from,
+            }
+    }
+
+    fn source(&self, line: usize) -> &str {
+        self.lines
+            .get(line.wrapping_sub(1))
+            .map_or("", |text| text.trim())
+    }
+
+    /// Reads commands until one runs the script on.
+    fn prompt(
+        &mut self,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+        calls: &[Call],
+    ) -> Result<(), RuntimeError> {
+        loop {
+            let _ = write!(self.output, "(ydb) ");
+            let _ = self.output.flush();
+            let mut buffer = String::new();
+            if self.input.read_line(&mut buffer).unwrap_or(0) == 0 {
+                // With no one left to ask, run the script to the end.
+                self.say("");
+                self.breakpoints.clear();
+                self.mode = Mode::Continue;
+                return Ok(());
+            }
+            if !buffer.trim().is_empty() {
+                self.last_command = buffer.trim().to_string();
+            }
+            let command = self.last_command.clone();
+            let mut words = command.split_whitespace();
+            let name = words.next().unwrap_or("");
+            let mode = match name {
+                "step" | "s" => Some(Mode::Step),
+                "next" | "n" => Some(Mode::Next(calls.len())),
+                "out" | "o" => Some(Mode::Out(calls.len())),
+                "continue" | "c" => Some(Mode::Continue),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                self.mode = mode;
+                return Ok(());
+            }
+            match name {
+                "break" | "b" => match words.next() {
+                    None => self.show_breakpoints(),
+                    Some(text) => match parse_line(text) {
+                        Some(target) => {
+                            self.breakpoints.insert(target);
+                            let text =
+                                format!("Breakpoint at line {}: {}", target, self.source(target));
+                            self.say(&text);
+                        }
+                        None => self.say(&format!("Expected a line number, got '{}'", text)),
+                    },
+                },
+                "delete" | "d" => match words.next().and_then(parse_line) {
+                    Some(target) if self.breakpoints.remove(&target) => {
+                        self.say(&format!("Deleted the breakpoint at line {}", target))
+                    }
+                    Some(target) => self.say(&format!("No breakpoint at line {}", target)),
+                    None => self.say("Expected a line number"),
+                },
+                "backtrace" | "bt" => self.say(&show_calls(line, calls)),
+                "env" | "e" => self.say(&show_scopes(env)),
+                "globals" | "g" => {
+                    for (name, value) in Globals::declared() {
+                        self.say(&format!("  {} = {}", name, show(&value)));
+                    }
+                }
+                "print" | "p" => match words.next() {
+                    Some(name) => match find(env, name) {
+                        Some(value) => self.say(&format!("{} = {}", name, show(&value))),
+                        None => self.say(&format!("No variable '{}' in scope", name)),
+                    },
+                    None => self.say("Expected a variable name"),
+                },
+                "list" | "l" => self.list(line),
+                "quit" | "q" => {
+                    self.quit.set(true);
+                    if let Some(handle) = interrupt::current() {
+                        handle.interrupt();
+                    }
+                    return Err(RuntimeError::with_kind(
+                        ErrorKind::Interrupted,
+                        "Quit from the debugger".to_string(),
+                        line,
+                    ));
+                }
+                "help" | "h" => self.say(HELP),
+                other => self.say(&format!("Unknown command '{}'; 'help' lists them", other)),
+            }
+        }
+    }
+
+    fn show_breakpoints(&mut self) {
+        if self.breakpoints.is_empty() {
+            self.say("No breakpoints");
+        }
+        let listed: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|&line| format!("  line {}: {}", line, self.source(line)))
+            .collect();
+        for text in listed {
+            self.say(&text);
+        }
+    }
+
+    /// Shows the lines around `line`, marking it and any breakpoints.
+    fn list(&mut self, line: usize) {
+        let first = line.saturating_sub(5).max(1);
+        let last = (line + 5).min(self.lines.len());
+        for number in first..=last {
+            let marker = match (number == line, self.breakpoints.contains(&number)) {
+                (true, _) => "->",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let text = format!("{} {:>4}  {}", marker, number, self.lines[number - 1]);
+            self.say(&text);
+        }
+    }
+}
+
+impl Hook for Debugger {
+    fn statement(
+        &mut self,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+        calls: &[Call],
+    ) -> Result<(), RuntimeError> {
+        let depth = calls.len();
+        if self.stopped == Some((line, depth)) {
+            return Ok(());
+        }
+        self.stopped = None;
+        if !self.should_stop(line, depth) {
+            return Ok(());
+        }
+        self.stopped = Some((line, depth));
+        let text = match calls.last() {
+            Some(call) => format!(
+                "line {} in '{}': {}",
+                line,
+                call.function,
+                self.source(line)
+            ),
+            None => format!("line {}: {}", line, self.source(line)),
+        };
+        self.say(&text);
+        self.prompt(line, env, calls)
+    }
+}
+
+fn parse_line(text: &str) -> Option<usize> {
+    text.parse().ok().filter(|&line| line > 0)
+}
+
+/// Shows a value as it would appear in a list, with strings quoted.
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+/// Shows the calls in progress, innermost first, each at the line it has
+/// got to.
+fn show_calls(line: usize, calls: &[Call]) -> String {
+    let mut text = String::new();
+    let mut at = line;
+    for (depth, call) in calls.iter().rev().enumerate() {
+        let _ = writeln!(text, "  #{} '{}' at line {}", depth, call.function, at);
+        at = call.line;
+    }
+    let _ = write!(text, "  #{} top level at line {}", calls.len(), at);
+    text
+}
+
+/// Shows the variables bound in each scope from `env` outwards, numbered
+/// from the innermost. Slots the scope didn't name are shown by number;
+/// those not bound yet, and scopes with none bound, are left out.
+fn show_scopes(env: &Rc<RefCell<Environment>>) -> String {
+    let mut shown = Vec::new();
+    let mut scope = Some(Rc::clone(env));
+    let mut depth = 0;
+    while let Some(current) = scope {
+        let environment = current.borrow();
+        let bound: Vec<String> = (0..environment.slots())
+            .filter_map(|index| {
+                let (value, constant) = environment.slot(index)?;
+                let name = match environment.names().and_then(|names| names.get(index)) {
+                    Some(name) => name.to_string(),
+                    None => format!("slot {}", index),
+                };
+                let keyword = if constant { "const " } else { "" };
+                Some(format!("{}{} = {}", keyword, name, show(&value)))
+            })
+            .collect();
+        if !bound.is_empty() {
+            shown.push(format!("  [{}] {}", depth, bound.join(", ")));
+            depth += 1;
+        }
+        scope = environment.enclosing().cloned();
+    }
+    if shown.is_empty() {
+        return "  No local variables; 'globals' shows the rest".to_string();
+    }
+    shown.join("\n")
+}
+
+/// The variable `name` as seen from `env`: the innermost local of that name
+/// that is bound, or else the global.
+fn find(env: &Rc<RefCell<Environment>>, name: &str) -> Option<Value> {
+    let mut scope = Some(Rc::clone(env));
+    while let Some(current) = scope {
+        let environment = current.borrow();
+        if let Some(names) = environment.names() {
+            let found = names
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| ***slot == *name)
+                .find_map(|(index, _)| environment.slot(index));
+            if let Some((value, _)) = found {
+                return Some(value);
+            }
+        }
+        scope = environment.enclosing().cloned();
+    }
+    Globals::lookup(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::{on_interpreter_thread, Y};
+    use std::cell::{Cell, RefCell};
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    /// Output that can still be read once the debugger owning it is gone.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Debugs `source`, typing `commands` one per line, and returns what
+    /// the debugger showed, prompts left out.
+    fn debug(source: &str, commands: &[&str]) -> Vec<String> {
+        let source = source.to_string();
+        let input: String = commands
+            .iter()
+            .map(|command| format!("{}\n", command))
+            .collect();
+        on_interpreter_thread(move || {
+            let output = Captured::default();
+            let debugger = Debugger::with_io(
+                &source,
+                Rc::new(Cell::new(false)),
+                Box::new(Cursor::new(input.into_bytes())),
+                Box::new(output.clone()),
+            );
+            Y::new().debug(&source, debugger);
+            let shown = String::from_utf8(output.0.take()).unwrap();
+            shown
+                .replace("(ydb) ", "")
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+    }
+
+    const SCRIPT: &str = "\
+fun add(a, b) {
+  var sum = a + b;
+  return sum;
+}
+var x = add(1, 2);
+var y = add(x, 3);
+";
+
+    #[test]
+    fn step_goes_into_calls() {
+        assert_eq!(
+            debug(SCRIPT, &["step", "s", "s", "s", "s", "s", "s"]),
+            [
+                "line 1: fun add(a, b) {",
+                "line 5: var x = add(1, 2);",
+                "line 2 in 'add': var sum = a + b;",
+                "line 3 in 'add': return sum;",
+                "line 6: var y = add(x, 3);",
+                "line 2 in 'add': var sum = a + b;",
+                "line 3 in 'add': return sum;",
+            ]
+        );
+    }
+
+    #[test]
+    fn next_stays_in_the_call_and_out_leaves_it() {
+        assert_eq!(
+            debug(SCRIPT, &["next", "n", "c"]),
+            [
+                "line 1: fun add(a, b) {",
+                "line 5: var x = add(1, 2);",
+                "line 6: var y = add(x, 3);",
+            ]
+        );
+        assert_eq!(
+            debug(SCRIPT, &["n", "s", "out", "c"]),
+            [
+                "line 1: fun add(a, b) {",
+                "line 5: var x = add(1, 2);",
+                "line 2 in 'add': var sum = a + b;",
+                "line 6: var y = add(x, 3);",
+            ]
+        );
+        // Next from inside a call stops at the caller once it returns.
+        assert_eq!(
+            debug(SCRIPT, &["b 3", "c", "n", "c"]),
+            [
+                "line 1: fun add(a, b) {",
+                "Breakpoint at line 3: return sum;",
+                "line 3 in 'add': return sum;",
+                "line 6: var y = add(x, 3);",
+                "line 3 in 'add': return sum;",
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoints_can_be_listed_and_deleted() {
+        assert_eq!(
+            debug(
+                SCRIPT,
+                &["b", "b 2", "break 6", "b x", "b", "c", "d 2", "d 2", "c"]
+            ),
+            [
+                "line 1: fun add(a, b) {",
+                "No breakpoints",
+                "Breakpoint at line 2: var sum = a + b;",
+                "Breakpoint at line 6: var y = add(x, 3);",
+                "Expected a line number, got 'x'",
+                "  line 2: var sum = a + b;",
+                "  line 6: var y = add(x, 3);",
+                "line 2 in 'add': var sum = a + b;",
+                "Deleted the breakpoint at line 2",
+                "No breakpoint at line 2",
+                "line 6: var y = add(x, 3);",
+            ]
+        );
+    }
+
+    #[test]
+    fn env_and_print_show_the_variables_in_scope() {
+        assert_eq!(
+            debug(
+                SCRIPT,
+                &["b 3", "c", "c", "env", "p sum", "p x", "p nope", "bt"]
+            ),
+            [
+                "line 1: fun add(a, b) {",
+                "Breakpoint at line 3: return sum;",
+                "line 3 in 'add': return sum;",
+                "line 3 in 'add': return sum;",
+                "  [0] a = 3, b = 3, sum = 6",
+                "sum = 6",
+                "x = 3",
+                "No variable 'nope' in scope",
+                "  #0 'add' at line 3",
+                "  #1 top level at line 6",
+            ]
+        );
+        assert_eq!(
+            debug(
+                "fun f(a) {\n  {\n    var b = a + 1;\n    var c = b;\n  }\n}\nf(1);",
+                &["b 4", "c", "e", "c"]
+            ),
+            [
+                "line 1: fun f(a) {",
+                "Breakpoint at line 4: var c = b;",
+                "line 4 in 'f': var c = b;",
+                "  [0] b = 2",
+                "  [1] a = 1",
+            ]
+        );
+        assert_eq!(
+            debug(SCRIPT, &["e", "c"]),
+            [
+                "line 1: fun add(a, b) {",
+                "  No local variables; 'globals' shows the rest",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_lines_repeat_and_the_end_of_input_runs_to_the_end() {
+        assert_eq!(
+            debug(SCRIPT, &["n", "", "what"]),
+            [
+                "line 1: fun add(a, b) {",
+                "line 5: var x = add(1, 2);",
+                "line 6: var y = add(x, 3);",
+                "Unknown command 'what'; 'help' lists them",
+            ]
+        );
+        // Running out of commands forgets the breakpoints too.
+        assert_eq!(
+            debug(SCRIPT, &["b 2"]),
+            [
+                "line 1: fun add(a, b) {",
+                "Breakpoint at line 2: var sum = a + b;",
+            ]
+        );
+    }
+
+    #[test]
+    fn scripts_run_as_written() {
+        // The optimizer would drop the `if`, and with it the line to stop at.
+        assert_eq!(
+            debug("if (true) {\n  var a = 1;\n}\n", &["s", "s"]),
+            ["line 1: if (true) {", "line 2: var a = 1;"]
+        );
+    }
+}
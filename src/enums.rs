@@ -0,0 +1,214 @@
+use crate::error::RuntimeError;
+use crate::function;
+use crate::token::Token;
+use crate::value::{self, Value};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+
+/// An `enum` declaration: a closed set of named variants, each either a
+/// plain tag or a constructor taking named fields. Enums hold no values, so
+/// one is shared by every thread a variant of it reaches, which keeps
+/// variants equal across threads.
+pub struct Enum {
+    pub name: String,
+    variants: Vec<(String, Option<Vec<String>>)>,
+}
+
+/// A value of an enum: the variant's position in its enum and the values of
+/// its fields, in declaration order. Variants are immutable and compare by
+/// structure.
+pub struct Variant {
+    pub enumeration: Arc<Enum>,
+    pub index: usize,
+    pub values: Vec<Value>,
+}
+
+impl Enum {
+    /// `fields` is `None` for variants declared without parentheses.
+    pub fn new(name: String, variants: Vec<(String, Option<Vec<String>>)>) -> Self {
+        Self { name, variants }
+    }
+
+    pub fn variant_name(&self, index: usize) -> &str {
+        &self.variants[index].0
+    }
+
+    pub fn fields(&self, index: usize) -> Option<&[String]> {
+        self.variants[index].1.as_deref()
+    }
+
+    /// Reads `Enum.Name`: the variant itself if it has no fields, otherwise
+    /// its constructor.
+    pub fn get(enumeration: &Arc<Enum>, name: &Token) -> Result<Value, RuntimeError> {
+        let Some(index) = enumeration
+            .variants
+            .iter()
+            .position(|(variant, _)| variant == name.lexeme())
+        else {
+            return Err(RuntimeError::new(
+                format!(
+                    "Enum '{}' has no variant '{}'",
+                    enumeration.name,
+                    name.lexeme()
+                ),
+                name.line,
+            ));
+        };
+        Ok(match enumeration.fields(index) {
+            Some(_) => Value::Constructor(Arc::clone(enumeration), index),
+            None => variant(enumeration, index, Vec::new()),
+        })
+    }
+
+    /// Builds a variant from constructor arguments, which are matched to
+    /// fields by position and then by name.
+    pub fn construct(
+        enumeration: &Arc<Enum>,
+        index: usize,
+        positional: Vec<Value>,
+        named: Vec<(Token, Value)>,
+        line: usize,
+    ) -> Result<Value, RuntimeError> {
+        let fields = enumeration.fields(index).unwrap_or_default();
+        let variant_name = enumeration.variant_name(index);
+        if positional.len() > fields.len() {
+            return Err(function::arity_error(
+                &format!("{}({})", variant_name, fields.join(", ")),
+                &function::arguments(fields.len()),
+                &(positional.len() + named.len()).to_string(),
+                line,
+            ));
+        }
+        let mut values: Vec<Option<Value>> = positional.into_iter().map(Some).collect();
+        values.resize(fields.len(), None);
+        for (name, value) in named {
+            let Some(position) = fields.iter().position(|field| field == name.lexeme()) else {
+                return Err(RuntimeError::new(
+                    format!("'{}' has no field '{}'", variant_name, name.lexeme()),
+                    name.line,
+                ));
+            };
+            if values[position].replace(value).is_some() {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Field '{}' of '{}' given twice",
+                        name.lexeme(),
+                        variant_name
+                    ),
+                    name.line,
+                ));
+            }
+        }
+        let values = values
+            .into_iter()
+            .zip(fields)
+            .map(|(value, field)| {
+                value.ok_or_else(|| {
+                    RuntimeError::new(
+                        format!("Missing field '{}' for '{}'", field, variant_name),
+                        line,
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(variant(enumeration, index, values))
+    }
+}
+
+impl Variant {
+    pub fn name(&self) -> &str {
+        self.enumeration.variant_name(self.index)
+    }
+
+    /// Reads the field declared as `name`.
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        self.enumeration
+            .fields(self.index)
+            .and_then(|fields| fields.iter().position(|field| field == name.lexeme()))
+            .map(|position| self.values[position].clone())
+            .ok_or_else(|| {
+                RuntimeError::new(
+                    format!("'{}' has no field '{}'", self.name(), name.lexeme()),
+                    name.line,
+                )
+            })
+    }
+
+    /// Whether this is the variant `name` of the builtin `enumeration`.
+    pub fn is(&self, enumeration: &Arc<Enum>, name: &str) -> bool {
+        Arc::ptr_eq(&self.enumeration, enumeration) && self.name() == name
+    }
+}
+
+impl fmt::Debug for Enum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<enum {}>", self.name)
+    }
+}
+
+impl Drop for Variant {
+    fn drop(&mut self) {
+        value::drop_nested(std::mem::take(&mut self.values));
+    }
+}
+
+impl fmt::Debug for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}.{}>", self.enumeration.name, self.name())
+    }
+}
+
+/// The builtin `Option { Some(value), None }`, returned by builtins that
+/// may have nothing to give back.
+pub fn option() -> &'static Arc<Enum> {
+    static OPTION: OnceLock<Arc<Enum>> = OnceLock::new();
+    OPTION.get_or_init(|| {
+        Arc::new(Enum::new(
+            "Option".to_string(),
+            vec![
+                ("Some".to_string(), Some(vec!["value".to_string()])),
+                ("None".to_string(), None),
+            ],
+        ))
+    })
+}
+
+/// The builtin `Result { Ok(value), Err(error) }`, returned by builtins
+/// that may fail in an expected way.
+pub fn result() -> &'static Arc<Enum> {
+    static RESULT: OnceLock<Arc<Enum>> = OnceLock::new();
+    RESULT.get_or_init(|| {
+        Arc::new(Enum::new(
+            "Result".to_string(),
+            vec![
+                ("Ok".to_string(), Some(vec!["value".to_string()])),
+                ("Err".to_string(), Some(vec!["error".to_string()])),
+            ],
+        ))
+    })
+}
+
+fn variant(enumeration: &Arc<Enum>, index: usize, values: Vec<Value>) -> Value {
+    Value::Variant(Rc::new(Variant {
+        enumeration: Arc::clone(enumeration),
+        index,
+        values,
+    }))
+}
+
+pub fn some(value: Value) -> Value {
+    variant(option(), 0, vec![value])
+}
+
+pub fn none() -> Value {
+    variant(option(), 1, Vec::new())
+}
+
+pub fn ok(value: Value) -> Value {
+    variant(result(), 0, vec![value])
+}
+
+pub fn err(error: Value) -> Value {
+    variant(result(), 1, vec![error])
+}
@@ -1,33 +1,298 @@
+use crate::ast::{Names, Slot};
+use crate::native;
 use crate::value::Value;
 use core::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 
+/// The variables of one scope: a block, a call, an iteration of a `for`
+/// loop, a `match` arm or a method's `this`. The resolver numbers the
+/// names each scope declares, so a scope is just a row of slots, empty
+/// until the declaration runs. Top-level names live in `Globals` instead.
 pub struct Environment {
-    values: HashMap<String, Value>,
+    values: Vec<Option<Value>>,
+    /// Which slots hold constants.
+    constants: Vec<bool>,
     enclosing: Option<Rc<RefCell<Environment>>>,
+    /// The name in each slot, for the debugger and for sending closures to
+    /// other threads. Scopes made before names are known have none.
+    names: Option<Names>,
+}
+
+/// Why an assignment failed.
+pub enum AssignError {
+    Undefined,
+    Constant,
 }
 
 impl Environment {
+    /// The scope top-level code runs in. It has no slots of its own, as
+    /// everything declared at the top level is global.
     pub fn new() -> Self {
+        Self::with_slots(0)
+    }
+
+    pub fn with_slots(slots: usize) -> Self {
         Self {
-            values: HashMap::new(),
+            values: vec![None; slots],
+            constants: vec![false; slots],
             enclosing: None,
+            names: None,
         }
     }
 
-    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(Environment {
-            values: HashMap::new(),
-            enclosing: Some(enclosing),
-        }))
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>, slots: usize) -> Rc<RefCell<Self>> {
+        let mut environment = Self::with_slots(slots);
+        environment.enclosing = Some(enclosing);
+        Rc::new(RefCell::new(environment))
+    }
+
+    /// A scope with a slot for each of `names`.
+    pub fn named(enclosing: Rc<RefCell<Environment>>, names: &Names) -> Rc<RefCell<Self>> {
+        let env = Self::with_enclosing(enclosing, names.len());
+        env.borrow_mut().name_slots(Arc::clone(names));
+        env
+    }
+
+    /// Records what this scope's slots are called.
+    pub fn name_slots(&mut self, names: Names) {
+        self.names = Some(names);
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+    pub fn names(&self) -> Option<&Names> {
+        self.names.as_ref()
     }
 
-    pub fn get(&self, name: &String) -> Option<&Value> {
-        self.values.get(name)
+    pub fn slots(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn enclosing(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.enclosing.as_ref()
+    }
+
+    /// Reads a slot of this scope directly, with whether it is constant.
+    pub fn slot(&self, index: usize) -> Option<(Value, bool)> {
+        let value = self.values[index].clone()?;
+        Some((value, self.constants[index]))
+    }
+
+    /// Binds slot `index` of this scope, replacing any existing binding.
+    /// Used for parameters, loop variables and pattern bindings, which
+    /// can't clash with a constant.
+    pub fn define(&mut self, index: usize, value: Value) {
+        self.values[index] = Some(value);
+        self.constants[index] = false;
+    }
+
+    /// Binds a slot for a `var`, `const`, `fun` or `class` declaration.
+    /// Redeclaring a variable in the same scope replaces it, but a constant
+    /// can't be redeclared, in which case this returns `false`.
+    pub fn declare(&mut self, index: usize, value: Value, constant: bool) -> bool {
+        if self.values[index].is_some() && self.constants[index] {
+            return false;
+        }
+        self.values[index] = Some(value);
+        self.constants[index] = constant;
+        true
+    }
+
+    /// Reads the variable at `slot`: the first of its local slots that is
+    /// bound, or else the global.
+    pub fn get(env: &Rc<RefCell<Environment>>, slot: &Slot) -> Option<Value> {
+        for &(depth, index) in &slot.locals {
+            let value = match depth {
+                0 => env.borrow().values[index].clone(),
+                _ => Self::ancestor(env, depth).borrow().values[index].clone(),
+            };
+            if value.is_some() {
+                return value;
+            }
+        }
+        Globals::get(slot.global)
+    }
+
+    /// Rebinds an existing variable in the nearest scope that declares it,
+    /// failing if none does or if it is a constant there.
+    pub fn assign(
+        env: &Rc<RefCell<Environment>>,
+        slot: &Slot,
+        value: Value,
+    ) -> Result<(), AssignError> {
+        for &(depth, index) in &slot.locals {
+            let scope = match depth {
+                0 => Rc::clone(env),
+                _ => Self::ancestor(env, depth),
+            };
+            let mut scope = scope.borrow_mut();
+            if scope.values[index].is_none() {
+                continue;
+            }
+            if scope.constants[index] {
+                return Err(AssignError::Constant);
+            }
+            scope.values[index] = Some(value);
+            return Ok(());
+        }
+        Globals::assign(slot.global, value)
+    }
+
+    /// Declares the variable at `slot` in the innermost scope of `env`, or
+    /// as a global at the top level.
+    pub fn declare_at(
+        env: &Rc<RefCell<Environment>>,
+        slot: &Slot,
+        value: Value,
+        constant: bool,
+    ) -> bool {
+        match slot.locals.first() {
+            Some(&(_, index)) => env.borrow_mut().declare(index, value, constant),
+            None => Globals::declare(slot.global, value, constant),
+        }
+    }
+
+    /// The scope `depth` levels out from `env`.
+    fn ancestor(env: &Rc<RefCell<Environment>>, depth: usize) -> Rc<RefCell<Environment>> {
+        let mut scope = Rc::clone(env);
+        for _ in 0..depth {
+            let enclosing = Rc::clone(
+                scope
+                    .borrow()
+                    .enclosing
+                    .as_ref()
+                    .expect("resolved scope depth exceeds the scope chain"),
+            );
+            scope = enclosing;
+        }
+        scope
     }
 }
+
+/// The variables declared at the top level, builtins included. Each
+/// thread has its own, indexed by the numbers `global_index` gives names,
+/// which are the same on every thread so code resolved on one can run on
+/// another.
+pub struct Globals {
+    values: Vec<Option<Value>>,
+    constants: Vec<bool>,
+    /// Which globals are builtins rather than declared by the script.
+    builtins: Vec<bool>,
+}
+
+thread_local! {
+    static GLOBALS: RefCell<Globals> = RefCell::new(Globals::with_builtins());
+}
+
+impl Globals {
+    fn with_builtins() -> Self {
+        let mut globals = Self {
+            values: Vec::new(),
+            constants: Vec::new(),
+            builtins: Vec::new(),
+        };
+        native::define_globals(&mut globals);
+        globals.builtins = globals.values.iter().map(Option::is_some).collect();
+        globals
+    }
+
+    /// Binds the global `name`, replacing any existing binding.
+    pub fn define(&mut self, name: &str, value: Value) {
+        let index = global_index(name);
+        self.grow(index);
+        self.values[index] = Some(value);
+        self.constants[index] = false;
+    }
+
+    fn grow(&mut self, index: usize) {
+        if index >= self.values.len() {
+            self.values.resize(index + 1, None);
+            self.constants.resize(index + 1, false);
+        }
+    }
+
+    pub fn get(index: usize) -> Option<Value> {
+        GLOBALS.with(|globals| globals.borrow().values.get(index).cloned().flatten())
+    }
+
+    /// The global `name`, without numbering it if there is none.
+    pub fn lookup(name: &str) -> Option<Value> {
+        let index = *indices().get(name)?;
+        Self::get(index)
+    }
+
+    /// The globals the script has declared so far, builtins left out, in
+    /// the order their names were first seen.
+    pub fn declared() -> Vec<(String, Value)> {
+        let mut names: Vec<(String, usize)> = indices()
+            .iter()
+            .map(|(name, &index)| (name.clone(), index))
+            .collect();
+        names.sort_by_key(|&(_, index)| index);
+        GLOBALS.with(|globals| {
+            let globals = globals.borrow();
+            names
+                .into_iter()
+                .filter(|&(_, index)| !globals.builtins.get(index).copied().unwrap_or(false))
+                .filter_map(|(name, index)| Some((name, globals.values.get(index)?.clone()?)))
+                .collect()
+        })
+    }
+
+    /// Whether the global `index` is bound to a constant.
+    pub fn is_constant(index: usize) -> bool {
+        GLOBALS.with(|globals| {
+            globals
+                .borrow()
+                .constants
+                .get(index)
+                .copied()
+                .unwrap_or(false)
+        })
+    }
+
+    pub fn assign(index: usize, value: Value) -> Result<(), AssignError> {
+        GLOBALS.with(|globals| {
+            let mut globals = globals.borrow_mut();
+            match globals.values.get(index) {
+                Some(Some(_)) if globals.constants[index] => Err(AssignError::Constant),
+                Some(Some(_)) => {
+                    globals.values[index] = Some(value);
+                    Ok(())
+                }
+                _ => Err(AssignError::Undefined),
+            }
+        })
+    }
+
+    /// Binds a global for a declaration, which fails if it is already a
+    /// constant.
+    pub fn declare(index: usize, value: Value, constant: bool) -> bool {
+        GLOBALS.with(|globals| {
+            let mut globals = globals.borrow_mut();
+            globals.grow(index);
+            if globals.values[index].is_some() && globals.constants[index] {
+                return false;
+            }
+            globals.values[index] = Some(value);
+            globals.constants[index] = constant;
+            true
+        })
+    }
+}
+
+/// The index of the global `name`, numbering it if it's new. Numbers are
+/// shared by all threads.
+pub fn global_index(name: &str) -> usize {
+    let mut indices = indices();
+    let next = indices.len();
+    *indices.entry(name.to_string()).or_insert(next)
+}
+
+fn indices() -> MutexGuard<'static, HashMap<String, usize>> {
+    static INDICES: OnceLock<Mutex<HashMap<String, usize>>> = OnceLock::new();
+    INDICES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+}
@@ -2,6 +2,7 @@ use std::error::Error;
 use std::fmt;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum InterpreterError {
     LexerError(LexerError),
     ParserError(ParserError),
@@ -29,6 +30,36 @@ impl fmt::Display for LexerError {
 
 impl Error for LexerError {}
 
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpreterError::LexerError(error) => write!(f, "{}", error),
+            InterpreterError::ParserError(error) => write!(f, "{}", error),
+            InterpreterError::RuntimeError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for InterpreterError {}
+
+impl From<LexerError> for InterpreterError {
+    fn from(error: LexerError) -> Self {
+        InterpreterError::LexerError(error)
+    }
+}
+
+impl From<ParserError> for InterpreterError {
+    fn from(error: ParserError) -> Self {
+        InterpreterError::ParserError(error)
+    }
+}
+
+impl From<RuntimeError> for InterpreterError {
+    fn from(error: RuntimeError) -> Self {
+        InterpreterError::RuntimeError(error)
+    }
+}
+
 #[derive(Debug)]
 pub struct ParserError {
     pub message: String,
@@ -41,14 +72,73 @@ impl ParserError {
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+impl Error for ParserError {}
+
+/// What went wrong, for errors that callers treat differently from an
+/// ordinary failure. Each resource limit a script runs under has its own,
+/// as do using a capability the script wasn't given and being interrupted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Error,
+    StepLimit,
+    Timeout,
+    StackOverflow,
+    MemoryLimit,
+    PermissionDenied,
+    Interrupted,
+}
+
+impl ErrorKind {
+    /// Whether `try` may turn the error into a value. Running out of steps
+    /// or time, or being interrupted, ends the whole run, since the script
+    /// could otherwise catch the error and carry on.
+    pub fn is_catchable(self) -> bool {
+        !matches!(
+            self,
+            ErrorKind::StepLimit | ErrorKind::Timeout | ErrorKind::Interrupted
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct RuntimeError {
     pub message: String,
     pub line: usize,
+    pub kind: ErrorKind,
+    /// For errors that ended an async task, the chain of tasks that led to
+    /// it, innermost first.
+    pub trace: Vec<String>,
 }
 
 impl RuntimeError {
     pub fn new(message: String, line: usize) -> Self {
-        Self { message, line }
+        Self::with_kind(ErrorKind::Error, message, line)
+    }
+
+    pub fn with_kind(kind: ErrorKind, message: String, line: usize) -> Self {
+        Self {
+            message,
+            line,
+            kind,
+            trace: Vec::new(),
+        }
     }
 }
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)?;
+        for frame in &self.trace {
+            write!(f, "\n    {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
@@ -0,0 +1,624 @@
+use crate::asyncio::Completed;
+use crate::error::RuntimeError;
+use crate::generator::{Generator, Resumed};
+use crate::interpreter::call;
+use crate::interrupt;
+use crate::limits;
+use crate::value::Value;
+use core::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+type Outcome = Result<Value, RuntimeError>;
+type Callback = Box<dyn FnOnce(&Outcome)>;
+
+/// The eventual result of an async task, timer or IO operation. Awaiting a
+/// future suspends the awaiting task until it settles.
+pub struct Future {
+    name: String,
+    state: RefCell<Option<Outcome>>,
+    callbacks: RefCell<Vec<Callback>>,
+    /// Whether anything has waited on the future, so that a failure
+    /// doesn't go unnoticed.
+    handled: Cell<bool>,
+    /// How many of the callbacks still want the outcome. `race` loses
+    /// interest in the futures that didn't win, and `gather` in the rest
+    /// once one fails.
+    interest: Cell<usize>,
+}
+
+impl Future {
+    pub fn new(name: String) -> Rc<Future> {
+        Rc::new(Future {
+            name,
+            state: RefCell::new(None),
+            callbacks: RefCell::new(Vec::new()),
+            handled: Cell::new(false),
+            interest: Cell::new(0),
+        })
+    }
+
+    /// Runs `callback` with the outcome once the future settles, or right
+    /// away if it already has.
+    fn then(&self, callback: Callback) {
+        self.handled.set(true);
+        let state = self.state.borrow();
+        match &*state {
+            Some(outcome) => callback(outcome),
+            None => {
+                drop(state);
+                self.interest.set(self.interest.get() + 1);
+                self.callbacks.borrow_mut().push(callback);
+            }
+        }
+    }
+
+    /// Takes back the interest of one callback, which will do nothing with
+    /// the outcome after all. A failure nothing wants goes unnoticed, so it
+    /// is reported like one nothing waited on.
+    fn forget(&self) {
+        if self.state.borrow().is_none() {
+            self.interest.set(self.interest.get() - 1);
+            if self.interest.get() == 0 {
+                self.handled.set(false);
+            }
+        }
+    }
+
+    /// Whether settling the future would run a callback that still wants
+    /// the outcome.
+    fn is_wanted(&self) -> bool {
+        self.interest.get() > 0
+    }
+
+    /// Settles the future unless it already has. A failure nobody is
+    /// waiting for is remembered, to be reported if nobody ever does.
+    fn settle(self: &Rc<Self>, outcome: Outcome) {
+        if self.state.borrow().is_some() {
+            return;
+        }
+        if outcome.is_err() && !self.handled.get() {
+            with_loop(|event_loop| event_loop.unhandled.push(Rc::clone(self)));
+        }
+        *self.state.borrow_mut() = Some(outcome);
+        let callbacks = std::mem::take(&mut *self.callbacks.borrow_mut());
+        let state = self.state.borrow();
+        let outcome = state.as_ref().expect("just settled");
+        for callback in callbacks {
+            callback(outcome);
+        }
+    }
+
+    /// The outcome, if the future has settled.
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.state.borrow().clone()
+    }
+}
+
+impl fmt::Debug for Future {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<future {}>", self.name)
+    }
+}
+
+/// A running call to an async function.
+struct Task {
+    coroutine: RefCell<Generator>,
+    future: Rc<Future>,
+    /// Where the task and each task that started it were started,
+    /// innermost first, for reporting the errors it fails with.
+    trace: Vec<String>,
+    line: usize,
+}
+
+impl Task {
+    fn fail(&self, mut error: RuntimeError) {
+        if error.trace.is_empty() {
+            error.trace = self.trace.clone();
+        }
+        self.future.settle(Err(error));
+    }
+}
+
+enum Job {
+    /// Resumes a task, making its pending `await` evaluate to the value.
+    Resume(Rc<Task>, Value),
+    /// Calls a timer's callback and settles its future with the result.
+    Call {
+        callback: Value,
+        future: Rc<Future>,
+        line: usize,
+    },
+}
+
+impl Job {
+    fn line(&self) -> usize {
+        match self {
+            Job::Resume(task, _) => task.line,
+            Job::Call { line, .. } => *line,
+        }
+    }
+}
+
+struct Timer {
+    deadline: Instant,
+    /// Where the timer was set.
+    line: usize,
+    /// Breaks ties between equal deadlines in the order timers were set.
+    sequence: u64,
+    job: TimerJob,
+}
+
+enum TimerJob {
+    Resolve(Rc<Future>),
+    Call(Value, Rc<Future>, usize),
+}
+
+/// Reversed so that `BinaryHeap` pops the earliest deadline first.
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.sequence).cmp(&(self.deadline, self.sequence))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+impl Timer {
+    /// Whether firing the timer would run something: a callback, or a
+    /// task waiting on `sleep`.
+    fn is_wanted(&self) -> bool {
+        match &self.job {
+            TimerJob::Resolve(future) => future.is_wanted(),
+            TimerJob::Call(..) => true,
+        }
+    }
+}
+
+/// A single-threaded cooperative scheduler. Tasks run one at a time until
+/// they `await` something unsettled; timers and blocking IO (done on helper
+/// threads) settle futures, which queues the tasks waiting on them. Each
+/// interpreter thread has its own loop.
+struct EventLoop {
+    ready: VecDeque<Job>,
+    timers: BinaryHeap<Timer>,
+    next_sequence: u64,
+    /// IO operations in flight, by id, with the line that started them.
+    io: HashMap<u64, (Rc<Future>, usize)>,
+    next_io: u64,
+    completions: Sender<(u64, Result<Completed, String>)>,
+    completed: Receiver<(u64, Result<Completed, String>)>,
+    /// Futures that failed with nobody waiting for them.
+    unhandled: Vec<Rc<Future>>,
+    /// The task currently running, parent of any task it starts.
+    current: Option<Rc<Task>>,
+}
+
+impl EventLoop {
+    fn new() -> Self {
+        let (completions, completed) = mpsc::channel();
+        Self {
+            ready: VecDeque::new(),
+            timers: BinaryHeap::new(),
+            next_sequence: 0,
+            io: HashMap::new(),
+            next_io: 0,
+            completions,
+            completed,
+            unhandled: Vec::new(),
+            current: None,
+        }
+    }
+
+    fn add_timer(&mut self, delay: Duration, job: TimerJob, line: usize) {
+        self.timers.push(Timer {
+            deadline: Instant::now() + delay,
+            line,
+            sequence: self.next_sequence,
+            job,
+        });
+        self.next_sequence += 1;
+    }
+}
+
+thread_local! {
+    static EVENT_LOOP: RefCell<EventLoop> = RefCell::new(EventLoop::new());
+}
+
+/// Borrows this thread's loop. Never held while interpreter code runs, so
+/// that code can schedule more work.
+fn with_loop<T>(f: impl FnOnce(&mut EventLoop) -> T) -> T {
+    EVENT_LOOP.with(|event_loop| f(&mut event_loop.borrow_mut()))
+}
+
+/// Starts running an async function's body as a task, returning the future
+/// of its result. The task first runs once the loop gets to it, so its
+/// caller always carries on first.
+pub fn start_task(coroutine: Generator, line: usize) -> Rc<Future> {
+    let frame = format!("in task '{}' started at line {}", coroutine.name(), line);
+    let future = Future::new(format!("task {}", coroutine.name()));
+    let mut trace = vec![frame];
+    if let Some(parent) = with_loop(|event_loop| event_loop.current.clone()) {
+        trace.extend(parent.trace.iter().cloned());
+    }
+    let task = Rc::new(Task {
+        coroutine: RefCell::new(coroutine),
+        future: Rc::clone(&future),
+        trace,
+        line,
+    });
+    with_loop(|event_loop| event_loop.ready.push_back(Job::Resume(task, Value::Nil)));
+    future
+}
+
+/// A future that resolves to `nil` after `delay`.
+pub fn sleep(delay: Duration, line: usize) -> Rc<Future> {
+    let future = Future::new("sleep".to_string());
+    with_loop(|event_loop| {
+        event_loop.add_timer(delay, TimerJob::Resolve(Rc::clone(&future)), line)
+    });
+    future
+}
+
+/// Calls `callback` after `delay`, returning a future of its result.
+pub fn set_timeout(callback: Value, delay: Duration, line: usize) -> Rc<Future> {
+    let future = Future::new("timeout".to_string());
+    with_loop(|event_loop| {
+        event_loop.add_timer(
+            delay,
+            TimerJob::Call(callback, Rc::clone(&future), line),
+            line,
+        )
+    });
+    future
+}
+
+/// The futures `gather` or `race` waits on, held weakly so that they can
+/// be forgotten once the combined future settles.
+type Entrants = Rc<RefCell<Vec<Weak<Future>>>>;
+
+/// Settles `future` with `outcome` and loses interest in `entrants`, whose
+/// outcomes no longer matter.
+fn settle_early(future: &Rc<Future>, outcome: Outcome, entrants: &Entrants) {
+    if future.outcome().is_some() {
+        return;
+    }
+    future.settle(outcome);
+    for entrant in entrants.borrow().iter().filter_map(Weak::upgrade) {
+        entrant.forget();
+    }
+}
+
+/// Calls `callback` once `item` settles, as one of `entrants`, unless the
+/// combined `future` already has.
+fn enter(future: &Rc<Future>, item: Value, entrants: &Entrants, callback: Callback) {
+    if future.outcome().is_some() {
+        return;
+    }
+    if let Value::Future(item) = &item {
+        entrants.borrow_mut().push(Rc::downgrade(item));
+    }
+    when_settled(item, callback);
+}
+
+/// A future of the list of results of `items`, once all of them settle,
+/// or of the first failure. Items that aren't futures stand for themselves.
+pub fn gather(items: Vec<Value>) -> Rc<Future> {
+    let future = Future::new("gather".to_string());
+    let results = Rc::new(RefCell::new(vec![Value::Nil; items.len()]));
+    let remaining = Rc::new(Cell::new(items.len()));
+    let entrants = Entrants::default();
+    if items.is_empty() {
+        future.settle(Ok(Value::list(Vec::new())));
+    }
+    for (position, item) in items.into_iter().enumerate() {
+        let (gathered, results, remaining, failed) = (
+            Rc::clone(&future),
+            Rc::clone(&results),
+            Rc::clone(&remaining),
+            Rc::clone(&entrants),
+        );
+        let callback = Box::new(move |outcome: &Outcome| match outcome {
+            Ok(value) => {
+                results.borrow_mut()[position] = value.clone();
+                remaining.set(remaining.get() - 1);
+                if remaining.get() == 0 {
+                    gathered.settle(Ok(Value::list(results.take())));
+                }
+            }
+            Err(error) => settle_early(&gathered, Err(error.clone()), &failed),
+        });
+        enter(&future, item, &entrants, callback);
+    }
+    future
+}
+
+/// A future that settles like whichever of `items` settles first. The
+/// others are forgotten, so that the loop doesn't wait on them for nothing.
+pub fn race(items: Vec<Value>) -> Rc<Future> {
+    let future = Future::new("race".to_string());
+    let entrants = Entrants::default();
+    for item in items {
+        let (raced, losers) = (Rc::clone(&future), Rc::clone(&entrants));
+        let callback =
+            Box::new(move |outcome: &Outcome| settle_early(&raced, outcome.clone(), &losers));
+        enter(&future, item, &entrants, callback);
+    }
+    future
+}
+
+fn when_settled(value: Value, callback: Callback) {
+    match value {
+        Value::Future(future) => future.then(callback),
+        value => callback(&Ok(value)),
+    }
+}
+
+/// Runs `operation` on a helper thread, returning a future of its result.
+pub fn start_io(
+    name: &str,
+    operation: impl FnOnce() -> Result<Completed, String> + Send + 'static,
+    line: usize,
+) -> Rc<Future> {
+    let future = Future::new(name.to_string());
+    let (id, completions) = with_loop(|event_loop| {
+        let id = event_loop.next_io;
+        event_loop.next_io += 1;
+        event_loop.io.insert(id, (Rc::clone(&future), line));
+        (id, event_loop.completions.clone())
+    });
+    std::thread::spawn(move || {
+        // The loop only goes away with its thread, and then nobody is
+        // waiting for the result anyway.
+        let _ = completions.send((id, operation()));
+    });
+    future
+}
+
+/// Runs tasks, timers and IO until there is nothing left to wait for, and
+/// returns the errors of tasks and operations that failed unobserved. An
+/// interrupt drops whatever is left to do. Timers and IO whose outcome
+/// nothing wants any more, like the losers of a `race`, aren't waited for;
+/// an IO operation left behind carries on, on its helper thread, and
+/// settles its future if a later run of the loop wants it again.
+pub fn run() -> Vec<RuntimeError> {
+    let mut errors = Vec::new();
+    loop {
+        while let Some(job) = with_loop(|event_loop| event_loop.ready.pop_front()) {
+            if let Err(error) = interrupt::check(job.line()) {
+                errors.push(error);
+                abandon();
+                break;
+            }
+            run_job(job);
+        }
+        let (deadline, waiting_for_io, wanted) = with_loop(|event_loop| {
+            (
+                event_loop.timers.peek().map(|timer| timer.deadline),
+                !event_loop.io.is_empty(),
+                event_loop.timers.iter().any(Timer::is_wanted)
+                    || event_loop.io.values().any(|(future, _)| future.is_wanted()),
+            )
+        });
+        if !wanted {
+            break;
+        }
+        // Waits are cut short to notice interrupts and timeouts in good
+        // time.
+        let wait_until = |deadline: Option<Instant>| {
+            let poll = Instant::now() + interrupt::POLL;
+            deadline.map_or(poll, |deadline| deadline.min(poll))
+        };
+        if waiting_for_io {
+            let completion = with_loop(|event_loop| {
+                event_loop
+                    .completed
+                    .recv_timeout(wait_until(deadline).saturating_duration_since(Instant::now()))
+                    .ok()
+            });
+            if let Some(completion) = completion {
+                complete_io(completion);
+            }
+        } else {
+            std::thread::sleep(wait_until(deadline).saturating_duration_since(Instant::now()));
+        }
+        if let Err(error) = limits::check_blocked(waiting_line()) {
+            errors.push(error);
+            abandon();
+            break;
+        }
+        fire_timers();
+    }
+    let unhandled = with_loop(|event_loop| std::mem::take(&mut event_loop.unhandled))
+        .into_iter()
+        .filter(|future| !future.handled.get())
+        .filter_map(|future| future.outcome()?.err());
+    unhandled.chain(errors).collect()
+}
+
+/// Drops every task, timer and IO operation still to come. Their futures
+/// never settle.
+fn abandon() {
+    with_loop(|event_loop| {
+        event_loop.ready.clear();
+        event_loop.timers.clear();
+        event_loop.io.clear();
+    });
+}
+
+/// The line of something the loop is waiting for: the next timer, or else
+/// an IO operation.
+fn waiting_line() -> usize {
+    with_loop(|event_loop| match event_loop.timers.peek() {
+        Some(timer) => timer.line,
+        None => event_loop
+            .io
+            .values()
+            .map(|(_, line)| *line)
+            .min()
+            .unwrap_or(0),
+    })
+}
+
+fn run_job(job: Job) {
+    match job {
+        Job::Resume(task, value) => {
+            let parent = with_loop(|event_loop| event_loop.current.replace(Rc::clone(&task)));
+            let resumed = task.coroutine.borrow_mut().resume(value, task.line);
+            with_loop(|event_loop| event_loop.current = parent);
+            match resumed {
+                Ok(Resumed::Yielded(awaited)) => {
+                    let waiting = Rc::clone(&task);
+                    when_settled(
+                        awaited,
+                        Box::new(move |outcome| match outcome {
+                            Ok(value) => with_loop(|event_loop| {
+                                event_loop
+                                    .ready
+                                    .push_back(Job::Resume(waiting, value.clone()))
+                            }),
+                            Err(error) => waiting.fail(error.clone()),
+                        }),
+                    );
+                }
+                Ok(Resumed::Returned(value)) => task.future.settle(Ok(value)),
+                Err(error) => task.fail(error),
+            }
+        }
+        Job::Call {
+            callback,
+            future,
+            line,
+        } => match call(&callback, Vec::new(), line) {
+            Ok(result) => when_settled(
+                result,
+                Box::new(move |outcome| future.settle(outcome.clone())),
+            ),
+            Err(error) => future.settle(Err(error)),
+        },
+    }
+}
+
+fn fire_timers() {
+    let now = Instant::now();
+    while let Some(timer) = with_loop(|event_loop| {
+        if event_loop.timers.peek()?.deadline <= now {
+            event_loop.timers.pop()
+        } else {
+            None
+        }
+    }) {
+        match timer.job {
+            TimerJob::Resolve(future) => future.settle(Ok(Value::Nil)),
+            TimerJob::Call(callback, future, line) => with_loop(|event_loop| {
+                event_loop.ready.push_back(Job::Call {
+                    callback,
+                    future,
+                    line,
+                })
+            }),
+        }
+    }
+}
+
+fn complete_io((id, result): (u64, Result<Completed, String>)) {
+    let Some((future, line)) = with_loop(|event_loop| event_loop.io.remove(&id)) else {
+        return;
+    };
+    future.settle(
+        result
+            .map_err(|message| RuntimeError::new(message, line))
+            .and_then(|completed| {
+                if let Completed::Text(text) = &completed {
+                    limits::check_string(text.len(), line)?;
+                }
+                Ok(completed.into_value())
+            }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    const DOUBLE: &str = "async fun double(x, ms) { await sleep(ms); return x * 2; }\n";
+
+    #[test]
+    fn gather_collects_results_in_order() {
+        let source = "async fun main() { return await gather([double(1, 20), double(2, 5), 3]); }
+            main();";
+        assert_eq!(
+            eval(&format!("{DOUBLE}{source}")),
+            Ok("[2, 4, 3]".to_string())
+        );
+        assert_eq!(
+            eval("async fun main() { return await gather([]); } main();"),
+            Ok("[]".to_string())
+        );
+    }
+
+    #[test]
+    fn gather_fails_with_the_first_failure() {
+        let source = "async fun boom() { await sleep(5); return nil + 1; }
+            async fun main() { return await gather([sleep(10000), boom()]); }
+            main();";
+        let error = eval(source).unwrap_err();
+        assert!(error.starts_with("Operands must be"), "{error}");
+    }
+
+    #[test]
+    fn race_settles_like_the_first_to_settle() {
+        let source = "async fun main() { return await race([sleep(10000), double(5, 5)]); }
+            main();";
+        assert_eq!(eval(&format!("{DOUBLE}{source}")), Ok("10".to_string()));
+        let source = "async fun main() { return await race([double(1, 5), \"now\"]); } main();";
+        assert_eq!(eval(&format!("{DOUBLE}{source}")), Ok("now".to_string()));
+    }
+
+    #[test]
+    fn the_losers_of_a_race_are_not_waited_for() {
+        let source = "var server = listen(\"127.0.0.1:0\");
+            async fun main() { return await race([accept(server), sleep(10)]); }
+            main();";
+        assert_eq!(eval(source), Ok("nil".to_string()));
+    }
+
+    #[test]
+    fn timers_fire_by_deadline_then_in_order_of_scheduling() {
+        let source = "var order = 0;
+            set_timeout(() => order = order * 10 + 2, 20);
+            set_timeout(() => order = order * 10 + 1, 10);
+            set_timeout(() => order = order * 10 + 3, 20);
+            async fun main() { await sleep(40); return order; }
+            main();";
+        assert_eq!(eval(source), Ok("123".to_string()));
+    }
+
+    #[test]
+    fn unhandled_failures_are_reported() {
+        let error = eval("async fun boom() { return nil + 1; } boom(); 1;").unwrap_err();
+        assert!(error.starts_with("Operands must be"), "{error}");
+        let error = eval("set_timeout(() => nil + 1, 5); 1;").unwrap_err();
+        assert!(error.starts_with("Operands must be"), "{error}");
+        let source = "async fun boom() { return nil + 1; }
+            async fun main() { var failed = boom(); return await race([failed, 1]); }
+            main();";
+        let error = eval(source).unwrap_err();
+        assert!(error.starts_with("Operands must be"), "{error}");
+    }
+}
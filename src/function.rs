@@ -0,0 +1,237 @@
+use crate::ast::{execute_block, Flow, FunctionDecl, Names};
+use crate::debug;
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::event_loop;
+use crate::generator::Generator;
+use crate::iterator::Iter;
+use crate::limits;
+use crate::token::Token;
+use crate::value::Value;
+use core::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+
+/// A user-defined function together with the environment it closes over.
+pub struct Function {
+    pub declaration: Arc<FunctionDecl>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl Function {
+    pub fn new(
+        declaration: Arc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.name.lexeme()
+    }
+
+    /// Returns a copy of this method whose closure binds `this` to
+    /// `instance`, in the one slot of a scope of its own. Other functions,
+    /// like one stored in a static field, are returned as they are.
+    pub fn bind(&self, instance: Value) -> Function {
+        let env = if self.declaration.is_method {
+            let env = Environment::with_enclosing(Rc::clone(&self.closure), 1);
+            env.borrow_mut().define(0, instance);
+            env.borrow_mut().name_slots(this_names());
+            env
+        } else {
+            Rc::clone(&self.closure)
+        };
+        Function::new(Arc::clone(&self.declaration), env, self.is_initializer)
+    }
+
+    pub fn call(
+        &self,
+        positional: Vec<Value>,
+        named: Vec<(Token, Value)>,
+        line: usize,
+    ) -> Result<Value, RuntimeError> {
+        let _call = limits::enter(line)?;
+        let _entered = debug::enter(self.name(), line);
+        let env = Environment::named(Rc::clone(&self.closure), &self.declaration.names);
+        self.bind_arguments(&env, positional, named, line)?;
+        if self.declaration.is_async {
+            let coroutine = Generator::new(Arc::clone(&self.declaration), env);
+            return Ok(Value::Future(event_loop::start_task(coroutine, line)));
+        }
+        if self.declaration.is_generator {
+            let generator = Generator::new(Arc::clone(&self.declaration), env);
+            return Ok(Value::Iterator(Rc::new(RefCell::new(Iter::Generator(
+                generator,
+            )))));
+        }
+
+        let flow = execute_block(&self.declaration.body, env)?;
+        if self.is_initializer {
+            let this = self.closure.borrow().slot(0);
+            return Ok(this.map_or(Value::Nil, |(this, _)| this));
+        }
+        match flow {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    /// Defines each parameter in `env`. Positional arguments fill parameters
+    /// in order with any surplus going to the rest parameter, named
+    /// arguments fill the parameter of that name, and parameters left over
+    /// take their default, evaluated now so it can see earlier parameters.
+    fn bind_arguments(
+        &self,
+        env: &Rc<RefCell<Environment>>,
+        positional: Vec<Value>,
+        named: Vec<(Token, Value)>,
+        line: usize,
+    ) -> Result<(), RuntimeError> {
+        let params = &self.declaration.params;
+        let provided = positional.len() + named.len();
+        let mut slots: Vec<Option<Value>> = vec![None; params.len()];
+
+        let mut positional = positional.into_iter();
+        for (slot, value) in slots.iter_mut().zip(positional.by_ref()) {
+            *slot = Some(value);
+        }
+        let surplus: Vec<Value> = positional.collect();
+        if !surplus.is_empty() && self.declaration.rest.is_none() {
+            return Err(self.arity_error(&provided.to_string(), line));
+        }
+
+        for (name, value) in named {
+            match params
+                .iter()
+                .position(|param| param.name.lexeme() == name.lexeme())
+            {
+                Some(i) if slots[i].is_some() => {
+                    return Err(self.arity_error(&format!("'{}' twice", name.lexeme()), name.line))
+                }
+                Some(i) => slots[i] = Some(value),
+                None => {
+                    return Err(RuntimeError::new(
+                        format!(
+                            "{} has no parameter '{}'",
+                            self.declaration.signature(),
+                            name.lexeme()
+                        ),
+                        name.line,
+                    ))
+                }
+            }
+        }
+
+        for (index, (param, slot)) in params.iter().zip(slots).enumerate() {
+            let value = match (slot, &param.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.evaluate(env)?,
+                (None, None) => {
+                    return Err(self.arity_error(
+                        &format!("{}, missing '{}'", provided, param.name.lexeme()),
+                        line,
+                    ))
+                }
+            };
+            env.borrow_mut().define(index, value);
+        }
+        if self.declaration.rest.is_some() {
+            env.borrow_mut().define(params.len(), Value::list(surplus));
+        }
+        Ok(())
+    }
+
+    /// Reports a call that doesn't fit the parameters, as in
+    /// "f(a, b = 1) takes 1 to 2 arguments; got 3".
+    fn arity_error(&self, got: &str, line: usize) -> RuntimeError {
+        let params = &self.declaration.params;
+        let required = params
+            .iter()
+            .filter(|param| param.default.is_none())
+            .count();
+        let expected = match (required, params.len(), &self.declaration.rest) {
+            (min, _, Some(_)) => format!("at least {}", arguments(min)),
+            (min, max, None) if min == max => arguments(min),
+            (min, max, None) => format!("{} to {} arguments", min, max),
+        };
+        arity_error(&self.declaration.signature(), &expected, got, line)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// A number of arguments, such as "1 argument" or "2 arguments".
+pub fn arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        count => format!("{} arguments", count),
+    }
+}
+
+/// The error for a call to `callee` with arguments it doesn't take.
+pub fn arity_error(callee: &str, expected: &str, got: &str, line: usize) -> RuntimeError {
+    RuntimeError::new(format!("{} takes {}; got {}", callee, expected, got), line)
+}
+
+/// A builtin implemented in Rust. `function` receives the evaluated
+/// arguments and the line of the call for error reporting.
+#[derive(Clone, Debug)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&[Value], usize) -> Result<Value, RuntimeError>,
+}
+
+/// The names in the scope binding a method's `this`, shared by every such
+/// scope.
+fn this_names() -> Names {
+    static NAMES: OnceLock<Names> = OnceLock::new();
+    Arc::clone(NAMES.get_or_init(|| Arc::from([Arc::from("this")])))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    #[test]
+    fn arity_errors_show_the_parameters_and_what_was_given() {
+        let functions =
+            "fun f(a) {} fun g(a, b = 1) {} fun h(a, ...r) {} fun k(a, b = a * 2, c = \"x\") {} class P {} enum E { A(x, y) }";
+        for (call, error) in [
+            ("f(1, 2)", "f(a) takes 1 argument; got 2"),
+            ("f(1, a: 2)", "f(a) takes 1 argument; got 'a' twice"),
+            ("f(b: 2)", "f(a) has no parameter 'b'"),
+            ("f()", "f(a) takes 1 argument; got 0, missing 'a'"),
+            ("g(1, 2, 3)", "g(a, b = 1) takes 1 to 2 arguments; got 3"),
+            (
+                "h()",
+                "h(a, ...r) takes at least 1 argument; got 0, missing 'a'",
+            ),
+            (
+                "k()",
+                "k(a, b = …, c = \"x\") takes 1 to 3 arguments; got 0, missing 'a'",
+            ),
+            ("k(1, d: 2)", "k(a, b = …, c = \"x\") has no parameter 'd'"),
+            ("len(1, 2)", "len() takes 1 argument; got 2"),
+            ("P(1)", "P() takes 0 arguments; got 1"),
+            ("E.A(1, 2, 3)", "A(x, y) takes 2 arguments; got 3"),
+        ] {
+            assert_eq!(
+                eval(&format!("{} {};", functions, call)),
+                Err(format!("{} at line 1", error))
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::ast::{
+    bind_loop_variables, block_scope, for_in_iterator, Flow, FunctionDecl, Stmt, YieldTarget,
+};
+use crate::debug;
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::interpreter::{assign, declare, show};
+use crate::iterator::Iter;
+use crate::limits;
+use crate::token::Token;
+use crate::value::Value;
+use core::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// One step on the path from a generator's body down to the `yield` it is
+/// suspended at. Frames record only positions and runtime state; the
+/// statements themselves are found again by walking the body on resume.
+enum Frame {
+    /// At statement `index` of a block whose scope is `env`.
+    Block {
+        index: usize,
+        env: Rc<RefCell<Environment>>,
+    },
+    /// Inside the `then` or `else` branch of an `if`.
+    If { then_branch: bool },
+    /// Inside the body of a `while`.
+    While,
+    /// Inside the body of a `for in`, in the scope `env` of the current item.
+    ForIn {
+        iter: Iter,
+        env: Rc<RefCell<Environment>>,
+    },
+    /// At the `yield` or `await` itself, waiting for the value it evaluates
+    /// to.
+    Yield,
+}
+
+/// Outcome of running a statement on the resumable executor.
+enum Step {
+    Done(Flow),
+    Yield(Value),
+}
+
+/// What a generator did when resumed.
+pub enum Resumed {
+    /// Suspended at a `yield` or `await` with this value.
+    Yielded(Value),
+    /// Finished, with the value of its `return` (or `nil`).
+    Returned(Value),
+}
+
+impl Resumed {
+    /// The yielded value, as an iterator sees it.
+    pub fn yielded(self) -> Option<Value> {
+        match self {
+            Resumed::Yielded(value) => Some(value),
+            Resumed::Returned(_) => None,
+        }
+    }
+}
+
+enum State {
+    Fresh,
+    Suspended(Vec<Frame>),
+    Running,
+    Finished,
+}
+
+/// A suspended call to a function containing `yield`, or to an `async`
+/// function, which suspends at each `await`. Its body runs on a small
+/// executor that, unlike `Stmt::execute`, can stop in the middle of nested
+/// blocks and loops and later pick up where it left off.
+pub struct Generator {
+    declaration: Arc<FunctionDecl>,
+    env: Rc<RefCell<Environment>>,
+    state: State,
+}
+
+impl Generator {
+    /// Creates a generator that will run `declaration`'s body in `env`,
+    /// where the call's arguments are already bound.
+    pub fn new(declaration: Arc<FunctionDecl>, env: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            declaration,
+            env,
+            state: State::Fresh,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.name.lexeme()
+    }
+
+    /// Runs until the next `yield` or until the body finishes. `sent`
+    /// becomes the value of the `yield` the generator is suspended at; a
+    /// generator that hasn't started yet has nowhere to put it, so it must
+    /// be `nil`.
+    pub fn resume(&mut self, sent: Value, line: usize) -> Result<Resumed, RuntimeError> {
+        // Resuming runs the body on top of the stack, just like a call.
+        let _call = limits::enter(line)?;
+        let _entered = debug::enter(self.name(), line);
+        let mut frames = match std::mem::replace(&mut self.state, State::Running) {
+            State::Fresh if sent != Value::Nil => {
+                self.state = State::Fresh;
+                return Err(RuntimeError::new(
+                    format!(
+                        "Can't send '{}' to generator '{}' before it has started",
+                        sent,
+                        self.name()
+                    ),
+                    line,
+                ));
+            }
+            State::Fresh => Vec::new(),
+            State::Suspended(frames) => frames,
+            State::Running => {
+                return Err(RuntimeError::new(
+                    format!("Generator '{}' is already running", self.name()),
+                    line,
+                ))
+            }
+            State::Finished => {
+                self.state = State::Finished;
+                return Ok(Resumed::Returned(Value::Nil));
+            }
+        };
+
+        let declaration = Arc::clone(&self.declaration);
+        let mut sent = Some(sent);
+        match execute_block(
+            &declaration.body,
+            Rc::clone(&self.env),
+            &mut frames,
+            &mut sent,
+        ) {
+            Ok(Step::Yield(value)) => {
+                self.state = State::Suspended(frames);
+                Ok(Resumed::Yielded(value))
+            }
+            Ok(Step::Done(flow)) => {
+                self.state = State::Finished;
+                match flow {
+                    Flow::Return(value) => Ok(Resumed::Returned(value)),
+                    _ => Ok(Resumed::Returned(Value::Nil)),
+                }
+            }
+            Err(error) => {
+                self.state = State::Finished;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Runs `statements` in `env`, or when `frames` is non-empty, resumes them
+/// at the block position and scope recorded in its last frame.
+fn execute_block(
+    statements: &[Stmt],
+    env: Rc<RefCell<Environment>>,
+    frames: &mut Vec<Frame>,
+    sent: &mut Option<Value>,
+) -> Result<Step, RuntimeError> {
+    let (start, env) = match frames.pop() {
+        None => (0, env),
+        Some(Frame::Block { index, env }) => (index, env),
+        Some(_) => unreachable!("generator resumed into a block at a non-block frame"),
+    };
+    for (index, statement) in statements.iter().enumerate().skip(start) {
+        match execute(statement, Rc::clone(&env), frames, sent)? {
+            Step::Done(Flow::Normal) => {}
+            Step::Yield(value) => {
+                frames.push(Frame::Block { index, env });
+                return Ok(Step::Yield(value));
+            }
+            done => return Ok(done),
+        }
+    }
+    Ok(Step::Done(Flow::Normal))
+}
+
+/// Resumable counterpart of `Stmt::execute`. Statements that can contain
+/// a `yield` push a frame describing their progress when one suspends
+/// beneath them, and pop it again on the way back down; everything else is
+/// delegated to `Stmt::execute`.
+fn execute(
+    statement: &Stmt,
+    env: Rc<RefCell<Environment>>,
+    frames: &mut Vec<Frame>,
+    sent: &mut Option<Value>,
+) -> Result<Step, RuntimeError> {
+    // The statements run here rather than by `Stmt::execute` are shown to
+    // a hook on the way in, not when resumed.
+    if debug::attached()
+        && frames.is_empty()
+        && matches!(
+            statement,
+            Stmt::If { .. } | Stmt::While { .. } | Stmt::ForIn { .. } | Stmt::Yield { .. }
+        )
+    {
+        if let Some(line) = statement.line() {
+            debug::statement(line, &env)?;
+        }
+    }
+    match statement {
+        Stmt::Block { statements, names } => {
+            let env = if frames.is_empty() {
+                block_scope(env, names)
+            } else {
+                env
+            };
+            execute_block(statements, env, frames, sent)
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            let took_then = match frames.pop() {
+                None => condition.evaluate(&env)?.is_truthy(),
+                Some(Frame::If { then_branch }) => then_branch,
+                Some(_) => unreachable!("generator resumed into an if at a non-if frame"),
+            };
+            let branch = if took_then {
+                Some(then_branch.as_ref())
+            } else {
+                else_branch.as_deref()
+            };
+            let Some(branch) = branch else {
+                return Ok(Step::Done(Flow::Normal));
+            };
+            let step = execute(branch, env, frames, sent)?;
+            if let Step::Yield(_) = step {
+                frames.push(Frame::If {
+                    then_branch: took_then,
+                });
+            }
+            Ok(step)
+        }
+        Stmt::While {
+            keyword,
+            label,
+            condition,
+            body,
+            increment,
+        } => {
+            let mut resuming = match frames.pop() {
+                None => false,
+                Some(Frame::While) => true,
+                Some(_) => unreachable!("generator resumed into a while at a non-while frame"),
+            };
+            loop {
+                if !resuming {
+                    if !condition.evaluate(&env)?.is_truthy() {
+                        break;
+                    }
+                    limits::step(keyword.line)?;
+                }
+                resuming = false;
+                match execute(body, Rc::clone(&env), frames, sent)? {
+                    Step::Yield(value) => {
+                        frames.push(Frame::While);
+                        return Ok(Step::Yield(value));
+                    }
+                    Step::Done(flow) => {
+                        if let Some(flow) = after_iteration(flow, label) {
+                            return Ok(Step::Done(flow));
+                        }
+                    }
+                }
+                if let Some(increment) = increment {
+                    increment.evaluate(&env)?;
+                }
+            }
+            Ok(Step::Done(Flow::Normal))
+        }
+        Stmt::ForIn {
+            label,
+            variables,
+            iterable,
+            body,
+            names,
+        } => {
+            let line = variables[0].line;
+            let (mut iter, mut resumed_env) = match frames.pop() {
+                None => (for_in_iterator(variables, &iterable.evaluate(&env)?)?, None),
+                Some(Frame::ForIn { iter, env }) => (iter, Some(env)),
+                Some(_) => unreachable!("generator resumed into a for-in at a non-for-in frame"),
+            };
+            loop {
+                let loop_env = match resumed_env.take() {
+                    Some(loop_env) => loop_env,
+                    None => {
+                        let Some(item) = iter.next(line)? else {
+                            break;
+                        };
+                        limits::step(line)?;
+                        let loop_env = Environment::named(Rc::clone(&env), names);
+                        bind_loop_variables(&mut loop_env.borrow_mut(), variables, item)?;
+                        loop_env
+                    }
+                };
+                match execute(body, Rc::clone(&loop_env), frames, sent)? {
+                    Step::Yield(value) => {
+                        frames.push(Frame::ForIn {
+                            iter,
+                            env: loop_env,
+                        });
+                        return Ok(Step::Yield(value));
+                    }
+                    Step::Done(flow) => {
+                        if let Some(flow) = after_iteration(flow, label) {
+                            return Ok(Step::Done(flow));
+                        }
+                    }
+                }
+            }
+            Ok(Step::Done(Flow::Normal))
+        }
+        Stmt::Yield {
+            keyword,
+            value,
+            target,
+        } => match frames.pop() {
+            None => {
+                let value = match value {
+                    Some(value) => value.evaluate(&env)?,
+                    None => Value::Nil,
+                };
+                frames.push(Frame::Yield);
+                Ok(Step::Yield(value))
+            }
+            Some(Frame::Yield) => {
+                let received = sent.take().unwrap_or(Value::Nil);
+                match target {
+                    YieldTarget::Discard => {}
+                    YieldTarget::Declare(name, slot) => declare(&env, name, slot, received, false)?,
+                    YieldTarget::Assign(name, slot) => assign(&env, name, slot, received)?,
+                    YieldTarget::Return => return Ok(Step::Done(Flow::Return(received))),
+                    YieldTarget::Print => println!("{}", show(&received, keyword.line)?),
+                }
+                Ok(Step::Done(Flow::Normal))
+            }
+            Some(_) => unreachable!("generator resumed into a yield at a non-yield frame"),
+        },
+        _ => statement.execute(env).map(Step::Done),
+    }
+}
+
+/// Decides what a loop labeled `label` does after an iteration ended with
+/// `flow`: `None` to go on looping, or the flow to finish the loop with.
+fn after_iteration(flow: Flow, label: &Option<Token>) -> Option<Flow> {
+    match flow {
+        Flow::Normal => None,
+        Flow::Continue(target) if Flow::targets(&target, label) => None,
+        Flow::Break(target) if Flow::targets(&target, label) => Some(Flow::Normal),
+        flow => Some(flow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    #[test]
+    fn yields_suspend_inside_nested_blocks_and_loops() {
+        let source = "fun g(n) {
+                for i in 0..n {
+                    if (i % 2 == 0) { yield i; }
+                    else { var j = 0; while (j < 2) { { yield [i, j]; } j = j + 1; } }
+                }
+            }
+            map(g(4), x => x);";
+        assert_eq!(
+            eval(source),
+            Ok("[0, [1, 0], [1, 1], 2, [3, 0], [3, 1]]".to_string())
+        );
+    }
+
+    #[test]
+    fn send_resumes_a_generator_with_a_value() {
+        let accumulator =
+            "fun acc() { var total = 0; while (true) { var x = yield total; total = total + x; } }";
+        assert_eq!(
+            eval(&format!(
+                "{accumulator} var a = acc(); next(a); [send(a, 2), send(a, 3)];"
+            )),
+            Ok("[2, 5]".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{accumulator} send(acc(), 1);")),
+            Err("Can't send '1' to generator 'acc' before it has started at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn generators_can_finish_early() {
+        assert_eq!(
+            eval("fun g() { yield 1; return 9; yield 2; } var it = g(); [next(it), next(it), next(it)];"),
+            Ok("[1, nil, nil]".to_string())
+        );
+        let source = "fun naturals() { var i = 0; while (true) { yield i; i = i + 1; } }
+            var seen = 0;
+            for x in naturals() { if (x == 3) break; seen = seen + 1; }
+            seen;";
+        assert_eq!(eval(source), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn yield_and_await_only_stand_alone() {
+        let source =
+            "async fun f() { return 1; } async fun g() { print await f(); return 2; } g();";
+        assert_eq!(eval(source), Ok("2".to_string()));
+        assert_eq!(
+            eval("async fun f() { return 1; } async fun g() { return 1 + await f(); }"),
+            Err("'await' must be a statement or the whole value of a 'var', assignment, 'return' or 'print'; store its value in a variable to use it in an expression. at line 1".to_string())
+        );
+    }
+}
@@ -1,9 +1,21 @@
-use crate::ast::Expr;
-use crate::environment::Environment;
+use crate::ast::{names_of, Binding, Expr, Pattern, Slot};
+use crate::bigint::{Progress, Stopped};
+use crate::class::{Class, Instance};
+use crate::enums::Enum;
+use crate::environment::{AssignError, Environment};
 use crate::error::RuntimeError;
-use crate::value::Value;
+use crate::function::{self, Function};
+use crate::iterator::Range;
+use crate::limits;
+use crate::map::{Map, UnhashableKey};
+use crate::numeric::{self, ArithmeticError, ArithmeticOp, BitwiseOp};
+use crate::token::{Token, TokenType};
+use crate::value::{self, Value};
 use core::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
 use std::rc::Rc;
+use std::sync::Arc;
 
 impl Expr {
     pub fn evaluate(&self, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
@@ -15,42 +27,1740 @@ impl Expr {
             } => {
                 let left = left.evaluate(env)?;
                 let right = right.evaluate(env)?;
-                match (&left, &right) {
-                    (Value::Number(left), Value::Number(right)) => match operator.lexeme().as_str()
+                binary(&left, operator, &right)
+            }
+            Expr::Unary { operator, right } => {
+                let right = right.evaluate(env)?;
+                if let (Value::Instance(instance), "-") = (&right, operator.lexeme()) {
+                    if let Some(result) =
+                        Instance::call_method(instance, "__neg__", Vec::new(), operator.line)?
                     {
-                        "+" => Ok(Value::Number(left + right)),
-                        "-" => Ok(Value::Number(left - right)),
-                        "*" => Ok(Value::Number(left * right)),
-                        "/" => Ok(Value::Number(left / right)),
-                        _ => Err(RuntimeError::new(
-                            format!("Invalid binary operator '{}'", operator.lexeme()),
+                        return Ok(result);
+                    }
+                }
+                match operator.lexeme() {
+                    "!" => Ok(Value::Boolean(!right.is_truthy())),
+                    "-" => numeric::negate(&right).ok_or_else(|| {
+                        RuntimeError::new(
+                            format!("Operand must be a number, got '{}'", right),
                             operator.line,
-                        )),
-                    },
+                        )
+                    }),
+                    "~" => numeric::complement(&right).map_err(|_| {
+                        RuntimeError::new(
+                            format!("Operand must be an integer, got '{}'", right),
+                            operator.line,
+                        )
+                    }),
                     _ => Err(RuntimeError::new(
-                        format!("Operands must be numbers, got '{}' and '{}'", left, right),
+                        format!("Invalid unary operator '{}'", operator.lexeme()),
                         operator.line,
                     )),
                 }
             }
-            Expr::Unary { operator, right } => {
-                let right = right.evaluate(env)?;
-                match right {
-                    Value::Number(right) => match operator.lexeme().as_str() {
-                        "-" => Ok(Value::Number(-right)),
-                        _ => Err(RuntimeError::new(
-                            format!("Invalid unary operator '{}'", operator.lexeme()),
-                            operator.line,
-                        )),
-                    },
-                    _ => Err(RuntimeError::new(
-                        format!("Operand must be a number, got '{}'", right),
+            Expr::Literal { value } => Ok(Value::from(value)),
+            Expr::Grouping(expression) => expression.evaluate(env),
+            Expr::Variable { name, slot } => lookup(env, name, slot),
+            Expr::Assign { name, value, slot } => {
+                let value = value.evaluate(env)?;
+                assign(env, name, slot, value.clone())?;
+                Ok(value)
+            }
+            Expr::CompoundAssign {
+                target,
+                operator,
+                value,
+                postfix,
+            } => match target.as_ref() {
+                Expr::Variable { name, slot } => {
+                    let old = lookup(env, name, slot)?;
+                    let new = binary(&old, operator, &value.evaluate(env)?)?;
+                    assign(env, name, slot, new.clone())?;
+                    Ok(if *postfix { old } else { new })
+                }
+                Expr::Get { object, name, .. } => {
+                    let object = object.evaluate(env)?;
+                    let old = get_property(&object, name)?;
+                    let new = binary(&old, operator, &value.evaluate(env)?)?;
+                    set_property(&object, name, new.clone())?;
+                    Ok(if *postfix { old } else { new })
+                }
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    let object = object.evaluate(env)?;
+                    let index = index.evaluate(env)?;
+                    let old = get_index(&object, &index, bracket)?;
+                    let new = binary(&old, operator, &value.evaluate(env)?)?;
+                    set_index(&object, &index, new.clone(), bracket)?;
+                    Ok(if *postfix { old } else { new })
+                }
+                _ => Err(RuntimeError::new(
+                    format!("Invalid target for '{}='", operator.lexeme()),
+                    operator.line,
+                )),
+            },
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left = left.evaluate(env)?;
+                let short_circuits = match operator.token_type {
+                    TokenType::Or => left.is_truthy(),
+                    TokenType::And => !left.is_truthy(),
+                    _ => left != Value::Nil,
+                };
+                if short_circuits {
+                    Ok(left)
+                } else {
+                    right.evaluate(env)
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if condition.evaluate(env)?.is_truthy() {
+                    then_branch.evaluate(env)
+                } else {
+                    else_branch.evaluate(env)
+                }
+            }
+            Expr::Call { .. } | Expr::Get { .. } => {
+                Ok(self.evaluate_chain(env)?.unwrap_or(Value::Nil))
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object = object.evaluate(env)?;
+                let value = value.evaluate(env)?;
+                set_property(&object, name, value.clone())?;
+                Ok(value)
+            }
+            Expr::This { keyword, slot } => lookup(env, keyword, slot),
+            Expr::Super { name, slot, this } => {
+                let Some(Value::Class(superclass)) = Environment::get(env, slot) else {
+                    unreachable!("'super' is bound around a subclass's members");
+                };
+                Class::get_super(&superclass, this.evaluate(env)?, name)
+            }
+            Expr::Lambda(declaration) => Ok(Value::Function(Rc::new(Function::new(
+                Arc::clone(declaration),
+                Rc::clone(env),
+                false,
+            )))),
+            Expr::List { elements } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.evaluate(env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::list(elements))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    let key = key.evaluate(env)?;
+                    let value = value.evaluate(env)?;
+                    map_insert(&mut map, key, value, brace.line)?;
+                }
+                Ok(Value::map(map))
+            }
+            Expr::Range {
+                start,
+                operator,
+                end,
+                step,
+            } => {
+                let bound = |expr: &Expr, what: &str| match expr.evaluate(env)? {
+                    Value::Integer(n) => Ok(n),
+                    other => Err(RuntimeError::new(
+                        format!("Range {} must be an integer, got '{}'", what, other),
                         operator.line,
                     )),
+                };
+                let range = Range {
+                    start: bound(start, "start")?,
+                    end: bound(end, "end")?,
+                    step: match step {
+                        Some(step) => bound(step, "step")?,
+                        None => 1,
+                    },
+                    inclusive: operator.token_type == TokenType::DotDotEqual,
+                };
+                if range.step == 0 {
+                    return Err(RuntimeError::new(
+                        "Range step can't be zero".to_string(),
+                        operator.line,
+                    ));
                 }
+                Ok(Value::Range(Rc::new(range)))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = object.evaluate(env)?;
+                let index = index.evaluate(env)?;
+                get_index(&object, &index, bracket)
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = object.evaluate(env)?;
+                let index = index.evaluate(env)?;
+                let value = value.evaluate(env)?;
+                set_index(&object, &index, value.clone(), bracket)?;
+                Ok(value)
+            }
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+            } => {
+                let subject = subject.evaluate(env)?;
+                for arm in arms {
+                    let arm_env = Environment::named(Rc::clone(env), &arm.names);
+                    if !arm.pattern.matches(&subject, &arm_env)? {
+                        continue;
+                    }
+                    if let Some(guard) = &arm.guard {
+                        if !guard.evaluate(&arm_env)?.is_truthy() {
+                            continue;
+                        }
+                    }
+                    return arm.body.evaluate(&arm_env);
+                }
+                Err(RuntimeError::new(
+                    format!("No match arm matches '{}'", subject),
+                    keyword.line,
+                ))
+            }
+        }
+    }
+
+    /// Evaluates a chain of calls and property accesses, returning `None`
+    /// when a `?.` link met `nil`. The whole rest of the chain is skipped in
+    /// that case, so `a?.b.c()` is `nil` rather than an error when `a` is.
+    fn evaluate_chain(
+        &self,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match self {
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => {
+                let object = match object.evaluate_chain(env)? {
+                    Some(Value::Nil) if *optional => return Ok(None),
+                    Some(object) => object,
+                    None => return Ok(None),
+                };
+                get_property(&object, name).map(Some)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                optional,
+            } => {
+                let callee = match callee.evaluate_chain(env)? {
+                    Some(Value::Nil) if *optional => return Ok(None),
+                    Some(callee) => callee,
+                    None => return Ok(None),
+                };
+                let mut positional = Vec::new();
+                let mut named = Vec::new();
+                for argument in arguments {
+                    let value = argument.value.evaluate(env)?;
+                    match &argument.name {
+                        Some(name) => named.push((name.clone(), value)),
+                        None => positional.push(value),
+                    }
+                }
+                call_with_named(&callee, positional, named, paren.line).map(Some)
+            }
+            _ => self.evaluate(env).map(Some),
+        }
+    }
+}
+
+/// The innermost part of a pattern that a value failed to match.
+struct Mismatch<'a> {
+    pattern: &'a Pattern,
+    value: Value,
+}
+
+type Bindings<'a> = Vec<(&'a Token, &'a Slot, Value)>;
+
+impl Pattern {
+    /// Tests `value` against the pattern, defining the names it binds in
+    /// `env` if it matches.
+    pub fn matches(
+        &self,
+        value: &Value,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<bool, RuntimeError> {
+        let mut bindings = Vec::new();
+        if self.test(value, env, &mut bindings)?.is_err() {
+            return Ok(false);
+        }
+        let mut env = env.borrow_mut();
+        for (_, slot, value) in bindings {
+            env.define(slot.locals[0].1, value);
+        }
+        Ok(true)
+    }
+
+    /// Binds the parts of `value` to the names in the pattern as `binding`
+    /// says. A value of the wrong shape is an error.
+    pub fn destructure(
+        &self,
+        value: &Value,
+        env: &Rc<RefCell<Environment>>,
+        binding: &Binding,
+        line: usize,
+    ) -> Result<(), RuntimeError> {
+        let mut bindings = Vec::new();
+        if let Err(Mismatch { pattern, value }) = self.test(value, env, &mut bindings)? {
+            return Err(RuntimeError::new(
+                format!(
+                    "Can't destructure '{}' with pattern '{}'",
+                    value,
+                    pattern.print()
+                ),
+                line,
+            ));
+        }
+        for (name, slot, value) in bindings {
+            match binding {
+                Binding::Var => declare(env, name, slot, value, false)?,
+                Binding::Const => declare(env, name, slot, value, true)?,
+                Binding::Assign => assign(env, name, slot, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Tests `value` against the pattern, collecting what it binds. `env`
+    /// is only read, for defaults and the classes of instance patterns.
+    fn test<'a>(
+        &'a self,
+        value: &Value,
+        env: &Rc<RefCell<Environment>>,
+        bindings: &mut Bindings<'a>,
+    ) -> Result<Result<(), Mismatch<'a>>, RuntimeError> {
+        let mismatch = || {
+            Ok(Err(Mismatch {
+                pattern: self,
+                value: value.clone(),
+            }))
+        };
+        match self {
+            Pattern::Wildcard => {}
+            Pattern::Bind(name, slot) => bindings.push((name, slot, value.clone())),
+            Pattern::Value(expected) => {
+                if !is_equal(&expected.evaluate(env)?, value) {
+                    return mismatch();
+                }
+            }
+            Pattern::Range {
+                low,
+                operator,
+                high,
+            } => {
+                let (low, high) = (low.evaluate(env)?, high.evaluate(env)?);
+                let above = pattern_order(value, &low).is_some_and(|o| o != Ordering::Less);
+                let below = pattern_order(value, &high).is_some_and(|o| {
+                    o == Ordering::Less
+                        || (o == Ordering::Equal && operator.token_type == TokenType::DotDotEqual)
+                });
+                if !(above && below) {
+                    return mismatch();
+                }
+            }
+            Pattern::Default { pattern, default } => {
+                if *value != Value::Nil {
+                    return pattern.test(value, env, bindings);
+                }
+                // Defaults can refer to what the pattern bound before them,
+                // which get a slot each in the order they were bound.
+                let scope = Environment::with_enclosing(Rc::clone(env), bindings.len());
+                for (index, (_, _, value)) in bindings.iter().enumerate() {
+                    scope.borrow_mut().define(index, value.clone());
+                }
+                scope
+                    .borrow_mut()
+                    .name_slots(names_of(bindings.iter().map(|(name, _, _)| *name)));
+                let default = default.evaluate(&scope)?;
+                return pattern.test(&default, env, bindings);
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                let Value::List(elements) = value else {
+                    return mismatch();
+                };
+                let elements = elements.borrow().clone();
+                // Trailing elements with defaults may be left out.
+                let optional = match (rest, after.is_empty()) {
+                    (None, true) => before
+                        .iter()
+                        .rev()
+                        .take_while(|pattern| matches!(pattern, Pattern::Default { .. }))
+                        .count(),
+                    _ => 0,
+                };
+                let fixed = before.len() + after.len();
+                if elements.len() + optional < fixed || (rest.is_none() && elements.len() > fixed) {
+                    return mismatch();
+                }
+                let middle = before.len().min(elements.len())..elements.len() - after.len();
+                for (position, pattern) in before.iter().enumerate() {
+                    let element = elements.get(position).unwrap_or(&Value::Nil);
+                    if let Err(mismatch) = pattern.test(element, env, bindings)? {
+                        return Ok(Err(mismatch));
+                    }
+                }
+                for (pattern, element) in after.iter().zip(&elements[middle.end..]) {
+                    if let Err(mismatch) = pattern.test(element, env, bindings)? {
+                        return Ok(Err(mismatch));
+                    }
+                }
+                if let Some(rest) = rest {
+                    let middle = Value::list(elements[middle].to_vec());
+                    return rest.test(&middle, env, bindings);
+                }
+            }
+            Pattern::Map { entries } => {
+                if !matches!(value, Value::Map(_) | Value::Instance(_)) {
+                    return mismatch();
+                }
+                for (key, pattern) in entries {
+                    let key = key.evaluate(env)?;
+                    let entry = match (value, &key) {
+                        // Keys in patterns are literals, so always hashable.
+                        (Value::Map(map), key) => map.borrow().get(key).unwrap_or_default(),
+                        (Value::Instance(instance), Value::String(name)) => {
+                            instance.borrow().fields.get(name).cloned()
+                        }
+                        _ => None,
+                    };
+                    let entry = match (entry, pattern) {
+                        (Some(entry), _) => entry,
+                        (None, Pattern::Default { .. }) => Value::Nil,
+                        (None, _) => return mismatch(),
+                    };
+                    if let Err(mismatch) = pattern.test(&entry, env, bindings)? {
+                        return Ok(Err(mismatch));
+                    }
+                }
+            }
+            Pattern::Instance { class, fields } => {
+                let (Expr::Variable {
+                    name: class_name, ..
+                }
+                | Expr::Get {
+                    name: class_name, ..
+                }) = class
+                else {
+                    unreachable!("class patterns name their class");
+                };
+                let expected = match class.evaluate(env)? {
+                    Value::Class(expected) => expected,
+                    Value::Constructor(enumeration, index) => {
+                        return self.test_variant(&enumeration, index, value, env, bindings);
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(
+                            format!(
+                                "'{}' in a pattern is not a class or enum variant",
+                                class.print()
+                            ),
+                            class_name.line,
+                        ))
+                    }
+                };
+                let Value::Instance(instance) = value else {
+                    return mismatch();
+                };
+                if !instance.borrow().class.is_subclass_of(&expected) {
+                    return mismatch();
+                }
+                let init = expected
+                    .find_method("init")
+                    .map(|init| Arc::clone(&init.declaration));
+                for (position, (name, pattern)) in fields.iter().enumerate() {
+                    let field = match (name, &init) {
+                        (Some(name), _) => name.lexeme(),
+                        (None, Some(init)) if position < init.params.len() => {
+                            let field = init.params[position].name.lexeme();
+                            // A parameter needn't name a field, and then the
+                            // pattern could never match.
+                            if !instance.borrow().fields.contains_key(field) {
+                                return Err(RuntimeError::new(
+                                    format!(
+                                        "Positional pattern {} for '{}' stands for field '{}', named by init's parameter, but the instance has no such field; match fields by name instead",
+                                        position + 1,
+                                        class.print(),
+                                        field
+                                    ),
+                                    class_name.line,
+                                ));
+                            }
+                            field
+                        }
+                        (None, _) => {
+                            return Err(RuntimeError::new(
+                                format!(
+                                    "Pattern for '{}' has more positional fields than its init has parameters",
+                                    class.print()
+                                ),
+                                class_name.line,
+                            ))
+                        }
+                    };
+                    let field = instance.borrow().fields.get(field).cloned();
+                    let field = match (field, pattern) {
+                        (Some(field), _) => field,
+                        (None, Pattern::Default { .. }) => Value::Nil,
+                        (None, _) => return mismatch(),
+                    };
+                    if let Err(mismatch) = pattern.test(&field, env, bindings)? {
+                        return Ok(Err(mismatch));
+                    }
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+impl Pattern {
+    /// Tests `value` against a variant pattern such as `Shape.Circle(r)`,
+    /// whose positional patterns stand for the variant's fields in
+    /// declaration order.
+    fn test_variant<'a>(
+        &'a self,
+        enumeration: &Arc<Enum>,
+        index: usize,
+        value: &Value,
+        env: &Rc<RefCell<Environment>>,
+        bindings: &mut Bindings<'a>,
+    ) -> Result<Result<(), Mismatch<'a>>, RuntimeError> {
+        let Pattern::Instance {
+            class: Expr::Variable { name, .. } | Expr::Get { name, .. },
+            fields,
+        } = self
+        else {
+            unreachable!("variant patterns name their variant");
+        };
+        let declared = enumeration.fields(index).unwrap_or_default();
+        let variant = match value {
+            Value::Variant(variant)
+                if Arc::ptr_eq(&variant.enumeration, enumeration) && variant.index == index =>
+            {
+                variant
+            }
+            _ => {
+                return Ok(Err(Mismatch {
+                    pattern: self,
+                    value: value.clone(),
+                }))
+            }
+        };
+        for (position, (field, pattern)) in fields.iter().enumerate() {
+            let position = match field {
+                Some(field) => declared
+                    .iter()
+                    .position(|declared| declared == field.lexeme())
+                    .ok_or_else(|| {
+                        RuntimeError::new(
+                            format!("'{}' has no field '{}'", name.lexeme(), field.lexeme()),
+                            field.line,
+                        )
+                    })?,
+                None if position < declared.len() => position,
+                None => {
+                    return Err(RuntimeError::new(
+                        format!(
+                            "Pattern for '{}' has more fields than the variant",
+                            name.lexeme()
+                        ),
+                        name.line,
+                    ))
+                }
+            };
+            if let Err(mismatch) = pattern.test(&variant.values[position], env, bindings)? {
+                return Ok(Err(mismatch));
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Orders two numbers or two strings, as range patterns compare them.
+fn pattern_order(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => numeric::compare(left, right),
+    }
+}
+
+/// Calls any callable value; `line` is the call site for error reporting.
+pub fn call(callee: &Value, arguments: Vec<Value>, line: usize) -> Result<Value, RuntimeError> {
+    call_with_named(callee, arguments, Vec::new(), line)
+}
+
+pub fn call_with_named(
+    callee: &Value,
+    positional: Vec<Value>,
+    named: Vec<(Token, Value)>,
+    line: usize,
+) -> Result<Value, RuntimeError> {
+    match callee {
+        Value::Function(function) => function.call(positional, named, line),
+        Value::NativeFunction(native) => {
+            if let Some((name, _)) = named.first() {
+                return Err(RuntimeError::new(
+                    format!(
+                        "Native function '{}' doesn't take named argument '{}'",
+                        native.name,
+                        name.lexeme()
+                    ),
+                    name.line,
+                ));
+            }
+            check_arity(
+                &format!("{}()", native.name),
+                native.arity,
+                positional.len(),
+                line,
+            )?;
+            (native.function)(&positional, line)
+        }
+        Value::Class(class) => {
+            let instance = Rc::new(RefCell::new(Instance::new(Rc::clone(class))));
+            match class.find_method("init") {
+                Some(initializer) => {
+                    initializer
+                        .bind(Value::Instance(Rc::clone(&instance)))
+                        .call(positional, named, line)?;
+                }
+                None => check_arity(
+                    &format!("{}()", class.name),
+                    0,
+                    positional.len() + named.len(),
+                    line,
+                )?,
+            }
+            Ok(Value::Instance(instance))
+        }
+        Value::Instance(instance) => {
+            let method = instance.borrow().class.find_method("__call__");
+            match method {
+                Some(method) => method.bind(callee.clone()).call(positional, named, line),
+                None => Err(not_callable(callee, line)),
+            }
+        }
+        Value::Constructor(enumeration, index) => {
+            Enum::construct(enumeration, *index, positional, named, line)
+        }
+        _ => Err(not_callable(callee, line)),
+    }
+}
+
+fn not_callable(callee: &Value, line: usize) -> RuntimeError {
+    RuntimeError::new(
+        format!(
+            "Can only call functions, classes and variant constructors, got '{}'",
+            callee
+        ),
+        line,
+    )
+}
+
+fn check_arity(callee: &str, expected: usize, got: usize, line: usize) -> Result<(), RuntimeError> {
+    if expected == got {
+        Ok(())
+    } else {
+        Err(function::arity_error(
+            callee,
+            &function::arguments(expected),
+            &got.to_string(),
+            line,
+        ))
+    }
+}
+
+fn get_index(object: &Value, index: &Value, bracket: &Token) -> Result<Value, RuntimeError> {
+    match object {
+        Value::List(elements) => {
+            let elements = elements.borrow();
+            let position = list_position(elements.len(), index, bracket)?;
+            Ok(elements[position].clone())
+        }
+        Value::Map(map) => map_get(&map.borrow(), index, bracket.line),
+        Value::Instance(instance) => {
+            Instance::call_method(instance, "__index__", vec![index.clone()], bracket.line)?
+                .ok_or_else(|| not_indexable(object, bracket))
+        }
+        _ => Err(not_indexable(object, bracket)),
+    }
+}
+
+fn not_indexable(object: &Value, bracket: &Token) -> RuntimeError {
+    RuntimeError::new(
+        format!("Only lists and maps can be indexed, got '{}'", object),
+        bracket.line,
+    )
+}
+
+fn set_index(
+    object: &Value,
+    index: &Value,
+    value: Value,
+    bracket: &Token,
+) -> Result<(), RuntimeError> {
+    match object {
+        Value::List(elements) => {
+            let mut elements = elements.borrow_mut();
+            if elements.frozen {
+                return Err(frozen("list", bracket.line));
+            }
+            let position = list_position(elements.len(), index, bracket)?;
+            elements[position] = value;
+            Ok(())
+        }
+        Value::Map(map) => map_insert(&mut map.borrow_mut(), index.clone(), value, bracket.line),
+        Value::Instance(instance) => {
+            let arguments = vec![index.clone(), value];
+            match Instance::call_method(instance, "__set_index__", arguments, bracket.line)? {
+                Some(_) => Ok(()),
+                None => Err(not_indexable(object, bracket)),
             }
-            Expr::Literal { value } => Ok(value.clone()),
-            Expr::Grouping(expression) => expression.evaluate(env),
         }
+        _ => Err(not_indexable(object, bracket)),
+    }
+}
+
+/// Resolves a list index, counting from the end when negative.
+fn list_position(len: usize, index: &Value, bracket: &Token) -> Result<usize, RuntimeError> {
+    let Value::Integer(index) = index else {
+        return Err(RuntimeError::new(
+            format!("List index must be an integer, got '{}'", index),
+            bracket.line,
+        ));
+    };
+    let position = if *index < 0 {
+        len as i64 + index
+    } else {
+        *index
+    };
+    if (0..len as i64).contains(&position) {
+        Ok(position as usize)
+    } else {
+        Err(RuntimeError::new(
+            format!("List index {} out of range for length {}", index, len),
+            bracket.line,
+        ))
+    }
+}
+
+/// Reads `object.name`: a field or bound method of an instance, or the
+/// entry under the string key `name` of a map (`nil` if absent).
+fn get_property(object: &Value, name: &Token) -> Result<Value, RuntimeError> {
+    match object {
+        Value::Instance(instance) => Instance::get(instance, name),
+        Value::Class(class) => Class::get_static(class, name),
+        Value::Enum(enumeration) => Enum::get(enumeration, name),
+        Value::Variant(variant) => variant.get(name),
+        Value::Map(map) => map_get(
+            &map.borrow(),
+            &Value::String(Arc::clone(&name.lexeme)),
+            name.line,
+        ),
+        other => Err(no_properties(other, name)),
+    }
+}
+
+fn set_property(object: &Value, name: &Token, value: Value) -> Result<(), RuntimeError> {
+    match object {
+        Value::Instance(instance) => Instance::set(instance, name, value),
+        Value::Class(class) => class.assign_static(name, value),
+        Value::Map(map) => map_insert(
+            &mut map.borrow_mut(),
+            Value::String(Arc::clone(&name.lexeme)),
+            value,
+            name.line,
+        ),
+        Value::Variant(variant) => Err(RuntimeError::new(
+            format!(
+                "Can't assign field '{}' of '{}': variants are immutable",
+                name.lexeme(),
+                variant.name()
+            ),
+            name.line,
+        )),
+        other => Err(no_properties(other, name)),
+    }
+}
+
+fn no_properties(object: &Value, name: &Token) -> RuntimeError {
+    RuntimeError::new(
+        format!(
+            "Only instances, classes, maps, enums and variants have properties, got '{}' for '{}'",
+            object,
+            name.lexeme()
+        ),
+        name.line,
+    )
+}
+
+fn map_get(map: &Map, key: &Value, line: usize) -> Result<Value, RuntimeError> {
+    map.get(key)
+        .map(|value| value.unwrap_or(Value::Nil))
+        .map_err(|UnhashableKey| unhashable(key, line))
+}
+
+fn map_insert(map: &mut Map, key: Value, value: Value, line: usize) -> Result<(), RuntimeError> {
+    if map.frozen {
+        return Err(frozen("map", line));
+    }
+    map.insert(key.clone(), value)
+        .map_err(|UnhashableKey| unhashable(&key, line))
+}
+
+pub fn frozen(kind: &str, line: usize) -> RuntimeError {
+    RuntimeError::new(format!("Can't modify a frozen {}", kind), line)
+}
+
+fn unhashable(key: &Value, line: usize) -> RuntimeError {
+    RuntimeError::new(format!("Can't use '{}' as a map key", key), line)
+}
+
+fn lookup(
+    env: &Rc<RefCell<Environment>>,
+    name: &Token,
+    slot: &Slot,
+) -> Result<Value, RuntimeError> {
+    Environment::get(env, slot).ok_or_else(|| {
+        RuntimeError::new(format!("Undefined variable '{}'", name.lexeme()), name.line)
+    })
+}
+
+pub fn assign(
+    env: &Rc<RefCell<Environment>>,
+    name: &Token,
+    slot: &Slot,
+    value: Value,
+) -> Result<(), RuntimeError> {
+    Environment::assign(env, slot, value).map_err(|error| {
+        let problem = match error {
+            AssignError::Undefined => "Undefined variable",
+            AssignError::Constant => "Can't assign to constant",
+        };
+        RuntimeError::new(format!("{} '{}'", problem, name.lexeme()), name.line)
+    })
+}
+
+/// Declares `name` in the innermost scope of `env`, which fails if it
+/// already holds a constant of that name.
+pub fn declare(
+    env: &Rc<RefCell<Environment>>,
+    name: &Token,
+    slot: &Slot,
+    value: Value,
+    constant: bool,
+) -> Result<(), RuntimeError> {
+    if Environment::declare_at(env, slot, value, constant) {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(
+            format!("Can't redeclare constant '{}'", name.lexeme()),
+            name.line,
+        ))
+    }
+}
+
+fn binary(left: &Value, operator: &Token, right: &Value) -> Result<Value, RuntimeError> {
+    if let Some(result) = overloaded(left, operator, right)? {
+        return Ok(result);
+    }
+    match operator.lexeme() {
+        "+" => arithmetic(ArithmeticOp::Add, left, right, operator),
+        "-" => arithmetic(ArithmeticOp::Subtract, left, right, operator),
+        "*" => arithmetic(ArithmeticOp::Multiply, left, right, operator),
+        "/" => arithmetic(ArithmeticOp::Divide, left, right, operator),
+        "%" => arithmetic(ArithmeticOp::Remainder, left, right, operator),
+        "**" => arithmetic(ArithmeticOp::Power, left, right, operator),
+        "&" => bitwise(BitwiseOp::And, left, right, operator),
+        "|" => bitwise(BitwiseOp::Or, left, right, operator),
+        "^" => bitwise(BitwiseOp::Xor, left, right, operator),
+        "<<" => bitwise(BitwiseOp::ShiftLeft, left, right, operator),
+        ">>" => bitwise(BitwiseOp::ShiftRight, left, right, operator),
+        ">" => comparison(left, right, operator, Ordering::is_gt),
+        ">=" => comparison(left, right, operator, Ordering::is_ge),
+        "<" => comparison(left, right, operator, Ordering::is_lt),
+        "<=" => comparison(left, right, operator, Ordering::is_le),
+        "==" => Ok(Value::Boolean(is_equal(left, right))),
+        "!=" => Ok(Value::Boolean(!is_equal(left, right))),
+        "is" => is_a(left, right, operator).map(Value::Boolean),
+        _ => Err(RuntimeError::new(
+            format!("Invalid binary operator '{}'", operator.lexeme()),
+            operator.line,
+        )),
+    }
+}
+
+/// The method a class defines to overload a binary operator for instances
+/// on its left. One `__compare__` method, returning a number below, at or
+/// above zero, serves all four orderings. The underscores keep ordinary
+/// methods, like a cart's `add`, from being taken for operators.
+fn operator_method(operator: &str) -> Option<&'static str> {
+    Some(match operator {
+        "+" => "__add__",
+        "-" => "__sub__",
+        "*" => "__mul__",
+        "/" => "__div__",
+        "%" => "__rem__",
+        "**" => "__pow__",
+        "==" | "!=" => "__eq__",
+        "<" | "<=" | ">" | ">=" => "__compare__",
+        _ => return None,
+    })
+}
+
+/// Applies `operator` through a method of `left`, or returns `None` if
+/// `left` isn't an instance whose class overloads it.
+fn overloaded(
+    left: &Value,
+    operator: &Token,
+    right: &Value,
+) -> Result<Option<Value>, RuntimeError> {
+    let Value::Instance(instance) = left else {
+        return Ok(None);
+    };
+    let Some(method) = operator_method(operator.lexeme()) else {
+        return Ok(None);
+    };
+    let Some(result) = Instance::call_method(instance, method, vec![right.clone()], operator.line)?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(match operator.lexeme() {
+        "==" => Value::Boolean(result.is_truthy()),
+        "!=" => Value::Boolean(!result.is_truthy()),
+        "<" | "<=" | ">" | ">=" => {
+            if !numeric::is_numeric(&result) {
+                return Err(RuntimeError::new(
+                    format!("__compare__() must return a number, got '{}'", result),
+                    operator.line,
+                ));
+            }
+            binary(&result, operator, &Value::Integer(0))?
+        }
+        _ => result,
+    }))
+}
+
+/// Shows `value` as `print` does: as it displays, except that instances
+/// whose class defines `__str__` show the string it returns. What goes
+/// wrong in `__str__` is raised here.
+pub fn show(value: &Value, line: usize) -> Result<String, RuntimeError> {
+    let failed = RefCell::new(None);
+    let write = |instance: &Rc<RefCell<Instance>>, f: &mut fmt::Formatter| {
+        let error = match Instance::call_method(instance, "__str__", Vec::new(), line) {
+            Ok(Some(Value::String(s))) => return write!(f, "{}", s),
+            Ok(None) => return value::write_instance(instance, f),
+            Ok(Some(other)) => RuntimeError::new(
+                format!("__str__() must return a string, got '{}'", other),
+                line,
+            ),
+            Err(error) => error,
+        };
+        *failed.borrow_mut() = Some(error);
+        Err(fmt::Error)
+    };
+    let mut shown = String::new();
+    match fmt::write(&mut shown, format_args!("{}", value.display_with(&write))) {
+        Ok(()) => Ok(shown),
+        Err(_) => Err(failed
+            .into_inner()
+            .expect("showing only stops for a failed __str__")),
+    }
+}
+
+/// `value is Type`: whether `value` is an instance of a class or one of its
+/// subclasses, of a class implementing a trait, or a variant of an enum.
+fn is_a(value: &Value, kind: &Value, operator: &Token) -> Result<bool, RuntimeError> {
+    Ok(match (value, kind) {
+        (Value::Instance(instance), Value::Class(class)) => {
+            instance.borrow().class.is_subclass_of(class)
+        }
+        (Value::Instance(instance), Value::Trait(implemented)) => {
+            instance.borrow().class.implements(implemented)
+        }
+        (Value::Variant(variant), Value::Enum(enumeration)) => {
+            Arc::ptr_eq(&variant.enumeration, enumeration)
+        }
+        (_, Value::Class(_) | Value::Trait(_) | Value::Enum(_)) => false,
+        _ => {
+            return Err(RuntimeError::new(
+                format!(
+                    "Right operand of 'is' must be a class, trait or enum, got '{}'",
+                    kind
+                ),
+                operator.line,
+            ))
+        }
+    })
+}
+
+fn arithmetic(
+    op: ArithmeticOp,
+    left: &Value,
+    right: &Value,
+    operator: &Token,
+) -> Result<Value, RuntimeError> {
+    paced(operator.line, |progress| {
+        numeric::arithmetic(op, left, right, progress)
+    })?
+    .map_err(|error| arithmetic_error(error, left, right, operator))
+}
+
+fn bitwise(
+    op: BitwiseOp,
+    left: &Value,
+    right: &Value,
+    operator: &Token,
+) -> Result<Value, RuntimeError> {
+    paced(operator.line, |progress| {
+        numeric::bitwise(op, left, right, progress)
+    })?
+    .map_err(|error| arithmetic_error(error, left, right, operator))
+}
+
+/// Runs `compute`, counting the work it reports as steps taken at `line`.
+/// A limit it runs into, or an interrupt, stops it with that error.
+fn paced<T>(
+    line: usize,
+    compute: impl FnOnce(&mut Progress) -> Result<T, ArithmeticError>,
+) -> Result<Result<T, ArithmeticError>, RuntimeError> {
+    let mut stopped = None;
+    let result = compute(&mut |work| {
+        limits::work(work, line).map_err(|error| {
+            stopped = Some(error);
+            Stopped
+        })
+    });
+    match stopped {
+        Some(error) => Err(error),
+        None => Ok(result),
+    }
+}
+
+fn arithmetic_error(
+    error: ArithmeticError,
+    left: &Value,
+    right: &Value,
+    operator: &Token,
+) -> RuntimeError {
+    let message = match error {
+        ArithmeticError::NotNumeric => {
+            format!("Operands must be numbers, got '{}' and '{}'", left, right)
+        }
+        ArithmeticError::NotInteger => {
+            format!("Operands must be integers, got '{}' and '{}'", left, right)
+        }
+        ArithmeticError::DivisionByZero => "Division by zero".to_string(),
+        ArithmeticError::NegativeShift => format!("Negative shift count '{}'", right),
+        ArithmeticError::TooLarge => {
+            format!(
+                "Operands too large for '{}', got '{}' and '{}'",
+                operator.lexeme(),
+                left,
+                right
+            )
+        }
+        ArithmeticError::Stopped => unreachable!("paced computations report what stopped them"),
+    };
+    RuntimeError::new(message, operator.line)
+}
+
+fn comparison(
+    left: &Value,
+    right: &Value,
+    operator: &Token,
+    accept: fn(Ordering) -> bool,
+) -> Result<Value, RuntimeError> {
+    let ordering = match (left, right) {
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ if numeric::is_numeric(left) && numeric::is_numeric(right) => {
+            numeric::compare(left, right)
+        }
+        _ => {
+            return Err(RuntimeError::new(
+                format!(
+                    "Operands must be two numbers or two strings, got '{}' and '{}'",
+                    left, right
+                ),
+                operator.line,
+            ))
+        }
+    };
+    // NaN compares false against everything, as with IEEE floats.
+    Ok(Value::Boolean(ordering.is_some_and(accept)))
+}
+
+/// Numbers compare by value across the numeric tower, so `1 == 1.0` and
+/// `1/2r == 0.5`, including inside lists and maps; everything else compares
+/// structurally.
+pub fn is_equal(left: &Value, right: &Value) -> bool {
+    value::equal(left, right, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    /// Evaluates each expression, pairing it with what it shows or the
+    /// error it raises.
+    fn results(expressions: &[&str]) -> Vec<String> {
+        expressions
+            .iter()
+            .map(|expression| match eval(&format!("{};", expression)) {
+                Ok(value) => value,
+                Err(error) => error,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn remainder_and_power() {
+        assert_eq!(
+            results(&["7 % 3", "-7 % 3", "7 % -3", "7.5 % 2", "1/2r % 1/3r"]),
+            ["1", "2", "-2", "1.5", "1/6"]
+        );
+        assert_eq!(
+            results(&[
+                "2 ** 10",
+                "2 ** 3 ** 2",
+                "-2 ** 2",
+                "2 ** -1",
+                "2r ** -2",
+                "0 ** 0"
+            ]),
+            ["1024", "512", "-4", "1/2", "1/4", "1"]
+        );
+        assert_eq!(results(&["1 + 2 * 3 ** 2 % 5", "(1 + 2) * 3"]), ["4", "9"]);
+    }
+
+    #[test]
+    fn dividing_by_zero_is_an_error() {
+        assert_eq!(
+            results(&[
+                "1 / 0",
+                "1 % 0",
+                "12345678901234567890 % 0",
+                "1r / 0",
+                "0 ** -1",
+                "0r ** -1"
+            ]),
+            vec!["Division by zero at line 1"; 6]
+        );
+        assert_eq!(results(&["1.0 / 0", "-1 / 0.0"]), ["inf", "-inf"]);
+    }
+
+    #[test]
+    fn overflow_promotes_to_big_integers() {
+        let max = "9223372036854775807";
+        let min = "(-9223372036854775807 - 1)";
+        assert_eq!(
+            results(&[
+                &format!("{} + 1", max),
+                &format!("{} - 1", min),
+                &format!("{} * 2", max),
+                &format!("{} / -1", min),
+                &format!("{} % -1", min),
+                &format!("-{}", min),
+                "2 ** 64",
+                "1 << 64",
+                &format!("({} + 1) - 1", max),
+            ]),
+            [
+                "9223372036854775808",
+                "-9223372036854775809",
+                "18446744073709551614",
+                "9223372036854775808",
+                "0",
+                "9223372036854775808",
+                "18446744073709551616",
+                "18446744073709551616",
+                max,
+            ]
+        );
+        assert_eq!(
+            results(&["2 ** 2000000", "1 << 2000000"]),
+            [
+                "Operands too large for '**', got '2' and '2000000' at line 1",
+                "Operands too large for '<<', got '1' and '2000000' at line 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn inexact_integer_division_is_rational() {
+        assert_eq!(
+            results(&[
+                "7 / 2",
+                "6 / 2",
+                "-1 / 3 + 1 / 3",
+                "9223372036854775807 / 2",
+                "-12345678901234567890123 / 7",
+                "12345678901234567890 / 4",
+                "12345678901234567890 / 2",
+                "(12345678901234567890 / 7) * 7",
+            ]),
+            [
+                "7/2",
+                "3",
+                "0",
+                "9223372036854775807/2",
+                "-12345678901234567890123/7",
+                "6172839450617283945/2",
+                "6172839450617283945",
+                "12345678901234567890",
+            ]
+        );
+    }
+
+    #[test]
+    fn floats_compare_exactly_with_integers_and_rationals() {
+        assert_eq!(
+            results(&[
+                "9007199254740993 == 9007199254740992.0",
+                "9007199254740993 > 9007199254740992.0",
+                "9007199254740992 == 9007199254740992.0",
+                "0.1 == 1/10r",
+                "0.5 == 1/2r",
+                "1/3r < 0.3333333333333333",
+                "100000000000000000000 < 1.0 / 0",
+                "-100000000000000000000 > -1.0 / 0",
+                "1 == 0.0 / 0",
+            ]),
+            ["false", "true", "true", "false", "true", "false", "true", "true", "false"]
+        );
+        assert_eq!(
+            eval("var m = {}; m[0.5] = 1; m[9007199254740992.0] = 2; [m[1/2r], m[9007199254740993]];"),
+            Ok("[1, nil]".to_string())
+        );
+    }
+
+    #[test]
+    fn bitwise_operators_take_integers() {
+        assert_eq!(
+            results(&[
+                "6 & 3",
+                "6 | 3",
+                "6 ^ 3",
+                "~5",
+                "-8 >> 1",
+                "1 << 62",
+                "1 | 2 ^ 3 & 4",
+                "1 << 2 + 1"
+            ]),
+            ["2", "7", "5", "-6", "-4", "4611686018427387904", "3", "8"]
+        );
+        assert_eq!(
+            results(&["5 & 1.5", "1 << -1", "12345678901234567890 & 1"]),
+            [
+                "Operands must be integers, got '5' and '1.5' at line 1",
+                "Negative shift count '-1' at line 1",
+                "Operands too large for '&', got '12345678901234567890' and '1' at line 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_assignment_and_increments() {
+        assert_eq!(
+            eval("var x = 5; x += 2; x -= 1; x *= 3; x /= 2; x %= 5; x;"),
+            Ok("4".to_string())
+        );
+        assert_eq!(
+            eval("var i = 1; [i++, i, ++i, i--, --i];"),
+            Ok("[1, 2, 3, 3, 1]".to_string())
+        );
+        assert_eq!(
+            eval("var l = [1]; var m = {\"k\": 2}; l[0] += 4; l[0]++; m[\"k\"] *= 4; [l, m];"),
+            Ok("[[6], {\"k\": 8}]".to_string())
+        );
+        assert_eq!(
+            eval("var x = 9223372036854775807; x++; x;"),
+            Ok("9223372036854775808".to_string())
+        );
+    }
+
+    const VECTOR: &str = "class V {
+        init(x) { this.x = x; }
+        __add__(o) { return V(this.x + o.x); }
+        __neg__() { return V(-this.x); }
+        __eq__(o) { return this.x == o.x; }
+        __compare__(o) { return this.x - o.x; }
+        __index__(i) { return this.x * i; }
+        __call__(a) { return a + this.x; }
+        __str__() { return \"V\"; }
+    }";
+
+    #[test]
+    fn labeled_jumps_leave_the_loop_they_name() {
+        let source = "var seen = 0;
+            outer: for (var i = 1; i <= 3; i = i + 1) {
+                for (var j = 1; j <= 3; j = j + 1) {
+                    if (j == 2) continue outer;
+                    if (i == 3) break outer;
+                    seen = seen * 100 + i * 10 + j;
+                }
+            }
+            seen;";
+        assert_eq!(eval(source), Ok("1121".to_string()));
+        let source = "var seen = 0;
+            rows: for x in [1, 2, 3] {
+                var k = 0;
+                while (true) {
+                    k = k + 1;
+                    if (k > x) continue rows;
+                    if (x == 3) break;
+                    seen = seen * 10 + x;
+                }
+            }
+            seen;";
+        assert_eq!(eval(source), Ok("122".to_string()));
+        let source = "var seen = 0; var i = 0;
+            odd: while (i < 5) {
+                i = i + 1;
+                if (i % 2 == 0) continue odd;
+                seen = seen * 10 + i;
+            }
+            seen;";
+        assert_eq!(eval(source), Ok("135".to_string()));
+    }
+
+    #[test]
+    fn jumps_need_a_loop_in_the_same_function() {
+        for (source, error) in [
+            ("break;", "Can't use 'break' outside of a loop."),
+            (
+                "while (true) { break nope; }",
+                "No enclosing loop labeled 'nope' for 'break'.",
+            ),
+            (
+                "while (true) { fun f() { continue; } }",
+                "Can't use 'continue' outside of a loop.",
+            ),
+            (
+                "a: while (true) { fun f() { while (true) { break a; } } }",
+                "No enclosing loop labeled 'a' for 'break'.",
+            ),
+            ("a: var x = 1;", "Expect loop after label."),
+        ] {
+            assert_eq!(eval(source), Err(format!("{} at line 1", error)));
+        }
+    }
+
+    #[test]
+    fn destructuring_assignment_swaps_values() {
+        assert_eq!(
+            eval("var a = 1; var b = 2; [a, b] = [b, a]; [a, b];"),
+            Ok("[2, 1]".to_string())
+        );
+        assert_eq!(
+            eval("var a = 1; var b = 2; var c = 3; [a, b, c] = [c, a, b]; [a, b, c];"),
+            Ok("[3, 1, 2]".to_string())
+        );
+        assert_eq!(
+            eval("var x; var y; {x, y} = {\"x\": 1, \"y\": 2}; [x, y];"),
+            Ok("[1, 2]".to_string())
+        );
+        assert_eq!(
+            eval("[a] = [1];"),
+            Err("Undefined variable 'a' at line 1".to_string())
+        );
+        assert_eq!(
+            eval("const [a, b] = [1, 2]; a = 3;"),
+            Err("Can't assign to constant 'a' at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn destructuring_fills_in_defaults_and_rest() {
+        assert_eq!(
+            results(&[
+                "var [x, y, ...r] = [1, 2, 3, 4]; [x, y, r]",
+                "var [x, ...r] = [1]; r",
+                "var [a, [b, c = 5]] = [1, [2]]; [a, b, c]",
+                "var [a, b = a * 2] = [3]; b",
+                "var {port = 8080, host} = {\"host\": \"h\"}; [port, host]",
+                "var {port = 8080} = {\"port\": 1}; port",
+                "var {port = 8080} = {\"port\": nil}; port",
+                "var {name, inner: {x}} = {\"name\": \"n\", \"inner\": {\"x\": 3}}; [name, x]",
+            ]),
+            [
+                "[1, 2, [3, 4]]",
+                "[]",
+                "[1, 2, 5]",
+                "6",
+                "[8080, \"h\"]",
+                "1",
+                "8080",
+                "[\"n\", 3]",
+            ]
+        );
+        // Defaults are only evaluated for what's missing.
+        assert_eq!(
+            eval("var calls = 0; fun f() { calls = calls + 1; return 9; } var [a = f(), b = f()] = [1]; [a, b, calls];"),
+            Ok("[1, 9, 1]".to_string())
+        );
+    }
+
+    #[test]
+    fn destructuring_the_wrong_shape_is_an_error() {
+        assert_eq!(
+            results(&[
+                "var [a, b] = [1]",
+                "var [a] = [1, 2]",
+                "var [a] = 1",
+                "var {a} = {}",
+                "var {a} = [1]",
+                "var [a, [b]] = [1, 2]",
+            ]),
+            [
+                "Can't destructure '[1]' with pattern '[a, b]' at line 1",
+                "Can't destructure '[1, 2]' with pattern '[a]' at line 1",
+                "Can't destructure '1' with pattern '[a]' at line 1",
+                "Can't destructure '{}' with pattern '{a}' at line 1",
+                "Can't destructure '[1]' with pattern '{a}' at line 1",
+                "Can't destructure '2' with pattern '[b]' at line 1",
+            ]
+        );
+    }
+
+    const COMPARABLE: &str =
+        "trait Comparable { compare(other); lt(other) { return this.compare(other) < 0; } }";
+
+    #[test]
+    fn traits_require_some_methods_and_lend_others() {
+        assert_eq!(
+            eval(&format!(
+                "{COMPARABLE} class Money impl Comparable {{
+                    init(n) {{ this.n = n; }}
+                    compare(other) {{ return this.n - other.n; }}
+                }}
+                [Money(1).lt(Money(2)), Money(3).lt(Money(2))];"
+            )),
+            Ok("[true, false]".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{COMPARABLE} class M impl Comparable {{
+                    compare(other) {{ return 0; }}
+                    lt(other) {{ return \"own\"; }}
+                }}
+                M().lt(M());"
+            )),
+            Ok("own".to_string())
+        );
+        for (source, error) in [
+            (
+                format!("{COMPARABLE} class M impl Comparable {{}}").as_str(),
+                "Class 'M' is missing method 'compare' required by trait 'Comparable'",
+            ),
+            (
+                "trait A { f() { return 1; } } trait B { f() { return 2; } } class C impl A, B {}",
+                "Class 'C' gets 'f' from both 'A' and 'B'; define it to choose",
+            ),
+            ("class A impl Nope {}", "'Nope' is not a trait"),
+            ("var x = 1; class A impl x {}", "'x' is not a trait"),
+            (
+                "trait A { f(); } A();",
+                "Can only call functions, classes and variant constructors, got 'A'",
+            ),
+        ] {
+            assert_eq!(eval(source), Err(format!("{} at line 1", error)));
+        }
+    }
+
+    #[test]
+    fn is_and_implements_ask_about_traits() {
+        let classes = format!(
+            "{COMPARABLE} trait Named {{ name(); }}
+            class M impl Comparable, Named {{
+                compare(other) {{ return 0; }}
+                name() {{ return \"m\"; }}
+            }}
+            class Plain {{}}"
+        );
+        assert_eq!(
+            eval(&format!(
+                "{classes} [M() is Comparable, M() is Named, M() is M, implements(M, Named),
+                    Plain() is Comparable, implements(Plain, Comparable),
+                    1 is Comparable, implements(1, Comparable)];"
+            )),
+            Ok("[true, true, true, true, false, false, false, false]".to_string())
+        );
+        assert_eq!(
+            results(&[
+                &format!("{classes} implements(M, M)"),
+                &format!("{classes} M() is 1"),
+            ]),
+            [
+                "implements() expects a trait, got 'M' at line 6",
+                "Right operand of 'is' must be a class, trait or enum, got '1' at line 6",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_read_the_outer_variable_until_shadowed() {
+        let source = "var a = \"global\"; var seen;
+            {
+                var before = a;
+                var a = \"inner\";
+                {
+                    var middle = a;
+                    var a = \"innermost\";
+                    seen = [before, middle, a];
+                }
+                seen = [seen, a];
+            }
+            [seen, a];";
+        assert_eq!(
+            eval(source),
+            Ok("[[[\"global\", \"inner\", \"innermost\"], \"inner\"], \"global\"]".to_string())
+        );
+        let source = "var v = \"global\";
+            map([1, 2], x => { var before = v; var v = x; return [before, v]; });";
+        assert_eq!(
+            eval(source),
+            Ok("[[\"global\", 1], [\"global\", 2]]".to_string())
+        );
+        assert_eq!(
+            eval("{ var before = z; var z = 1; }"),
+            Err("Undefined variable 'z' at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn closures_see_shadowing_declared_after_them() {
+        let source = "var a = \"global\"; var seen;
+            {
+                fun show() { return a; }
+                var before = show();
+                var a = \"local\";
+                seen = [before, show()];
+            }
+            seen;";
+        assert_eq!(eval(source), Ok("[\"global\", \"local\"]".to_string()));
+        // Scopes are lexical: a caller's locals don't shadow the callee's.
+        let source = "var a = \"global\"; fun f() { return a; } var seen; { var a = \"shadow\"; seen = f(); } seen;";
+        assert_eq!(eval(source), Ok("global".to_string()));
+        let source = "fun g(a) { var inner; { var before = a; var a = \"inner\"; inner = [before, a]; } return [inner, a]; }
+            g(\"param\");";
+        assert_eq!(
+            eval(source),
+            Ok("[[\"param\", \"inner\"], \"param\"]".to_string())
+        );
+    }
+
+    #[test]
+    fn assignment_before_shadowing_changes_the_outer_variable() {
+        assert_eq!(
+            eval("var b = 1; var inner; { b = 2; var b = 3; b = 4; inner = b; } [inner, b];"),
+            Ok("[4, 2]".to_string())
+        );
+        assert_eq!(
+            eval("const c = 1; { c = 2; var c = 3; }"),
+            Err("Can't assign to constant 'c' at line 1".to_string())
+        );
+        assert_eq!(
+            eval("const c = 1; var inner; { var c = 3; c = 4; inner = c; } [inner, c];"),
+            Ok("[4, 1]".to_string())
+        );
+    }
+
+    #[test]
+    fn reserved_methods_implement_operators() {
+        assert_eq!(
+            eval(&format!(
+                "{} [(V(1) + V(2)).x, (-V(1)).x, V(1) == V(1), V(1) < V(2), V(3)[2], V(3)(4)];",
+                VECTOR
+            )),
+            Ok("[3, -1, true, true, 6, 7]".to_string())
+        );
+        assert_eq!(eval(&format!("{} V(1);", VECTOR)), Ok("V".to_string()));
+    }
+
+    #[test]
+    fn ordinary_methods_are_not_operators() {
+        let cart =
+            "class Cart { init() { this.n = 0; } add(x) { this.n = this.n + x; return this; } }";
+        assert_eq!(
+            eval(&format!("{} Cart().add(2).n;", cart)),
+            Ok("2".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{} Cart() + 5;", cart)),
+            Err("Operands must be numbers, got 'Cart instance' and '5' at line 1".to_string())
+        );
+    }
+
+    const SHAPES: &str = "trait Named { describe() { return [\"a\", this.name()]; } }
+    class Shape impl Named {
+        init(name) { this.label = name; }
+        name() { return this.label; }
+        area() { return 0; }
+        get kind { return \"shape\"; }
+        set size(s) { this.label = s; }
+        static made = 0;
+        static make(n) { return this(n); }
+    }
+    class Square < Shape {
+        init(side) { super.init(\"square\"); this.side = side; }
+        area() { return this.side * this.side; }
+        get kind { return [\"square\", super.kind]; }
+        static make(n) { return [\"made\", super.make(n).side]; }
+    }
+    class Unit < Square { init() { super.init(1); } }";
+
+    #[test]
+    fn subclasses_inherit_what_they_dont_override() {
+        assert_eq!(
+            eval(&format!(
+                "{SHAPES} var s = Square(3); [s.area(), s.name(), s.describe(), s.kind];"
+            )),
+            Ok("[9, \"square\", [\"a\", \"square\"], [\"square\", \"shape\"]]".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{SHAPES} var u = Unit(); u.size = \"unit\"; [u.area(), u.name(), u.kind];"
+            )),
+            Ok("[1, \"unit\", [\"square\", \"shape\"]]".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{SHAPES} [Unit.made, Square.make(2)];")),
+            Ok("[0, [\"made\", 2]]".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{SHAPES} [Unit() is Shape, Unit() is Named, Shape(1) is Square, implements(Square, Named)];"
+            )),
+            Ok("[true, true, false, true]".to_string())
+        );
+    }
+
+    #[test]
+    fn superclasses_must_be_classes_that_have_the_member() {
+        assert_eq!(
+            eval("var N = 1; class A < N {}"),
+            Err("'N' is not a class at line 1".to_string())
+        );
+        assert_eq!(
+            eval("class A {} class B < A { f() { return super.g; } } B().f();"),
+            Err("Undefined property 'g' of superclass 'A' at line 1".to_string())
+        );
+        assert_eq!(
+            eval("class A { f() { return super.f(); } }"),
+            Err("Can't use 'super' outside a class with a superclass. at line 1".to_string())
+        );
+        assert_eq!(
+            eval("class A < A {}"),
+            Err("A class can't inherit from itself. at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn inherited_methods_satisfy_traits() {
+        let source = "trait Sized { size(); big() { return this.size() > 1; } }
+            class A { size() { return 2; } }
+            class B < A impl Sized {}
+            B().big();";
+        assert_eq!(eval(source), Ok("true".to_string()));
+        let source = "trait T { f() { return \"trait\"; } }
+            class A { f() { return \"inherited\"; } }
+            class B < A impl T {}
+            B().f();";
+        assert_eq!(eval(source), Ok("inherited".to_string()));
+    }
+
+    #[test]
+    fn positional_instance_patterns_need_the_fields_init_names() {
+        let point = "class P { init(x, y) { this.x = x; this.y = y; } }";
+        assert_eq!(
+            eval(&format!(
+                "{point} match P(1, 2) {{ P(a, b) => [a, b], _ => \"no\" }};"
+            )),
+            Ok("[1, 2]".to_string())
+        );
+        let renamed = "class Q { init(qx, qy) { this.x = qx; this.y = qy; } }";
+        assert_eq!(
+            eval(&format!("{renamed} match Q(1, 2) {{ Q(a, b) => [a, b], _ => \"no\" }};")),
+            Err("Positional pattern 1 for 'Q' stands for field 'qx', named by init's parameter, but the instance has no such field; match fields by name instead at line 1".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{renamed} match Q(1, 2) {{ Q(x: a, y: b) => [a, b], _ => \"no\" }};"
+            )),
+            Ok("[1, 2]".to_string())
+        );
+    }
+
+    #[test]
+    fn instance_patterns_match_subclasses() {
+        let classes = "class P { init(x) { this.x = x; } } class Q < P {} class R {}";
+        assert_eq!(
+            eval(&format!(
+                "{classes} map([P(1), Q(2), R()], v => match v {{ P(x) => x, _ => \"no\" }});"
+            )),
+            Ok("[1, 2, \"no\"]".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{classes} match P(1) {{ Q(x) => x, _ => \"no\" }};"
+            )),
+            Ok("no".to_string())
+        );
+    }
+
+    #[test]
+    fn printing_raises_what_goes_wrong_in_str() {
+        assert_eq!(
+            eval("class W { __str__() { return 1; } } try(fun() { print W(); });"),
+            Ok("Err(\"__str__() must return a string, got '1'\")".to_string())
+        );
+        assert_eq!(
+            eval("class E { __str__() { return [1][5]; } } try(fun() { print E(); });"),
+            Ok("Err(\"List index 5 out of range for length 1\")".to_string())
+        );
+    }
+
+    #[test]
+    fn error_messages_do_not_run_str() {
+        assert_eq!(
+            eval("var calls = 0; class S { __str__() { calls = calls + 1; return \"S\"; } } try(() => 1 + S()); calls;"),
+            Ok("0".to_string())
+        );
     }
 }
@@ -0,0 +1,116 @@
+use crate::error::{ErrorKind, RuntimeError};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest a blocking wait, like `recv` or the event loop's, goes without
+/// checking whether it has been interrupted or has timed out.
+pub const POLL: Duration = Duration::from_millis(50);
+
+/// How many handles are interrupted, so that the interpreter only has to
+/// look at its own handle when some handle is.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Stops a run from another thread, or from a signal handler. The run, and
+/// the threads it spawned, fail with an `Interrupted` error at their next
+/// step, which `try` doesn't catch.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the run to stop. Only touches atomics, so it is safe to call
+    /// from a signal handler.
+    pub fn interrupt(&self) {
+        if !self.interrupted.swap(true, Ordering::Relaxed) {
+            PENDING.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Forgets an interrupt, ready for the next run.
+    pub fn reset(&self) {
+        if self.interrupted.swap(false, Ordering::Relaxed) {
+            PENDING.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<InterruptHandle>> = const { RefCell::new(None) };
+}
+
+/// Makes `handle` the one the current thread stops for.
+pub fn start(handle: &InterruptHandle) {
+    CURRENT.with(|current| *current.borrow_mut() = Some(handle.clone()));
+}
+
+/// The handle the current thread stops for, for a thread it spawns.
+pub fn current() -> Option<InterruptHandle> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Whether the current thread has been asked to stop.
+#[inline]
+pub fn interrupted() -> bool {
+    PENDING.load(Ordering::Relaxed) > 0
+        && CURRENT.with(|current| {
+            current
+                .borrow()
+                .as_ref()
+                .is_some_and(|h| h.is_interrupted())
+        })
+}
+
+/// Fails if the current thread has been asked to stop.
+#[inline]
+pub fn check(line: usize) -> Result<(), RuntimeError> {
+    if interrupted() {
+        return Err(RuntimeError::with_kind(
+            ErrorKind::Interrupted,
+            "Interrupted".to_string(),
+            line,
+        ));
+    }
+    Ok(())
+}
+
+/// Interrupts `handle` whenever the process gets SIGINT, as from Ctrl-C.
+#[cfg(unix)]
+pub fn on_sigint(handle: &InterruptHandle) {
+    use std::os::raw::c_int;
+    use std::sync::OnceLock;
+
+    const SIGINT: c_int = 2;
+    static HANDLE: OnceLock<InterruptHandle> = OnceLock::new();
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn handler(_: c_int) {
+        if let Some(handle) = HANDLE.get() {
+            handle.interrupt();
+        }
+    }
+
+    if HANDLE.set(handle.clone()).is_ok() {
+        // SAFETY: `handler` only touches atomics, which is allowed in a
+        // signal handler.
+        unsafe {
+            signal(SIGINT, handler);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn on_sigint(_: &InterruptHandle) {}
@@ -0,0 +1,227 @@
+use crate::class::Instance;
+use crate::error::RuntimeError;
+use crate::generator::Generator;
+use crate::map::Map;
+use crate::thread::{self, Channel};
+use crate::value::{List, Value};
+use core::cell::{RefCell, RefMut};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Lazy integer sequence from `start..end` or `start..=end`, advancing by
+/// `step` (which may be negative but never zero).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    /// Number of values the range produces, which for `i64::MIN..=i64::MAX`
+    /// is one more than `u64` holds.
+    pub fn len(&self) -> u128 {
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        let last = match (self.inclusive, step > 0) {
+            (true, _) => end,
+            (false, true) => end - 1,
+            (false, false) => end + 1,
+        };
+        let span = if step > 0 { last - start } else { start - last };
+        if span < 0 {
+            0
+        } else {
+            (span / step.abs() + 1) as u128
+        }
+    }
+
+    fn nth(&self, n: usize) -> i64 {
+        (self.start as i128 + n as i128 * self.step as i128) as i64
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)?;
+        if self.step != 1 {
+            write!(f, " step {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iteration state over any iterable value. Built-in collections are
+/// walked by position, so a list appended to mid-loop yields the new
+/// elements too; user objects are driven through their `next()` method,
+/// with `nil` marking the end.
+pub enum Iter {
+    List {
+        elements: Rc<RefCell<List>>,
+        position: usize,
+    },
+    /// Yields keys, or `[key, value]` pairs when `entries` is set.
+    Map {
+        map: Rc<RefCell<Map>>,
+        position: usize,
+        entries: bool,
+    },
+    String {
+        chars: Vec<char>,
+        position: usize,
+    },
+    Range {
+        range: Range,
+        position: usize,
+    },
+    Object(Rc<RefCell<Instance>>),
+    Generator(Generator),
+    /// Receives until the channel is closed and drained.
+    Channel(Arc<Channel>),
+    /// An iterator value from `iter()`, advanced in place so that progress
+    /// is visible to every holder.
+    Shared(Rc<RefCell<Iter>>),
+}
+
+impl Iter {
+    pub fn next(&mut self, line: usize) -> Result<Option<Value>, RuntimeError> {
+        match self {
+            Iter::List { elements, position } => {
+                let element = elements.borrow().get(*position).cloned();
+                *position += 1;
+                Ok(element)
+            }
+            Iter::Map {
+                map,
+                position,
+                entries,
+            } => {
+                let item = map.borrow().entry(*position).map(|(key, value)| {
+                    if *entries {
+                        Value::list(vec![key.clone(), value.clone()])
+                    } else {
+                        key.clone()
+                    }
+                });
+                *position += 1;
+                Ok(item)
+            }
+            Iter::String { chars, position } => {
+                let c = chars
+                    .get(*position)
+                    .map(|c| Value::String(c.to_string().into()));
+                *position += 1;
+                Ok(c)
+            }
+            Iter::Range { range, position } => {
+                if *position as u128 >= range.len() {
+                    return Ok(None);
+                }
+                *position += 1;
+                Ok(Some(Value::Integer(range.nth(*position - 1))))
+            }
+            Iter::Object(instance) => {
+                match Instance::call_method(instance, "next", Vec::new(), line)? {
+                    Some(Value::Nil) => Ok(None),
+                    Some(value) => Ok(Some(value)),
+                    None => Err(RuntimeError::new(
+                        format!(
+                            "Iterator '{}' has no 'next' method",
+                            Value::Instance(Rc::clone(instance))
+                        ),
+                        line,
+                    )),
+                }
+            }
+            Iter::Generator(generator) => Ok(generator.resume(Value::Nil, line)?.yielded()),
+            Iter::Channel(channel) => {
+                Ok(thread::receive(std::slice::from_ref(channel), line)?.map(|(_, value)| value))
+            }
+            Iter::Shared(iter) => borrow_running(iter, line)?.next(line),
+        }
+    }
+
+    /// Drains the remaining items into a vector.
+    pub fn collect(mut self, line: usize) -> Result<Vec<Value>, RuntimeError> {
+        let mut items = Vec::new();
+        while let Some(item) = self.next(line)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+impl fmt::Debug for Iter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Iter::Generator(generator) => write!(f, "<generator {}>", generator.name()),
+            _ => write!(f, "<iterator>"),
+        }
+    }
+}
+
+/// Borrows a shared iterator to advance it, failing rather than panicking
+/// when it is advanced again from inside its own `next()`.
+pub fn borrow_running(
+    iter: &Rc<RefCell<Iter>>,
+    line: usize,
+) -> Result<RefMut<'_, Iter>, RuntimeError> {
+    iter.try_borrow_mut()
+        .map_err(|_| RuntimeError::new("Iterator is already running".to_string(), line))
+}
+
+/// Starts iterating over `value`. Instances are iterable when their class
+/// defines `iter()`, whose result is iterated in turn, or `next()`, in
+/// which case the instance is its own iterator.
+pub fn iterate(value: &Value, line: usize) -> Result<Iter, RuntimeError> {
+    match value {
+        Value::List(elements) => Ok(Iter::List {
+            elements: Rc::clone(elements),
+            position: 0,
+        }),
+        Value::Map(map) => Ok(Iter::Map {
+            map: Rc::clone(map),
+            position: 0,
+            entries: false,
+        }),
+        Value::String(s) => Ok(Iter::String {
+            chars: s.chars().collect(),
+            position: 0,
+        }),
+        Value::Range(range) => Ok(Iter::Range {
+            range: (**range).clone(),
+            position: 0,
+        }),
+        Value::Iterator(iter) => Ok(Iter::Shared(Rc::clone(iter))),
+        Value::Channel(channel) => Ok(Iter::Channel(Arc::clone(channel))),
+        Value::Instance(instance) => {
+            match Instance::call_method(instance, "iter", Vec::new(), line)? {
+                Some(Value::Instance(iterator)) => Ok(Iter::Object(iterator)),
+                Some(iterable) => iterate(&iterable, line),
+                None if instance.borrow().class.find_method("next").is_some() => {
+                    Ok(Iter::Object(Rc::clone(instance)))
+                }
+                None => Err(not_iterable(value, line)),
+            }
+        }
+        _ => Err(not_iterable(value, line)),
+    }
+}
+
+/// Like `iterate`, but a map yields `[key, value]` pairs rather than keys.
+pub fn iterate_entries(value: &Value, line: usize) -> Result<Iter, RuntimeError> {
+    match value {
+        Value::Map(map) => Ok(Iter::Map {
+            map: Rc::clone(map),
+            position: 0,
+            entries: true,
+        }),
+        _ => iterate(value, line),
+    }
+}
+
+fn not_iterable(value: &Value, line: usize) -> RuntimeError {
+    RuntimeError::new(format!("Can't iterate over '{}'", value), line)
+}
@@ -1,17 +1,22 @@
 use crate::error::LexerError;
 use crate::token::{Token, TokenType};
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::{mem, str::Chars};
 
 pub struct Lexer<'a> {
     source: &'a str,
     tokens: Vec<Token>,
+    /// One copy of each distinct lexeme and string literal, shared by every
+    /// token that spells it. The parser's names and literals then clone in
+    /// O(1), and string literals evaluate without copying.
+    interned: HashSet<Arc<str>>,
 
     start: usize,
     current: usize,
     line: usize,
 
     char_iter: Chars<'a>,
-    current_char: Option<char>,
 }
 
 impl<'a> Lexer<'a> {
@@ -19,13 +24,13 @@ impl<'a> Lexer<'a> {
         Self {
             source,
             tokens: Vec::new(),
+            interned: HashSet::new(),
 
             start: 0,
             current: 0,
             line: 1,
 
             char_iter: source.chars(),
-            current_char: None,
         }
     }
 
@@ -35,8 +40,9 @@ impl<'a> Lexer<'a> {
             self.scan_token()?;
         }
 
+        let lexeme = self.intern("");
         self.tokens
-            .push(Token::new(TokenType::EOF, "".to_string(), None, self.line));
+            .push(Token::new(TokenType::Eof, lexeme, None, self.line));
         Ok(mem::take(&mut self.tokens))
     }
 
@@ -48,18 +54,67 @@ impl<'a> Lexer<'a> {
                 ')' => self.add_token(TokenType::RightParen, None),
                 '{' => self.add_token(TokenType::LeftBrace, None),
                 '}' => self.add_token(TokenType::RightBrace, None),
+                '[' => self.add_token(TokenType::LeftBracket, None),
+                ']' => self.add_token(TokenType::RightBracket, None),
                 ',' => self.add_token(TokenType::Comma, None),
-                '.' => self.add_token(TokenType::Dot, None),
-                '-' => self.add_token(TokenType::Minus, None),
-                '+' => self.add_token(TokenType::Plus, None),
+                '.' => {
+                    if self.match_char('.') {
+                        self.check_matches(
+                            &[('.', TokenType::Ellipsis), ('=', TokenType::DotDotEqual)],
+                            TokenType::DotDot,
+                        )
+                    } else {
+                        self.add_token(TokenType::Dot, None)
+                    }
+                }
                 ';' => self.add_token(TokenType::Semicolon, None),
-                '*' => self.add_token(TokenType::Star, None),
+                '&' => self.add_token(TokenType::Ampersand, None),
+                '|' => self.add_token(TokenType::Pipe, None),
+                '^' => self.add_token(TokenType::Caret, None),
+                '~' => self.add_token(TokenType::Tilde, None),
+                ':' => self.add_token(TokenType::Colon, None),
 
                 // operators (single or double char lexemes)
                 '!' => self.check_match('=', TokenType::BangEqual, TokenType::Bang),
-                '=' => self.check_match('=', TokenType::EqualEqual, TokenType::Equal),
-                '<' => self.check_match('=', TokenType::LessEqual, TokenType::Less),
-                '>' => self.check_match('=', TokenType::GreaterEqual, TokenType::Greater),
+                '=' => self.check_matches(
+                    &[('=', TokenType::EqualEqual), ('>', TokenType::Arrow)],
+                    TokenType::Equal,
+                ),
+                '%' => self.check_match('=', TokenType::PercentEqual, TokenType::Percent),
+                '<' => self.check_matches(
+                    &[('=', TokenType::LessEqual), ('<', TokenType::LessLess)],
+                    TokenType::Less,
+                ),
+                '>' => self.check_matches(
+                    &[
+                        ('=', TokenType::GreaterEqual),
+                        ('>', TokenType::GreaterGreater),
+                    ],
+                    TokenType::Greater,
+                ),
+                '?' => self.check_matches(
+                    &[
+                        ('.', TokenType::QuestionDot),
+                        ('?', TokenType::QuestionQuestion),
+                    ],
+                    TokenType::Question,
+                ),
+                '+' => self.check_matches(
+                    &[('=', TokenType::PlusEqual), ('+', TokenType::PlusPlus)],
+                    TokenType::Plus,
+                ),
+                '-' => self.check_matches(
+                    &[
+                        ('=', TokenType::MinusEqual),
+                        ('-', TokenType::MinusMinus),
+                        ('>', TokenType::MinusGreater),
+                    ],
+                    TokenType::Minus,
+                ),
+                '*' => self.check_matches(
+                    &[('=', TokenType::StarEqual), ('*', TokenType::StarStar)],
+                    TokenType::Star,
+                ),
 
                 // longer lexemes
                 '/' => {
@@ -71,7 +126,7 @@ impl<'a> Lexer<'a> {
                             }
                         }
                     } else {
-                        let _ = self.add_token(TokenType::Slash, None);
+                        let _ = self.check_match('=', TokenType::SlashEqual, TokenType::Slash);
                     }
                     Ok(())
                 }
@@ -89,10 +144,10 @@ impl<'a> Lexer<'a> {
                 '"' => self.string(),
 
                 // number literals
-                c if c.is_digit(10) => self.number(),
+                c if c.is_ascii_digit() => self.number(),
 
                 // reserved words and identifiers
-                c if c.is_alphabetic() => self.identifier(),
+                c if c.is_alphabetic() || c == '_' => self.identifier(),
 
                 // default
                 _ => Err(LexerError::UnexpectedCharacter {
@@ -101,14 +156,14 @@ impl<'a> Lexer<'a> {
                 }),
             }
         } else {
-            let _ = self.add_token(TokenType::EOF, None);
+            let _ = self.add_token(TokenType::Eof, None);
             Ok(())
         }
     }
 
     fn identifier(&mut self) -> Result<(), LexerError> {
         while let Some(c) = self.peek() {
-            if c.is_alphanumeric() {
+            if c.is_alphanumeric() || c == '_' {
                 self.advance();
             } else {
                 break;
@@ -117,21 +172,33 @@ impl<'a> Lexer<'a> {
         let text = &self.source[self.start..self.current];
         let token_type = match text {
             "and" => TokenType::And,
+            "async" => TokenType::Async,
+            "await" => TokenType::Await,
+            "break" => TokenType::Break,
             "class" => TokenType::Class,
+            "const" => TokenType::Const,
+            "continue" => TokenType::Continue,
             "else" => TokenType::Else,
+            "enum" => TokenType::Enum,
             "false" => TokenType::False,
             "for" => TokenType::For,
             "fun" => TokenType::Fun,
             "if" => TokenType::If,
+            "impl" => TokenType::Impl,
+            "in" => TokenType::In,
+            "is" => TokenType::Is,
+            "match" => TokenType::Match,
             "nil" => TokenType::Nil,
             "or" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
             "this" => TokenType::This,
+            "trait" => TokenType::Trait,
             "true" => TokenType::True,
             "var" => TokenType::Var,
             "while" => TokenType::While,
+            "yield" => TokenType::Yield,
             _ => TokenType::Identifier,
         };
         self.add_token(token_type, None)?;
@@ -141,25 +208,28 @@ impl<'a> Lexer<'a> {
 
     fn number(&mut self) -> Result<(), LexerError> {
         while let Some(c) = self.peek() {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 self.advance();
             } else {
                 break;
             }
         }
-        if let Some(c) = self.peek() {
-            if c == '.' {
-                self.advance();
-                while let Some(c) = self.peek() {
-                    if c.is_digit(10) {
-                        self.advance();
-                    } else {
-                        break;
-                    }
+        // a '.' only starts a fraction when a digit follows, so `0..10` is a range
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+            while let Some(c) = self.peek() {
+                if c.is_ascii_digit() {
+                    self.advance();
+                } else {
+                    break;
                 }
             }
         }
-        let literal = self.source[self.start..self.current].to_string();
+        // a trailing `r` marks an exact rational literal, e.g. `0.1r`
+        if self.peek() == Some('r') && !self.peek_next().is_some_and(|c| c.is_alphanumeric()) {
+            self.advance();
+        }
+        let literal = &self.source[self.start..self.current];
         self.add_token(TokenType::Number, Some(literal))?;
 
         Ok(())
@@ -178,7 +248,7 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        let literal = self.source[self.start + 1..self.current - 1].to_string();
+        let literal = &self.source[self.start + 1..self.current - 1];
         self.add_token(TokenType::String, Some(literal))?;
 
         Ok(())
@@ -188,6 +258,10 @@ impl<'a> Lexer<'a> {
         self.char_iter.clone().next()
     }
 
+    fn peek_next(&self) -> Option<char> {
+        self.char_iter.clone().nth(1)
+    }
+
     fn check_match(
         &mut self,
         expected: char,
@@ -204,8 +278,22 @@ impl<'a> Lexer<'a> {
         Ok(())
     }
 
+    fn check_matches(
+        &mut self,
+        candidates: &[(char, TokenType)],
+        otherwise: TokenType,
+    ) -> Result<(), LexerError> {
+        let token_type = candidates
+            .iter()
+            .find(|(expected, _)| self.match_char(*expected))
+            .map_or(otherwise, |(_, token_type)| *token_type);
+        self.add_token(token_type, None)?;
+
+        Ok(())
+    }
+
     fn match_char(&mut self, expected: char) -> bool {
-        match self.current_char {
+        match self.peek() {
             Some(c) if c == expected => {
                 self.advance();
                 true
@@ -216,23 +304,32 @@ impl<'a> Lexer<'a> {
 
     fn advance(&mut self) -> Option<char> {
         let next_char = self.char_iter.next();
-        self.current += 1;
-        self.current_char = next_char;
+        self.current += next_char.map_or(0, char::len_utf8);
         next_char
     }
 
     fn add_token(
         &mut self,
         token_type: TokenType,
-        literal: Option<String>,
+        literal: Option<&str>,
     ) -> Result<(), LexerError> {
-        let text = &self.source[self.start..self.current];
+        let lexeme = self.intern(&self.source[self.start..self.current]);
+        let literal = literal.map(|literal| self.intern(literal));
         self.tokens
-            .push(Token::new(token_type, text.to_string(), literal, self.line));
+            .push(Token::new(token_type, lexeme, literal, self.line));
 
         Ok(())
     }
 
+    fn intern(&mut self, text: &str) -> Arc<str> {
+        if let Some(interned) = self.interned.get(text) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(text);
+        self.interned.insert(Arc::clone(&interned));
+        interned
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
@@ -0,0 +1,410 @@
+use crate::error::{ErrorKind, RuntimeError};
+use crate::interrupt;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The stack every interpreter thread runs on, the main one included, so
+/// that running out of it can be noticed before it happens.
+pub const STACK_SIZE: usize = 64 << 20;
+
+/// How much of the stack calls may use before they fail with a stack
+/// overflow. The rest is headroom for the Rust frames between two calls,
+/// such as deeply nested expressions.
+const STACK_BUDGET: usize = STACK_SIZE / 4 * 3;
+
+/// How many steps go by between looks at the clock, the heap and the steps
+/// other threads have taken.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// How many limb operations of big-number arithmetic count as one step.
+const WORK_PER_STEP: u64 = 1024;
+
+/// Bounds on what a script may use, each unlimited when `None`. A step is
+/// one call or one loop iteration, which together bound how long a script
+/// can run without returning to its caller, or `WORK_PER_STEP` operations
+/// on the limbs of big numbers. Steps are counted across all the threads a
+/// run spawns. Time spent blocked, as in `recv` or waiting on `sleep`,
+/// counts towards the timeout too.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub max_depth: Option<usize>,
+    /// Bytes allocated by the whole process, threads included. Only what
+    /// is allocated once some run has a heap limit is counted.
+    pub max_heap: Option<usize>,
+    /// Bytes in any one string read from outside the script.
+    pub max_string: Option<usize>,
+}
+
+impl Limits {
+    /// Sets the limit a `--name=value` command-line flag names, returning
+    /// whether `name` is one.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<bool, String> {
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Expected a whole number for --{}, got '{}'", name, value))
+        };
+        match name {
+            "max-steps" => self.max_steps = Some(number()?),
+            "timeout" => {
+                let seconds = value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| format!("Expected seconds for --timeout, got '{}'", value))?;
+                self.timeout = Some(seconds);
+            }
+            "max-depth" => self.max_depth = Some(number()? as usize),
+            "max-heap" => self.max_heap = Some(number()? as usize),
+            "max-string" => self.max_string = Some(number()? as usize),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// The limits the current thread runs under, when its time is up and the
+/// steps taken by the threads of its run, up to each one's last check.
+#[derive(Clone, Default)]
+pub struct Inherited {
+    limits: Limits,
+    deadline: Option<Instant>,
+    taken: Arc<AtomicU64>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Inherited> = RefCell::new(Inherited::default());
+    /// Steps taken since the last check, and the count at which the limits
+    /// on steps, time and memory are next checked, so that most steps only
+    /// count.
+    static STEPS: Cell<u64> = const { Cell::new(0) };
+    static CHECK_AT: Cell<u64> = const { Cell::new(u64::MAX) };
+    /// Work done towards the next step, in limb operations.
+    static WORK: Cell<u64> = const { Cell::new(0) };
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(usize::MAX) };
+    /// Roughly where the stack began, taken when the thread started
+    /// running under its limits or else at its first call; 0 until then.
+    static STACK_BASE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Starts a run under `limits` on the current thread, with no steps taken
+/// and the clock starting now.
+pub fn start(limits: &Limits) {
+    let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+    resume(Inherited {
+        limits: limits.clone(),
+        deadline,
+        taken: Arc::new(AtomicU64::new(0)),
+    });
+}
+
+/// The limits the current thread runs under, for a thread it spawns, which
+/// shares its deadline and steps but counts its own calls.
+pub fn inherit() -> Inherited {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Runs the current thread under limits inherited from another.
+pub fn resume(inherited: Inherited) {
+    if inherited.limits.max_heap.is_some() {
+        COUNTING.store(true, Ordering::Relaxed);
+    }
+    STEPS.set(0);
+    WORK.set(0);
+    CHECK_AT.set(next_check(&inherited));
+    DEPTH.set(0);
+    MAX_DEPTH.set(inherited.limits.max_depth.unwrap_or(usize::MAX));
+    STACK_BASE.set(stack_position());
+    CURRENT.with(|current| *current.borrow_mut() = inherited);
+}
+
+/// How many steps after a check `step` next has something to check: at
+/// most `CHECK_INTERVAL`, so that other threads' steps are noticed, and no
+/// more than would take the run past its step limit.
+fn next_check(current: &Inherited) -> u64 {
+    let mut next = u64::MAX;
+    if let Some(max) = current.limits.max_steps {
+        let taken = current.taken.load(Ordering::Relaxed);
+        next = (max + 1).saturating_sub(taken).min(CHECK_INTERVAL);
+    }
+    if current.deadline.is_some() || current.limits.max_heap.is_some() {
+        next = next.min(CHECK_INTERVAL);
+    }
+    next
+}
+
+/// Counts a step taken at `line`, failing once the script has run out of
+/// steps, time or memory, or has been interrupted.
+#[inline]
+pub fn step(line: usize) -> Result<(), RuntimeError> {
+    take_steps(1, line)
+}
+
+/// Counts `work` limb operations of big-number arithmetic done at `line`
+/// towards steps, failing as `step` does.
+pub fn work(work: usize, line: usize) -> Result<(), RuntimeError> {
+    let work = WORK.get() + work as u64;
+    WORK.set(work % WORK_PER_STEP);
+    take_steps(work / WORK_PER_STEP, line)
+}
+
+#[inline]
+fn take_steps(count: u64, line: usize) -> Result<(), RuntimeError> {
+    let steps = STEPS.get() + count;
+    STEPS.set(steps);
+    interrupt::check(line)?;
+    if steps < CHECK_AT.get() {
+        return Ok(());
+    }
+    settle(line)
+}
+
+/// Adds the steps the current thread has taken since its last check to
+/// those of its run and checks the limits. A spawned thread does this as
+/// it finishes, so that its steps count even if it never reached a check.
+pub fn settle(line: usize) -> Result<(), RuntimeError> {
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        let steps = STEPS.replace(0);
+        let taken = current.taken.fetch_add(steps, Ordering::Relaxed) + steps;
+        CHECK_AT.set(next_check(&current));
+        check(taken, &current, line)
+    })
+}
+
+fn check(taken: u64, current: &Inherited, line: usize) -> Result<(), RuntimeError> {
+    if let Some(max) = current.limits.max_steps {
+        if taken > max {
+            return Err(RuntimeError::with_kind(
+                ErrorKind::StepLimit,
+                format!("Step limit of {} exceeded", max),
+                line,
+            ));
+        }
+    }
+    check_time(current, line)?;
+    if let Some(max) = current.limits.max_heap {
+        let heap = HEAP.load(Ordering::Relaxed).max(0) as usize;
+        if heap > max {
+            return Err(RuntimeError::with_kind(
+                ErrorKind::MemoryLimit,
+                format!("Heap limit of {} bytes exceeded, using {}", max, heap),
+                line,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_time(current: &Inherited, line: usize) -> Result<(), RuntimeError> {
+    match current.deadline {
+        Some(deadline) if Instant::now() >= deadline => {
+            let timeout = current.limits.timeout.unwrap_or_default();
+            Err(RuntimeError::with_kind(
+                ErrorKind::Timeout,
+                format!("Timed out after {:?}", timeout),
+                line,
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails if the current thread, blocked at `line` without taking steps,
+/// has been interrupted or has run out of time. Blocking waits call this
+/// at least every `interrupt::POLL`.
+pub fn check_blocked(line: usize) -> Result<(), RuntimeError> {
+    interrupt::check(line)?;
+    CURRENT.with(|current| check_time(&current.borrow(), line))
+}
+
+/// One call in progress, which ends when this is dropped.
+pub struct Call(());
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        DEPTH.set(DEPTH.get() - 1);
+    }
+}
+
+/// Enters a call made at `line`, failing with a stack overflow if calls
+/// are nested deeper than allowed or than the stack can hold. The call
+/// also counts as a step.
+pub fn enter(line: usize) -> Result<Call, RuntimeError> {
+    step(line)?;
+    let depth = DEPTH.get();
+    if depth >= MAX_DEPTH.get() {
+        return Err(RuntimeError::with_kind(
+            ErrorKind::StackOverflow,
+            format!("Stack overflow: more than {} nested calls", depth),
+            line,
+        ));
+    }
+    let position = stack_position();
+    if STACK_BASE.get() == 0 {
+        STACK_BASE.set(position);
+    }
+    if STACK_BASE.get().abs_diff(position) > STACK_BUDGET {
+        return Err(RuntimeError::with_kind(
+            ErrorKind::StackOverflow,
+            format!("Stack overflow after {} nested calls", depth),
+            line,
+        ));
+    }
+    DEPTH.set(depth + 1);
+    Ok(Call(()))
+}
+
+/// Fails if a string of `len` bytes is longer than strings may be.
+pub fn check_string(len: usize, line: usize) -> Result<(), RuntimeError> {
+    CURRENT.with(|current| match current.borrow().limits.max_string {
+        Some(max) if len > max => Err(RuntimeError::with_kind(
+            ErrorKind::MemoryLimit,
+            format!("String of {} bytes exceeds the limit of {}", len, max),
+            line,
+        )),
+        _ => Ok(()),
+    })
+}
+
+/// The address of a local, which is as good a measure of how deep the
+/// stack is as any.
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Bytes currently allocated by the process, counted from when `COUNTING`
+/// was turned on. Freeing what was allocated before can take it below 0.
+static HEAP: AtomicIsize = AtomicIsize::new(0);
+
+/// Whether allocations are being counted, which costs a little on each one
+/// and so waits until a heap limit needs it.
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+fn count(bytes: usize, allocated: bool) {
+    if COUNTING.load(Ordering::Relaxed) {
+        if allocated {
+            HEAP.fetch_add(bytes as isize, Ordering::Relaxed);
+        } else {
+            HEAP.fetch_sub(bytes as isize, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The system allocator, counting what is allocated through it in `HEAP`.
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            count(layout.size(), true);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            count(layout.size(), true);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        count(layout.size(), false);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            count(new_size, true);
+            count(layout.size(), false);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[cfg(test)]
+mod tests {
+    use super::Limits;
+    use crate::interrupt::InterruptHandle;
+    use std::time::Duration;
+
+    const HUGE: &str = "var x = 7 ** 1000000; var y = x * x; var z = y * y;";
+
+    #[test]
+    fn big_number_arithmetic_counts_as_steps() {
+        let limits = Limits {
+            max_steps: Some(10),
+            ..Limits::default()
+        };
+        assert_eq!(
+            crate::eval_limited(HUGE, limits.clone()),
+            Err("Step limit of 10 exceeded at line 1".to_string())
+        );
+        assert_eq!(
+            crate::eval_limited("(2 ** 64 + 1) * (2 ** 64 + 1);", limits),
+            Ok("340282366920938463500268095579187314689".to_string())
+        );
+    }
+
+    #[test]
+    fn big_number_arithmetic_stops_for_timeouts_and_interrupts() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        assert_eq!(
+            crate::eval_limited(HUGE, limits),
+            Err("Timed out after 100ms at line 1".to_string())
+        );
+        let handle = InterruptHandle::new();
+        let interrupter = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            interrupter.interrupt();
+        });
+        assert_eq!(
+            crate::eval_with(HUGE, handle),
+            Err("Interrupted at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn spawned_threads_share_the_step_limit() {
+        let limits = Limits {
+            max_steps: Some(2000),
+            ..Limits::default()
+        };
+        let spawn = |threads: usize| {
+            format!(
+                "fun work() {{ for i in 0..600 {{}} }}
+                 for thread in map(0..{}, i => spawn(work)) {{ join(thread); }}",
+                threads
+            )
+        };
+        assert_eq!(
+            crate::eval_limited(&spawn(1), limits.clone()),
+            Ok("nil".to_string())
+        );
+        let error = crate::eval_limited(&spawn(4), limits).unwrap_err();
+        assert!(
+            error.starts_with("Step limit of 2000 exceeded"),
+            "{}",
+            error
+        );
+    }
+}
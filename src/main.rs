@@ -1,34 +1,89 @@
 mod ast;
+mod asyncio;
+mod bigint;
+mod checker;
+mod class;
+mod debug;
+mod debugger;
+mod enums;
 mod environment;
 mod error;
+mod event_loop;
+mod function;
+mod generator;
 mod interpreter;
+mod interrupt;
+mod iterator;
 mod lexer;
+mod limits;
+mod map;
+mod native;
+mod numeric;
+mod optimizer;
 mod parser;
+mod permissions;
+mod rational;
+mod resolver;
+mod thread;
 mod token;
+mod transfer;
 mod value;
 
+use crate::ast::Stmt;
+use crate::checker::Checker;
+use crate::debugger::Debugger;
 use crate::environment::Environment;
+use crate::error::{ErrorKind, InterpreterError, RuntimeError};
+use crate::interrupt::InterruptHandle;
+use crate::limits::{Limits, STACK_SIZE};
+use crate::optimizer::Optimizer;
+use crate::permissions::Permissions;
+use crate::resolver::Resolver;
 use crate::{lexer::Lexer, parser::Parser};
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 use std::{
     env, fs,
     io::{self, BufRead, Write},
 };
 
+const USAGE: &str = "Usage: y [--max-steps=N] [--timeout=SECONDS] [--max-depth=N] \
+[--max-heap=BYTES] [--max-string=BYTES] [--deny-all] [--allow-read=PATHS] \
+[--allow-write=PATHS] [--allow-net] [--allow-env] [--allow-exec] \
+[check | debug | --dump-ast] <script>";
+
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mut y = Y::new();
+    // The interpreter runs on a thread of its own so that it knows how much
+    // stack it has to recurse into.
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)?
+        .join()
+        .unwrap_or_else(|_| std::process::exit(101))
+}
 
-    if args.len() > 2 {
-        println!("Usage: y <script>");
+fn run_cli() -> io::Result<()> {
+    let mut y = Y::new();
+    let args = y.configure(env::args()).unwrap_or_else(|message| {
+        eprintln!("{}", message);
         std::process::exit(64);
-    }
+    });
+
+    interrupt::on_sigint(&y.interrupt);
 
-    if args.len() == 2 {
-        y.run_file(&args[1])?;
-    } else {
-        y.run_prompt()?;
+    match args.as_slice() {
+        [_] => y.run_prompt()?,
+        [_, script] => y.run_file(script)?,
+        [_, command, script] if command == "check" => y.check_file(script)?,
+        [_, command, script] if command == "debug" => y.debug_file(script)?,
+        [_, flag, script] if flag == "--dump-ast" => y.dump_file(script)?,
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(64);
+        }
     }
 
     if y.had_error {
@@ -40,11 +95,62 @@ fn main() -> io::Result<()> {
 
 struct Y {
     had_error: bool,
+    /// What each run may use. Every run in the REPL starts afresh.
+    limits: Limits,
+    /// What scripts may do outside of themselves.
+    permissions: Permissions,
+    /// Stops the run in progress, which the next run forgets.
+    interrupt: InterruptHandle,
+    /// Whether to optimize scripts before running them. The debugger runs
+    /// them as written, so that it stops where the source says.
+    optimize: bool,
+    /// The scope top-level statements run in. What they declare goes in
+    /// the globals rather than here.
+    env: Rc<RefCell<Environment>>,
 }
 
 impl Y {
     fn new() -> Self {
-        Self { had_error: false }
+        Self {
+            had_error: false,
+            limits: Limits::default(),
+            permissions: Permissions::default(),
+            interrupt: InterruptHandle::new(),
+            optimize: true,
+            env: Rc::new(RefCell::new(Environment::new())),
+        }
+    }
+
+    /// Applies the limit and permission flags among `args`, returning the
+    /// rest. Any permission flag denies whatever the flags don't allow.
+    fn configure(&mut self, args: impl Iterator<Item = String>) -> Result<Vec<String>, String> {
+        let mut rest = Vec::new();
+        let mut sandbox = Permissions::none();
+        let mut sandboxed = false;
+        for arg in args {
+            let Some(flag) = arg.strip_prefix("--") else {
+                rest.push(arg);
+                continue;
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (flag, None),
+            };
+            if let Some(value) = value {
+                if self.limits.set(name, value)? {
+                    continue;
+                }
+            }
+            if sandbox.set(name, value)? {
+                sandboxed = true;
+            } else {
+                rest.push(arg);
+            }
+        }
+        if sandboxed {
+            self.permissions = sandbox;
+        }
+        Ok(rest)
     }
 
     fn run_file(&mut self, path: &String) -> io::Result<()> {
@@ -57,6 +163,72 @@ impl Y {
         Ok(())
     }
 
+    /// Runs the script at `path` under the debugger, which stops before
+    /// its first statement. Quitting the debugger isn't an error.
+    fn debug_file(&mut self, path: &String) -> io::Result<()> {
+        let source = fs::read_to_string(path)?;
+        let quit = Rc::new(Cell::new(false));
+        self.debug(&source, Debugger::new(&source, Rc::clone(&quit)));
+        if quit.get() {
+            self.had_error = false;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `source` as written under `debugger`.
+    fn debug(&mut self, source: &str, debugger: Debugger) {
+        self.optimize = false;
+        debug::attach(Box::new(debugger));
+        self.run(source);
+        debug::detach();
+    }
+
+    /// Type-checks the script at `path` without running it, printing any
+    /// mismatches found.
+    fn check_file(&mut self, path: &String) -> io::Result<()> {
+        let source = fs::read_to_string(path)?;
+        match Self::check(&source) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", diagnostic);
+                }
+                self.had_error = !diagnostics.is_empty();
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                self.had_error = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints the syntax tree of the script at `path` as it runs after
+    /// optimization, without running it.
+    fn dump_file(&mut self, path: &String) -> io::Result<()> {
+        let source = fs::read_to_string(path)?;
+        match Self::parse(&source) {
+            Ok(statements) => {
+                for statement in statements {
+                    println!("{}", statement.print(0));
+                }
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                self.had_error = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check(source: &str) -> Result<Vec<checker::Diagnostic>, InterpreterError> {
+        let tokens = Lexer::new(source).scan_tokens()?;
+        let statements = Parser::new(&tokens).parse()?;
+        Ok(Checker::new().check(&statements))
+    }
+
     fn run_prompt(&mut self) -> io::Result<()> {
         let stdin = io::stdin();
         let mut handle = stdin.lock();
@@ -75,37 +247,192 @@ impl Y {
         Ok(())
     }
 
+    /// Parses `source`, optimizes the result and resolves its variables.
+    fn parse(source: &str) -> Result<Vec<Stmt>, InterpreterError> {
+        let tokens = Lexer::new(source).scan_tokens()?;
+        let mut statements = Parser::new(&tokens).parse()?;
+        Optimizer::new().optimize(&mut statements);
+        Resolver::new().resolve(&mut statements);
+        Ok(statements)
+    }
+
+    /// Parses `source` and resolves its variables, leaving it as written.
+    fn parse_unoptimized(source: &str) -> Result<Vec<Stmt>, InterpreterError> {
+        let tokens = Lexer::new(source).scan_tokens()?;
+        let mut statements = Parser::new(&tokens).parse()?;
+        Resolver::new().resolve(&mut statements);
+        Ok(statements)
+    }
+
+    /// Runs `source`, then drives the event loop until every task it
+    /// started has finished. An interrupt is reported once, however many
+    /// tasks it stopped.
     fn run(&mut self, source: &str) {
-        let mut lexer = Lexer::new(source);
-        match lexer.scan_tokens() {
-            Ok(tokens) => {
-                let mut parser = Parser::new(&tokens);
-                match parser.parse() {
-                    Ok(statements) => {
-                        if !self.had_error {
-                            let global_env = Rc::new(RefCell::new(Environment::new()));
-
-                            for statement in statements {
-                                match statement.execute(global_env.clone()) {
-                                    Ok(_) => {}
-                                    Err(runtime_error) => {
-                                        eprintln!("{:?}", runtime_error);
-                                        self.had_error = true;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(parser_error) => {
-                        eprintln!("{:?}", parser_error);
-                        self.had_error = true;
-                    }
+        limits::start(&self.limits);
+        permissions::start(&self.permissions);
+        self.interrupt.reset();
+        interrupt::start(&self.interrupt);
+        let mut interrupted = false;
+        if let Err(error) = self.interpret(source) {
+            eprintln!("{}", error);
+            self.had_error = true;
+            interrupted = matches!(
+                error,
+                InterpreterError::RuntimeError(RuntimeError {
+                    kind: ErrorKind::Interrupted,
+                    ..
+                })
+            );
+        }
+        for error in event_loop::run() {
+            if error.kind == ErrorKind::Interrupted {
+                if !interrupted {
+                    eprintln!("{}", error);
                 }
+                interrupted = true;
+            } else {
+                eprintln!("Unhandled error: {}", error);
             }
-            Err(lexer_error) => {
-                eprintln!("{:?}", lexer_error);
-                self.had_error = true;
-            }
+            self.had_error = true;
         }
     }
+
+    fn interpret(&mut self, source: &str) -> Result<(), InterpreterError> {
+        let statements = if self.optimize {
+            Self::parse(source)?
+        } else {
+            Self::parse_unoptimized(source)?
+        };
+
+        for statement in statements {
+            statement.execute(Rc::clone(&self.env))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `source` as a script on an interpreter thread of its own, for
+/// tests. If the script ends in an expression, its value is shown as
+/// `print` would; otherwise the result is `nil`.
+#[cfg(test)]
+fn eval(source: &str) -> Result<String, String> {
+    eval_with(source, InterruptHandle::new())
+}
+
+/// `eval`, stopping if `interrupt` is.
+#[cfg(test)]
+fn eval_with(source: &str, interrupt: InterruptHandle) -> Result<String, String> {
+    run_script(
+        source,
+        interrupt,
+        Limits::default(),
+        Permissions::default(),
+        Y::parse,
+    )
+}
+
+/// `eval`, under `limits`.
+#[cfg(test)]
+fn eval_limited(source: &str, limits: Limits) -> Result<String, String> {
+    run_script(
+        source,
+        InterruptHandle::new(),
+        limits,
+        Permissions::default(),
+        Y::parse,
+    )
+}
+
+/// `eval`, with only what `permissions` allows.
+#[cfg(test)]
+fn eval_permitted(source: &str, permissions: Permissions) -> Result<String, String> {
+    run_script(
+        source,
+        InterruptHandle::new(),
+        Limits::default(),
+        permissions,
+        Y::parse,
+    )
+}
+
+/// `eval`, without the optimizer's rewrites.
+#[cfg(test)]
+fn eval_unoptimized(source: &str) -> Result<String, String> {
+    run_script(
+        source,
+        InterruptHandle::new(),
+        Limits::default(),
+        Permissions::default(),
+        Y::parse_unoptimized,
+    )
+}
+
+#[cfg(test)]
+fn run_script(
+    source: &str,
+    interrupt: InterruptHandle,
+    limits: Limits,
+    permissions: Permissions,
+    parse: fn(&str) -> Result<Vec<Stmt>, InterpreterError>,
+) -> Result<String, String> {
+    let source = source.to_string();
+    let run = move || {
+        let mut y = Y::new();
+        y.interrupt = interrupt;
+        y.limits = limits;
+        y.permissions = permissions;
+        limits::start(&y.limits);
+        permissions::start(&y.permissions);
+        interrupt::start(&y.interrupt);
+        let mut statements = parse(&source).map_err(|error| error.to_string())?;
+        let last = match statements.last() {
+            Some(Stmt::Expression(_)) => statements.pop(),
+            _ => None,
+        };
+        for statement in statements {
+            statement
+                .execute(Rc::clone(&y.env))
+                .map_err(|error| error.to_string())?;
+        }
+        let value = match last {
+            Some(Stmt::Expression(expr)) => expr.evaluate(&y.env).map_err(|e| e.to_string())?,
+            _ => value::Value::Nil,
+        };
+        if let Some(error) = event_loop::run().into_iter().next() {
+            return Err(error.to_string());
+        }
+        // A script ending in an async call gives the result of its task.
+        let value = match &value {
+            value::Value::Future(future) => match future.outcome() {
+                Some(outcome) => outcome.map_err(|error| error.to_string())?,
+                None => value,
+            },
+            _ => value,
+        };
+        interpreter::show(&value, 0).map_err(|error| error.to_string())
+    };
+    on_interpreter_thread(run)
+}
+
+/// Type-checks `source` as `y check` does, for tests, returning the
+/// problems found.
+#[cfg(test)]
+fn check(source: &str) -> Result<Vec<String>, String> {
+    let source = source.to_string();
+    on_interpreter_thread(move || match Y::check(&source) {
+        Ok(diagnostics) => Ok(diagnostics.iter().map(ToString::to_string).collect()),
+        Err(error) => Err(error.to_string()),
+    })
+}
+
+/// Runs `run` on a thread with the stack the interpreter's threads have.
+#[cfg(test)]
+fn on_interpreter_thread<T: Send + 'static>(run: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("couldn't start the interpreter thread")
+        .join()
+        .expect("the interpreter panicked")
 }
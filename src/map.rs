@@ -0,0 +1,108 @@
+use crate::bigint::BigInt;
+use crate::numeric;
+use crate::rational::Rational;
+use crate::value::{self, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Hashable projection of a map key. Numbers are normalized so that keys
+/// which compare equal (`1`, `1.0`, `1r`, or `0.5` and `1/2r`) land on the
+/// same entry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(Rational),
+    Float(u64),
+    String(Arc<str>),
+}
+
+impl Key {
+    fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Nil => Some(Key::Nil),
+            Value::Boolean(b) => Some(Key::Boolean(*b)),
+            Value::Integer(n) => Some(Key::Integer(*n)),
+            Value::BigInt(n) => Some(Key::BigInt((**n).clone())),
+            Value::Rational(r) if r.denominator().is_one() => {
+                Key::from_value(&numeric::integer_value(r.numerator().clone()))
+            }
+            Value::Rational(r) => Some(Key::Rational((**r).clone())),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                Some(Key::Integer(*n as i64))
+            }
+            Value::Number(n) => match Rational::from_f64(*n) {
+                Some(exact) => Key::from_value(&Value::Rational(exact.into())),
+                None => Some(Key::Float(n.to_bits())),
+            },
+            Value::String(s) => Some(Key::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Returned when a list, map, function or instance is used as a map key.
+#[derive(Debug)]
+pub struct UnhashableKey;
+
+/// Insertion-ordered hash map from values to values.
+#[derive(Clone, Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    /// Set by `freeze`, after which the interpreter refuses to change the
+    /// map.
+    pub frozen: bool,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, UnhashableKey> {
+        let key = Key::from_value(key).ok_or(UnhashableKey)?;
+        Ok(self
+            .index
+            .get(&key)
+            .map(|&position| self.entries[position].1.clone()))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), UnhashableKey> {
+        let hashed = Key::from_value(&key).ok_or(UnhashableKey)?;
+        match self.index.get(&hashed) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entry(&self, position: usize) -> Option<&(Value, Value)> {
+        self.entries.get(position)
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    /// Empties the map, returning its values. Keys are never containers.
+    pub fn take_values(&mut self) -> Vec<Value> {
+        self.index.clear();
+        self.entries.drain(..).map(|(_, value)| value).collect()
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        value::drop_nested(self.take_values());
+    }
+}
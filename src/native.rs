@@ -0,0 +1,910 @@
+use crate::asyncio::{self, Socket};
+use crate::bigint::BigInt;
+use crate::enums;
+use crate::environment::Globals;
+use crate::error::RuntimeError;
+use crate::event_loop;
+use crate::function::NativeFunction;
+use crate::interpreter::{call, show};
+use crate::iterator::{self, Iter};
+use crate::numeric;
+use crate::permissions::{self, Capability};
+use crate::rational::Rational;
+use crate::thread::{self, Channel, Polled, ThreadHandle};
+use crate::value::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction {
+        name: "clock",
+        arity: 0,
+        function: clock,
+    },
+    NativeFunction {
+        name: "len",
+        arity: 1,
+        function: len,
+    },
+    NativeFunction {
+        name: "iter",
+        arity: 1,
+        function: iter,
+    },
+    NativeFunction {
+        name: "next",
+        arity: 1,
+        function: next,
+    },
+    NativeFunction {
+        name: "send",
+        arity: 2,
+        function: send,
+    },
+    NativeFunction {
+        name: "spawn",
+        arity: 1,
+        function: spawn,
+    },
+    NativeFunction {
+        name: "join",
+        arity: 1,
+        function: join,
+    },
+    NativeFunction {
+        name: "channel",
+        arity: 0,
+        function: channel,
+    },
+    NativeFunction {
+        name: "recv",
+        arity: 1,
+        function: recv,
+    },
+    NativeFunction {
+        name: "try_recv",
+        arity: 1,
+        function: try_recv,
+    },
+    NativeFunction {
+        name: "select",
+        arity: 1,
+        function: select,
+    },
+    NativeFunction {
+        name: "close",
+        arity: 1,
+        function: close,
+    },
+    NativeFunction {
+        name: "sleep",
+        arity: 1,
+        function: sleep,
+    },
+    NativeFunction {
+        name: "set_timeout",
+        arity: 2,
+        function: set_timeout,
+    },
+    NativeFunction {
+        name: "gather",
+        arity: 1,
+        function: gather,
+    },
+    NativeFunction {
+        name: "race",
+        arity: 1,
+        function: race,
+    },
+    NativeFunction {
+        name: "read_file",
+        arity: 1,
+        function: read_file,
+    },
+    NativeFunction {
+        name: "write_file",
+        arity: 2,
+        function: write_file,
+    },
+    NativeFunction {
+        name: "connect",
+        arity: 1,
+        function: connect,
+    },
+    NativeFunction {
+        name: "listen",
+        arity: 1,
+        function: listen,
+    },
+    NativeFunction {
+        name: "accept",
+        arity: 1,
+        function: accept,
+    },
+    NativeFunction {
+        name: "read",
+        arity: 1,
+        function: read,
+    },
+    NativeFunction {
+        name: "write",
+        arity: 2,
+        function: write,
+    },
+    NativeFunction {
+        name: "address",
+        arity: 1,
+        function: address,
+    },
+    NativeFunction {
+        name: "env",
+        arity: 1,
+        function: env,
+    },
+    NativeFunction {
+        name: "exec",
+        arity: 2,
+        function: exec,
+    },
+    NativeFunction {
+        name: "map",
+        arity: 2,
+        function: map,
+    },
+    NativeFunction {
+        name: "filter",
+        arity: 2,
+        function: filter,
+    },
+    NativeFunction {
+        name: "sort_by",
+        arity: 2,
+        function: sort_by,
+    },
+    NativeFunction {
+        name: "freeze",
+        arity: 1,
+        function: freeze,
+    },
+    NativeFunction {
+        name: "is_frozen",
+        arity: 1,
+        function: is_frozen,
+    },
+    NativeFunction {
+        name: "implements",
+        arity: 2,
+        function: implements,
+    },
+    NativeFunction {
+        name: "parse_number",
+        arity: 1,
+        function: parse_number,
+    },
+    NativeFunction {
+        name: "decimal",
+        arity: 1,
+        function: decimal,
+    },
+    NativeFunction {
+        name: "format",
+        arity: 2,
+        function: format_number,
+    },
+    NativeFunction {
+        name: "try",
+        arity: 1,
+        function: try_call,
+    },
+    NativeFunction {
+        name: "unwrap",
+        arity: 1,
+        function: unwrap,
+    },
+    NativeFunction {
+        name: "unwrap_or",
+        arity: 2,
+        function: unwrap_or,
+    },
+];
+
+/// Defines every builtin function in `globals`, along with the builtin
+/// `Option` and `Result` enums and their variants.
+pub fn define_globals(globals: &mut Globals) {
+    for native in NATIVES {
+        globals.define(native.name, Value::NativeFunction(Rc::new(native.clone())));
+    }
+    for enumeration in [enums::option(), enums::result()] {
+        globals.define(&enumeration.name, Value::Enum(Arc::clone(enumeration)));
+    }
+    globals.define("Some", Value::Constructor(Arc::clone(enums::option()), 0));
+    globals.define("None", enums::none());
+    globals.define("Ok", Value::Constructor(Arc::clone(enums::result()), 0));
+    globals.define("Err", Value::Constructor(Arc::clone(enums::result()), 1));
+}
+
+fn clock(_arguments: &[Value], _line: usize) -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+fn len(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::List(elements) => Ok(Value::Integer(elements.borrow().len() as i64)),
+        Value::String(s) => Ok(Value::Integer(s.chars().count() as i64)),
+        Value::Map(map) => Ok(Value::Integer(map.borrow().len() as i64)),
+        Value::Range(range) => Ok(numeric::integer_value(BigInt::from_u128(range.len()))),
+        other => Err(RuntimeError::new(
+            format!(
+                "len() expects a list, string, map or range, got '{}'",
+                other
+            ),
+            line,
+        )),
+    }
+}
+
+/// Returns an iterator over any iterable, advanced with `next()`.
+fn iter(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Iterator(iter) => Ok(Value::Iterator(Rc::clone(iter))),
+        iterable => Ok(Value::Iterator(Rc::new(RefCell::new(iterator::iterate(
+            iterable, line,
+        )?)))),
+    }
+}
+
+/// Advances an iterator, returning `nil` once it is exhausted.
+fn next(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Iterator(iter) => Ok(iterator::borrow_running(iter, line)?
+            .next(line)?
+            .unwrap_or(Value::Nil)),
+        other => Err(RuntimeError::new(
+            format!("next() expects an iterator, got '{}'", other),
+            line,
+        )),
+    }
+}
+
+/// Sends a copy of `arguments[1]` on a channel, or resumes a generator,
+/// making its pending `yield` evaluate to `arguments[1]`, and returns the
+/// next value it yields (`nil` once done).
+fn send(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    if let Value::Channel(channel) = &arguments[0] {
+        channel.send(&arguments[1], line)?;
+        return Ok(Value::Nil);
+    }
+    if let Value::Iterator(iter) = &arguments[0] {
+        if let Iter::Generator(generator) = &mut *iterator::borrow_running(iter, line)? {
+            return Ok(generator
+                .resume(arguments[1].clone(), line)?
+                .yielded()
+                .unwrap_or(Value::Nil));
+        }
+    }
+    Err(RuntimeError::new(
+        format!(
+            "send() expects a channel or generator, got '{}'",
+            arguments[0]
+        ),
+        line,
+    ))
+}
+
+/// Runs a function on a new thread; see `Transfer` for what it can see.
+fn spawn(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        function @ (Value::Function(_) | Value::NativeFunction(_)) => Ok(Value::Thread(Arc::new(
+            ThreadHandle::spawn(function, line)?,
+        ))),
+        other => Err(RuntimeError::new(
+            format!("spawn() expects a function, got '{}'", other),
+            line,
+        )),
+    }
+}
+
+fn join(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Thread(thread) => thread.join(line),
+        other => Err(RuntimeError::new(
+            format!("join() expects a thread, got '{}'", other),
+            line,
+        )),
+    }
+}
+
+fn channel(_arguments: &[Value], _line: usize) -> Result<Value, RuntimeError> {
+    Ok(Value::Channel(Arc::new(Channel::default())))
+}
+
+fn channel_argument<'a>(
+    value: &'a Value,
+    function: &str,
+    line: usize,
+) -> Result<&'a Arc<Channel>, RuntimeError> {
+    match value {
+        Value::Channel(channel) => Ok(channel),
+        other => Err(RuntimeError::new(
+            format!("{}() expects a channel, got '{}'", function, other),
+            line,
+        )),
+    }
+}
+
+/// Blocks for the next value, returning `nil` once the channel is closed
+/// and drained.
+fn recv(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let channel = channel_argument(&arguments[0], "recv", line)?;
+    Ok(
+        thread::receive(std::slice::from_ref(channel), line)?
+            .map_or(Value::Nil, |(_, value)| value),
+    )
+}
+
+/// Returns the next value if one is waiting, or `nil` without blocking.
+fn try_recv(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match channel_argument(&arguments[0], "try_recv", line)?.poll() {
+        Polled::Value(value) => Ok(value),
+        Polled::Empty | Polled::Closed => Ok(Value::Nil),
+    }
+}
+
+/// Waits on a list of channels and returns `[index, value]` for the first
+/// one with a value, or `nil` once all are closed and drained.
+fn select(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let channels = list_argument(&arguments[0], "select", line)?
+        .iter()
+        .map(|value| channel_argument(value, "select", line).cloned())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(match thread::receive(&channels, line)? {
+        Some((index, value)) => Value::list(vec![Value::Integer(index as i64), value]),
+        None => Value::Nil,
+    })
+}
+
+fn close(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Socket(socket) => socket.close(),
+        other => channel_argument(other, "close", line)?.close(),
+    }
+    Ok(Value::Nil)
+}
+
+fn delay_argument(value: &Value, function: &str, line: usize) -> Result<Duration, RuntimeError> {
+    match numeric::to_f64(value) {
+        Some(ms) if !ms.is_nan() => Ok(Duration::from_secs_f64(ms.max(0.0) / 1000.0)),
+        _ => Err(RuntimeError::new(
+            format!(
+                "{}() expects a delay in milliseconds, got '{}'",
+                function, value
+            ),
+            line,
+        )),
+    }
+}
+
+/// Returns a future that resolves to `nil` after `arguments[0]` ms.
+fn sleep(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let delay = delay_argument(&arguments[0], "sleep", line)?;
+    Ok(Value::Future(event_loop::sleep(delay, line)))
+}
+
+/// Calls `arguments[0]` after `arguments[1]` ms, returning a future of
+/// what it returns.
+fn set_timeout(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let delay = delay_argument(&arguments[1], "set_timeout", line)?;
+    Ok(Value::Future(event_loop::set_timeout(
+        arguments[0].clone(),
+        delay,
+        line,
+    )))
+}
+
+/// Returns a future of the list of results of an iterable of futures.
+fn gather(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let items = list_argument(&arguments[0], "gather", line)?;
+    Ok(Value::Future(event_loop::gather(items)))
+}
+
+/// Returns a future that settles like the first future of an iterable to
+/// settle.
+fn race(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let items = list_argument(&arguments[0], "race", line)?;
+    if items.is_empty() {
+        return Err(RuntimeError::new(
+            "race() needs at least one future".to_string(),
+            line,
+        ));
+    }
+    Ok(Value::Future(event_loop::race(items)))
+}
+
+fn string_argument<'a>(
+    value: &'a Value,
+    function: &str,
+    line: usize,
+) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(RuntimeError::new(
+            format!("{}() expects a string, got '{}'", function, other),
+            line,
+        )),
+    }
+}
+
+fn socket_argument(
+    value: &Value,
+    function: &str,
+    line: usize,
+) -> Result<Arc<Socket>, RuntimeError> {
+    match value {
+        Value::Socket(socket) => Ok(Arc::clone(socket)),
+        other => Err(RuntimeError::new(
+            format!("{}() expects a socket, got '{}'", function, other),
+            line,
+        )),
+    }
+}
+
+fn read_file(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let path = string_argument(&arguments[0], "read_file", line)?;
+    let resolved = permissions::resolve(Path::new(path));
+    permissions::require(Capability::Read(resolved.clone()), "read_file", line)?;
+    Ok(Value::Future(asyncio::read_file(
+        path.to_string(),
+        resolved,
+        line,
+    )))
+}
+
+fn write_file(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let path = string_argument(&arguments[0], "write_file", line)?;
+    let resolved = permissions::resolve(Path::new(path));
+    permissions::require(Capability::Write(resolved.clone()), "write_file", line)?;
+    Ok(Value::Future(asyncio::write_file(
+        path.to_string(),
+        resolved,
+        show(&arguments[1], line)?,
+        line,
+    )))
+}
+
+/// Returns a future of a socket connected to `"host:port"`.
+fn connect(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let address = string_argument(&arguments[0], "connect", line)?;
+    permissions::require(Capability::Net, "connect", line)?;
+    Ok(Value::Future(asyncio::connect(address.to_string(), line)))
+}
+
+/// Listens on `"host:port"`; port 0 picks a free port, which `address()`
+/// then reports.
+fn listen(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let address = string_argument(&arguments[0], "listen", line)?;
+    permissions::require(Capability::Net, "listen", line)?;
+    asyncio::listen(address, line)
+}
+
+/// Returns a future of the socket for the next incoming connection.
+fn accept(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let socket = socket_argument(&arguments[0], "accept", line)?;
+    Ok(Value::Future(asyncio::accept(socket, line)?))
+}
+
+/// Returns a future of the next text received, or of `nil` once the peer
+/// has closed the connection.
+fn read(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let socket = socket_argument(&arguments[0], "read", line)?;
+    Ok(Value::Future(asyncio::read(socket, line)?))
+}
+
+fn write(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let socket = socket_argument(&arguments[0], "write", line)?;
+    Ok(Value::Future(asyncio::write(
+        socket,
+        show(&arguments[1], line)?,
+        line,
+    )?))
+}
+
+/// The peer address of a connection, or the local address of a listener.
+fn address(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let socket = socket_argument(&arguments[0], "address", line)?;
+    Ok(socket
+        .address()
+        .map_or(Value::Nil, |address| Value::String(address.into())))
+}
+
+/// The value of an environment variable, or `None` if it isn't set.
+fn env(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let name = string_argument(&arguments[0], "env", line)?;
+    permissions::require(Capability::Env, "env", line)?;
+    Ok(std::env::var(name).map_or_else(
+        |_| enums::none(),
+        |value| enums::some(Value::String(value.into())),
+    ))
+}
+
+/// Runs a program with a list of arguments, returning a future of what it
+/// prints.
+fn exec(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let program = string_argument(&arguments[0], "exec", line)?;
+    let args = list_argument(&arguments[1], "exec", line)?
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    permissions::require(Capability::Exec, "exec", line)?;
+    Ok(Value::Future(asyncio::exec(
+        program.to_string(),
+        args,
+        line,
+    )))
+}
+
+/// Copies the items out of an iterable argument so callbacks are free to
+/// mutate the original while we iterate.
+fn list_argument(value: &Value, function: &str, line: usize) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::List(elements) => Ok(elements.borrow().clone()),
+        Value::Map(_)
+        | Value::String(_)
+        | Value::Range(_)
+        | Value::Iterator(_)
+        | Value::Instance(_)
+        | Value::Channel(_) => iterator::iterate(value, line)?.collect(line),
+        other => Err(RuntimeError::new(
+            format!("{}() expects an iterable, got '{}'", function, other),
+            line,
+        )),
+    }
+}
+
+fn map(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let elements = list_argument(&arguments[0], "map", line)?;
+    let mapped = elements
+        .into_iter()
+        .map(|element| call(&arguments[1], vec![element], line))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::list(mapped))
+}
+
+fn filter(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let mut kept = Vec::new();
+    for element in list_argument(&arguments[0], "filter", line)? {
+        if call(&arguments[1], vec![element.clone()], line)?.is_truthy() {
+            kept.push(element);
+        }
+    }
+    Ok(Value::list(kept))
+}
+
+/// Returns a new list sorted (stably) by the key `arguments[1]` computes
+/// for each element. Keys must be all numbers or all strings.
+fn sort_by(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let elements = list_argument(&arguments[0], "sort_by", line)?;
+    let keys = elements
+        .iter()
+        .map(|element| call(&arguments[1], vec![element.clone()], line))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut order: Vec<usize> = (0..elements.len()).collect();
+    let mut incomparable = None;
+    order.sort_by(|&a, &b| {
+        compare_keys(&keys[a], &keys[b]).unwrap_or_else(|| {
+            incomparable.get_or_insert((a, b));
+            Ordering::Equal
+        })
+    });
+    if let Some((a, b)) = incomparable {
+        return Err(RuntimeError::new(
+            format!(
+                "sort_by() can't compare keys '{}' and '{}'",
+                keys[a], keys[b]
+            ),
+            line,
+        ));
+    }
+
+    Ok(Value::list(
+        order.into_iter().map(|i| elements[i].clone()).collect(),
+    ))
+}
+
+fn compare_keys(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => numeric::compare(a, b),
+    }
+}
+
+/// Makes a list, map, instance or class's statics and everything reachable
+/// through them immutable, and returns the value. Functions, iterators,
+/// channels and the like keep whatever state they have and are returned as
+/// they are.
+fn freeze(arguments: &[Value], _line: usize) -> Result<Value, RuntimeError> {
+    freeze_deeply(&arguments[0]);
+    Ok(arguments[0].clone())
+}
+
+/// Everything reachable from a frozen value is frozen too, so marking a
+/// value before visiting its contents also stops at cycles. The contents
+/// are copied out first since they may lead back to the value itself.
+fn freeze_deeply(value: &Value) {
+    let contents: Vec<Value> = match value {
+        Value::List(elements) => {
+            let mut elements = elements.borrow_mut();
+            if std::mem::replace(&mut elements.frozen, true) {
+                return;
+            }
+            elements.to_vec()
+        }
+        Value::Map(map) => {
+            let mut map = map.borrow_mut();
+            if std::mem::replace(&mut map.frozen, true) {
+                return;
+            }
+            map.entries()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect()
+        }
+        Value::Instance(instance) => {
+            let mut instance = instance.borrow_mut();
+            if std::mem::replace(&mut instance.frozen, true) {
+                return;
+            }
+            instance.fields.values().cloned().collect()
+        }
+        Value::Class(class) => {
+            if class.freeze() {
+                return;
+            }
+            class
+                .statics()
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect()
+        }
+        Value::Variant(variant) => variant.values.clone(),
+        _ => return,
+    };
+    for value in &contents {
+        freeze_deeply(value);
+    }
+}
+
+/// Whether nothing can change a value: one `freeze` has frozen, plain data
+/// that can't change, or a variant of such values. Functions, iterators,
+/// channels and the like have state that can change, so aren't frozen.
+fn is_frozen(arguments: &[Value], _line: usize) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(frozen(&arguments[0])))
+}
+
+fn frozen(value: &Value) -> bool {
+    match value {
+        Value::List(elements) => elements.borrow().frozen,
+        Value::Map(map) => map.borrow().frozen,
+        Value::Instance(instance) => instance.borrow().frozen,
+        Value::Class(class) => class.is_frozen(),
+        Value::Variant(variant) => variant.values.iter().all(frozen),
+        Value::Number(_)
+        | Value::Integer(_)
+        | Value::BigInt(_)
+        | Value::Rational(_)
+        | Value::String(_)
+        | Value::Boolean(_)
+        | Value::Nil
+        | Value::Range(_)
+        | Value::NativeFunction(_)
+        | Value::Trait(_)
+        | Value::Enum(_)
+        | Value::Constructor(..) => true,
+        Value::Function(_)
+        | Value::Iterator(_)
+        | Value::Channel(_)
+        | Value::Thread(_)
+        | Value::Future(_)
+        | Value::Socket(_) => false,
+    }
+}
+
+/// Whether a class, or the class of an instance, implements a trait.
+fn implements(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let Value::Trait(implemented) = &arguments[1] else {
+        return Err(RuntimeError::new(
+            format!("implements() expects a trait, got '{}'", arguments[1]),
+            line,
+        ));
+    };
+    Ok(Value::Boolean(match &arguments[0] {
+        Value::Class(class) => class.implements(implemented),
+        Value::Instance(instance) => instance.borrow().class.implements(implemented),
+        _ => false,
+    }))
+}
+
+/// Reads an integer or decimal number from a string, giving `None` if the
+/// whole string (ignoring surrounding whitespace) isn't one.
+fn parse_number(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments[0], "parse_number", line)?.trim();
+    if let Ok(n) = text.parse::<i64>() {
+        return Ok(enums::some(Value::Integer(n)));
+    }
+    Ok(match text.parse::<f64>() {
+        Ok(n) if n.is_finite() => enums::some(Value::Number(n)),
+        _ => enums::none(),
+    })
+}
+
+/// Parses decimal notation such as `"0.1"` into an exact rational, so money
+/// amounts can be written the way they are read.
+fn decimal(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments[0], "decimal", line)?.trim();
+    match Rational::parse_decimal(text) {
+        Some(value) => Ok(Value::Rational(Rc::new(value))),
+        None => Err(RuntimeError::new(
+            format!("decimal() expects a decimal number, got '{}'", text),
+            line,
+        )),
+    }
+}
+
+/// Most digits `format()` writes after the decimal point.
+const MAX_PLACES: i64 = 1000;
+
+/// Writes a number in decimal notation rounded to a number of places, so a
+/// rational such as `1/3r` can be shown as `0.33`.
+fn format_number(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    let places = match &arguments[1] {
+        Value::Integer(n) if (0..=MAX_PLACES).contains(n) => *n as usize,
+        other => {
+            return Err(RuntimeError::new(
+                format!(
+                    "format() expects a number of places from 0 to {}, got '{}'",
+                    MAX_PLACES, other
+                ),
+                line,
+            ))
+        }
+    };
+    match numeric::to_decimal(&arguments[0], places) {
+        Some(text) => Ok(Value::String(text.into())),
+        None => Err(RuntimeError::new(
+            format!("format() expects a number, got '{}'", arguments[0]),
+            line,
+        )),
+    }
+}
+
+/// Calls a function with no arguments, giving `Ok(result)`, or `Err` with
+/// the message of the runtime error it raised. Running out of steps or time
+/// isn't caught.
+fn try_call(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    Ok(match call(&arguments[0], Vec::new(), line) {
+        Ok(value) => enums::ok(value),
+        Err(error) if !error.kind.is_catchable() => return Err(error),
+        Err(error) => enums::err(Value::String(error.message.into())),
+    })
+}
+
+/// The value inside `Some` or `Ok`, if that is what `value` is.
+fn unwrapped(value: &Value, function: &str, line: usize) -> Result<Option<Value>, RuntimeError> {
+    match value {
+        Value::Variant(variant)
+            if variant.is(enums::option(), "Some") || variant.is(enums::result(), "Ok") =>
+        {
+            Ok(Some(variant.values[0].clone()))
+        }
+        Value::Variant(variant)
+            if variant.is(enums::option(), "None") || variant.is(enums::result(), "Err") =>
+        {
+            Ok(None)
+        }
+        other => Err(RuntimeError::new(
+            format!(
+                "{}() expects an Option or Result, got '{}'",
+                function, other
+            ),
+            line,
+        )),
+    }
+}
+
+fn unwrap(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    unwrapped(&arguments[0], "unwrap", line)?
+        .ok_or_else(|| RuntimeError::new(format!("Called unwrap() on {}", arguments[0]), line))
+}
+
+fn unwrap_or(arguments: &[Value], line: usize) -> Result<Value, RuntimeError> {
+    Ok(unwrapped(&arguments[0], "unwrap_or", line)?.unwrap_or_else(|| arguments[1].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    #[test]
+    fn len_counts_ranges_too_long_for_an_integer() {
+        assert_eq!(
+            eval(
+                "var min = -9223372036854775807 - 1; var max = 9223372036854775807;
+                [len(0..=max), len(min..max), len(min..=max), len(max..=max), len(10..0 step -3)];"
+            ),
+            Ok(
+                "[9223372036854775808, 18446744073709551615, 18446744073709551616, 1, 4]"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn decimal_parses_exact_rationals() {
+        assert_eq!(
+            eval("[decimal(\"0.1\") + decimal(\"0.2\") == decimal(\"0.3\"), decimal(\" -3.25 \"), decimal(\"12\")];"),
+            Ok("[true, -13/4, 12]".to_string())
+        );
+        assert_eq!(
+            eval("decimal(\"1e5\");"),
+            Err("decimal() expects a decimal number, got '1e5' at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn format_rounds_numbers_to_decimal_places() {
+        assert_eq!(
+            eval("[format(1/3r, 2), format(2/3r, 0), format(-1/8r, 2), format(7, 2), format(3.14159, 3)];"),
+            Ok("[\"0.33\", \"1\", \"-0.13\", \"7.00\", \"3.142\"]".to_string())
+        );
+        assert_eq!(
+            eval("format(1, -1);"),
+            Err(
+                "format() expects a number of places from 0 to 1000, got '-1' at line 1"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            eval("format(\"1\", 2);"),
+            Err("format() expects a number, got '1' at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn frozen_classes_refuse_static_assignment() {
+        let class = "class R { static x = 3; static items = [1]; } freeze(R);";
+        assert_eq!(
+            eval(&format!("{} R.x = 4;", class)),
+            Err("Can't modify a frozen class R at line 1".to_string())
+        );
+        assert_eq!(
+            eval(&format!(
+                "{} [is_frozen(R), is_frozen(R.items), R.x];",
+                class
+            )),
+            Ok("[true, true, 3]".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{} join(spawn(() => is_frozen(R)));", class)),
+            Ok("true".to_string())
+        );
+    }
+
+    #[test]
+    fn only_values_nothing_can_change_are_frozen() {
+        let frozen =
+            "[is_frozen(1), is_frozen(\"s\"), is_frozen(0..3), is_frozen(Some(freeze([1])))];";
+        assert_eq!(eval(frozen), Ok("[true, true, true, true]".to_string()));
+        let unfrozen = "class C {} [is_frozen(C), is_frozen(Some([1])), is_frozen(fun () {}), is_frozen(channel())];";
+        assert_eq!(
+            eval(unfrozen),
+            Ok("[false, false, false, false]".to_string())
+        );
+    }
+}
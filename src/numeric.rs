@@ -0,0 +1,417 @@
+use crate::bigint::{BigInt, Progress, Stopped};
+use crate::rational::Rational;
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Position of a value in the numeric tower. Binary operations promote both
+/// operands to the higher of their two ranks before computing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Integer,
+    BigInt,
+    Rational,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitwiseOp {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ArithmeticError {
+    NotNumeric,
+    NotInteger,
+    DivisionByZero,
+    NegativeShift,
+    TooLarge,
+    /// The computation's `Progress` stopped it.
+    Stopped,
+}
+
+impl From<Stopped> for ArithmeticError {
+    fn from(_: Stopped) -> Self {
+        ArithmeticError::Stopped
+    }
+}
+
+/// Largest exponent or shift count accepted before giving up on exactness.
+const MAX_EXPONENT: u32 = 1 << 20;
+
+fn rank(value: &Value) -> Option<Rank> {
+    match value {
+        Value::Integer(_) => Some(Rank::Integer),
+        Value::BigInt(_) => Some(Rank::BigInt),
+        Value::Rational(_) => Some(Rank::Rational),
+        Value::Number(_) => Some(Rank::Float),
+        _ => None,
+    }
+}
+
+fn common_rank(left: &Value, right: &Value) -> Option<Rank> {
+    Some(rank(left)?.max(rank(right)?))
+}
+
+pub fn is_numeric(value: &Value) -> bool {
+    rank(value).is_some()
+}
+
+/// Wraps an arbitrary-precision result, demoting it to a machine integer
+/// whenever it fits so small values stay on the fast path.
+pub fn integer_value(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(n) => Value::Integer(n),
+        None => Value::BigInt(Rc::new(n)),
+    }
+}
+
+fn to_bigint(value: &Value) -> BigInt {
+    match value {
+        Value::Integer(n) => BigInt::from_i64(*n),
+        Value::BigInt(n) => (**n).clone(),
+        _ => unreachable!("only integers rank below rationals"),
+    }
+}
+
+fn to_rational(value: &Value) -> Rational {
+    match value {
+        Value::Rational(r) => (**r).clone(),
+        other => Rational::from_integer(to_bigint(other)),
+    }
+}
+
+pub fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(n) => Some(*n as f64),
+        Value::BigInt(n) => Some(n.to_f64()),
+        Value::Rational(r) => Some(r.to_f64()),
+        Value::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// `value` in decimal notation, rounded to `places` digits after the point,
+/// or `None` if it isn't a number.
+pub fn to_decimal(value: &Value, places: usize) -> Option<String> {
+    Some(match value {
+        Value::Number(n) => format!("{:.*}", places, n),
+        _ if is_numeric(value) => format!("{:.*}", places, to_rational(value)),
+        _ => return None,
+    })
+}
+
+/// Computes `left op right`, telling `progress` how computing with big
+/// integers and rationals goes.
+pub fn arithmetic(
+    op: ArithmeticOp,
+    left: &Value,
+    right: &Value,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    if op == ArithmeticOp::Power {
+        return power(left, right, progress);
+    }
+    match common_rank(left, right).ok_or(ArithmeticError::NotNumeric)? {
+        Rank::Integer => match (left, right) {
+            (Value::Integer(a), Value::Integer(b)) => integer_arithmetic(op, *a, *b, progress),
+            _ => unreachable!(),
+        },
+        Rank::BigInt => bigint_arithmetic(op, &to_bigint(left), &to_bigint(right), progress),
+        Rank::Rational => {
+            rational_arithmetic(op, &to_rational(left), &to_rational(right), progress)
+        }
+        Rank::Float => {
+            let (a, b) = (to_f64(left).unwrap(), to_f64(right).unwrap());
+            Ok(Value::Number(match op {
+                ArithmeticOp::Add => a + b,
+                ArithmeticOp::Subtract => a - b,
+                ArithmeticOp::Multiply => a * b,
+                ArithmeticOp::Divide => a / b,
+                ArithmeticOp::Remainder => {
+                    let remainder = a % b;
+                    if remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+                        remainder + b
+                    } else {
+                        remainder
+                    }
+                }
+                ArithmeticOp::Power => unreachable!(),
+            }))
+        }
+    }
+}
+
+fn integer_arithmetic(
+    op: ArithmeticOp,
+    a: i64,
+    b: i64,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    let result = match op {
+        ArithmeticOp::Add => a.checked_add(b),
+        ArithmeticOp::Subtract => a.checked_sub(b),
+        ArithmeticOp::Multiply => a.checked_mul(b),
+        ArithmeticOp::Divide => {
+            if b == 0 {
+                return Err(ArithmeticError::DivisionByZero);
+            }
+            // Inexact quotients are exact rationals, as for big integers.
+            match a.checked_rem(b) {
+                Some(0) => a.checked_div(b),
+                _ => None,
+            }
+        }
+        ArithmeticOp::Remainder => {
+            if b == 0 {
+                return Err(ArithmeticError::DivisionByZero);
+            }
+            match a.checked_rem(b) {
+                Some(r) if r != 0 && (r < 0) != (b < 0) => Some(r + b),
+                Some(r) => Some(r),
+                None => Some(0),
+            }
+        }
+        ArithmeticOp::Power => unreachable!(),
+    };
+    match result {
+        Some(n) => Ok(Value::Integer(n)),
+        None => bigint_arithmetic(op, &BigInt::from_i64(a), &BigInt::from_i64(b), progress),
+    }
+}
+
+fn bigint_arithmetic(
+    op: ArithmeticOp,
+    a: &BigInt,
+    b: &BigInt,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    let result = match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Subtract => a - b,
+        ArithmeticOp::Multiply => a.mul_with(b, progress)?,
+        ArithmeticOp::Divide => {
+            if b.is_zero() {
+                return Err(ArithmeticError::DivisionByZero);
+            }
+            let (quotient, remainder) = a.div_rem_with(b, progress)?;
+            if !remainder.is_zero() {
+                let exact = Rational::new_with(a.clone(), b.clone(), progress)?
+                    .expect("divisor is non-zero");
+                return Ok(Value::Rational(Rc::new(exact)));
+            }
+            quotient
+        }
+        ArithmeticOp::Remainder => {
+            if b.is_zero() {
+                return Err(ArithmeticError::DivisionByZero);
+            }
+            let (_, remainder) = a.div_rem_with(b, progress)?;
+            if !remainder.is_zero() && remainder.is_negative() != b.is_negative() {
+                &remainder + b
+            } else {
+                remainder
+            }
+        }
+        ArithmeticOp::Power => unreachable!(),
+    };
+    Ok(integer_value(result))
+}
+
+fn rational_arithmetic(
+    op: ArithmeticOp,
+    a: &Rational,
+    b: &Rational,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    let result = match op {
+        ArithmeticOp::Add => a.add_with(b, progress)?,
+        ArithmeticOp::Subtract => a.add_with(&-b, progress)?,
+        ArithmeticOp::Multiply => a.mul_with(b, progress)?,
+        ArithmeticOp::Divide => a
+            .checked_div_with(b, progress)?
+            .ok_or(ArithmeticError::DivisionByZero)?,
+        ArithmeticOp::Remainder => {
+            let quotient = a
+                .checked_div_with(b, progress)?
+                .ok_or(ArithmeticError::DivisionByZero)?;
+            let floor = Rational::from_integer(quotient.floor_with(progress)?);
+            a.add_with(&-&b.mul_with(&floor, progress)?, progress)?
+        }
+        ArithmeticOp::Power => unreachable!(),
+    };
+    Ok(Value::Rational(Rc::new(result)))
+}
+
+/// Exponentiation stays exact for integer exponents on integer and rational
+/// bases, a negative exponent giving a rational like division does; any
+/// fractional exponent falls back to floats. Zero has no negative powers.
+fn power(
+    base: &Value,
+    exponent: &Value,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    let base_rank = rank(base).ok_or(ArithmeticError::NotNumeric)?;
+    let exponent_rank = rank(exponent).ok_or(ArithmeticError::NotNumeric)?;
+    let float_power = || {
+        Ok(Value::Number(
+            to_f64(base).unwrap().powf(to_f64(exponent).unwrap()),
+        ))
+    };
+
+    if base_rank == Rank::Float || exponent_rank > Rank::BigInt {
+        return float_power();
+    }
+    let exponent = to_bigint(exponent);
+    let negative = exponent.is_negative();
+    let base = if negative && base_rank != Rank::Rational {
+        if matches!(base, Value::Integer(0)) {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        &Value::Rational(Rc::new(to_rational(base)))
+    } else {
+        base
+    };
+    let magnitude = exponent
+        .abs()
+        .to_i64()
+        .filter(|&n| n <= MAX_EXPONENT as i64)
+        .ok_or(ArithmeticError::TooLarge)? as u32;
+
+    match base {
+        Value::Integer(a) => Ok(match a.checked_pow(magnitude) {
+            Some(n) => Value::Integer(n),
+            None => integer_value(BigInt::from_i64(*a).pow_with(magnitude, progress)?),
+        }),
+        Value::BigInt(a) => Ok(integer_value(a.pow_with(magnitude, progress)?)),
+        Value::Rational(r) => {
+            let raised = r.pow_with(magnitude, progress)?;
+            if negative {
+                Rational::from_integer(BigInt::from_i64(1))
+                    .checked_div_with(&raised, progress)?
+                    .map(|raised| Value::Rational(Rc::new(raised)))
+                    .ok_or(ArithmeticError::DivisionByZero)
+            } else {
+                Ok(Value::Rational(Rc::new(raised)))
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Bitwise operators work on integers only. `<<` overflows into a `BigInt`
+/// like the arithmetic operators do and `>>` floors, so both accept big
+/// operands; `&`, `|` and `^` require values that fit in 64 bits. Shifts
+/// tell `progress` how they go.
+pub fn bitwise(
+    op: BitwiseOp,
+    left: &Value,
+    right: &Value,
+    progress: &mut Progress,
+) -> Result<Value, ArithmeticError> {
+    let is_integer = |value: &Value| matches!(value, Value::Integer(_) | Value::BigInt(_));
+    if !is_integer(left) || !is_integer(right) {
+        return Err(ArithmeticError::NotInteger);
+    }
+    match op {
+        BitwiseOp::ShiftLeft | BitwiseOp::ShiftRight => {
+            let count = match right {
+                Value::Integer(n) if *n < 0 => return Err(ArithmeticError::NegativeShift),
+                Value::Integer(n) if *n <= MAX_EXPONENT as i64 => *n as u32,
+                Value::BigInt(n) if n.is_negative() => return Err(ArithmeticError::NegativeShift),
+                _ => return Err(ArithmeticError::TooLarge),
+            };
+            let scale = BigInt::from_i64(2).pow_with(count, progress)?;
+            let value = to_bigint(left);
+            Ok(integer_value(if op == BitwiseOp::ShiftLeft {
+                value.mul_with(&scale, progress)?
+            } else {
+                Rational::from_integer(value)
+                    .checked_div_with(&Rational::from_integer(scale), progress)?
+                    .expect("power of two is non-zero")
+                    .floor_with(progress)?
+            }))
+        }
+        _ => match (left, right) {
+            (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(match op {
+                BitwiseOp::And => a & b,
+                BitwiseOp::Or => a | b,
+                BitwiseOp::Xor => a ^ b,
+                _ => unreachable!(),
+            })),
+            _ => Err(ArithmeticError::TooLarge),
+        },
+    }
+}
+
+/// Bitwise complement, using `~x == -x - 1` so it extends to big integers.
+pub fn complement(value: &Value) -> Result<Value, ArithmeticError> {
+    match value {
+        Value::Integer(n) => Ok(Value::Integer(!n)),
+        Value::BigInt(n) => Ok(integer_value(&(-&**n) - &BigInt::from_i64(1))),
+        _ => Err(ArithmeticError::NotInteger),
+    }
+}
+
+pub fn negate(value: &Value) -> Option<Value> {
+    match value {
+        Value::Integer(n) => Some(match n.checked_neg() {
+            Some(n) => Value::Integer(n),
+            None => Value::BigInt(Rc::new(-&BigInt::from_i64(*n))),
+        }),
+        Value::BigInt(n) => Some(integer_value(-&**n)),
+        Value::Rational(r) => Some(Value::Rational(Rc::new(-&**r))),
+        Value::Number(n) => Some(Value::Number(-n)),
+        _ => None,
+    }
+}
+
+/// Orders two numbers exactly, even a float against an integer or rational
+/// that the float can't represent. Returns `None` for non-numbers and NaN.
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match common_rank(left, right)? {
+        Rank::Integer => match (left, right) {
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            _ => unreachable!(),
+        },
+        Rank::BigInt => Some(to_bigint(left).cmp(&to_bigint(right))),
+        Rank::Rational => Some(to_rational(left).cmp(&to_rational(right))),
+        Rank::Float => match (left, right) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Number(a), exact) => compare_float(*a, exact),
+            (exact, Value::Number(b)) => compare_float(*b, exact).map(Ordering::reverse),
+            _ => unreachable!(),
+        },
+    }
+}
+
+/// Orders a float against an exact number.
+fn compare_float(float: f64, exact: &Value) -> Option<Ordering> {
+    match exact {
+        // Floats hold integers this small exactly.
+        Value::Integer(n) if n.unsigned_abs() <= 1 << f64::MANTISSA_DIGITS => {
+            float.partial_cmp(&(*n as f64))
+        }
+        _ if float.is_nan() => None,
+        _ if float.is_infinite() => Some(if float > 0.0 {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }),
+        _ => Some(Rational::from_f64(float)?.cmp(&to_rational(exact))),
+    }
+}
@@ -0,0 +1,450 @@
+use crate::ast::{Expr, FunctionDecl, Literal, Names, Pattern, Stmt, YieldTarget};
+use crate::environment::Environment;
+use crate::value::Value;
+use core::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Rewrites a parsed program before it runs: operators whose operands are
+/// all literals are computed once, groupings are dropped unless they end a
+/// `?.` chain, `if` and `while`
+/// statements with literal conditions lose the branches that can't run, and
+/// statements after a `return`, `break` or `continue` are removed.
+///
+/// Folding evaluates the operator exactly as the interpreter would, so the
+/// results are the same. An expression that fails, like `1 / 0`, is left as
+/// written to fail when it runs.
+pub struct Optimizer {
+    /// An empty scope to evaluate literal operations in.
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self {
+            env: Rc::new(RefCell::new(Environment::new())),
+        }
+    }
+
+    pub fn optimize(&self, statements: &mut Vec<Stmt>) {
+        for statement in statements.iter_mut() {
+            self.statement(statement);
+        }
+        if let Some(end) = statements.iter().position(ends_flow) {
+            statements.truncate(end + 1);
+        }
+    }
+
+    fn statement(&self, statement: &mut Stmt) {
+        match statement {
+            Stmt::Expression(expr) | Stmt::Print { value: expr, .. } => self.expression(expr),
+            Stmt::Var { initializer, .. } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+            }
+            Stmt::Const { initializer, .. } => self.expression(initializer),
+            Stmt::Destructure { pattern, value, .. } => {
+                self.pattern(pattern);
+                self.expression(value);
+            }
+            Stmt::Block { statements, .. } => self.optimize(statements),
+            Stmt::Function { declaration, .. } => self.function(declaration),
+            Stmt::Return { value, .. } | Stmt::Yield { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Class {
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+                ..
+            } => {
+                for method in methods
+                    .iter_mut()
+                    .chain(getters)
+                    .chain(setters)
+                    .chain(static_methods)
+                {
+                    self.function(method);
+                }
+                for (_, value) in static_fields {
+                    self.expression(value);
+                }
+            }
+            Stmt::Trait {
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                for method in methods.iter_mut().chain(getters).chain(setters) {
+                    self.function(method);
+                }
+            }
+            Stmt::Enum { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                if let Some(condition) = truthiness(condition) {
+                    let taken = if condition {
+                        Some(std::mem::replace(then_branch.as_mut(), empty()))
+                    } else {
+                        else_branch.take().map(|branch| *branch)
+                    };
+                    *statement = taken.unwrap_or_else(empty);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+                if truthiness(condition) == Some(false) {
+                    *statement = empty();
+                }
+            }
+            Stmt::ForIn { iterable, body, .. } => {
+                self.expression(iterable);
+                self.statement(body);
+            }
+        }
+    }
+
+    /// Optimizes a function's defaults and body. Declarations are only
+    /// shared once the program runs, so each is still unique here.
+    fn function(&self, declaration: &mut Arc<FunctionDecl>) {
+        let Some(declaration) = Arc::get_mut(declaration) else {
+            return;
+        };
+        for param in &mut declaration.params {
+            if let Some(default) = &mut param.default {
+                self.expression(default);
+            }
+        }
+        self.optimize(&mut declaration.body);
+    }
+
+    fn pattern(&self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Bind(..) => {}
+            Pattern::Value(value) => self.expression(value),
+            Pattern::Range { low, high, .. } => {
+                self.expression(low);
+                self.expression(high);
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                for pattern in before
+                    .iter_mut()
+                    .chain(rest.as_deref_mut())
+                    .chain(after.iter_mut())
+                {
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Default { pattern, default } => {
+                self.pattern(pattern);
+                self.expression(default);
+            }
+            Pattern::Map { entries } => {
+                for (key, pattern) in entries {
+                    self.expression(key);
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Instance { fields, .. } => {
+                for (_, pattern) in fields {
+                    self.pattern(pattern);
+                }
+            }
+        }
+    }
+
+    /// Optimizes the operands of `expr`, then folds `expr` itself.
+    fn expression(&self, expr: &mut Expr) {
+        match expr {
+            Expr::Literal { .. } | Expr::Variable { .. } | Expr::This { .. } => {}
+            Expr::Super { this, .. } => self.expression(this),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expr::Unary { right, .. } => self.expression(right),
+            Expr::Grouping(inner) => self.expression(inner),
+            Expr::Assign { value, .. } => self.expression(value),
+            Expr::CompoundAssign { target, value, .. } => {
+                self.expression(target);
+                self.expression(value);
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.expression(then_branch);
+                self.expression(else_branch);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(&mut argument.value);
+                }
+            }
+            Expr::Get { object, .. } => self.expression(object),
+            Expr::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            Expr::Lambda(declaration) => self.function(declaration),
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expression(subject);
+                for arm in arms {
+                    self.pattern(&mut arm.pattern);
+                    if let Some(guard) = &mut arm.guard {
+                        self.expression(guard);
+                    }
+                    self.expression(&mut arm.body);
+                }
+            }
+        }
+        if let Some(folded) = self.fold(expr) {
+            *expr = folded;
+        }
+    }
+
+    /// The simpler expression `expr` reduces to, if any, given that its
+    /// operands are already folded.
+    fn fold(&self, expr: &mut Expr) -> Option<Expr> {
+        match expr {
+            // Parentheses stop a `?.` chain from skipping what follows
+            // them, so those around one stay.
+            Expr::Grouping(inner) if is_optional_chain(inner) => None,
+            Expr::Grouping(inner) => Some(std::mem::replace(inner.as_mut(), nil())),
+            Expr::Binary { left, right, .. } if is_literal(left) && is_literal(right) => {
+                self.evaluate(expr)
+            }
+            Expr::Unary { right, .. } if is_literal(right) => self.evaluate(expr),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let Expr::Literal { value } = left.as_ref() else {
+                    return None;
+                };
+                let keep_left = match operator.lexeme() {
+                    "and" => !Value::from(value).is_truthy(),
+                    "or" => Value::from(value).is_truthy(),
+                    "??" => !matches!(value, Literal::Nil),
+                    _ => return None,
+                };
+                let kept = if keep_left { left } else { right };
+                Some(in_place_of(expr_take(kept)))
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let kept = if truthiness(condition)? {
+                    then_branch
+                } else {
+                    else_branch
+                };
+                Some(in_place_of(expr_take(kept)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Computes an operation on literals, unless it fails or its result
+    /// can't be written as a literal.
+    fn evaluate(&self, expr: &Expr) -> Option<Expr> {
+        let value = expr.evaluate(&self.env).ok()?;
+        Some(Expr::Literal {
+            value: Literal::try_from(value).ok()?,
+        })
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Literal { .. })
+}
+
+/// Whether `expr` is a chain of calls and property accesses with a `?.`
+/// link, whose short circuit would reach further if it lost its
+/// surroundings.
+fn is_optional_chain(expr: &Expr) -> bool {
+    match expr {
+        Expr::Get {
+            object: inner,
+            optional,
+            ..
+        }
+        | Expr::Call {
+            callee: inner,
+            optional,
+            ..
+        } => *optional || is_optional_chain(inner),
+        _ => false,
+    }
+}
+
+fn expr_take(expr: &mut Expr) -> Expr {
+    std::mem::replace(expr, nil())
+}
+
+/// `kept`, ready to take the place of the operator it was an operand of,
+/// which like parentheses ended any `?.` chain in it.
+fn in_place_of(kept: Expr) -> Expr {
+    if is_optional_chain(&kept) {
+        Expr::Grouping(Box::new(kept))
+    } else {
+        kept
+    }
+}
+
+/// Whether a literal condition is truthy, or `None` if it isn't a literal.
+fn truthiness(condition: &Expr) -> Option<bool> {
+    match condition {
+        Expr::Literal { value } => Some(Value::from(value).is_truthy()),
+        _ => None,
+    }
+}
+
+/// Whether nothing after `statement` in its block can run.
+fn ends_flow(statement: &Stmt) -> bool {
+    matches!(
+        statement,
+        Stmt::Return { .. }
+            | Stmt::Break { .. }
+            | Stmt::Continue { .. }
+            | Stmt::Yield {
+                target: YieldTarget::Return,
+                ..
+            }
+    )
+}
+
+fn empty() -> Stmt {
+    Stmt::Block {
+        statements: Vec::new(),
+        names: Names::default(),
+    }
+}
+
+fn nil() -> Expr {
+    Expr::Literal {
+        value: Literal::Nil,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval, eval_unoptimized};
+
+    /// Runs `source` with and without the optimizer, expecting the same
+    /// outcome from both, and returns it.
+    fn same(source: &str) -> Result<String, String> {
+        let optimized = eval(source);
+        assert_eq!(optimized, eval_unoptimized(source), "for {}", source);
+        optimized
+    }
+
+    #[test]
+    fn folded_operators_behave_as_unfolded() {
+        assert_eq!(same("1 + 2 * 3;"), Ok("7".to_string()));
+        assert!(same("\"a\" + \"b\";").is_err());
+        assert_eq!(same("!nil and 2;"), Ok("2".to_string()));
+        assert_eq!(same("false or nil ?? 3;"), Ok("3".to_string()));
+        assert!(same("1 + 1 / 0;").is_err());
+        assert!(same("-\"a\";").is_err());
+        assert!(same("1 < \"a\";").is_err());
+        assert!(same("9223372036854775807 + 1 > 0;").is_ok());
+    }
+
+    #[test]
+    fn dead_code_goes_without_changing_what_runs() {
+        same("var n = 0; if (false) n = 1 / 0; else n = 2; n;").unwrap();
+        same("var n = 0; while (false) n = 1 / 0; n;").unwrap();
+        same("fun f() { return 1; 1 / 0; } f();").unwrap();
+        assert!(same("fun f() { if (true) return 1 / 0; return 1; } f();").is_err());
+        assert!(same("(true ? nil : 1).x;").is_err());
+    }
+
+    #[test]
+    fn groupings_still_end_optional_chains() {
+        let setup = "var a = nil; var b = {\"c\": {\"d\": 1}};";
+        for chain in [
+            "(a?.b).c;",
+            "(a?.b)();",
+            "(true ? a?.b : 1).c;",
+            "(false ? 1 : a?.b?.c).d;",
+            "(nil ?? a?.b).c;",
+            "(true and a?.b).c;",
+            "(false or a?.b()).c;",
+        ] {
+            assert!(same(&format!("{} {}", setup, chain)).is_err(), "{}", chain);
+        }
+        assert_eq!(same(&format!("{} a?.b.c;", setup)), Ok("nil".to_string()));
+        assert_eq!(same(&format!("{} (b?.c).d;", setup)), Ok("1".to_string()));
+    }
+}
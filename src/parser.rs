@@ -1,16 +1,69 @@
-use crate::ast::{Expr, Stmt};
+use crate::ast::{
+    Argument, Binding, Expr, FunctionDecl, Literal, MatchArm, Names, Param, Pattern, Slot, Stmt,
+    TypeExpr, YieldTarget,
+};
+use crate::bigint::BigInt;
 use crate::error::ParserError;
+use crate::rational::Rational;
 use crate::token::{Token, TokenType};
-use crate::value::Value;
+use std::sync::Arc;
+
+/// How many levels deep the syntax tree may be nested. The passes that
+/// walk it recurse once per level, so a deeper tree could overflow the
+/// stack.
+pub const MAX_NESTING: usize = 1000;
+
+/// A class's methods, getters, setters, static methods and static fields.
+type ClassBody = (
+    Vec<Arc<FunctionDecl>>,
+    Vec<Arc<FunctionDecl>>,
+    Vec<Arc<FunctionDecl>>,
+    Vec<Arc<FunctionDecl>>,
+    Vec<(Token, Expr)>,
+);
 
 pub struct Parser<'a> {
     pub tokens: &'a Vec<Token>,
     current: usize,
+    function_depth: usize,
+    /// Labels of the loops enclosing the current statement, innermost last,
+    /// reset on entering a function body since control can't cross it.
+    loops: Vec<Option<Token>>,
+    /// Whether the function body being parsed contains a `yield`.
+    yields: bool,
+    /// Whether the function body being parsed is `async`, allowing `await`.
+    in_async: bool,
+    /// Whether the class body being parsed is a subclass's, allowing
+    /// `super`.
+    in_subclass: bool,
+    /// Position of the `=>` ending the `match` arm whose guard is being
+    /// parsed, which mustn't be taken for part of an arrow function.
+    arm_arrow: Option<usize>,
+    /// Whether the pattern being parsed is a `match` arm's, where a
+    /// capitalised name stands for an existing value rather than binding.
+    matching: bool,
+    /// How many levels of the tree enclose what is being parsed, and the
+    /// deepest level reached since a chain like `a + b + c` started
+    /// measuring an operand, which tells it how tall the operand is.
+    depth: usize,
+    reached: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            function_depth: 0,
+            loops: Vec::new(),
+            yields: false,
+            in_async: false,
+            in_subclass: false,
+            arm_arrow: None,
+            matching: false,
+            depth: 0,
+            reached: 0,
+        }
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, ParserError> {
@@ -26,14 +79,507 @@ impl<'a> Parser<'a> {
     pub fn declaration(&mut self) -> Result<Stmt, ParserError> {
         if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.match_token(&[TokenType::Const]) {
+            self.const_declaration()
+        } else if self.check(&TokenType::Fun) && self.check_next(&TokenType::Identifier) {
+            self.advance();
+            Ok(Stmt::Function {
+                declaration: Arc::new(self.function("function", false)?),
+                slot: Slot::default(),
+            })
+        } else if self.check(&TokenType::Async) && self.check_next(&TokenType::Fun) {
+            self.advance();
+            self.advance();
+            Ok(Stmt::Function {
+                declaration: Arc::new(self.function("function", true)?),
+                slot: Slot::default(),
+            })
+        } else if self.match_token(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.match_token(&[TokenType::Trait]) {
+            self.trait_declaration()
+        } else if self.match_token(&[TokenType::Enum]) {
+            self.enum_declaration()
         } else {
             self.statement()
         }
     }
 
+    fn class_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
+        let mut superclass = None;
+        if self.match_token(&[TokenType::Less]) {
+            let parent = self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            if parent.lexeme() == name.lexeme() {
+                return Err(ParserError::new(
+                    "A class can't inherit from itself.".to_string(),
+                    parent.line,
+                ));
+            }
+            superclass = Some((parent, Slot::default()));
+        }
+        let mut traits = Vec::new();
+        if self.match_token(&[TokenType::Impl]) {
+            loop {
+                let name = self.consume(TokenType::Identifier, "Expect trait name.")?;
+                traits.push((name, Slot::default()));
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let enclosing_subclass = std::mem::replace(&mut self.in_subclass, superclass.is_some());
+        let members = self.class_body();
+        self.in_subclass = enclosing_subclass;
+        let (methods, getters, setters, static_methods, static_fields) = members?;
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class {
+            name,
+            slot: Slot::default(),
+            superclass,
+            traits,
+            methods,
+            getters,
+            setters,
+            static_methods,
+            static_fields,
+        })
+    }
+
+    /// Parses the members of a class up to its closing brace: methods,
+    /// getters, setters, static methods and static fields.
+    fn class_body(&mut self) -> Result<ClassBody, ParserError> {
+        let (mut methods, mut getters, mut setters) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut static_methods, mut static_fields) = (Vec::new(), Vec::new());
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            // `get`, `set` and `static` are only special before a name.
+            let modifier = if self.check(&TokenType::Identifier)
+                && (self.check_next(&TokenType::Identifier) || self.check_next(&TokenType::Async))
+            {
+                Some(self.advance().lexeme)
+            } else {
+                None
+            };
+            match modifier.as_deref() {
+                None => {}
+                Some("static") => {
+                    if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Equal) {
+                        let field = self.advance();
+                        self.advance();
+                        let value = self.expression()?;
+                        self.consume(TokenType::Semicolon, "Expect ';' after static field.")?;
+                        static_fields.push((field, value));
+                    } else {
+                        let is_async = self.match_token(&[TokenType::Async]);
+                        static_methods.push(Arc::new(self.function("method", is_async)?));
+                    }
+                    continue;
+                }
+                Some("get") => {
+                    getters.push(Arc::new(self.getter()?));
+                    continue;
+                }
+                Some("set") => {
+                    setters.push(Arc::new(self.setter()?));
+                    continue;
+                }
+                Some(other) => {
+                    return Err(ParserError::new(
+                        format!("Unknown class member modifier '{}'.", other),
+                        self.previous().line,
+                    ))
+                }
+            }
+            let is_async = self.match_token(&[TokenType::Async]);
+            let method = self.function("method", is_async)?;
+            if method.name.lexeme() == "init" && (method.is_generator || method.is_async) {
+                let problem = if method.is_async {
+                    "An initializer can't be async."
+                } else {
+                    "Can't yield from an initializer."
+                };
+                return Err(ParserError::new(problem.to_string(), method.name.line));
+            }
+            methods.push(Arc::new(method));
+        }
+        Ok((methods, getters, setters, static_methods, static_fields))
+    }
+
+    /// Parses `name(value) { body }` after `set`.
+    fn setter(&mut self) -> Result<FunctionDecl, ParserError> {
+        let setter = self.function("setter", false)?;
+        if setter.params.len() != 1 || setter.rest.is_some() || setter.is_generator {
+            return Err(ParserError::new(
+                "A setter takes exactly one parameter and can't yield.".to_string(),
+                setter.name.line,
+            ));
+        }
+        Ok(setter)
+    }
+
+    /// Parses `name { body }` after `get`: a method without parameters,
+    /// called when the property is read.
+    fn getter(&mut self) -> Result<FunctionDecl, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect getter name.")?;
+        let return_type = self.return_type()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before getter body.")?;
+        let (body, is_generator) = self.function_body(Self::block, false)?;
+        if is_generator {
+            return Err(ParserError::new(
+                "Can't yield from a getter.".to_string(),
+                name.line,
+            ));
+        }
+        Ok(FunctionDecl {
+            name,
+            params: Vec::new(),
+            rest: None,
+            return_type,
+            body,
+            names: Names::default(),
+            is_generator,
+            is_async: false,
+            is_method: true,
+        })
+    }
+
+    /// Parses a trait body, whose methods may be written with `fun`. A
+    /// method ending in `;` instead of a body is required of implementing
+    /// classes. Accessors are written as in classes.
+    fn trait_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect trait name.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before trait body.")?;
+
+        let (mut required, mut methods) = (Vec::new(), Vec::new());
+        let (mut getters, mut setters) = (Vec::new(), Vec::new());
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Identifier) {
+                match self.advance().lexeme() {
+                    "get" => getters.push(Arc::new(self.getter()?)),
+                    "set" => setters.push(Arc::new(self.setter()?)),
+                    other => {
+                        return Err(ParserError::new(
+                            format!("Unknown trait member modifier '{}'.", other),
+                            self.previous().line,
+                        ))
+                    }
+                }
+                continue;
+            }
+            let is_async = self.match_token(&[TokenType::Async]);
+            self.match_token(&[TokenType::Fun]);
+            let start = self.current;
+            let method = self.consume(TokenType::Identifier, "Expect method name.")?;
+            if method.lexeme() == "init" {
+                return Err(ParserError::new(
+                    "A trait can't have an initializer.".to_string(),
+                    method.line,
+                ));
+            }
+            self.consume(TokenType::LeftParen, "Expect '(' after method name.")?;
+            self.parameters()?;
+            self.return_type()?;
+            if self.match_token(&[TokenType::Semicolon]) {
+                required.push(method);
+                continue;
+            }
+            self.current = start;
+            methods.push(Arc::new(self.function("method", is_async)?));
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after trait body.")?;
+        Ok(Stmt::Trait {
+            name,
+            slot: Slot::default(),
+            required,
+            methods,
+            getters,
+            setters,
+        })
+    }
+
+    fn enum_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect enum name.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before enum body.")?;
+
+        let mut variants: Vec<(Token, Option<Vec<Token>>)> = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let variant = self.consume(TokenType::Identifier, "Expect variant name.")?;
+            if variants
+                .iter()
+                .any(|(other, _)| other.lexeme == variant.lexeme)
+            {
+                return Err(ParserError::new(
+                    format!("Duplicate variant '{}' in enum.", variant.lexeme),
+                    variant.line,
+                ));
+            }
+            let fields = if self.match_token(&[TokenType::LeftParen]) {
+                let mut fields = Vec::new();
+                while !self.check(&TokenType::RightParen) {
+                    fields.push(self.consume(TokenType::Identifier, "Expect field name.")?);
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightParen, "Expect ')' after fields.")?;
+                Some(fields)
+            } else {
+                None
+            };
+            variants.push((variant, fields));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after enum body.")?;
+        Ok(Stmt::Enum {
+            name,
+            slot: Slot::default(),
+            variants,
+        })
+    }
+
+    fn function(&mut self, kind: &str, is_async: bool) -> Result<FunctionDecl, ParserError> {
+        let name = self.consume(TokenType::Identifier, &format!("Expect {kind} name."))?;
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {kind} name."),
+        )?;
+        let (params, rest) = self.parameters()?;
+        let return_type = self.return_type()?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {kind} body."),
+        )?;
+        let (body, is_generator) = self.function_body(Self::block, is_async)?;
+        Ok(FunctionDecl {
+            name,
+            params,
+            rest,
+            return_type,
+            body,
+            names: Names::default(),
+            is_generator,
+            is_async,
+            is_method: kind != "function",
+        })
+    }
+
+    /// Parses a parameter list up to and including the closing ')',
+    /// returning the named parameters and the optional `...rest` parameter.
+    fn parameters(&mut self) -> Result<(Vec<Param>, Option<Token>), ParserError> {
+        let mut params: Vec<Param> = Vec::new();
+        let mut rest = None;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                let is_rest = self.match_token(&[TokenType::Ellipsis]);
+                let name = if is_rest {
+                    self.consume(TokenType::Identifier, "Expect parameter name after '...'.")?
+                } else {
+                    self.consume(TokenType::Identifier, "Expect parameter name.")?
+                };
+                if params.iter().any(|param| param.name.lexeme == name.lexeme) {
+                    return Err(ParserError::new(
+                        format!("Duplicate parameter '{}' in parameter list.", name.lexeme),
+                        name.line,
+                    ));
+                }
+                if is_rest {
+                    rest = Some(name);
+                    break;
+                }
+                let annotation = self.annotation()?;
+                let default = if self.match_token(&[TokenType::Equal]) {
+                    Some(self.conditional()?)
+                } else {
+                    if params.last().is_some_and(|param| param.default.is_some()) {
+                        return Err(ParserError::new(
+                            format!(
+                                "Parameter '{}' without a default can't follow one with a default.",
+                                name.lexeme()
+                            ),
+                            name.line,
+                        ));
+                    }
+                    None
+                };
+                params.push(Param {
+                    name,
+                    annotation,
+                    default,
+                });
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        Ok((params, rest))
+    }
+
+    /// Parses an optional `: Type` annotation after a variable or
+    /// parameter name.
+    fn annotation(&mut self) -> Result<Option<TypeExpr>, ParserError> {
+        if self.match_token(&[TokenType::Colon]) {
+            Ok(Some(self.type_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses an optional `-> Type` after a parameter list.
+    fn return_type(&mut self) -> Result<Option<TypeExpr>, ParserError> {
+        if self.match_token(&[TokenType::MinusGreater]) {
+            Ok(Some(self.type_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses `Name`, `Name[Type, ...]` or either followed by `?`.
+    fn type_expr(&mut self) -> Result<TypeExpr, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expect type name.")?;
+        let mut arguments = Vec::new();
+        if self.match_token(&[TokenType::LeftBracket]) {
+            loop {
+                arguments.push(self.nested(Self::type_expr)?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after type arguments.")?;
+        }
+        let optional = self.match_token(&[TokenType::Question]);
+        Ok(TypeExpr {
+            name,
+            arguments,
+            optional,
+        })
+    }
+
+    /// Parses a function body with `body`, allowing `return` and `yield`
+    /// inside it, or `await` instead of `yield` when `is_async`. Also
+    /// reports whether the body yields, which makes the function a
+    /// generator.
+    fn function_body(
+        &mut self,
+        body: fn(&mut Self) -> Result<Vec<Stmt>, ParserError>,
+        is_async: bool,
+    ) -> Result<(Vec<Stmt>, bool), ParserError> {
+        self.function_depth += 1;
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        let enclosing_yields = std::mem::replace(&mut self.yields, false);
+        let enclosing_async = std::mem::replace(&mut self.in_async, is_async);
+        let body = body(self);
+        let is_generator = std::mem::replace(&mut self.yields, enclosing_yields);
+        self.in_async = enclosing_async;
+        self.loops = enclosing_loops;
+        self.function_depth -= 1;
+        Ok((body?, is_generator))
+    }
+
+    /// Parses the rest of `fun (params) { ... }` after the `fun` keyword.
+    fn lambda(&mut self, is_async: bool) -> Result<Expr, ParserError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+        let (params, rest) = self.parameters()?;
+        let return_type = self.return_type()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        let (body, is_generator) = self.function_body(Self::block, is_async)?;
+        Ok(Expr::Lambda(Arc::new(FunctionDecl {
+            name: lambda_name(&keyword),
+            params,
+            rest,
+            return_type,
+            body,
+            names: Names::default(),
+            is_generator,
+            is_async,
+            is_method: false,
+        })))
+    }
+
+    /// Parses the rest of `(params) -> Type => body` after its parameter
+    /// list, where the return type is optional. The body is either a block
+    /// or a single expression whose value is returned.
+    fn arrow_function(
+        &mut self,
+        params: Vec<Param>,
+        rest: Option<Token>,
+        is_async: bool,
+    ) -> Result<Expr, ParserError> {
+        let return_type = self.return_type()?;
+        let arrow = self.consume(TokenType::Arrow, "Expect '=>' after parameters.")?;
+        let (body, is_generator) = self.function_body(
+            |parser| {
+                if parser.match_token(&[TokenType::LeftBrace]) {
+                    parser.block()
+                } else {
+                    let keyword = parser.peek().clone();
+                    let value = Some(parser.expression()?);
+                    Ok(vec![Stmt::Return { keyword, value }])
+                }
+            },
+            is_async,
+        )?;
+        Ok(Expr::Lambda(Arc::new(FunctionDecl {
+            name: lambda_name(&arrow),
+            params,
+            rest,
+            return_type,
+            body,
+            names: Names::default(),
+            is_generator,
+            is_async,
+            is_method: false,
+        })))
+    }
+
+    /// Looks past a parenthesized group starting at the current '(' to see
+    /// whether it is the parameter list of an arrow function: whether `=>`
+    /// or a `->` return type follows it.
+    fn is_arrow_function(&self) -> bool {
+        let mut depth = 0;
+        for (offset, token) in self.tokens[self.current..].iter().enumerate() {
+            match token.token_type {
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let arrow = self.current + offset + 1;
+                        return match self.tokens.get(arrow).map(|next| &next.token_type) {
+                            Some(TokenType::Arrow) => self.arm_arrow != Some(arrow),
+                            Some(TokenType::MinusGreater) => true,
+                            _ => false,
+                        };
+                    }
+                }
+                TokenType::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
+        if self.starts_pattern() {
+            return self.destructuring(Binding::Var);
+        }
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let annotation = self.annotation()?;
 
+        if self.check(&TokenType::Equal)
+            && (self.check_next(&TokenType::Yield) || self.check_next(&TokenType::Await))
+        {
+            self.advance();
+            self.advance();
+            return self.yield_statement(YieldTarget::Declare(name, Slot::default()));
+        }
         let initializer = if self.match_token(&[TokenType::Equal]) {
             Some(self.expression()?)
         } else {
@@ -45,21 +591,381 @@ impl<'a> Parser<'a> {
             "Expect ';' after variable declaration.",
         )?;
 
-        Ok(Stmt::Var(name.lexeme, initializer))
+        Ok(Stmt::Var {
+            name,
+            annotation,
+            initializer,
+            slot: Slot::default(),
+        })
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, ParserError> {
+        if self.starts_pattern() {
+            return self.destructuring(Binding::Const);
+        }
+        let name = self.consume(TokenType::Identifier, "Expect constant name.")?;
+        let annotation = self.annotation()?;
+        self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+        Ok(Stmt::Const {
+            name,
+            annotation,
+            initializer,
+            slot: Slot::default(),
+        })
+    }
+
+    /// Whether a declaration continues with a destructuring pattern rather
+    /// than a plain name.
+    fn starts_pattern(&self) -> bool {
+        self.check(&TokenType::LeftBracket)
+            || self.check(&TokenType::LeftBrace)
+            || (self.check(&TokenType::Identifier)
+                && (self.check_next(&TokenType::LeftParen) || self.check_next(&TokenType::Dot)))
+    }
+
+    /// Parses `pattern = value;`, the rest of a destructuring `var`, `const`
+    /// or assignment.
+    fn destructuring(&mut self, binding: Binding) -> Result<Stmt, ParserError> {
+        let matching = std::mem::replace(&mut self.matching, false);
+        let pattern = self.whole_pattern();
+        self.matching = matching;
+        let pattern = pattern?;
+        let equals = self.consume(TokenType::Equal, "Expect '=' after destructuring pattern.")?;
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after destructuring.")?;
+        Ok(Stmt::Destructure {
+            pattern,
+            equals,
+            value,
+            binding,
+        })
+    }
+
+    /// Whether the statement starting at the current '[' or '{' is a
+    /// destructuring assignment, which a '=' after the closing bracket
+    /// shows: a list literal can't be assigned to, and a block can't be
+    /// followed by one.
+    fn is_destructuring_assignment(&self) -> bool {
+        let mut depth = 0;
+        for (offset, token) in self.tokens[self.current..].iter().enumerate() {
+            match token.token_type {
+                TokenType::LeftBracket | TokenType::LeftBrace | TokenType::LeftParen => depth += 1,
+                TokenType::RightBracket | TokenType::RightBrace | TokenType::RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self
+                            .tokens
+                            .get(self.current + offset + 1)
+                            .is_some_and(|next| next.token_type == TokenType::Equal);
+                    }
+                }
+                TokenType::Eof => return false,
+                _ => {}
+            }
+        }
+        false
     }
 
     fn statement(&mut self) -> Result<Stmt, ParserError> {
         if self.match_token(&[TokenType::Print]) {
             self.print_statement()
+        } else if self.match_token(&[TokenType::Return]) {
+            self.return_statement()
+        } else if (self.check(&TokenType::LeftBracket) || self.check(&TokenType::LeftBrace))
+            && self.is_destructuring_assignment()
+        {
+            self.destructuring(Binding::Assign)
+        } else if self.match_token(&[TokenType::LeftBrace]) {
+            Ok(block(self.block()?))
+        } else if self.match_token(&[TokenType::If]) {
+            self.if_statement()
+        } else if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+            let label = self.advance();
+            self.advance();
+            self.loop_statement(Some(label))
+        } else if self.check(&TokenType::While) || self.check(&TokenType::For) {
+            self.loop_statement(None)
+        } else if self.match_token(&[TokenType::Break, TokenType::Continue]) {
+            self.jump_statement()
+        } else if self.match_token(&[TokenType::Yield, TokenType::Await]) {
+            self.yield_statement(YieldTarget::Discard)
+        } else if self.check(&TokenType::Identifier)
+            && self.check_next(&TokenType::Equal)
+            && self.tokens.get(self.current + 2).is_some_and(|token| {
+                matches!(token.token_type, TokenType::Yield | TokenType::Await)
+            })
+        {
+            let name = self.advance();
+            self.advance();
+            self.advance();
+            self.yield_statement(YieldTarget::Assign(name, Slot::default()))
         } else {
             self.expression_statement()
         }
     }
 
+    /// Parses a condition followed by the statement it guards. The body
+    /// must be a block unless the condition is parenthesized, which
+    /// allows both `while x { ... }` and `while (x) stmt;`.
+    fn condition_and_body(&mut self, keyword: &str) -> Result<(Expr, Stmt), ParserError> {
+        let condition = self.expression()?;
+        if self.match_token(&[TokenType::LeftBrace]) {
+            return Ok((condition, block(self.block()?)));
+        }
+        if !matches!(condition, Expr::Grouping(_)) {
+            return Err(ParserError::new(
+                format!("Expect '{{' after {keyword} condition."),
+                self.peek().line,
+            ));
+        }
+        Ok((condition, self.nested(Self::statement)?))
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let (condition, then_branch) = self.condition_and_body("if")?;
+        let else_branch = if self.match_token(&[TokenType::Else]) {
+            Some(Box::new(self.nested(Self::statement)?))
+        } else {
+            None
+        };
+        Ok(Stmt::If {
+            keyword,
+            condition,
+            then_branch: Box::new(then_branch),
+            else_branch,
+        })
+    }
+
+    fn loop_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParserError> {
+        self.loops.push(label.clone());
+        let statement = if self.match_token(&[TokenType::While]) {
+            self.while_statement(label)
+        } else if self.match_token(&[TokenType::For]) {
+            self.for_statement(label)
+        } else {
+            Err(ParserError::new(
+                "Expect loop after label.".to_string(),
+                self.peek().line,
+            ))
+        };
+        self.loops.pop();
+        statement
+    }
+
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let (condition, body) = self.condition_and_body("while")?;
+        Ok(Stmt::While {
+            keyword,
+            label,
+            condition,
+            body: Box::new(body),
+            increment: None,
+        })
+    }
+
+    /// Parses `for (initializer; condition; increment) body`, scoping the
+    /// initializer to a block around the loop, or a `for x in xs { ... }`.
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParserError> {
+        if self.check(&TokenType::Identifier)
+            && (self.check_next(&TokenType::In) || self.check_next(&TokenType::Comma))
+        {
+            return self.for_in_statement(label);
+        }
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        let initializer = if self.match_token(&[TokenType::Semicolon]) {
+            None
+        } else if self.match_token(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            Expr::Literal {
+                value: Literal::Boolean(true),
+            }
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(&TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.nested(Self::statement)?;
+        let mut statements: Vec<Stmt> = initializer.into_iter().collect();
+        statements.push(Stmt::While {
+            keyword,
+            label,
+            condition,
+            body: Box::new(body),
+            increment,
+        });
+        Ok(block(statements))
+    }
+
+    fn for_in_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParserError> {
+        let mut variables = vec![self.advance()];
+        if self.match_token(&[TokenType::Comma]) {
+            let second = self.consume(
+                TokenType::Identifier,
+                "Expect second loop variable after ','.",
+            )?;
+            if second.lexeme == variables[0].lexeme {
+                return Err(ParserError::new(
+                    format!("Duplicate loop variable '{}'.", second.lexeme),
+                    second.line,
+                ));
+            }
+            variables.push(second);
+        }
+        self.consume(TokenType::In, "Expect 'in' after loop variables.")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' after for-in iterable.")?;
+        let body = block(self.block()?);
+        Ok(Stmt::ForIn {
+            label,
+            variables,
+            iterable,
+            body: Box::new(body),
+            names: Names::default(),
+        })
+    }
+
+    /// Parses `break` or `continue` with an optional label, checking that
+    /// it names an enclosing loop of the current function.
+    fn jump_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let label = if self.check(&TokenType::Identifier) {
+            Some(self.advance())
+        } else {
+            None
+        };
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after '{}'.", keyword.lexeme()),
+        )?;
+
+        match &label {
+            None if self.loops.is_empty() => {
+                return Err(ParserError::new(
+                    format!("Can't use '{}' outside of a loop.", keyword.lexeme()),
+                    keyword.line,
+                ))
+            }
+            Some(label)
+                if !self
+                    .loops
+                    .iter()
+                    .flatten()
+                    .any(|l| l.lexeme() == label.lexeme()) =>
+            {
+                return Err(ParserError::new(
+                    format!(
+                        "No enclosing loop labeled '{}' for '{}'.",
+                        label.lexeme(),
+                        keyword.lexeme()
+                    ),
+                    label.line,
+                ))
+            }
+            _ => {}
+        }
+
+        Ok(match keyword.token_type {
+            TokenType::Break => Stmt::Break { keyword, label },
+            _ => Stmt::Continue { keyword, label },
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, ParserError> {
+        let mut statements = Vec::new();
+
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.nested(Self::declaration)?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        if self.match_token(&[TokenType::Yield, TokenType::Await]) {
+            return self.yield_statement(YieldTarget::Print);
+        }
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print { keyword, value })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        if self.function_depth == 0 {
+            return Err(ParserError::new(
+                "Can't return from top-level code.".to_string(),
+                keyword.line,
+            ));
+        }
+        if self.match_token(&[TokenType::Yield, TokenType::Await]) {
+            return self.yield_statement(YieldTarget::Return);
+        }
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    /// Parses the rest of a `yield` or `await` after the keyword. Both are
+    /// limited to these statement forms so that a generator or async
+    /// function only ever suspends between statements, never halfway
+    /// through evaluating an expression.
+    fn yield_statement(&mut self, target: YieldTarget) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let problem = match (keyword.token_type, self.in_async) {
+            (TokenType::Await, false) => Some("Can't use 'await' outside an async function."),
+            (TokenType::Yield, _) if self.function_depth == 0 => {
+                Some("Can't yield outside of a function.")
+            }
+            (TokenType::Yield, true) => Some("Can't yield from an async function."),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            return Err(ParserError::new(problem.to_string(), keyword.line));
+        }
+        if keyword.token_type == TokenType::Yield {
+            self.yields = true;
+        }
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after {} value.", keyword.lexeme()),
+        )?;
+        Ok(Stmt::Yield {
+            keyword,
+            value,
+            target,
+        })
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
@@ -69,15 +975,195 @@ impl<'a> Parser<'a> {
     }
 
     fn expression(&mut self) -> Result<Expr, ParserError> {
-        self.equality()
+        self.nested(Self::assignment)
+    }
+
+    /// Parses with `parse` one level deeper in the tree, failing if that
+    /// is deeper than `MAX_NESTING`.
+    fn nested<T>(
+        &mut self,
+        parse: fn(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError> {
+        self.grown(1)?;
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    /// Parses with `parse`, returning what it parsed and how many levels
+    /// tall that is.
+    fn measured<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<(T, usize), ParserError> {
+        let enclosing = std::mem::replace(&mut self.reached, self.depth);
+        let parsed = parse(self);
+        let height = self.reached - self.depth;
+        self.reached = self.reached.max(enclosing);
+        Ok((parsed?, height))
+    }
+
+    /// Notes that the tree being built at this level has grown to
+    /// `height` levels, failing if that is deeper than `MAX_NESTING`.
+    fn grown(&mut self, height: usize) -> Result<usize, ParserError> {
+        let depth = self.depth + height;
+        if depth > MAX_NESTING {
+            return Err(ParserError::new(
+                "Code nested too deeply.".to_string(),
+                self.peek().line,
+            ));
+        }
+        self.reached = self.reached.max(depth);
+        Ok(height)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParserError> {
+        let expr = self.conditional()?;
+
+        if self.match_token(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = self.nested(Self::assignment)?;
+            return match expr {
+                Expr::Variable { name, .. } => Ok(Expr::Assign {
+                    name,
+                    value: Box::new(value),
+                    slot: Slot::default(),
+                }),
+                Expr::Get {
+                    object,
+                    name,
+                    optional: false,
+                } => Ok(Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Expr::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                _ => Err(ParserError::new(
+                    "Invalid assignment target.".to_string(),
+                    equals.line,
+                )),
+            };
+        }
+
+        if self.match_token(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PercentEqual,
+        ]) {
+            let token = self.previous().clone();
+            self.check_assignable(&expr, &token)?;
+            let operator = binary_operator_of(&token);
+            let value = self.nested(Self::assignment)?;
+            return Ok(Expr::CompoundAssign {
+                target: Box::new(expr),
+                operator,
+                value: Box::new(value),
+                postfix: false,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn check_assignable(&self, target: &Expr, operator: &Token) -> Result<(), ParserError> {
+        match target {
+            Expr::Variable { .. }
+            | Expr::Index { .. }
+            | Expr::Get {
+                optional: false, ..
+            } => Ok(()),
+            _ => Err(ParserError::new(
+                format!("Invalid target for '{}'.", operator.lexeme()),
+                operator.line,
+            )),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Expr, ParserError> {
+        let condition = self.coalesce()?;
+
+        if self.match_token(&[TokenType::Question]) {
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            let else_branch = self.nested(Self::conditional)?;
+            return Ok(Expr::Conditional {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
+        }
+        Ok(condition)
+    }
+
+    fn coalesce(&mut self) -> Result<Expr, ParserError> {
+        self.logical(&[TokenType::QuestionQuestion], Self::or)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParserError> {
+        self.logical(&[TokenType::Or], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParserError> {
+        self.logical(&[TokenType::And], Self::equality)
+    }
+
+    /// Like `left_associative`, but builds short-circuiting `Logical` nodes.
+    fn logical(
+        &mut self,
+        types: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr, ParserError>,
+    ) -> Result<Expr, ParserError> {
+        let (mut expr, mut height) = self.measured(operand)?;
+
+        while self.match_token(types) {
+            let operator = self.previous().clone();
+            let (right, right_height) = self.measured(operand)?;
+            height = self.grown(height.max(right_height) + 1)?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.comparison()?;
+        self.left_associative(
+            &[TokenType::BangEqual, TokenType::EqualEqual],
+            Self::comparison,
+        )
+    }
+
+    /// Parses a run of left-associative binary operators from `types`
+    /// whose operands are parsed by the next-higher precedence level.
+    fn left_associative(
+        &mut self,
+        types: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr, ParserError>,
+    ) -> Result<Expr, ParserError> {
+        let (mut expr, mut height) = self.measured(operand)?;
 
-        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+        while self.match_token(types) {
             let operator = self.previous().clone();
-            let right = self.comparison()?;
+            let (right, right_height) = self.measured(operand)?;
+            height = self.grown(height.max(right_height) + 1)?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 operator,
@@ -104,6 +1190,12 @@ impl<'a> Parser<'a> {
         self.peek().token_type == *token_type
     }
 
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|token| token.token_type == *token_type)
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -112,7 +1204,7 @@ impl<'a> Parser<'a> {
     }
 
     fn is_at_end(&self) -> bool {
-        self.peek().token_type == TokenType::EOF
+        self.peek().token_type == TokenType::Eof
     }
 
     fn peek(&self) -> &Token {
@@ -124,63 +1216,206 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.term()?;
-        while self.match_token(&[
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
+        self.left_associative(
+            &[
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::Less,
+                TokenType::LessEqual,
+                TokenType::Is,
+            ],
+            Self::range,
+        )
+    }
+
+    /// Parses `start..end` or `start..=end` with an optional trailing
+    /// `step n`; `step` is only special in this position.
+    fn range(&mut self) -> Result<Expr, ParserError> {
+        let start = self.bit_or()?;
+
+        if self.match_token(&[TokenType::DotDot, TokenType::DotDotEqual]) {
             let operator = self.previous().clone();
-            let right = self.term()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
+            let end = self.bit_or()?;
+            let step = if self.check(&TokenType::Identifier) && self.peek().lexeme() == "step" {
+                self.advance();
+                Some(Box::new(self.bit_or()?))
+            } else {
+                None
             };
+            return Ok(Expr::Range {
+                start: Box::new(start),
+                operator,
+                end: Box::new(end),
+                step,
+            });
         }
-        Ok(expr)
+        Ok(start)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ParserError> {
+        self.left_associative(&[TokenType::Pipe], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParserError> {
+        self.left_associative(&[TokenType::Caret], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParserError> {
+        self.left_associative(&[TokenType::Ampersand], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParserError> {
+        self.left_associative(
+            &[TokenType::LessLess, TokenType::GreaterGreater],
+            Self::term,
+        )
     }
 
     fn term(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.factor()?;
-        while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right = self.factor()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_associative(&[TokenType::Minus, TokenType::Plus], Self::factor)
     }
 
     fn factor(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.unary()?;
-        while self.match_token(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_associative(
+            &[TokenType::Slash, TokenType::Star, TokenType::Percent],
+            Self::unary,
+        )
     }
 
     fn unary(&mut self) -> Result<Expr, ParserError> {
-        if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
+        if self.match_token(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             Ok(Expr::Unary {
                 operator,
                 right: Box::new(right),
             })
+        } else if self.match_token(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let token = self.previous().clone();
+            let target = self.nested(Self::unary)?;
+            self.check_assignable(&target, &token)?;
+            Ok(increment(target, binary_operator_of(&token), false))
         } else {
-            self.primary()
+            self.exponent()
+        }
+    }
+
+    /// `**` binds tighter than unary minus on its left (`-2 ** 2 == -4`) and
+    /// is right-associative, so its right operand is parsed as a unary.
+    fn exponent(&mut self) -> Result<Expr, ParserError> {
+        let base = self.postfix()?;
+
+        if self.match_token(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let exponent = self.nested(Self::unary)?;
+            return Ok(Expr::Binary {
+                left: Box::new(base),
+                operator,
+                right: Box::new(exponent),
+            });
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.call()?;
+
+        while self.match_token(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let token = self.previous().clone();
+            self.check_assignable(&expr, &token)?;
+            expr = increment(expr, binary_operator_of(&token), true);
+        }
+        Ok(expr)
+    }
+
+    fn call(&mut self) -> Result<Expr, ParserError> {
+        let (mut expr, mut height) = self.measured(Self::primary)?;
+
+        loop {
+            // Each link nests what came before it a level deeper.
+            let link_height;
+            if self.match_token(&[TokenType::LeftParen]) {
+                (expr, link_height) = self.measured(|parser| parser.finish_call(expr, false))?;
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index;
+                (index, link_height) = self.measured(Self::expression)?;
+                self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                    optional: false,
+                };
+                link_height = 0;
+            } else if self.match_token(&[TokenType::QuestionDot]) {
+                if self.match_token(&[TokenType::LeftParen]) {
+                    (expr, link_height) = self.measured(|parser| parser.finish_call(expr, true))?;
+                } else {
+                    let name = self.consume(
+                        TokenType::Identifier,
+                        "Expect property name or '(' after '?.'.",
+                    )?;
+                    expr = Expr::Get {
+                        object: Box::new(expr),
+                        name,
+                        optional: true,
+                    };
+                    link_height = 0;
+                }
+            } else {
+                break;
+            }
+            height = self.grown(height.max(link_height) + 1)?;
         }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr, optional: bool) -> Result<Expr, ParserError> {
+        let mut arguments: Vec<Argument> = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                let name =
+                    if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+                        let name = self.advance();
+                        self.advance();
+                        Some(name)
+                    } else {
+                        if let Some(named) =
+                            arguments.iter().find_map(|argument| argument.name.as_ref())
+                        {
+                            return Err(ParserError::new(
+                                format!(
+                                    "Positional argument can't follow named argument '{}'.",
+                                    named.lexeme()
+                                ),
+                                self.peek().line,
+                            ));
+                        }
+                        None
+                    };
+                let value = self.expression()?;
+                arguments.push(Argument { name, value });
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+            optional,
+        })
     }
 
     fn primary(&mut self) -> Result<Expr, ParserError> {
@@ -188,48 +1423,136 @@ impl<'a> Parser<'a> {
             TokenType::False => {
                 self.advance();
                 Ok(Expr::Literal {
-                    value: Value::Boolean(false),
+                    value: Literal::Boolean(false),
                 })
             }
             TokenType::True => {
                 self.advance();
                 Ok(Expr::Literal {
-                    value: Value::Boolean(true),
+                    value: Literal::Boolean(true),
                 })
             }
             TokenType::Nil => {
                 self.advance();
-                Ok(Expr::Literal { value: Value::Nil })
+                Ok(Expr::Literal {
+                    value: Literal::Nil,
+                })
             }
             TokenType::Number | TokenType::String => {
                 let token = self.advance();
                 match token.token_type {
                     TokenType::Number => {
-                        let value = token
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse()
-                            .unwrap_or_else(|_| panic!("Failed to parse number."));
-                        Ok(Expr::Literal {
-                            value: Value::Number(value),
-                        })
+                        let value =
+                            number_literal(token.literal.as_ref().unwrap()).ok_or_else(|| {
+                                ParserError::new(
+                                    format!("Invalid number literal '{}'", token.lexeme()),
+                                    token.line,
+                                )
+                            })?;
+                        Ok(Expr::Literal { value })
                     }
                     TokenType::String => {
                         let value = token.literal.as_ref().unwrap().clone();
                         Ok(Expr::Literal {
-                            value: Value::String(value),
+                            value: Literal::String(value),
                         })
                     }
                     _ => unreachable!(),
                 }
             }
+            TokenType::Identifier
+                if self.check_next(&TokenType::Arrow)
+                    && self.arm_arrow != Some(self.current + 1) =>
+            {
+                let name = self.advance();
+                self.arrow_function(
+                    vec![Param {
+                        name,
+                        annotation: None,
+                        default: None,
+                    }],
+                    None,
+                    false,
+                )
+            }
             TokenType::Identifier => {
-                let token = self.advance();
-                Ok(Expr::Literal {
-                    value: Value::String(token.lexeme().to_string()),
+                let name = self.advance();
+                Ok(Expr::Variable {
+                    name,
+                    slot: Slot::default(),
                 })
             }
+            TokenType::Fun => {
+                self.advance();
+                self.lambda(false)
+            }
+            TokenType::Async => {
+                self.advance();
+                if self.match_token(&[TokenType::Fun]) {
+                    return self.lambda(true);
+                }
+                self.consume(TokenType::LeftParen, "Expect 'fun' or '(' after 'async'.")?;
+                let (params, rest) = self.parameters()?;
+                self.arrow_function(params, rest, true)
+            }
+            TokenType::LeftParen if self.is_arrow_function() => {
+                self.advance();
+                let (params, rest) = self.parameters()?;
+                self.arrow_function(params, rest, false)
+            }
+            TokenType::LeftBracket => {
+                self.advance();
+                let mut elements = Vec::new();
+                while !self.check(&TokenType::RightBracket) {
+                    elements.push(self.expression()?);
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+                Ok(Expr::List { elements })
+            }
+            TokenType::LeftBrace => {
+                let brace = self.advance();
+                let mut entries = Vec::new();
+                while !self.check(&TokenType::RightBrace) {
+                    let key = if self.check(&TokenType::Identifier)
+                        && self.check_next(&TokenType::Colon)
+                    {
+                        Expr::Literal {
+                            value: Literal::String(self.advance().lexeme),
+                        }
+                    } else {
+                        self.expression()?
+                    };
+                    self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+                Ok(Expr::Map { brace, entries })
+            }
+            TokenType::Match => {
+                let keyword = self.advance();
+                self.match_expression(keyword)
+            }
+            TokenType::Yield | TokenType::Await => Err(ParserError::new(
+                format!(
+                    "'{}' must be a statement or the whole value of a 'var', assignment, 'return' or 'print'; store its value in a variable to use it in an expression.",
+                    self.peek().lexeme()
+                ),
+                self.peek().line,
+            )),
+            TokenType::This => {
+                let keyword = self.advance();
+                Ok(Expr::This {
+                    keyword,
+                    slot: Slot::default(),
+                })
+            }
+            TokenType::Super => self.super_expression(),
             TokenType::LeftParen => {
                 self.advance();
                 let expr = self.expression()?;
@@ -245,6 +1568,268 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses `super.name`, which only a subclass's members can use.
+    fn super_expression(&mut self) -> Result<Expr, ParserError> {
+        let keyword = self.advance();
+        if !self.in_subclass {
+            return Err(ParserError::new(
+                "Can't use 'super' outside a class with a superclass.".to_string(),
+                keyword.line,
+            ));
+        }
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+        let name = self.consume(TokenType::Identifier, "Expect superclass member name.")?;
+        let this = Token::new(TokenType::This, "this".into(), None, keyword.line);
+        Ok(Expr::Super {
+            name,
+            slot: Slot::default(),
+            this: Box::new(Expr::This {
+                keyword: this,
+                slot: Slot::default(),
+            }),
+        })
+    }
+
+    /// Parses the rest of a `match` after the keyword.
+    fn match_expression(&mut self, keyword: Token) -> Result<Expr, ParserError> {
+        let subject = self.expression()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' after match value.")?;
+        let mut arms = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let matching = std::mem::replace(&mut self.matching, true);
+            let pattern = self.whole_pattern();
+            self.matching = matching;
+            let pattern = pattern?;
+            let guard = if self.match_token(&[TokenType::If]) {
+                let enclosing = self.arm_arrow.replace(self.arm_arrow_position());
+                let guard = self.expression();
+                self.arm_arrow = enclosing;
+                Some(guard?)
+            } else {
+                None
+            };
+            self.consume(TokenType::Arrow, "Expect '=>' after match pattern.")?;
+            let body = self.expression()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+                names: Names::default(),
+            });
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+        Ok(Expr::Match {
+            keyword,
+            subject: Box::new(subject),
+            arms,
+        })
+    }
+
+    /// Finds the `=>` that ends the guard starting at the current token:
+    /// the first one outside any brackets.
+    fn arm_arrow_position(&self) -> usize {
+        let mut depth = 0usize;
+        for (offset, token) in self.tokens[self.current..].iter().enumerate() {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                    depth = depth.saturating_sub(1)
+                }
+                TokenType::Arrow if depth == 0 => return self.current + offset,
+                _ => {}
+            }
+        }
+        self.tokens.len()
+    }
+
+    /// Parses a pattern, checking that it binds each name only once.
+    fn whole_pattern(&mut self) -> Result<Pattern, ParserError> {
+        let line = self.peek().line;
+        let pattern = self.pattern()?;
+        let mut names = Vec::new();
+        pattern.bound_names(&mut names);
+        for (index, name) in names.iter().enumerate() {
+            if names[..index].contains(name) {
+                return Err(ParserError::new(
+                    format!("Duplicate binding '{}' in pattern.", name),
+                    line,
+                ));
+            }
+        }
+        Ok(pattern)
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParserError> {
+        match self.peek().token_type {
+            TokenType::Identifier if self.peek().lexeme() == "_" => {
+                self.advance();
+                Ok(Pattern::Wildcard)
+            }
+            // Dotted names, and in a `match` capitalised ones, refer to
+            // classes, enum variants and other existing values rather than
+            // binding.
+            TokenType::Identifier
+                if self.check_next(&TokenType::Dot)
+                    || self.check_next(&TokenType::LeftParen)
+                    || (self.matching
+                        && self
+                            .peek()
+                            .lexeme
+                            .starts_with(|c: char| c.is_ascii_uppercase())) =>
+            {
+                let mut class = Expr::Variable {
+                    name: self.advance(),
+                    slot: Slot::default(),
+                };
+                while self.match_token(&[TokenType::Dot]) {
+                    let name = self.consume(TokenType::Identifier, "Expect name after '.'.")?;
+                    class = Expr::Get {
+                        object: Box::new(class),
+                        name,
+                        optional: false,
+                    };
+                }
+                if !self.match_token(&[TokenType::LeftParen]) {
+                    return Ok(Pattern::Value(class));
+                }
+                let mut fields = Vec::new();
+                while !self.check(&TokenType::RightParen) {
+                    let name = if self.check(&TokenType::Identifier)
+                        && self.check_next(&TokenType::Colon)
+                    {
+                        let name = self.advance();
+                        self.advance();
+                        Some(name)
+                    } else {
+                        None
+                    };
+                    fields.push((name, self.element_pattern()?));
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightParen, "Expect ')' after field patterns.")?;
+                Ok(Pattern::Instance { class, fields })
+            }
+            TokenType::Identifier => Ok(Pattern::Bind(self.advance(), Slot::default())),
+            TokenType::LeftBracket => {
+                self.advance();
+                let (mut before, mut rest, mut after) = (Vec::new(), None, Vec::new());
+                while !self.check(&TokenType::RightBracket) {
+                    if self.match_token(&[TokenType::Ellipsis]) {
+                        if rest.is_some() {
+                            return Err(ParserError::new(
+                                "A list pattern can only have one rest pattern.".to_string(),
+                                self.previous().line,
+                            ));
+                        }
+                        rest = Some(Box::new(
+                            if self.check(&TokenType::Comma) || self.check(&TokenType::RightBracket)
+                            {
+                                Pattern::Wildcard
+                            } else {
+                                self.nested(Self::pattern)?
+                            },
+                        ));
+                    } else if rest.is_some() {
+                        after.push(self.element_pattern()?);
+                    } else {
+                        before.push(self.element_pattern()?);
+                    }
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightBracket, "Expect ']' after list pattern.")?;
+                Ok(Pattern::List {
+                    before,
+                    rest,
+                    after,
+                })
+            }
+            TokenType::LeftBrace => {
+                self.advance();
+                let mut entries = Vec::new();
+                while !self.check(&TokenType::RightBrace) {
+                    if self.check(&TokenType::Identifier) && !self.check_next(&TokenType::Colon) {
+                        let name = self.peek().clone();
+                        let key = Expr::Literal {
+                            value: Literal::String(Arc::clone(&name.lexeme)),
+                        };
+                        entries.push((key, self.element_pattern()?));
+                    } else {
+                        let key = if self.check(&TokenType::Identifier) {
+                            Expr::Literal {
+                                value: Literal::String(self.advance().lexeme),
+                            }
+                        } else {
+                            self.literal_pattern_value()?
+                        };
+                        self.consume(TokenType::Colon, "Expect ':' after map pattern key.")?;
+                        entries.push((key, self.element_pattern()?));
+                    }
+                    if !self.match_token(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightBrace, "Expect '}' after map pattern.")?;
+                Ok(Pattern::Map { entries })
+            }
+            _ => {
+                let low = self.literal_pattern_value()?;
+                if self.match_token(&[TokenType::DotDot, TokenType::DotDotEqual]) {
+                    let operator = self.previous().clone();
+                    let high = self.literal_pattern_value()?;
+                    return Ok(Pattern::Range {
+                        low,
+                        operator,
+                        high,
+                    });
+                }
+                Ok(Pattern::Value(low))
+            }
+        }
+    }
+
+    /// Parses a pattern inside a list, map or instance pattern, which may
+    /// be followed by `= default`.
+    fn element_pattern(&mut self) -> Result<Pattern, ParserError> {
+        let pattern = self.nested(Self::pattern)?;
+        if !self.match_token(&[TokenType::Equal]) {
+            return Ok(pattern);
+        }
+        Ok(Pattern::Default {
+            pattern: Box::new(pattern),
+            default: self.conditional()?,
+        })
+    }
+
+    /// Parses a literal in a pattern, which may be a negated number.
+    fn literal_pattern_value(&mut self) -> Result<Expr, ParserError> {
+        match self.peek().token_type {
+            TokenType::Number
+            | TokenType::String
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil => self.primary(),
+            TokenType::Minus if self.check_next(&TokenType::Number) => {
+                let operator = self.advance();
+                let right = self.primary()?;
+                Ok(Expr::Unary {
+                    operator,
+                    right: Box::new(right),
+                })
+            }
+            _ => Err(ParserError::new(
+                format!("Expect pattern, found {:?}", self.peek().token_type),
+                self.peek().line,
+            )),
+        }
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParserError> {
         if self.check(&token_type) {
             Ok(self.advance())
@@ -255,26 +1840,161 @@ impl<'a> Parser<'a> {
             ))
         }
     }
+}
 
-    fn synchronize(&mut self) {
-        self.advance();
+/// Maps a compound assignment or increment token to the binary operator it
+/// applies, keeping the original line for error reporting.
+fn binary_operator_of(token: &Token) -> Token {
+    let (token_type, lexeme) = match token.token_type {
+        TokenType::PlusEqual | TokenType::PlusPlus => (TokenType::Plus, "+"),
+        TokenType::MinusEqual | TokenType::MinusMinus => (TokenType::Minus, "-"),
+        TokenType::StarEqual => (TokenType::Star, "*"),
+        TokenType::SlashEqual => (TokenType::Slash, "/"),
+        TokenType::PercentEqual => (TokenType::Percent, "%"),
+        _ => unreachable!("not a compound operator: {:?}", token.token_type),
+    };
+    Token::new(token_type, Arc::from(lexeme), None, token.line)
+}
 
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
-            match self.peek().token_type {
-                TokenType::Class
-                | TokenType::Fun
-                | TokenType::Var
-                | TokenType::For
-                | TokenType::If
-                | TokenType::While
-                | TokenType::Print
-                | TokenType::Return => return,
-                _ => {}
-            }
-            self.advance();
+/// A block of `statements`, whose scope the resolver sizes later.
+fn block(statements: Vec<Stmt>) -> Stmt {
+    Stmt::Block {
+        statements,
+        names: Names::default(),
+    }
+}
+
+fn lambda_name(token: &Token) -> Token {
+    Token::new(token.token_type, Arc::from("lambda"), None, token.line)
+}
+
+fn increment(target: Expr, operator: Token, postfix: bool) -> Expr {
+    Expr::CompoundAssign {
+        target: Box::new(target),
+        operator,
+        value: Box::new(Expr::Literal {
+            value: Literal::Integer(1),
+        }),
+        postfix,
+    }
+}
+
+/// Integer literals become `Integer` (or `BigInt` when they don't fit in 64
+/// bits), literals with a fraction become floats, and an `r` suffix makes
+/// either form an exact `Rational`.
+fn number_literal(text: &str) -> Option<Literal> {
+    if let Some(digits) = text.strip_suffix('r') {
+        Rational::parse_decimal(digits).map(Literal::Rational)
+    } else if text.contains('.') {
+        text.parse().ok().map(Literal::Number)
+    } else if let Ok(n) = text.parse() {
+        Some(Literal::Integer(n))
+    } else {
+        BigInt::parse(text).map(Literal::BigInt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{check, eval};
+
+    #[test]
+    fn parameters_are_declared_once() {
+        for source in [
+            "fun f(a, a) {}",
+            "fun f(a, ...a) {}",
+            "var f = (a, b = 1, a) => a;",
+            "class C { m(a, a) {} }",
+        ] {
+            assert_eq!(
+                eval(source),
+                Err("Duplicate parameter 'a' in parameter list. at line 1".to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn patterns_bind_each_name_once() {
+        for source in [
+            "var [a, a] = [1, 2];",
+            "var [a, ...a] = [1, 2];",
+            "var {x, y: a, z: a} = {};",
+            "var a; [a, a] = [1, 2];",
+            "match [1, 2] { [a, [a]] => 1, _ => 2 };",
+            "match p { Point(x: a, y: a) => 1 };",
+        ] {
+            assert_eq!(
+                eval(source),
+                Err("Duplicate binding 'a' in pattern. at line 1".to_string()),
+                "{}",
+                source
+            );
         }
+        assert_eq!(
+            eval("for k, k in {} {}"),
+            Err("Duplicate loop variable 'k'. at line 1".to_string())
+        );
+        assert_eq!(
+            eval("match [1, 1] { [a, b] if a == b => \"same\", _ => 0 };"),
+            Ok("same".to_string())
+        );
+    }
+
+    #[test]
+    fn capitalised_names_bind_when_destructuring() {
+        assert_eq!(eval("var [X, y] = [1, 2]; X + y;"), Ok("3".to_string()));
+        assert_eq!(
+            eval("var {Port} = {\"Port\": 5}; Port;"),
+            Ok("5".to_string())
+        );
+        assert_eq!(
+            eval("var X; var y; [X, y] = [3, 4]; X;"),
+            Ok("3".to_string())
+        );
+        assert_eq!(
+            eval("const [C] = [1]; [C] = [2];"),
+            Err("Can't assign to constant 'C' at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn capitalised_names_are_values_in_match_arms() {
+        let source = "var X = 3; var Y = 4; match 4 { X => \"x\", Y => \"y\", _ => \"neither\" };";
+        assert_eq!(eval(source), Ok("y".to_string()));
+        assert_eq!(eval("var Some(v) = Some(7); v;"), Ok("7".to_string()));
+    }
+
+    #[test]
+    fn deeply_nested_code_is_refused() {
+        let too_deep = "Code nested too deeply. at line 1".to_string();
+        let n = 5_000;
+        for source in [
+            format!("{}1;", "- ".repeat(n)),
+            format!("{}1{};", "(".repeat(n), ")".repeat(n)),
+            format!("{}{};", "[".repeat(n), "]".repeat(n)),
+            format!("{}1;", "1 + ".repeat(n)),
+            format!("{}{}", "{".repeat(n), "}".repeat(n)),
+            format!("var x = {{}}; x{};", "?.x".repeat(n)),
+            format!("var {}a{} = 1;", "[".repeat(n), "]".repeat(n)),
+        ] {
+            assert_eq!(eval(&source), Err(too_deep.clone()));
+            assert_eq!(check(&source), Err(too_deep.clone()));
+        }
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_runs() {
+        let n = super::MAX_NESTING - 10;
+        assert_eq!(eval(&format!("{}1;", "- ".repeat(n))), Ok("1".to_string()));
+        assert_eq!(
+            eval(&format!("{}1;", "1 + ".repeat(n))),
+            Ok((n + 1).to_string())
+        );
+        let lists = format!("len({}{});", "[".repeat(n), "]".repeat(n));
+        assert_eq!(eval(&lists), Ok("1".to_string()));
+        let source = format!("{}1{};", "(".repeat(n), ")".repeat(n));
+        assert_eq!(check(&source), Ok(Vec::new()));
     }
 }
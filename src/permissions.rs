@@ -0,0 +1,351 @@
+use crate::error::{ErrorKind, RuntimeError};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// What a script may do outside of itself. Builtins that read or write
+/// files, use the network, read the environment or run other programs each
+/// check for the capability they need first. Everything is allowed unless
+/// denied; `deny_all` followed by `allow_*` grants only what is listed.
+#[derive(Clone, Debug)]
+pub struct Permissions {
+    /// Directories and files that may be read, or `None` for anywhere.
+    read: Option<Vec<PathBuf>>,
+    /// Directories and files that may be written, or `None` for anywhere.
+    write: Option<Vec<PathBuf>>,
+    net: bool,
+    env: bool,
+    exec: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            read: None,
+            write: None,
+            net: true,
+            env: true,
+            exec: true,
+        }
+    }
+}
+
+impl Permissions {
+    /// Permissions that allow nothing.
+    pub fn none() -> Self {
+        let mut permissions = Self::default();
+        permissions.deny_all();
+        permissions
+    }
+
+    pub fn deny_all(&mut self) -> &mut Self {
+        self.read = Some(Vec::new());
+        self.write = Some(Vec::new());
+        self.net = false;
+        self.env = false;
+        self.exec = false;
+        self
+    }
+
+    /// Allows reading `path` and, if it's a directory, anything beneath it.
+    /// An empty path allows nothing.
+    pub fn allow_read(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        if path.as_ref().as_os_str().is_empty() {
+            return self;
+        }
+        if let Some(paths) = &mut self.read {
+            paths.push(resolve(path.as_ref()));
+        }
+        self
+    }
+
+    /// Allows writing `path` and, if it's a directory, anything beneath it.
+    /// An empty path allows nothing.
+    pub fn allow_write(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        if path.as_ref().as_os_str().is_empty() {
+            return self;
+        }
+        if let Some(paths) = &mut self.write {
+            paths.push(resolve(path.as_ref()));
+        }
+        self
+    }
+
+    pub fn allow_net(&mut self) -> &mut Self {
+        self.net = true;
+        self
+    }
+
+    pub fn allow_env(&mut self) -> &mut Self {
+        self.env = true;
+        self
+    }
+
+    pub fn allow_exec(&mut self) -> &mut Self {
+        self.exec = true;
+        self
+    }
+
+    /// Grants what a `--name` or `--name=value` command-line flag names,
+    /// returning whether `name` is one. `--allow-read` and `--allow-write`
+    /// take a comma-separated list of paths, empty entries aside.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<bool, String> {
+        let paths = || -> Result<Vec<&str>, String> {
+            let paths: Vec<&str> = value
+                .unwrap_or("")
+                .split(',')
+                .filter(|path| !path.is_empty())
+                .collect();
+            if paths.is_empty() {
+                return Err(format!("Expected paths for --{}=PATHS", name));
+            }
+            Ok(paths)
+        };
+        match (name, value) {
+            ("allow-read", _) => {
+                for path in paths()? {
+                    self.allow_read(path);
+                }
+            }
+            ("allow-write", _) => {
+                for path in paths()? {
+                    self.allow_write(path);
+                }
+            }
+            ("allow-net", None) => {
+                self.allow_net();
+            }
+            ("allow-env", None) => {
+                self.allow_env();
+            }
+            ("allow-exec", None) => {
+                self.allow_exec();
+            }
+            ("deny-all", None) => {
+                self.deny_all();
+            }
+            ("allow-net" | "allow-env" | "allow-exec" | "deny-all", Some(_)) => {
+                return Err(format!("--{} doesn't take a value", name))
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn allows(&self, capability: &Capability) -> bool {
+        let within = |allowed: &Option<Vec<PathBuf>>, path: &Path| match allowed {
+            None => true,
+            Some(paths) => paths.iter().any(|allowed| path.starts_with(allowed)),
+        };
+        match capability {
+            Capability::Read(path) => within(&self.read, path),
+            Capability::Write(path) => within(&self.write, path),
+            Capability::Net => self.net,
+            Capability::Env => self.env,
+            Capability::Exec => self.exec,
+        }
+    }
+}
+
+/// Something a builtin needs to be allowed to do.
+pub enum Capability {
+    Read(PathBuf),
+    Write(PathBuf),
+    Net,
+    Env,
+    Exec,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Read(path) => write!(f, "'read' capability for '{}'", path.display()),
+            Capability::Write(path) => write!(f, "'write' capability for '{}'", path.display()),
+            Capability::Net => write!(f, "'net' capability"),
+            Capability::Env => write!(f, "'env' capability"),
+            Capability::Exec => write!(f, "'exec' capability"),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Permissions> = RefCell::new(Permissions::default());
+}
+
+/// Runs the current thread, and the threads it goes on to spawn, with
+/// `permissions`.
+pub fn start(permissions: &Permissions) {
+    CURRENT.with(|current| *current.borrow_mut() = permissions.clone());
+}
+
+/// The permissions the current thread runs with.
+pub fn current() -> Permissions {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Fails unless the current thread may use `capability`, which builtin
+/// `function` needs.
+pub fn require(capability: Capability, function: &str, line: usize) -> Result<(), RuntimeError> {
+    if CURRENT.with(|current| current.borrow().allows(&capability)) {
+        return Ok(());
+    }
+    Err(RuntimeError::with_kind(
+        ErrorKind::PermissionDenied,
+        format!("Permission denied: {}() needs the {}", function, capability),
+        line,
+    ))
+}
+
+/// Where `path` really is: absolute, with links followed as far as it
+/// exists, so that neither `..` nor a link can lead out of an allowed
+/// directory. Builtins open the resolved path, the one that was checked.
+pub fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut existing = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir if rest.is_empty() => {
+                existing.pop();
+            }
+            Component::ParentDir => {
+                rest.pop();
+            }
+            Component::CurDir => {}
+            component => rest.push(component.as_os_str().to_owned()),
+        }
+        if let Some(name) = rest.first() {
+            let next = existing.join(name);
+            if rest.len() == 1 && next.exists() {
+                existing = next.canonicalize().unwrap_or(next);
+                rest.clear();
+            }
+        }
+    }
+    rest.iter().fold(existing, |path, name| path.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval_permitted;
+    use std::fs;
+
+    fn read(path: impl AsRef<Path>) -> Capability {
+        Capability::Read(resolve(path.as_ref()))
+    }
+
+    fn write(path: impl AsRef<Path>) -> Capability {
+        Capability::Write(resolve(path.as_ref()))
+    }
+
+    /// A script that reads `path` and gives what it holds.
+    fn reading(path: &Path) -> String {
+        format!(
+            "async fun main() {{ return await read_file({:?}); }} main();",
+            path.display().to_string()
+        )
+    }
+
+    /// A fresh directory for `test` with an `allowed` directory and a
+    /// `secret` file beside it.
+    fn sandbox(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("y-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("allowed")).unwrap();
+        fs::write(root.join("allowed/notes"), "notes").unwrap();
+        fs::write(root.join("secret"), "secret").unwrap();
+        root
+    }
+
+    #[test]
+    fn empty_paths_allow_nothing() {
+        let mut permissions = Permissions::none();
+        assert!(permissions.set("allow-read", Some("")).is_err());
+        assert!(permissions.set("allow-write", Some(",")).is_err());
+        assert!(permissions.set("allow-read", None).is_err());
+        permissions.set("allow-read", Some("./data,")).unwrap();
+        assert!(!permissions.allows(&read("/etc/hostname")));
+        permissions.allow_write("");
+        assert!(!permissions.allows(&write("/etc/hostname")));
+    }
+
+    #[test]
+    fn denied_builtins_fail_and_allowed_ones_run() {
+        let root = sandbox("denied");
+        let notes = root.join("allowed/notes");
+        let source = reading(&notes);
+        let error = eval_permitted(&source, Permissions::none()).unwrap_err();
+        assert!(
+            error.starts_with(&format!(
+                "Permission denied: read_file() needs the 'read' capability for '{}' at line 1",
+                resolve(&notes).display()
+            )),
+            "{}",
+            error
+        );
+        let mut permissions = Permissions::none();
+        permissions.allow_read(root.join("allowed"));
+        assert_eq!(
+            eval_permitted(&source, permissions.clone()),
+            Ok("notes".to_string())
+        );
+        assert_eq!(
+            eval_permitted("env(\"HOME\");", permissions),
+            Err("Permission denied: env() needs the 'env' capability at line 1".to_string())
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parent_directories_dont_lead_out_of_allowed_ones() {
+        let root = sandbox("parent");
+        let mut permissions = Permissions::none();
+        permissions.allow_read(root.join("allowed"));
+        permissions.allow_write(root.join("allowed"));
+        assert!(permissions.allows(&read(root.join("allowed/notes"))));
+        assert!(permissions.allows(&read(root.join("allowed/./new/../notes"))));
+        assert!(permissions.allows(&write(root.join("allowed/new/file"))));
+        assert!(!permissions.allows(&read(root.join("allowed/../secret"))));
+        assert!(!permissions.allows(&write(root.join("allowed/new/../../secret"))));
+        assert!(!permissions.allows(&read(root.join("allowed-too/file"))));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_judged_by_where_they_lead() {
+        use std::os::unix::fs::symlink;
+        let root = sandbox("links");
+        symlink(root.join("secret"), root.join("allowed/to-secret")).unwrap();
+        symlink(&root, root.join("allowed/to-root")).unwrap();
+        symlink(root.join("allowed/notes"), root.join("allowed/to-notes")).unwrap();
+        symlink(root.join("allowed"), root.join("to-allowed")).unwrap();
+        let mut permissions = Permissions::none();
+        permissions.allow_read(root.join("allowed"));
+        assert!(!permissions.allows(&read(root.join("allowed/to-secret"))));
+        assert!(!permissions.allows(&read(root.join("allowed/to-root/secret"))));
+        assert!(permissions.allows(&read(root.join("allowed/to-notes"))));
+        assert!(permissions.allows(&read(root.join("to-allowed/notes"))));
+        let read_file =
+            |path: &str| eval_permitted(&reading(&root.join(path)), permissions.clone());
+        assert!(read_file("allowed/to-secret")
+            .unwrap_err()
+            .starts_with("Permission denied: read_file()"));
+        assert_eq!(read_file("allowed/to-notes"), Ok("notes".to_string()));
+        assert_eq!(read_file("to-allowed/notes"), Ok("notes".to_string()));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn spawned_threads_inherit_permissions() {
+        let source = "join(spawn(fun () { return env(\"HOME\"); }));";
+        assert!(eval_permitted(source, Permissions::default()).is_ok());
+        let error = eval_permitted(source, Permissions::none()).unwrap_err();
+        assert!(
+            error.contains("Permission denied: env() needs the 'env' capability"),
+            "{}",
+            error
+        );
+    }
+}
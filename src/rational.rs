@@ -0,0 +1,252 @@
+use crate::bigint::{unpaced, BigInt, Progress, Stopped};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Exact fraction kept in lowest terms with a positive denominator.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: BigInt,
+    denominator: BigInt,
+}
+
+impl Rational {
+    /// Builds a reduced fraction, or `None` when `denominator` is zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Self> {
+        unpaced(|progress| Self::new_with(numerator, denominator, progress))
+    }
+
+    /// `new`, telling `progress` how reducing the fraction goes.
+    pub fn new_with(
+        numerator: BigInt,
+        denominator: BigInt,
+        progress: &mut Progress,
+    ) -> Result<Option<Self>, Stopped> {
+        if denominator.is_zero() {
+            return Ok(None);
+        }
+        let (numerator, denominator) = if denominator.is_negative() {
+            (-&numerator, -&denominator)
+        } else {
+            (numerator, denominator)
+        };
+        let divisor = numerator.gcd_with(&denominator, progress)?;
+        if divisor.is_zero() || divisor.is_one() {
+            return Ok(Some(Self {
+                numerator,
+                denominator,
+            }));
+        }
+        Ok(Some(Self {
+            numerator: numerator.div_rem_with(&divisor, progress)?.0,
+            denominator: denominator.div_rem_with(&divisor, progress)?.0,
+        }))
+    }
+
+    pub fn from_integer(n: BigInt) -> Self {
+        Self {
+            numerator: n,
+            denominator: BigInt::from_i64(1),
+        }
+    }
+
+    /// Parses decimal notation such as `12`, `0.1` or `-3.25` exactly.
+    pub fn parse_decimal(text: &str) -> Option<Self> {
+        let (whole, fraction) = match text.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (text, ""),
+        };
+        if fraction.starts_with('-') {
+            return None;
+        }
+        let numerator = BigInt::parse(&format!("{}{}", whole, fraction))?;
+        let denominator = BigInt::from_i64(10).pow(fraction.len() as u32);
+        Self::new(numerator, denominator)
+    }
+
+    /// The exact value of a float, which is always a fraction with a power
+    /// of two below, or `None` for infinities and NaN.
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        let bits = x.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = (bits & ((1 << 52) - 1)) as i64;
+        // Subnormals lack the implicit leading bit.
+        let (mantissa, exponent) = match exponent {
+            0 => (fraction, -1074),
+            _ => (fraction | 1 << 52, exponent - 1075),
+        };
+        let mantissa = BigInt::from_i64(if x < 0.0 { -mantissa } else { mantissa });
+        let scale = BigInt::from_i64(2).pow(exponent.unsigned_abs());
+        if exponent >= 0 {
+            Some(Self::from_integer(&mantissa * &scale))
+        } else {
+            Self::new(mantissa, scale)
+        }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numerator.to_f64() / self.denominator.to_f64()
+    }
+
+    /// Divides, returning `None` when `other` is zero.
+    pub fn checked_div(&self, other: &Rational) -> Option<Rational> {
+        unpaced(|progress| self.checked_div_with(other, progress))
+    }
+
+    /// `checked_div`, telling `progress` how it goes.
+    pub fn checked_div_with(
+        &self,
+        other: &Rational,
+        progress: &mut Progress,
+    ) -> Result<Option<Rational>, Stopped> {
+        Rational::new_with(
+            self.numerator.mul_with(&other.denominator, progress)?,
+            self.denominator.mul_with(&other.numerator, progress)?,
+            progress,
+        )
+    }
+
+    /// `self + other`, telling `progress` how it goes.
+    pub fn add_with(&self, other: &Rational, progress: &mut Progress) -> Result<Rational, Stopped> {
+        let numerator = &self.numerator.mul_with(&other.denominator, progress)?
+            + &other.numerator.mul_with(&self.denominator, progress)?;
+        let denominator = self.denominator.mul_with(&other.denominator, progress)?;
+        Ok(Rational::new_with(numerator, denominator, progress)?
+            .expect("product of denominators is non-zero"))
+    }
+
+    /// `self * other`, telling `progress` how it goes.
+    pub fn mul_with(&self, other: &Rational, progress: &mut Progress) -> Result<Rational, Stopped> {
+        let numerator = self.numerator.mul_with(&other.numerator, progress)?;
+        let denominator = self.denominator.mul_with(&other.denominator, progress)?;
+        Ok(Rational::new_with(numerator, denominator, progress)?
+            .expect("product of denominators is non-zero"))
+    }
+
+    /// Largest integer not greater than this value.
+    pub fn floor(&self) -> BigInt {
+        unpaced(|progress| self.floor_with(progress))
+    }
+
+    /// `floor`, telling `progress` how it goes.
+    pub fn floor_with(&self, progress: &mut Progress) -> Result<BigInt, Stopped> {
+        let (quotient, remainder) = self.numerator.div_rem_with(&self.denominator, progress)?;
+        Ok(if remainder.is_negative() {
+            &quotient - &BigInt::from_i64(1)
+        } else {
+            quotient
+        })
+    }
+
+    pub fn pow(&self, exponent: u32) -> Rational {
+        unpaced(|progress| self.pow_with(exponent, progress))
+    }
+
+    /// `pow`, telling `progress` how it goes.
+    pub fn pow_with(&self, exponent: u32, progress: &mut Progress) -> Result<Rational, Stopped> {
+        Ok(Rational {
+            numerator: self.numerator.pow_with(exponent, progress)?,
+            denominator: self.denominator.pow_with(exponent, progress)?,
+        })
+    }
+
+    /// Decimal expansion rounded half away from zero to `places` digits.
+    fn to_decimal(&self, places: usize) -> String {
+        let scale = BigInt::from_i64(10).pow(places as u32);
+        let two = BigInt::from_i64(2);
+        let scaled = &(&(&self.numerator.abs() * &scale) * &two) + &self.denominator;
+        let (rounded, _) = scaled.div_rem(&(&self.denominator * &two));
+
+        let digits = rounded.to_string();
+        let digits = format!("{:0>width$}", digits, width = places + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - places);
+        let sign = if self.numerator.is_negative() && !rounded.is_zero() {
+            "-"
+        } else {
+            ""
+        };
+        if places == 0 {
+            format!("{}{}", sign, whole)
+        } else {
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        unpaced(|progress| self.add_with(other, progress))
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &(-other)
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        unpaced(|progress| self.mul_with(other, progress))
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numerator: -&self.numerator,
+            denominator: self.denominator.clone(),
+        }
+    }
+}
+
+/// Formats as `n/d` (or just `n` for whole values) by default; a precision
+/// such as `{:.2}` renders a rounded decimal expansion instead.
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match f.precision() {
+            Some(places) => self.to_decimal(places),
+            None if self.denominator.is_one() => self.numerator.to_string(),
+            None => format!("{}/{}", self.numerator, self.denominator),
+        };
+        match f.width() {
+            Some(width) => write!(f, "{:>width$}", text, width = width),
+            None => f.write_str(&text),
+        }
+    }
+}
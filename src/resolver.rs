@@ -0,0 +1,487 @@
+use crate::ast::{Binding, Expr, FunctionDecl, Names, Pattern, Slot, Stmt, YieldTarget};
+use crate::environment::global_index;
+use crate::token::Token;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Works out where each variable lives before a program runs, so that
+/// reading one indexes straight into a scope instead of searching the scope
+/// chain by name.
+///
+/// Every scope the resolver tracks is one the interpreter creates at run
+/// time: a block that declares something, a call, a subclass's `super`, a
+/// method's `this`, one iteration of a `for in` loop, a `match` arm and the
+/// scope a pattern's default sees. A scope numbers the names declared directly in it in a
+/// first pass, so a function can refer to one declared after it. Names not
+/// declared in any enclosing scope are globals.
+pub struct Resolver {
+    scopes: Vec<Scope>,
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, usize>,
+    /// The name in each slot.
+    slots: Vec<Arc<str>>,
+}
+
+impl Scope {
+    /// Numbers `name` in this scope, unless it already has a slot.
+    fn declare(&mut self, name: &str) {
+        if !self.names.contains_key(name) {
+            self.push(name);
+        }
+    }
+
+    /// Gives `name` a slot of its own, replacing any earlier one.
+    fn push(&mut self, name: &str) {
+        self.names.insert(name.to_string(), self.slots.len());
+        self.slots.push(name.into());
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self { scopes: Vec::new() }
+    }
+
+    /// Resolves a program, whose top-level declarations are globals.
+    pub fn resolve(&mut self, statements: &mut [Stmt]) {
+        self.statements(statements);
+    }
+
+    fn statements(&mut self, statements: &mut [Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Resolves a block, returning the names in its scope's slots. A block
+    /// that declares nothing runs in the enclosing scope.
+    fn block(&mut self, statements: &mut [Stmt]) -> Names {
+        let mut scope = Scope::default();
+        for name in declared(statements) {
+            scope.declare(&name);
+        }
+        if scope.slots.is_empty() {
+            self.statements(statements);
+            return Names::default();
+        }
+        self.scopes.push(scope);
+        self.statements(statements);
+        self.pop()
+    }
+
+    /// Leaves the innermost scope, returning the names in its slots.
+    fn pop(&mut self) -> Names {
+        self.scopes
+            .pop()
+            .map_or_else(Names::default, |scope| scope.slots.into())
+    }
+
+    fn statement(&mut self, statement: &mut Stmt) {
+        match statement {
+            Stmt::Expression(expr) | Stmt::Print { value: expr, .. } => self.expression(expr),
+            Stmt::Var {
+                name,
+                initializer,
+                slot,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                *slot = self.declaration(name);
+            }
+            Stmt::Const {
+                name,
+                initializer,
+                slot,
+                ..
+            } => {
+                self.expression(initializer);
+                *slot = self.declaration(name);
+            }
+            Stmt::Destructure {
+                pattern,
+                value,
+                binding,
+                ..
+            } => {
+                self.expression(value);
+                let declaring = !matches!(binding, Binding::Assign);
+                self.pattern(pattern, declaring, &mut Vec::new());
+            }
+            Stmt::Block { statements, names } => *names = self.block(statements),
+            Stmt::Function { declaration, slot } => {
+                *slot = self.declaration(&declaration.name);
+                self.function(declaration);
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Class {
+                name,
+                slot,
+                superclass,
+                traits,
+                methods,
+                getters,
+                setters,
+                static_methods,
+                static_fields,
+            } => {
+                *slot = self.declaration(name);
+                for (name, slot) in superclass.iter_mut().chain(traits) {
+                    *slot = self.usage(name);
+                }
+                if superclass.is_some() {
+                    let mut parent = Scope::default();
+                    parent.push("super");
+                    self.scopes.push(parent);
+                }
+                for method in methods
+                    .iter_mut()
+                    .chain(getters)
+                    .chain(setters)
+                    .chain(static_methods)
+                {
+                    self.function(method);
+                }
+                for (_, value) in static_fields {
+                    self.expression(value);
+                }
+                if superclass.is_some() {
+                    self.pop();
+                }
+            }
+            Stmt::Trait {
+                name,
+                slot,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                *slot = self.declaration(name);
+                for method in methods.iter_mut().chain(getters).chain(setters) {
+                    self.function(method);
+                }
+            }
+            Stmt::Enum { name, slot, .. } => *slot = self.declaration(name),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+            }
+            Stmt::ForIn {
+                variables,
+                iterable,
+                body,
+                names,
+                ..
+            } => {
+                self.expression(iterable);
+                let mut scope = Scope::default();
+                for variable in variables.iter() {
+                    scope.push(&variable.lexeme);
+                }
+                self.scopes.push(scope);
+                self.statement(body);
+                *names = self.pop();
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Yield { value, target, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                match target {
+                    YieldTarget::Declare(name, slot) => *slot = self.declaration(name),
+                    YieldTarget::Assign(name, slot) => *slot = self.usage(name),
+                    YieldTarget::Discard | YieldTarget::Return | YieldTarget::Print => {}
+                }
+            }
+        }
+    }
+
+    /// Resolves a function's defaults and body in the scope of a call:
+    /// its parameters in order, then the rest parameter, then what the body
+    /// declares. Methods see `this` in a scope around that.
+    fn function(&mut self, declaration: &mut Arc<FunctionDecl>) {
+        // Declarations are only shared once the program runs.
+        let Some(declaration) = Arc::get_mut(declaration) else {
+            return;
+        };
+        if declaration.is_method {
+            let mut this = Scope::default();
+            this.push("this");
+            self.scopes.push(this);
+        }
+        let mut scope = Scope::default();
+        for param in &declaration.params {
+            scope.push(&param.name.lexeme);
+        }
+        if let Some(rest) = &declaration.rest {
+            scope.push(&rest.lexeme);
+        }
+        for name in declared(&declaration.body) {
+            scope.declare(&name);
+        }
+        self.scopes.push(scope);
+        for param in &mut declaration.params {
+            if let Some(default) = &mut param.default {
+                self.expression(default);
+            }
+        }
+        self.statements(&mut declaration.body);
+        declaration.names = self.pop();
+        if declaration.is_method {
+            self.scopes.pop();
+        }
+    }
+
+    /// Resolves a pattern, whose names are declared in the innermost scope
+    /// when `declaring`, and otherwise refer to existing variables. `bound`
+    /// lists the names bound so far in the order they're bound, which is
+    /// how a default's scope numbers them.
+    fn pattern(&mut self, pattern: &mut Pattern, declaring: bool, bound: &mut Vec<String>) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Bind(name, slot) => {
+                *slot = if declaring {
+                    self.declaration(name)
+                } else {
+                    self.usage(name)
+                };
+                bound.push(name.lexeme.to_string());
+            }
+            Pattern::Value(value) => self.expression(value),
+            Pattern::Range { low, high, .. } => {
+                self.expression(low);
+                self.expression(high);
+            }
+            Pattern::Default { pattern, default } => {
+                let mut scope = Scope::default();
+                for name in bound.iter() {
+                    scope.push(name);
+                }
+                self.scopes.push(scope);
+                self.expression(default);
+                self.scopes.pop();
+                self.pattern(pattern, declaring, bound);
+            }
+            Pattern::List {
+                before,
+                rest,
+                after,
+            } => {
+                for pattern in before
+                    .iter_mut()
+                    .chain(after.iter_mut())
+                    .chain(rest.as_deref_mut())
+                {
+                    self.pattern(pattern, declaring, bound);
+                }
+            }
+            Pattern::Map { entries } => {
+                for (key, pattern) in entries {
+                    self.expression(key);
+                    self.pattern(pattern, declaring, bound);
+                }
+            }
+            Pattern::Instance { class, fields } => {
+                self.expression(class);
+                for (_, pattern) in fields {
+                    self.pattern(pattern, declaring, bound);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Literal { .. } => {}
+            Expr::Variable { name, slot } => *slot = self.usage(name),
+            Expr::Assign { name, value, slot } => {
+                *slot = self.usage(name);
+                self.expression(value);
+            }
+            Expr::This { keyword, slot } => *slot = self.usage(keyword),
+            Expr::Super { slot, this, .. } => {
+                *slot = self.usage_of("super");
+                self.expression(this);
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expr::Unary { right, .. } => self.expression(right),
+            Expr::Grouping(inner) => self.expression(inner),
+            Expr::CompoundAssign { target, value, .. } => {
+                self.expression(target);
+                self.expression(value);
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.expression(then_branch);
+                self.expression(else_branch);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(&mut argument.value);
+                }
+            }
+            Expr::Get { object, .. } => self.expression(object),
+            Expr::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            Expr::Lambda(declaration) => self.function(declaration),
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expression(subject);
+                for arm in arms {
+                    // The pattern is tested in the arm's scope, before any of
+                    // its names are bound there.
+                    let mut scope = Scope::default();
+                    let mut names = Vec::new();
+                    arm.pattern.bound_names(&mut names);
+                    for name in &names {
+                        scope.declare(name);
+                    }
+                    self.scopes.push(scope);
+                    self.pattern(&mut arm.pattern, true, &mut Vec::new());
+                    if let Some(guard) = &mut arm.guard {
+                        self.expression(guard);
+                    }
+                    self.expression(&mut arm.body);
+                    arm.names = self.pop();
+                }
+            }
+        }
+    }
+
+    /// The slot a declaration of `name` binds: in the innermost scope, or
+    /// a global at the top level.
+    fn declaration(&self, name: &Token) -> Slot {
+        let global = global_index(&name.lexeme);
+        match self.scopes.last() {
+            Some(scope) => Slot {
+                locals: vec![(0, scope.names[name.lexeme()])],
+                global,
+            },
+            None => Slot {
+                locals: Vec::new(),
+                global,
+            },
+        }
+    }
+
+    /// Every slot `name` might be read from where it's used: each enclosing
+    /// scope that declares it, innermost first, then the global.
+    fn usage(&self, name: &Token) -> Slot {
+        self.usage_of(name.lexeme())
+    }
+
+    fn usage_of(&self, name: &str) -> Slot {
+        let locals = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .filter_map(|(depth, scope)| {
+                let index = scope.names.get(name)?;
+                Some((depth, *index))
+            })
+            .collect();
+        Slot {
+            locals,
+            global: global_index(name),
+        }
+    }
+}
+
+/// The names `statements` declare directly, in order.
+fn declared(statements: &[Stmt]) -> Vec<String> {
+    let mut names = Vec::new();
+    for statement in statements {
+        match statement {
+            Stmt::Var { name, .. }
+            | Stmt::Const { name, .. }
+            | Stmt::Class { name, .. }
+            | Stmt::Trait { name, .. }
+            | Stmt::Enum { name, .. }
+            | Stmt::Yield {
+                target: YieldTarget::Declare(name, _),
+                ..
+            } => names.push(name.lexeme.to_string()),
+            Stmt::Function { declaration, .. } => names.push(declaration.name.lexeme.to_string()),
+            Stmt::Destructure {
+                pattern, binding, ..
+            } if !matches!(binding, Binding::Assign) => pattern.bound_names(&mut names),
+            _ => {}
+        }
+    }
+    names
+}
@@ -0,0 +1,421 @@
+use crate::error::RuntimeError;
+use crate::event_loop;
+use crate::interpreter::call;
+use crate::interrupt;
+use crate::limits::{self, STACK_SIZE};
+use crate::permissions;
+use crate::transfer::{Transfer, Unsendable};
+use crate::value::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle};
+
+/// Every blocking receive waits on this one condition variable, which is
+/// notified whenever any channel gets a value or is closed. A single shared
+/// wake-up is what lets `select` wait on several channels at once.
+static ACTIVITY_LOCK: Mutex<()> = Mutex::new(());
+static ACTIVITY: Condvar = Condvar::new();
+
+/// Threads run interpreter code only through `Transfer`s and never panic
+/// while holding a lock, so poisoning can safely be ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn notify_activity() {
+    let _guard = lock(&ACTIVITY_LOCK);
+    ACTIVITY.notify_all();
+}
+
+/// An unbounded multi-producer, multi-consumer queue of values. Each value
+/// is copied on `send`, so the receiver owns what it gets.
+#[derive(Default)]
+pub struct Channel {
+    state: Mutex<ChannelState>,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    queue: VecDeque<Transfer>,
+    closed: bool,
+}
+
+/// Result of checking a channel without blocking.
+pub enum Polled {
+    Value(Value),
+    Empty,
+    Closed,
+}
+
+impl Channel {
+    pub fn send(&self, value: &Value, line: usize) -> Result<(), RuntimeError> {
+        let transfer = Transfer::new(value).map_err(|error| unsendable(error, line))?;
+        {
+            let mut state = lock(&self.state);
+            if state.closed {
+                return Err(RuntimeError::new(
+                    "Can't send on a closed channel".to_string(),
+                    line,
+                ));
+            }
+            state.queue.push_back(transfer);
+        }
+        notify_activity();
+        Ok(())
+    }
+
+    /// Closes the channel. Receivers still get the values already queued,
+    /// then `nil`.
+    pub fn close(&self) {
+        lock(&self.state).closed = true;
+        notify_activity();
+    }
+
+    pub fn poll(&self) -> Polled {
+        match self.take() {
+            Ok(transfer) => Polled::Value(transfer.into_value()),
+            Err(true) => Polled::Closed,
+            Err(false) => Polled::Empty,
+        }
+    }
+
+    /// Pops the oldest value, or reports whether the channel is closed.
+    fn take(&self) -> Result<Transfer, bool> {
+        let mut state = lock(&self.state);
+        state.queue.pop_front().ok_or(state.closed)
+    }
+}
+
+/// Waits for activity, or for a little while, whichever comes first, so
+/// that a blocked thread still notices being interrupted or timing out.
+fn wait<'a>(guard: MutexGuard<'a, ()>, line: usize) -> Result<MutexGuard<'a, ()>, RuntimeError> {
+    let (guard, _) = ACTIVITY
+        .wait_timeout(guard, interrupt::POLL)
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    limits::check_blocked(line)?;
+    Ok(guard)
+}
+
+/// Blocks until one of `channels` has a value, returning its position and
+/// the value, or `None` once all of them are closed and drained. Channels
+/// earlier in the slice win when several are ready.
+pub fn receive(
+    channels: &[Arc<Channel>],
+    line: usize,
+) -> Result<Option<(usize, Value)>, RuntimeError> {
+    let mut guard = lock(&ACTIVITY_LOCK);
+    loop {
+        let mut all_closed = true;
+        for (position, channel) in channels.iter().enumerate() {
+            match channel.take() {
+                Ok(transfer) => {
+                    drop(guard);
+                    return Ok(Some((position, transfer.into_value())));
+                }
+                Err(closed) => all_closed &= closed,
+            }
+        }
+        if all_closed {
+            return Ok(None);
+        }
+        guard = wait(guard, line)?;
+    }
+}
+
+type Outcome = Result<Transfer, RuntimeError>;
+
+/// A spawned thread, joinable any number of times from any thread.
+pub struct ThreadHandle {
+    handle: Mutex<Option<JoinHandle<Outcome>>>,
+    outcome: OnceLock<Outcome>,
+    /// Set as the thread's function finishes, or panics.
+    finished: Arc<AtomicBool>,
+}
+
+/// Marks a thread finished when dropped, however its function ends.
+struct Finishing(Arc<AtomicBool>);
+
+impl Drop for Finishing {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+        notify_activity();
+    }
+}
+
+impl ThreadHandle {
+    /// Runs a copy of `function` on a new thread with its own interpreter
+    /// state; only values sent through channels or returned are seen again.
+    pub fn spawn(function: &Value, line: usize) -> Result<ThreadHandle, RuntimeError> {
+        let transfer = Transfer::new(function).map_err(|error| unsendable(error, line))?;
+        let limits = limits::inherit();
+        let permissions = permissions::current();
+        let interrupt = interrupt::current();
+        let finished = Arc::new(AtomicBool::new(false));
+        let finishing = Finishing(Arc::clone(&finished));
+        let handle = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let _finishing = finishing;
+                limits::resume(limits);
+                permissions::start(&permissions);
+                if let Some(interrupt) = &interrupt {
+                    interrupt::start(interrupt);
+                }
+                let mut result = call(&transfer.into_value(), Vec::new(), line)?;
+                // The thread has its own event loop, which runs to completion
+                // before the thread ends. An async function's result is that
+                // of its task.
+                if let Some(error) = event_loop::run().into_iter().next() {
+                    return Err(error);
+                }
+                if let Value::Future(future) = &result {
+                    result = future.outcome().unwrap_or(Ok(Value::Nil))?;
+                }
+                limits::settle(line)?;
+                Transfer::new(&result).map_err(|error| {
+                    let message = match error {
+                        Unsendable::Value(value) => {
+                            format!("Can't return '{}' from a spawned thread", value)
+                        }
+                        Unsendable::TooDeep => {
+                            "Value nested too deeply to return from a spawned thread".to_string()
+                        }
+                    };
+                    RuntimeError::new(message, line)
+                })
+            })
+            .map_err(|error| {
+                RuntimeError::new(format!("Couldn't spawn thread: {}", error), line)
+            })?;
+        Ok(ThreadHandle {
+            handle: Mutex::new(Some(handle)),
+            outcome: OnceLock::new(),
+            finished,
+        })
+    }
+
+    /// Waits for the thread and returns what its function returned, or
+    /// raises the error it failed with.
+    pub fn join(&self, line: usize) -> Result<Value, RuntimeError> {
+        let mut guard = lock(&ACTIVITY_LOCK);
+        while !self.finished.load(Ordering::Acquire) {
+            guard = wait(guard, line)?;
+        }
+        drop(guard);
+        {
+            let mut handle = lock(&self.handle);
+            if let Some(handle) = handle.take() {
+                let outcome = handle.join().unwrap_or_else(|_| {
+                    Err(RuntimeError::new(
+                        "Spawned thread panicked".to_string(),
+                        line,
+                    ))
+                });
+                let _ = self.outcome.set(outcome);
+            }
+        }
+        match self.outcome.get() {
+            Some(Ok(transfer)) => Ok(transfer.clone().into_value()),
+            Some(Err(error)) => Err(error.clone()),
+            None => unreachable!("the outcome is set before the handle is released"),
+        }
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<channel>")
+    }
+}
+
+impl fmt::Debug for ThreadHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<thread>")
+    }
+}
+
+fn unsendable(error: Unsendable, line: usize) -> RuntimeError {
+    let message = match error {
+        Unsendable::Value(value) => format!("Can't send '{}' to another thread", value),
+        Unsendable::TooDeep => "Value nested too deeply to send to another thread".to_string(),
+    };
+    RuntimeError::new(message, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupt::InterruptHandle;
+    use crate::limits::Limits;
+    use std::time::Duration;
+
+    /// Runs `source`, interrupting it after a moment.
+    fn interrupted(source: &str) -> Result<String, String> {
+        let handle = InterruptHandle::new();
+        let interrupter = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            interrupter.interrupt();
+        });
+        crate::eval_with(source, handle)
+    }
+
+    #[test]
+    fn blocked_receives_stop_when_interrupted() {
+        for source in [
+            "recv(channel());",
+            "select([channel(), channel()]);",
+            "for x in channel() {}",
+            "var c = channel(); join(spawn(fun () { return recv(c); }));",
+        ] {
+            let error = interrupted(source).unwrap_err();
+            assert!(
+                error.starts_with("Interrupted at line 1"),
+                "{}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn blocked_waits_time_out() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        for source in [
+            "recv(channel());",
+            "for x in channel() {}",
+            "var c = channel(); join(spawn(fun () { return recv(c); }));",
+            "async fun f() { await sleep(30000); } f();",
+        ] {
+            let error = crate::eval_limited(source, limits.clone()).unwrap_err();
+            assert!(
+                error.starts_with("Timed out after 100ms at line 1"),
+                "{}: {}",
+                source,
+                error
+            );
+        }
+    }
+
+    /// Runs `source` several times, expecting the same result every time.
+    fn deterministic(source: &str) -> String {
+        let first = crate::eval(source).unwrap();
+        for _ in 0..10 {
+            assert_eq!(crate::eval(source), Ok(first.clone()), "{}", source);
+        }
+        first
+    }
+
+    #[test]
+    fn pipelines_deliver_every_value_in_order() {
+        let source = "
+            fun stage(input, output, f) {
+                for x in input { send(output, f(x)); }
+                close(output);
+            }
+            var numbers = channel();
+            var squares = channel();
+            var results = channel();
+            spawn(() => stage(numbers, squares, x => x * x));
+            spawn(() => stage(squares, results, x => x + 1));
+            var producer = spawn(fun () {
+                for i in 0..100 { send(numbers, i); }
+                close(numbers);
+                return \"done\";
+            });
+            var seen = {};
+            for x in results { seen[len(seen)] = x; }
+            [len(seen), seen[0], seen[1], seen[99], join(producer)];";
+        assert_eq!(deterministic(source), "[100, 1, 2, 9802, \"done\"]");
+    }
+
+    #[test]
+    fn select_keeps_each_producers_order() {
+        let source = "
+            var channels = [channel(), channel(), channel()];
+            for c in channels {
+                spawn(fun () {
+                    for i in 0..50 { send(c, i); }
+                    close(c);
+                });
+            }
+            var last = {0: -1, 1: -1, 2: -1};
+            var ordered = true;
+            var total = 0;
+            while (true) {
+                var next = select(channels);
+                if (next == nil) break;
+                var from = next[0];
+                var value = next[1];
+                ordered = ordered and value == last[from] + 1;
+                last[from] = value;
+                total += value;
+            }
+            [ordered, total, last];";
+        assert_eq!(deterministic(source), "[true, 3675, {0: 49, 1: 49, 2: 49}]");
+    }
+
+    #[test]
+    fn request_and_reply_take_turns() {
+        let source = "
+            var requests = channel();
+            var replies = channel();
+            var server = spawn(fun () {
+                var served = 0;
+                for n in requests {
+                    send(replies, n * 2);
+                    served += 1;
+                }
+                return served;
+            });
+            var sum = 0;
+            for i in 1..=10 {
+                send(requests, i);
+                sum += recv(replies);
+            }
+            close(requests);
+            [sum, join(server), try_recv(replies)];";
+        assert_eq!(deterministic(source), "[110, 10, nil]");
+    }
+
+    #[test]
+    fn sent_values_are_copies() {
+        let source = "
+            var c = channel();
+            var items = [1, {\"a\": [2]}];
+            send(c, items);
+            items[0] = 9;
+            items[1][\"a\"][0] = 9;
+            var received = recv(c);
+            received[0] = 7;
+            [received, items];";
+        assert_eq!(
+            deterministic(source),
+            "[[7, {\"a\": [2]}], [9, {\"a\": [9]}]]"
+        );
+    }
+
+    #[test]
+    fn values_nested_too_deeply_are_refused() {
+        let deep = "var cur = []; for i in 0..200000 { cur = [cur]; }";
+        for send in ["send(channel(), cur);", "spawn(() => len(cur));"] {
+            assert_eq!(
+                crate::eval(&format!("{} {}", deep, send)),
+                Err("Value nested too deeply to send to another thread at line 1".to_string())
+            );
+        }
+        assert_eq!(
+            crate::eval(&format!("join(spawn(fun () {{ {} return cur; }}));", deep)),
+            Err("Value nested too deeply to return from a spawned thread at line 1".to_string())
+        );
+        let source = "var cur = []; for i in 0..900 { cur = [cur]; }
+            var c = channel(); send(c, cur); recv(c) == cur;";
+        assert_eq!(crate::eval(source), Ok("true".to_string()));
+    }
+}
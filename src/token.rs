@@ -1,4 +1,5 @@
 use std::fmt;
+use std::sync::Arc;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TokenType {
@@ -7,6 +8,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -14,6 +17,12 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Colon,
 
     // One or two character tokens
     Bang,
@@ -22,8 +31,26 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    StarStar,
+    PlusPlus,
+    MinusMinus,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    Question,
+    QuestionQuestion,
+    QuestionDot,
+    Arrow,
+    MinusGreater,
+    Ellipsis,
+    DotDot,
+    DotDotEqual,
 
     // Literals
     Identifier,
@@ -32,39 +59,51 @@ pub enum TokenType {
 
     // Keywords
     And,
+    Async,
+    Await,
+    Break,
     Class,
+    Const,
+    Continue,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    Impl,
+    In,
+    Is,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    Yield,
 
     // Misc
-    EOF,
+    Eof,
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
-    pub literal: Option<String>,
+    pub lexeme: Arc<str>,
+    pub literal: Option<Arc<str>>,
     pub line: usize,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
-        lexeme: String,
-        literal: Option<String>,
+        lexeme: Arc<str>,
+        literal: Option<Arc<str>>,
         line: usize,
     ) -> Self {
         Self {
@@ -75,22 +114,19 @@ impl Token {
         }
     }
 
-    pub fn lexeme(&self) -> &String {
+    pub fn lexeme(&self) -> &str {
         &self.lexeme
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!(
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "{:?} {} {}",
             self.token_type,
             self.lexeme,
-            self.literal.as_ref().map(|s| s.as_str()).unwrap_or("")
+            self.literal.as_deref().unwrap_or("")
         )
     }
 }
-
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
-    }
-}
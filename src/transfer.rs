@@ -0,0 +1,693 @@
+use crate::ast::{FunctionDecl, Names};
+use crate::asyncio::Socket;
+use crate::bigint::BigInt;
+use crate::class::{Accessors, Class, Instance, Trait};
+use crate::enums::{Enum, Variant};
+use crate::environment::{global_index, Environment, Globals};
+use crate::function::{Function, NativeFunction};
+use crate::iterator::Range;
+use crate::map::Map;
+use crate::rational::Rational;
+use crate::thread::{Channel, ThreadHandle};
+use crate::value::{List, Value};
+use core::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A deep copy of a value in a form that can move to another thread.
+///
+/// This is the one rule for sharing between threads: everything that
+/// crosses is copied, except channels, thread handles and sockets, which are
+/// shared, and enums and strings, which are immutable.
+/// Lists, maps and instances arrive as independent copies, with aliasing
+/// and cycles inside a single transfer preserved. Functions take along
+/// copies of the scopes they close over and of the globals they mention
+/// (their code is immutable and shared), so a spawned function sees a
+/// snapshot of its closure as of the moment it was sent. Captured globals
+/// become globals of the receiving thread, unless it already has its own of
+/// that name. Iterators, generators and futures can't be copied and are
+/// refused, including when a function sent uses a variable holding one,
+/// and so are values nested more than `MAX_SENT_DEPTH` levels deep.
+#[derive(Clone)]
+pub struct Transfer {
+    nodes: Vec<Node>,
+    root: Shared,
+}
+
+/// Why a value can't be sent to another thread.
+pub enum Unsendable {
+    /// The part of it that can't be copied, like an iterator.
+    Value(Value),
+    /// It is nested more than `MAX_SENT_DEPTH` levels deep.
+    TooDeep,
+}
+
+/// How deeply lists, maps, instances and variants may nest inside a value
+/// sent to another thread.
+const MAX_SENT_DEPTH: usize = 1000;
+
+#[derive(Clone)]
+enum Shared {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(Rational),
+    String(Arc<str>),
+    Range(Range),
+    Native(NativeFunction),
+    Channel(Arc<Channel>),
+    Thread(Arc<ThreadHandle>),
+    Socket(Arc<Socket>),
+    Enum(Arc<Enum>),
+    Constructor(Arc<Enum>, usize),
+    /// Variants are immutable and compare by structure, so they are copied
+    /// in place rather than kept as nodes.
+    Variant(Arc<Enum>, usize, Vec<Shared>),
+    /// A reference-typed value, stored once in `Transfer::nodes`.
+    Node(usize),
+}
+
+#[derive(Clone)]
+enum Node {
+    List {
+        elements: Vec<Shared>,
+        frozen: bool,
+    },
+    Map {
+        entries: Vec<(Shared, Shared)>,
+        frozen: bool,
+    },
+    /// `closure` refers to a `Node::Scope`.
+    Function {
+        declaration: Arc<FunctionDecl>,
+        closure: usize,
+        /// Each global the function mentions, with whether it's a `const`.
+        globals: Vec<(String, Shared, bool)>,
+        is_initializer: bool,
+    },
+    /// A scope a function closes over, with whether each slot is a `const`.
+    /// Empty slots are unbound, or held something that can't be sent and
+    /// that no function sent uses. `enclosing` refers to another
+    /// `Node::Scope`.
+    Scope {
+        slots: Vec<Option<(Shared, bool)>>,
+        names: Option<Names>,
+        enclosing: Option<usize>,
+    },
+    /// Methods and accessors refer to `Node::Function`s, the superclass to
+    /// a `Node::Class` and traits to `Node::Trait`s.
+    Class {
+        name: String,
+        superclass: Option<usize>,
+        methods: Vec<(String, usize)>,
+        getters: Vec<(String, usize)>,
+        setters: Vec<(String, usize)>,
+        statics: Vec<(String, Shared)>,
+        traits: Vec<usize>,
+        frozen: bool,
+    },
+    /// Methods and accessors refer to `Node::Function`s.
+    Trait {
+        name: String,
+        required: Vec<String>,
+        methods: Vec<(String, usize)>,
+        getters: Vec<(String, usize)>,
+        setters: Vec<(String, usize)>,
+    },
+    /// `class` refers to a `Node::Class`.
+    Instance {
+        class: usize,
+        fields: Vec<(Arc<str>, Shared)>,
+        frozen: bool,
+    },
+}
+
+impl Transfer {
+    /// Copies `value`, or says why it can't be sent.
+    pub fn new(value: &Value) -> Result<Transfer, Unsendable> {
+        let mut packer = Packer {
+            nodes: Vec::new(),
+            seen: HashMap::new(),
+            depth: 0,
+        };
+        let root = packer.pack(value)?;
+        Ok(Transfer {
+            nodes: packer.nodes,
+            root,
+        })
+    }
+
+    /// Rebuilds the value on the receiving thread.
+    pub fn into_value(self) -> Value {
+        let mut unpacker = Unpacker {
+            nodes: &self.nodes,
+            values: vec![None; self.nodes.len()],
+            scopes: vec![None; self.nodes.len()],
+            pending: Vec::new(),
+        };
+        let value = unpacker.unpack(&self.root);
+        // Scopes and globals are filled in last, once every value they
+        // might refer back to exists.
+        while let Some(index) = unpacker.pending.pop() {
+            match &self.nodes[index] {
+                Node::Scope { slots, .. } => {
+                    let env = unpacker.scope(index);
+                    for (slot, captured) in slots.iter().enumerate() {
+                        if let Some((shared, constant)) = captured {
+                            let value = unpacker.unpack(shared);
+                            env.borrow_mut().declare(slot, value, *constant);
+                        }
+                    }
+                }
+                Node::Function { globals, .. } => {
+                    for (name, shared, constant) in globals {
+                        let global = global_index(name);
+                        if Globals::get(global).is_none() {
+                            let value = unpacker.unpack(shared);
+                            Globals::declare(global, value, *constant);
+                        }
+                    }
+                }
+                _ => unreachable!("pending fill for a node without one"),
+            }
+        }
+        value
+    }
+}
+
+struct Packer {
+    nodes: Vec<Node>,
+    /// Node index of each reference-typed value already packed, keyed by
+    /// its address.
+    seen: HashMap<usize, usize>,
+    /// How many values enclose the one being packed.
+    depth: usize,
+}
+
+impl Packer {
+    /// Packs `value`, refusing one nested more than `MAX_SENT_DEPTH` levels
+    /// deep, since packing and unpacking recurse once per level.
+    fn pack(&mut self, value: &Value) -> Result<Shared, Unsendable> {
+        if self.depth == MAX_SENT_DEPTH {
+            return Err(Unsendable::TooDeep);
+        }
+        self.depth += 1;
+        let packed = self.pack_value(value);
+        self.depth -= 1;
+        packed
+    }
+
+    fn pack_value(&mut self, value: &Value) -> Result<Shared, Unsendable> {
+        Ok(match value {
+            Value::Nil => Shared::Nil,
+            Value::Boolean(b) => Shared::Boolean(*b),
+            Value::Number(n) => Shared::Number(*n),
+            Value::Integer(n) => Shared::Integer(*n),
+            Value::BigInt(n) => Shared::BigInt((**n).clone()),
+            Value::Rational(r) => Shared::Rational((**r).clone()),
+            Value::String(s) => Shared::String(s.clone()),
+            Value::Range(range) => Shared::Range((**range).clone()),
+            Value::NativeFunction(native) => Shared::Native(NativeFunction::clone(native)),
+            Value::Channel(channel) => Shared::Channel(Arc::clone(channel)),
+            Value::Thread(thread) => Shared::Thread(Arc::clone(thread)),
+            Value::Socket(socket) => Shared::Socket(Arc::clone(socket)),
+            Value::Enum(enumeration) => Shared::Enum(Arc::clone(enumeration)),
+            Value::Constructor(enumeration, index) => {
+                Shared::Constructor(Arc::clone(enumeration), *index)
+            }
+            Value::Variant(variant) => Shared::Variant(
+                Arc::clone(&variant.enumeration),
+                variant.index,
+                variant
+                    .values
+                    .iter()
+                    .map(|value| self.pack(value))
+                    .collect::<Result<_, Unsendable>>()?,
+            ),
+            Value::List(elements) => self.node(Rc::as_ptr(elements) as usize, |packer| {
+                let list = elements.borrow();
+                let elements = list
+                    .iter()
+                    .map(|element| packer.pack(element))
+                    .collect::<Result<_, Unsendable>>()?;
+                Ok(Node::List {
+                    elements,
+                    frozen: list.frozen,
+                })
+            })?,
+            Value::Map(map) => self.node(Rc::as_ptr(map) as usize, |packer| {
+                let map = map.borrow();
+                let entries = map
+                    .entries()
+                    .iter()
+                    .map(|(key, value)| Ok((packer.pack(key)?, packer.pack(value)?)))
+                    .collect::<Result<_, Unsendable>>()?;
+                Ok(Node::Map {
+                    entries,
+                    frozen: map.frozen,
+                })
+            })?,
+            Value::Function(function) => Shared::Node(self.function(function)?),
+            Value::Class(class) => Shared::Node(self.class(class)?),
+            Value::Trait(implemented) => Shared::Node(self.trait_node(implemented)?),
+            Value::Instance(instance) => self.node(Rc::as_ptr(instance) as usize, |packer| {
+                let instance = instance.borrow();
+                let class = packer.class(&instance.class)?;
+                let fields = instance
+                    .fields
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), packer.pack(value)?)))
+                    .collect::<Result<_, Unsendable>>()?;
+                Ok(Node::Instance {
+                    class,
+                    fields,
+                    frozen: instance.frozen,
+                })
+            })?,
+            Value::Iterator(_) | Value::Future(_) => return Err(Unsendable::Value(value.clone())),
+        })
+    }
+
+    /// Packs a reference-typed value at `address` once, reserving its node
+    /// before `build` runs so that cycles back to it resolve.
+    fn node(
+        &mut self,
+        address: usize,
+        build: impl FnOnce(&mut Self) -> Result<Node, Unsendable>,
+    ) -> Result<Shared, Unsendable> {
+        if let Some(&index) = self.seen.get(&address) {
+            return Ok(Shared::Node(index));
+        }
+        let index = self.nodes.len();
+        self.nodes.push(Node::List {
+            elements: Vec::new(),
+            frozen: false,
+        });
+        self.seen.insert(address, index);
+        self.nodes[index] = build(self)?;
+        Ok(Shared::Node(index))
+    }
+
+    fn function(&mut self, function: &Rc<Function>) -> Result<usize, Unsendable> {
+        let shared = self.node(Rc::as_ptr(function) as usize, |packer| {
+            let referenced = function.declaration.referenced_names();
+            captured(&function.closure, &referenced, |value| {
+                packer.pack(value).map(drop)
+            })?;
+            let closure = packer.scope(&function.closure)?;
+            let mut globals = Vec::new();
+            for name in referenced {
+                let global = global_index(&name);
+                let Some(value) = Globals::get(global) else {
+                    continue;
+                };
+                // The name may only be a local of the function that happens
+                // to match something unsendable at the top level.
+                if let Some(shared) = packer.pack_leniently(&value)? {
+                    globals.push((name, shared, Globals::is_constant(global)));
+                }
+            }
+            Ok(Node::Function {
+                declaration: Arc::clone(&function.declaration),
+                closure,
+                globals,
+                is_initializer: function.is_initializer,
+            })
+        })?;
+        Ok(node_index(shared))
+    }
+
+    /// Packs a scope and those enclosing it. A slot holding something
+    /// unsendable, or nested too deeply, is left empty; `function` has
+    /// already checked that no function uses it.
+    fn scope(&mut self, env: &Rc<RefCell<Environment>>) -> Result<usize, Unsendable> {
+        let shared = self.node(Rc::as_ptr(env) as usize, |packer| {
+            let slots = (0..env.borrow().slots())
+                .map(|index| {
+                    let (value, constant) = env.borrow().slot(index)?;
+                    Some((packer.pack_leniently(&value).ok().flatten()?, constant))
+                })
+                .collect();
+            let names = env.borrow().names().cloned();
+            let enclosing = env.borrow().enclosing().map(Rc::clone);
+            let enclosing = match enclosing {
+                Some(enclosing) => Some(packer.scope(&enclosing)?),
+                None => None,
+            };
+            Ok(Node::Scope {
+                slots,
+                names,
+                enclosing,
+            })
+        })?;
+        Ok(node_index(shared))
+    }
+
+    /// Packs `value`, or drops any nodes packed on the way and returns
+    /// `None` if part of it can't be sent. Being nested too deeply is still
+    /// an error.
+    fn pack_leniently(&mut self, value: &Value) -> Result<Option<Shared>, Unsendable> {
+        let checkpoint = self.nodes.len();
+        match self.pack(value) {
+            Ok(shared) => Ok(Some(shared)),
+            Err(error) => {
+                self.nodes.truncate(checkpoint);
+                self.seen.retain(|_, index| *index < checkpoint);
+                match error {
+                    Unsendable::Value(_) => Ok(None),
+                    Unsendable::TooDeep => Err(error),
+                }
+            }
+        }
+    }
+
+    fn class(&mut self, class: &Rc<Class>) -> Result<usize, Unsendable> {
+        let shared = self.node(Rc::as_ptr(class) as usize, |packer| {
+            let superclass = class
+                .superclass()
+                .map(|superclass| packer.class(superclass))
+                .transpose()?;
+            let methods = packer.methods(class.methods())?;
+            let getters = packer.methods(&class.accessors().getters)?;
+            let setters = packer.methods(&class.accessors().setters)?;
+            let statics = class
+                .statics()
+                .into_iter()
+                .map(|(name, value)| Ok((name, packer.pack(&value)?)))
+                .collect::<Result<_, Unsendable>>()?;
+            let traits = class
+                .traits()
+                .iter()
+                .map(|implemented| packer.trait_node(implemented))
+                .collect::<Result<_, Unsendable>>()?;
+            Ok(Node::Class {
+                name: class.name.clone(),
+                superclass,
+                methods,
+                getters,
+                setters,
+                statics,
+                traits,
+                frozen: class.is_frozen(),
+            })
+        })?;
+        Ok(node_index(shared))
+    }
+
+    fn methods(
+        &mut self,
+        methods: &HashMap<String, Rc<Function>>,
+    ) -> Result<Vec<(String, usize)>, Unsendable> {
+        methods
+            .iter()
+            .map(|(name, method)| Ok((name.clone(), self.function(method)?)))
+            .collect()
+    }
+
+    fn trait_node(&mut self, implemented: &Rc<Trait>) -> Result<usize, Unsendable> {
+        let shared = self.node(Rc::as_ptr(implemented) as usize, |packer| {
+            Ok(Node::Trait {
+                name: implemented.name.clone(),
+                required: implemented.required().to_vec(),
+                methods: packer.methods(implemented.methods())?,
+                getters: packer.methods(&implemented.accessors().getters)?,
+                setters: packer.methods(&implemented.accessors().setters)?,
+            })
+        })?;
+        Ok(node_index(shared))
+    }
+}
+
+/// Calls `check` with the value of each variable in `closure`, or the
+/// scopes enclosing it, that a function mentioning `referenced` could read:
+/// the innermost bound one of each name. Slots of scopes without names are
+/// skipped.
+fn captured(
+    closure: &Rc<RefCell<Environment>>,
+    referenced: &HashSet<String>,
+    mut check: impl FnMut(&Value) -> Result<(), Unsendable>,
+) -> Result<(), Unsendable> {
+    let mut found = HashSet::new();
+    let mut scope = Some(Rc::clone(closure));
+    while let Some(env) = scope {
+        let env = env.borrow();
+        if let Some(names) = env.names() {
+            for (index, name) in names.iter().enumerate() {
+                if !referenced.contains(&**name) || found.contains(name) {
+                    continue;
+                }
+                if let Some((value, _)) = env.slot(index) {
+                    found.insert(Arc::clone(name));
+                    check(&value)?;
+                }
+            }
+        }
+        scope = env.enclosing().cloned();
+    }
+    Ok(())
+}
+
+fn node_index(shared: Shared) -> usize {
+    match shared {
+        Shared::Node(index) => index,
+        _ => unreachable!("reference-typed values pack to nodes"),
+    }
+}
+
+struct Unpacker<'a> {
+    nodes: &'a [Node],
+    values: Vec<Option<Value>>,
+    /// The scope rebuilt from each `Node::Scope`.
+    scopes: Vec<Option<Rc<RefCell<Environment>>>>,
+    /// Scopes whose slots, and functions whose globals, still need filling
+    /// in.
+    pending: Vec<usize>,
+}
+
+impl Unpacker<'_> {
+    fn scope(&mut self, index: usize) -> Rc<RefCell<Environment>> {
+        if let Some(env) = &self.scopes[index] {
+            return Rc::clone(env);
+        }
+        let Node::Scope {
+            slots,
+            names,
+            enclosing,
+        } = &self.nodes[index]
+        else {
+            unreachable!("closures refer to scopes");
+        };
+        let env = match enclosing {
+            Some(enclosing) => Environment::with_enclosing(self.scope(*enclosing), slots.len()),
+            None => Rc::new(RefCell::new(Environment::with_slots(slots.len()))),
+        };
+        if let Some(names) = names {
+            env.borrow_mut().name_slots(Arc::clone(names));
+        }
+        self.scopes[index] = Some(Rc::clone(&env));
+        self.pending.push(index);
+        env
+    }
+
+    fn methods(&mut self, methods: &[(String, usize)]) -> HashMap<String, Rc<Function>> {
+        methods
+            .iter()
+            .map(|(name, method)| match self.node(*method) {
+                Value::Function(function) => (name.clone(), function),
+                _ => unreachable!("methods unpack to functions"),
+            })
+            .collect()
+    }
+
+    fn unpack(&mut self, shared: &Shared) -> Value {
+        match shared {
+            Shared::Nil => Value::Nil,
+            Shared::Boolean(b) => Value::Boolean(*b),
+            Shared::Number(n) => Value::Number(*n),
+            Shared::Integer(n) => Value::Integer(*n),
+            Shared::BigInt(n) => Value::BigInt(Rc::new(n.clone())),
+            Shared::Rational(r) => Value::Rational(Rc::new(r.clone())),
+            Shared::String(s) => Value::String(s.clone()),
+            Shared::Range(range) => Value::Range(Rc::new(range.clone())),
+            Shared::Native(native) => Value::NativeFunction(Rc::new(native.clone())),
+            Shared::Channel(channel) => Value::Channel(Arc::clone(channel)),
+            Shared::Thread(thread) => Value::Thread(Arc::clone(thread)),
+            Shared::Socket(socket) => Value::Socket(Arc::clone(socket)),
+            Shared::Enum(enumeration) => Value::Enum(Arc::clone(enumeration)),
+            Shared::Constructor(enumeration, index) => {
+                Value::Constructor(Arc::clone(enumeration), *index)
+            }
+            Shared::Variant(enumeration, index, values) => Value::Variant(Rc::new(Variant {
+                enumeration: Arc::clone(enumeration),
+                index: *index,
+                values: values.iter().map(|value| self.unpack(value)).collect(),
+            })),
+            Shared::Node(index) => self.node(*index),
+        }
+    }
+
+    fn node(&mut self, index: usize) -> Value {
+        if let Some(value) = &self.values[index] {
+            return value.clone();
+        }
+        match &self.nodes[index] {
+            Node::List { elements, frozen } => {
+                let list = Rc::new(RefCell::new(List::default()));
+                self.values[index] = Some(Value::List(Rc::clone(&list)));
+                let elements = elements
+                    .iter()
+                    .map(|element| self.unpack(element))
+                    .collect();
+                **list.borrow_mut() = elements;
+                list.borrow_mut().frozen = *frozen;
+                Value::List(list)
+            }
+            Node::Map { entries, frozen } => {
+                let map = Rc::new(RefCell::new(Map::new()));
+                self.values[index] = Some(Value::Map(Rc::clone(&map)));
+                for (key, value) in entries {
+                    let (key, value) = (self.unpack(key), self.unpack(value));
+                    map.borrow_mut()
+                        .insert(key, value)
+                        .expect("keys were hashable when sent");
+                }
+                map.borrow_mut().frozen = *frozen;
+                Value::Map(map)
+            }
+            Node::Function {
+                declaration,
+                closure,
+                is_initializer,
+                ..
+            } => {
+                let function = Value::Function(Rc::new(Function::new(
+                    Arc::clone(declaration),
+                    self.scope(*closure),
+                    *is_initializer,
+                )));
+                self.values[index] = Some(function.clone());
+                self.pending.push(index);
+                function
+            }
+            Node::Scope { .. } => unreachable!("scopes aren't values"),
+            Node::Class {
+                name,
+                superclass,
+                methods,
+                getters,
+                setters,
+                statics,
+                traits,
+                frozen,
+            } => {
+                let methods = self.methods(methods);
+                let accessors = Accessors {
+                    getters: self.methods(getters),
+                    setters: self.methods(setters),
+                };
+                let traits = traits
+                    .iter()
+                    .map(|implemented| match self.node(*implemented) {
+                        Value::Trait(implemented) => implemented,
+                        _ => unreachable!("traits unpack to traits"),
+                    })
+                    .collect();
+                let superclass = superclass.map(|superclass| match self.node(superclass) {
+                    Value::Class(superclass) => superclass,
+                    _ => unreachable!("superclasses unpack to classes"),
+                });
+                let class = Rc::new(Class::new(
+                    name.clone(),
+                    superclass,
+                    methods,
+                    accessors,
+                    traits,
+                ));
+                self.values[index] = Some(Value::Class(Rc::clone(&class)));
+                // Static fields may refer back to the class.
+                for (name, value) in statics {
+                    let value = self.unpack(value);
+                    class.set_static(name.clone(), value);
+                }
+                if *frozen {
+                    class.freeze();
+                }
+                Value::Class(class)
+            }
+            Node::Trait {
+                name,
+                required,
+                methods,
+                getters,
+                setters,
+            } => {
+                let methods = self.methods(methods);
+                let accessors = Accessors {
+                    getters: self.methods(getters),
+                    setters: self.methods(setters),
+                };
+                let implemented = Trait::new(name.clone(), required.clone(), methods, accessors);
+                let implemented = Value::Trait(Rc::new(implemented));
+                self.values[index] = Some(implemented.clone());
+                implemented
+            }
+            Node::Instance {
+                class,
+                fields,
+                frozen,
+            } => {
+                let Value::Class(class) = self.node(*class) else {
+                    unreachable!("instances refer to classes");
+                };
+                let instance = Rc::new(RefCell::new(Instance::new(class)));
+                self.values[index] = Some(Value::Instance(Rc::clone(&instance)));
+                for (name, value) in fields {
+                    let value = self.unpack(value);
+                    instance.borrow_mut().fields.insert(name.clone(), value);
+                }
+                instance.borrow_mut().frozen = *frozen;
+                Value::Instance(instance)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    #[test]
+    fn closures_using_unsendable_locals_are_refused() {
+        let error = eval(
+            "var it = [9]; fun gen() { yield 1; }
+             fun f() { var it = gen(); return join(spawn(() => it)); }
+             f();",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "Can't send '<generator gen>' to another thread at line 2"
+        );
+    }
+
+    #[test]
+    fn unsendable_locals_a_closure_doesnt_use_stay_behind() {
+        let source = "fun gen() { yield 1; }
+            fun f() { var unused = gen(); var it = 5; return join(spawn(() => it)); }
+            fun g() { var it = gen(); { var it = 3; return join(spawn(() => it)); } }
+            [f(), g()];";
+        assert_eq!(eval(source), Ok("[5, 3]".to_string()));
+    }
+
+    #[test]
+    fn subclasses_keep_their_superclass_in_another_thread() {
+        let source = "class A { f() { return 1; } }
+            class B < A { f() { return [super.f(), 2]; } }
+            var b = B();
+            join(spawn(() => [b.f(), b is A, B() is A]));";
+        assert_eq!(eval(source), Ok("[[1, 2], true, true]".to_string()));
+    }
+}
@@ -1,20 +1,443 @@
+use crate::asyncio::Socket;
+use crate::bigint::BigInt;
+use crate::class::{Class, Instance, Trait};
+use crate::enums::{Enum, Variant};
+use crate::event_loop::Future;
+use crate::function::{Function, NativeFunction};
+use crate::iterator::{Iter, Range};
+use crate::map::Map;
+use crate::numeric;
+use crate::rational::Rational;
+use crate::thread::{Channel, ThreadHandle};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Value {
     Number(f64),
-    String(String),
+    Integer(i64),
+    BigInt(Rc<BigInt>),
+    Rational(Rc<Rational>),
+    String(Arc<str>),
     Boolean(bool),
     Nil,
+    Function(Rc<Function>),
+    NativeFunction(Rc<NativeFunction>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    Trait(Rc<Trait>),
+    Enum(Arc<Enum>),
+    /// The constructor of the variant at this position in the enum.
+    Constructor(Arc<Enum>, usize),
+    Variant(Rc<Variant>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+    Range(Rc<Range>),
+    Iterator(Rc<RefCell<Iter>>),
+    Channel(Arc<Channel>),
+    Thread(Arc<ThreadHandle>),
+    Future(Rc<Future>),
+    Socket(Arc<Socket>),
 }
 
+impl Value {
+    /// `nil` and `false` are falsey; every other value is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn list(elements: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(List {
+            elements,
+            frozen: false,
+        })))
+    }
+
+    pub fn map(map: Map) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+
+    /// Formats a value nested inside a collection, quoting strings so that
+    /// `["a"]` and `[a]` stay distinguishable.
+    fn fmt_nested(&self, f: &mut fmt::Formatter, instance: InstanceWriter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            other => other.write(f, instance),
+        }
+    }
+}
+
+/// How deeply lists, maps and variants print inside each other before
+/// the rest is elided, so that printing a deep one can't overflow the
+/// stack.
+const MAX_SHOWN_DEPTH: usize = 1000;
+
+thread_local! {
+    /// The lists, maps and variants being shown further up the stack.
+    static SHOWING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Shows the value at `address` with `show`, unless it is already being
+/// shown further up the stack, as when a list holds itself, or is nested
+/// too deeply to show.
+fn show_once(address: usize, show: impl FnOnce() -> fmt::Result) -> Option<fmt::Result> {
+    let elided = SHOWING.with(|showing| {
+        let showing = showing.borrow();
+        showing.len() >= MAX_SHOWN_DEPTH || showing.contains(&address)
+    });
+    if elided {
+        return None;
+    }
+    SHOWING.with(|showing| showing.borrow_mut().push(address));
+    let shown = show();
+    SHOWING.with(|showing| showing.borrow_mut().pop());
+    Some(shown)
+}
+
+/// Compares lists, maps and variants element by element with a worklist
+/// rather than by recursing, so that however deeply they are nested the
+/// stack can't overflow. A pair of containers met again, as when both
+/// hold themselves, is taken as equal, so they are equal unless some other
+/// difference is found. Maps are equal when they hold equal values under
+/// the same keys, regardless of insertion order, comparing numbers by
+/// value; `by_value` compares numbers that way everywhere, as `==` does.
+#[inline]
+pub fn equal(left: &Value, right: &Value, by_value: bool) -> bool {
+    match (left, right) {
+        (Value::List(_), Value::List(_))
+        | (Value::Map(_), Value::Map(_))
+        | (Value::Variant(_), Value::Variant(_)) => containers_equal(left, right, by_value),
+        _ => scalars_equal(left, right, by_value),
+    }
+}
+
+#[inline]
+fn scalars_equal(left: &Value, right: &Value, by_value: bool) -> bool {
+    if by_value && numeric::is_numeric(left) && numeric::is_numeric(right) {
+        numeric::compare(left, right) == Some(Ordering::Equal)
+    } else {
+        left == right
+    }
+}
+
+fn containers_equal(left: &Value, right: &Value, by_value: bool) -> bool {
+    let mut pending = vec![(left.clone(), right.clone(), by_value)];
+    let mut seen = HashSet::new();
+    while let Some((left, right, by_value)) = pending.pop() {
+        match (&left, &right) {
+            (Value::List(a), Value::List(b)) => {
+                if !by_value && Rc::ptr_eq(a, b)
+                    || !seen.insert((a.as_ptr() as usize, b.as_ptr() as usize))
+                {
+                    continue;
+                }
+                let (a, b) = (a.borrow(), b.borrow());
+                if a.len() != b.len() {
+                    return false;
+                }
+                let pairs = a.iter().zip(b.iter()).rev();
+                pending.extend(pairs.map(|(x, y)| (x.clone(), y.clone(), by_value)));
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                if !by_value && Rc::ptr_eq(a, b)
+                    || !seen.insert((a.as_ptr() as usize, b.as_ptr() as usize))
+                {
+                    continue;
+                }
+                let (a, b) = (a.borrow(), b.borrow());
+                if a.len() != b.len() {
+                    return false;
+                }
+                for (key, value) in a.entries().iter().rev() {
+                    match b.get(key) {
+                        Ok(Some(other)) => pending.push((value.clone(), other, true)),
+                        _ => return false,
+                    }
+                }
+            }
+            // Fields of variants compare as `PartialEq` does.
+            (Value::Variant(a), Value::Variant(b)) => {
+                if !Arc::ptr_eq(&a.enumeration, &b.enumeration)
+                    || a.index != b.index
+                    || a.values.len() != b.values.len()
+                {
+                    return false;
+                }
+                let pairs = a.values.iter().zip(b.values.iter()).rev();
+                pending.extend(pairs.map(|(x, y)| (x.clone(), y.clone(), false)));
+            }
+            _ => {
+                if !scalars_equal(&left, &right, by_value) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Drops `values` with a worklist rather than by recursing, so that
+/// dropping a deeply nested value can't overflow the stack. Lists, maps,
+/// variants and instances that nothing else refers to are emptied into the
+/// worklist before they go.
+pub fn drop_nested(mut pending: Vec<Value>) {
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::List(list) => {
+                if let Some(list) = Rc::into_inner(list) {
+                    pending.append(&mut list.into_inner().elements);
+                }
+            }
+            Value::Map(map) => {
+                if let Some(map) = Rc::into_inner(map) {
+                    pending.extend(map.into_inner().take_values());
+                }
+            }
+            Value::Variant(variant) => {
+                if let Some(mut variant) = Rc::into_inner(variant) {
+                    pending.append(&mut variant.values);
+                }
+            }
+            Value::Instance(instance) => {
+                if let Some(instance) = Rc::into_inner(instance) {
+                    pending.extend(instance.into_inner().fields.drain().map(|(_, value)| value));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The elements of a list value. It reads and writes as a `Vec`, but once
+/// `frozen` is set the interpreter refuses to change it.
+#[derive(Debug, Default)]
+pub struct List {
+    elements: Vec<Value>,
+    pub frozen: bool,
+}
+
+impl Deref for List {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.elements
+    }
+}
+
+impl DerefMut for List {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.elements
+    }
+}
+
+impl Drop for List {
+    fn drop(&mut self) {
+        drop_nested(std::mem::take(&mut self.elements));
+    }
+}
+
+/// Plain data, lists, maps, ranges and enum variants compare structurally;
+/// functions, classes, traits, enums, instances, iterators, channels,
+/// threads, futures and sockets compare by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFunction(a), Value::NativeFunction(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::Trait(a), Value::Trait(b)) => Rc::ptr_eq(a, b),
+            (Value::Enum(a), Value::Enum(b)) => Arc::ptr_eq(a, b),
+            (Value::Constructor(a, i), Value::Constructor(b, j)) => Arc::ptr_eq(a, b) && i == j,
+            (Value::Variant(_), Value::Variant(_))
+            | (Value::List(_), Value::List(_))
+            | (Value::Map(_), Value::Map(_)) => equal(self, other, false),
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => Arc::ptr_eq(a, b),
+            (Value::Thread(a), Value::Thread(b)) => Arc::ptr_eq(a, b),
+            (Value::Future(a), Value::Future(b)) => Rc::ptr_eq(a, b),
+            (Value::Socket(a), Value::Socket(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Writes an instance for `Value::display_with`.
+pub type InstanceWriter<'a> =
+    &'a dyn Fn(&Rc<RefCell<Instance>>, &mut fmt::Formatter) -> fmt::Result;
+
+/// Writes an instance as `Display` does, by its class's name.
+pub fn write_instance(instance: &Rc<RefCell<Instance>>, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} instance", instance.borrow().class.name)
+}
+
+/// A value shown with `Value::display_with`.
+pub struct DisplayWith<'a> {
+    value: &'a Value,
+    instance: InstanceWriter<'a>,
+}
+
+impl fmt::Display for DisplayWith<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.write(f, self.instance)
+    }
+}
+
+/// Numeric variants forward the formatter so width and precision apply,
+/// e.g. `{:.2}` prints a rational as a rounded decimal. A list or map
+/// inside itself, or too deeply nested, prints as `[...]` or `{...}`.
+/// Instances show as their class's name: displaying a value never runs the
+/// script's code.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &write_instance)
+    }
+}
+
+impl Value {
+    /// The value displayed with the instances in it written by `instance`,
+    /// which lets `print` use a class's `__str__` method.
+    pub fn display_with<'a>(&'a self, instance: InstanceWriter<'a>) -> DisplayWith<'a> {
+        DisplayWith {
+            value: self,
+            instance,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, instance: InstanceWriter) -> fmt::Result {
         match self {
-            Value::Number(n) => write!(f, "{}", n),
+            Value::Number(n) => fmt::Display::fmt(n, f),
+            Value::Integer(n) => fmt::Display::fmt(n, f),
+            Value::BigInt(n) => fmt::Display::fmt(n, f),
+            Value::Rational(r) => fmt::Display::fmt(r, f),
             Value::String(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Nil => write!(f, "nil"),
+            Value::Function(function) => write!(f, "<fn {}>", function.name()),
+            Value::NativeFunction(native) => write!(f, "<native fn {}>", native.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(object) => instance(object, f),
+            Value::Trait(implemented) => write!(f, "{}", implemented.name),
+            Value::Enum(enumeration) => write!(f, "{}", enumeration.name),
+            Value::Constructor(enumeration, index) => write!(
+                f,
+                "<constructor {}.{}>",
+                enumeration.name,
+                enumeration.variant_name(*index)
+            ),
+            Value::Variant(variant) => {
+                write!(f, "{}", variant.name())?;
+                if variant.enumeration.fields(variant.index).is_none() {
+                    return Ok(());
+                }
+                show_once(Rc::as_ptr(variant) as usize, || {
+                    write!(f, "(")?;
+                    for (i, value) in variant.values.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        value.fmt_nested(f, instance)?;
+                    }
+                    write!(f, ")")
+                })
+                .unwrap_or_else(|| write!(f, "(...)"))
+            }
+            Value::List(elements) => show_once(elements.as_ptr() as usize, || {
+                write!(f, "[")?;
+                // Copied out, since showing an instance may change the list.
+                let elements = elements.borrow().to_vec();
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, instance)?;
+                }
+                write!(f, "]")
+            })
+            .unwrap_or_else(|| write!(f, "[...]")),
+            Value::Map(map) => show_once(map.as_ptr() as usize, || {
+                write!(f, "{{")?;
+                let entries = map.borrow().entries().to_vec();
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_nested(f, instance)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f, instance)?;
+                }
+                write!(f, "}}")
+            })
+            .unwrap_or_else(|| write!(f, "{{...}}")),
+            Value::Range(range) => write!(f, "{}", range),
+            Value::Iterator(iter) => match iter.try_borrow() {
+                Ok(iter) => write!(f, "{:?}", *iter),
+                Err(_) => write!(f, "<iterator>"),
+            },
+            Value::Channel(_) => write!(f, "<channel>"),
+            Value::Thread(_) => write!(f, "<thread>"),
+            Value::Future(future) => write!(f, "{:?}", future),
+            Value::Socket(socket) => write!(f, "{:?}", socket),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::eval;
+
+    #[test]
+    fn containers_inside_themselves_print_elided() {
+        assert_eq!(eval("var l = [1]; l[0] = l; l;"), Ok("[[...]]".to_string()));
+        assert_eq!(
+            eval("var m = {\"a\": 1}; m[\"m\"] = m; m;"),
+            Ok("{\"a\": 1, \"m\": {...}}".to_string())
+        );
+        assert_eq!(
+            eval("var l = []; var s = [l, l]; s;"),
+            Ok("[[], []]".to_string())
+        );
+    }
+
+    #[test]
+    fn containers_inside_themselves_compare_structurally() {
+        let lists = "var a = [1, 2]; a[0] = a; var b = [1, 2]; b[0] = b;";
+        assert_eq!(eval(&format!("{} a == a;", lists)), Ok("true".to_string()));
+        assert_eq!(eval(&format!("{} a == b;", lists)), Ok("true".to_string()));
+        assert_eq!(
+            eval(&format!("{} b[1] = 3; a == b;", lists)),
+            Ok("false".to_string())
+        );
+        let maps = "var a = {}; a[\"a\"] = a; var b = {}; b[\"a\"] = b;";
+        assert_eq!(eval(&format!("{} a == b;", maps)), Ok("true".to_string()));
+        assert_eq!(
+            eval(&format!("{} b[\"b\"] = 1; a != b;", maps)),
+            Ok("true".to_string())
+        );
+    }
+
+    #[test]
+    fn deeply_nested_values_print_and_compare() {
+        let lists = "var a = []; var b = []; for i in 0..100000 { a = [a]; b = [b]; }";
+        assert_eq!(eval(&format!("{} a == b;", lists)), Ok("true".to_string()));
+        let shown = eval(&format!("{} a;", lists)).unwrap();
+        assert!(shown.starts_with("[[[") && shown.contains("[...]"));
+        let variants = "enum L { Cons(head, tail), Nil }
+            var a = L.Nil; for i in 0..100000 { a = L.Cons(i, a); }";
+        let shown = eval(&format!("{} a;", variants)).unwrap();
+        assert!(shown.starts_with("Cons(99999, Cons(99998, ") && shown.contains("(...)"));
+    }
+}